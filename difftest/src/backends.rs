@@ -1,13 +1,20 @@
 use std::{
+    collections::HashMap,
     env,
     ffi::{OsStr, OsString},
+    fmt, fs,
     hash::{Hash, Hasher},
-    io::Write,
+    io::{self, Write},
     path::{Path, PathBuf},
-    process::{self, Command, ExitStatus, Stdio},
+    process::{self, Child, Command, ExitStatus, Stdio},
+    sync::mpsc,
+    thread,
+    time::Duration,
 };
 
 use log::debug;
+use mir::interpret::InterpError;
+use serde::Serialize;
 
 use crate::Source;
 
@@ -67,8 +74,29 @@ impl From<process::Output> for ProcessOutput {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Compile,
+    Execute,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Compile => f.write_str("compiling"),
+            Stage::Execute => f.write_str("executing"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
-pub struct CompExecError(pub ProcessOutput);
+pub enum CompExecError {
+    /// The compiler or the program exited unsuccessfully
+    Failed(Stage, ProcessOutput),
+    /// The process group was killed after running out of time
+    Timeout(Stage),
+}
 
 pub type ExecResult = Result<ProcessOutput, CompExecError>;
 
@@ -76,26 +104,135 @@ pub type ExecResult = Result<ProcessOutput, CompExecError>;
 pub struct BackendInitError(pub String);
 
 pub trait Backend: Send + Sync {
-    fn compile(&self, _: &Source, _: &Path) -> ProcessOutput {
-        panic!("not implemented")
+    /// A rustc invocation with this backend's codegen options, but no input or output
+    fn rustc_command(&self) -> Option<Command> {
+        None
+    }
+
+    /// Flags sampled in swarm mode, reported along with the results
+    fn swarm_flags(&self) -> &[String] {
+        &[]
+    }
+
+    /// Whether this backend runs programs unoptimised and checks for UB, so
+    /// the other backends are expected to agree with it
+    fn is_reference(&self) -> bool {
+        false
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        Timeouts::default()
+    }
+
+    /// The compiler's `--version`
+    fn version(&self) -> Option<String> {
+        let mut command = self.rustc_command()?;
+        version(command.arg("--version"))
+    }
+
+    fn compile(&self, source: &Source, target: &Path) -> ExecResult {
+        let command = self.rustc_command().expect("not implemented");
+        let timeout = self.timeouts().compile;
+        if source.is_split() {
+            let lower = self.rustc_command().unwrap();
+            return compile_crates(command, lower, timeout, source, target);
+        }
+        compile_single(command, timeout, source, target)
     }
 
     fn execute(&self, source: &Source, target: &Path) -> ExecResult {
         debug!("Compiling {source}");
-        let compile_out = self.compile(source, target);
-        if !compile_out.status.success() {
-            return Err(CompExecError(compile_out));
-        }
+        self.compile(source, target)?;
 
         debug!("Executing compiled {source}");
-        let exec_out = Command::new(target)
-            .output()
-            .expect("can execute target program and get output");
+        let mut command = Command::new(target);
+        own_process_group(&mut command);
+        let program = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("can execute target program");
+        let exec_out = wait_with_timeout(program, self.timeouts().run, Stage::Execute)?;
         Ok(exec_out.into())
     }
 }
 
-fn run_compile_command(mut command: Command, source: &Source) -> process::Output {
+fn version(command: &mut Command) -> Option<String> {
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let version = String::from_utf8(output.stdout).ok()?;
+    Some(version.trim().to_owned())
+}
+
+/// Puts the child in a process group of its own, so that a timeout can kill
+/// everything it spawned too
+fn own_process_group(command: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        // taskkill /T kills the whole tree instead
+        let _ = command;
+    }
+}
+
+fn kill_process_group(pid: u32) {
+    #[cfg(unix)]
+    unsafe {
+        libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+    }
+    #[cfg(windows)]
+    {
+        let _ = Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .output();
+    }
+}
+
+/// Waits for the child to exit, or kills its process group once the timeout runs out
+fn wait_with_timeout(
+    child: Child,
+    timeout: Option<Duration>,
+    stage: Stage,
+) -> Result<process::Output, CompExecError> {
+    let Some(timeout) = timeout else {
+        return Ok(child.wait_with_output().expect("can wait for child"));
+    };
+
+    let pid = child.id();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || tx.send(child.wait_with_output()));
+    match rx.recv_timeout(timeout) {
+        Ok(output) => Ok(output.expect("can wait for child")),
+        Err(_) => {
+            debug!("{pid} timed out while {stage}, killing its process group");
+            kill_process_group(pid);
+            // Reap the child before returning
+            let _ = rx.recv();
+            Err(CompExecError::Timeout(stage))
+        }
+    }
+}
+
+fn run_compile_command(
+    mut command: Command,
+    source: &Source,
+    timeout: Option<Duration>,
+    stage: Stage,
+) -> Result<process::Output, CompExecError> {
+    own_process_group(&mut command);
+    // ICEs print their backtrace to stderr, for triage, rather than to a file
+    for (key, value) in [("RUST_BACKTRACE", "1"), ("RUSTC_ICE", "0")] {
+        if !command.get_envs().any(|(set, _)| set == key) {
+            command.env(key, value);
+        }
+    }
     let compiler = match source {
         Source::File(path) => {
             command.arg(path.canonicalize().expect("path is correct"));
@@ -112,27 +249,70 @@ fn run_compile_command(mut command: Command, source: &Source) -> process::Output
                 .stderr(Stdio::piped())
                 .spawn()
                 .expect("can spawn compiler");
-            child
-                .stdin
-                .as_mut()
-                .unwrap()
-                .write_all(code.as_bytes())
-                .unwrap();
+            // A compiler that exits before reading the program (e.g. on a bad flag) fails
+            // with its own error
+            match child.stdin.as_mut().unwrap().write_all(code.as_bytes()) {
+                Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                    panic!("can write source code to the compiler: {e}")
+                }
+                _ => {}
+            }
             child
         }
     };
 
-    let compile_out = compiler
-        .wait_with_output()
-        .expect("can execute rustc and get output");
+    wait_with_timeout(compiler, timeout, stage)
+}
+
+/// Fails if the compiler exited unsuccessfully
+fn compile_result(output: process::Output, stage: Stage) -> ExecResult {
+    if output.status.success() {
+        Ok(output.into())
+    } else {
+        Err(CompExecError::Failed(stage, output.into()))
+    }
+}
+
+fn run_rustc(command: Command, source: &Source, timeout: Option<Duration>) -> ExecResult {
+    let output = run_compile_command(command, source, timeout, Stage::Compile)?;
+    compile_result(output, Stage::Compile)
+}
 
-    compile_out
+/// Compiles the C translation unit embedded in the source, if there is one, next to
+/// the target and returns the rustc arguments that link it in
+fn c_unit_args(source: &Source, target: &Path) -> Result<Vec<OsString>, CompExecError> {
+    let Some(unit) = source.c_unit() else {
+        return Ok(vec![]);
+    };
+    let c_path = target.with_extension("c");
+    let object_path = target.with_extension("o");
+    fs::write(&c_path, unit).expect("can write C translation unit");
+
+    let cc = env::var_os("CC").unwrap_or_else(|| OsString::from("cc"));
+    let cc_out = Command::new(cc)
+        .args(["-c", "-O2", "-o"])
+        .arg(&object_path)
+        .arg(&c_path)
+        .output()
+        .expect("can run C compiler and get output");
+    compile_result(cc_out, Stage::Compile)?;
+
+    let mut link_arg = OsString::from("link-arg=");
+    link_arg.push(&object_path);
+    Ok(vec![
+        OsString::from("--cfg"),
+        OsString::from("rustlantis_cc"),
+        OsString::from("-C"),
+        link_arg,
+    ])
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum OptLevel {
     Unoptimised,
     Optimised,
+    /// Passed to rustc as is, for both -C opt-level and -Z mir-opt-level
+    Explicit(usize),
 }
 
 impl OptLevel {
@@ -140,6 +320,7 @@ impl OptLevel {
         match self {
             OptLevel::Unoptimised => 0,
             OptLevel::Optimised => 3,
+            OptLevel::Explicit(level) => *level,
         }
     }
 
@@ -147,14 +328,40 @@ impl OptLevel {
         match self {
             OptLevel::Unoptimised => 0,
             OptLevel::Optimised => 4,
+            OptLevel::Explicit(level) => *level,
         }
     }
 }
 
+/// Extra rustc flags and environment variables for a backend, on top of
+/// the ones it sets itself
+#[derive(Debug, Clone, Default)]
+pub struct BackendOptions {
+    pub flags: Vec<String>,
+    pub env: HashMap<String, String>,
+    pub timeouts: Timeouts,
+}
+
+/// How long compiling and running a program may take, unlimited if None
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeouts {
+    pub compile: Option<Duration>,
+    pub run: Option<Duration>,
+}
+
+impl BackendOptions {
+    fn apply(&self, command: &mut Command) {
+        command.args(&self.flags).envs(&self.env);
+    }
+}
+
+#[derive(Clone)]
 pub struct LLVM {
     toolchain: Option<String>,
     codegen_opt: OptLevel,
     mir_opt: OptLevel,
+    lto: bool,
+    options: BackendOptions,
 }
 
 impl LLVM {
@@ -163,19 +370,33 @@ impl LLVM {
             codegen_opt,
             mir_opt,
             toolchain,
+            lto: false,
+            options: BackendOptions::default(),
         }
     }
+
+    /// Enable fat LTO, which lets LLVM optimise across the crates of a split program
+    pub fn lto(self) -> Self {
+        Self { lto: true, ..self }
+    }
+
+    pub fn with_options(self, options: BackendOptions) -> Self {
+        Self { options, ..self }
+    }
 }
 
 impl Backend for LLVM {
-    fn compile(&self, source: &Source, target: &Path) -> ProcessOutput {
+    fn timeouts(&self) -> Timeouts {
+        self.options.timeouts
+    }
+
+    fn rustc_command(&self) -> Option<Command> {
         let mut command = Command::new("rustc");
         if let Some(toolchain) = &self.toolchain {
             command.arg(format!("+{}", toolchain));
         }
 
         command
-            .args(["-o", target.to_str().unwrap()])
             .args([
                 "-C",
                 &format!("opt-level={}", self.codegen_opt.codegen_opt_level()),
@@ -185,10 +406,15 @@ impl Backend for LLVM {
                 "-Z",
                 &format!("mir-opt-level={}", self.mir_opt.mir_opt_level()),
             ]);
-        run_compile_command(command, source).into()
+        if self.lto {
+            command.args(["-C", "lto=fat"]);
+        }
+        self.options.apply(&mut command);
+        Some(command)
     }
 }
 
+#[derive(Clone)]
 enum BackendSource {
     Path(PathBuf),
     Rustup(String),
@@ -198,6 +424,7 @@ pub struct Miri {
     miri: BackendSource,
     sysroot: PathBuf,
     check_ub: bool,
+    options: BackendOptions,
 }
 
 impl Miri {
@@ -293,6 +520,7 @@ impl Miri {
             miri: BackendSource::Path(miri_dir.join("target/release/miri")),
             sysroot,
             check_ub,
+            options: BackendOptions::default(),
         })
     }
 
@@ -302,21 +530,44 @@ impl Miri {
             miri: BackendSource::Rustup(toolchain.to_owned()),
             sysroot,
             check_ub,
+            options: BackendOptions::default(),
         })
     }
-}
 
-impl Backend for Miri {
-    fn execute(&self, source: &Source, _: &Path) -> ExecResult {
-        debug!("Executing with Miri {source}");
+    pub fn with_options(self, options: BackendOptions) -> Self {
+        Self { options, ..self }
+    }
+
+    fn miri_command(&self) -> Command {
         let mut command = match &self.miri {
             BackendSource::Path(binary) => Command::new(binary),
             BackendSource::Rustup(toolchain) => {
                 let mut cmd = Command::new("rustup");
-                cmd.args(["run", &toolchain, "miri"]);
+                cmd.args(["run", toolchain, "miri"]);
                 cmd
             }
         };
+        command.clear_env(&["PATH", "DEVELOPER_DIR"]);
+        command
+    }
+}
+
+impl Backend for Miri {
+    fn is_reference(&self) -> bool {
+        self.check_ub
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        self.options.timeouts
+    }
+
+    fn version(&self) -> Option<String> {
+        version(self.miri_command().arg("--version"))
+    }
+
+    fn execute(&self, source: &Source, _: &Path) -> ExecResult {
+        debug!("Executing with Miri {source}");
+        let mut command = self.miri_command();
         if self.check_ub {
             command.arg("-Zmiri-tree-borrows");
         } else {
@@ -325,25 +576,36 @@ impl Backend for Miri {
                 .arg("-Zmiri-disable-validation")
                 .arg("-Zmiri-disable-alignment-check");
         }
-        command
-            .clear_env(&["PATH", "DEVELOPER_DIR"])
-            .args([OsStr::new("--sysroot"), self.sysroot.as_os_str()]);
-
-        let miri_out = run_compile_command(command, source);
-
-        // FIXME: we assume the source always exits with 0, and any non-zero return code
-        // came from Miri itself (e.g. UB and type check errors)
-        if !miri_out.status.success() {
-            return Err(CompExecError(miri_out.into()));
+        command.args([OsStr::new("--sysroot"), self.sysroot.as_os_str()]);
+        self.options.apply(&mut command);
+
+        // Miri interprets the program while "compiling" it
+        let timeout = self.options.timeouts.run;
+        let miri_out = run_compile_command(command, source, timeout, Stage::Execute)?;
+
+        // Miri passes the program's exit code through, so an unsuccessful exit is only
+        // Miri's own if it reported an error or crashed
+        let stderr = String::from_utf8_lossy(&miri_out.stderr);
+        if miri_out.status.code().is_none() || is_interpreter_error(&stderr) {
+            return Err(CompExecError::Failed(Stage::Execute, miri_out.into()));
         }
         Ok(miri_out.into())
     }
 }
 
+/// Whether Miri stopped the program with an error: UB, an unsupported operation,
+/// a type check failure or an ICE. These end with rustc's usual diagnostics, while
+/// the program's own panics and exits don't
+fn is_interpreter_error(stderr: &str) -> bool {
+    stderr.contains("error: aborting due to") || stderr.contains("error: internal compiler error")
+}
+
+#[derive(Clone)]
 pub struct Cranelift {
     clif: BackendSource,
     codegen_opt: OptLevel,
     mir_opt: OptLevel,
+    options: BackendOptions,
 }
 
 impl Cranelift {
@@ -387,6 +649,7 @@ impl Cranelift {
             clif: BackendSource::Path(clif_dir.join("dist/rustc-clif")),
             codegen_opt,
             mir_opt,
+            options: BackendOptions::default(),
         })
     }
 
@@ -399,6 +662,7 @@ impl Cranelift {
             clif: BackendSource::Path(binary_path.as_ref().to_owned()),
             codegen_opt,
             mir_opt,
+            options: BackendOptions::default(),
         }
     }
 
@@ -411,12 +675,21 @@ impl Cranelift {
             clif: BackendSource::Rustup(toolchain.to_owned()),
             codegen_opt,
             mir_opt,
+            options: BackendOptions::default(),
         })
     }
+
+    pub fn with_options(self, options: BackendOptions) -> Self {
+        Self { options, ..self }
+    }
 }
 
 impl Backend for Cranelift {
-    fn compile(&self, source: &Source, target: &Path) -> ProcessOutput {
+    fn timeouts(&self) -> Timeouts {
+        self.options.timeouts
+    }
+
+    fn rustc_command(&self) -> Option<Command> {
         let mut command = match &self.clif {
             BackendSource::Path(binary) => Command::new(binary),
             BackendSource::Rustup(toolchain) => {
@@ -427,7 +700,6 @@ impl Backend for Cranelift {
             }
         };
         command
-            .args(["-o", target.to_str().unwrap()])
             .args([
                 "-C",
                 &format!("opt-level={}", self.codegen_opt.codegen_opt_level()),
@@ -436,7 +708,8 @@ impl Backend for Cranelift {
                 "-Z",
                 &format!("mir-opt-level={}", self.mir_opt.mir_opt_level()),
             ]);
-        run_compile_command(command, source).into()
+        self.options.apply(&mut command);
+        Some(command)
     }
 }
 
@@ -446,6 +719,7 @@ pub struct GCC {
     repo: PathBuf,
     codegen_opt: OptLevel,
     mir_opt: OptLevel,
+    options: BackendOptions,
 }
 
 impl GCC {
@@ -478,11 +752,20 @@ impl GCC {
             repo: cg_gcc,
             codegen_opt,
             mir_opt,
+            options: BackendOptions::default(),
         })
     }
+
+    pub fn with_options(self, options: BackendOptions) -> Self {
+        Self { options, ..self }
+    }
 }
 impl Backend for GCC {
-    fn compile(&self, source: &Source, target: &Path) -> ProcessOutput {
+    fn timeouts(&self) -> Timeouts {
+        self.options.timeouts
+    }
+
+    fn rustc_command(&self) -> Option<Command> {
         let mut command = Command::new("rustc");
         command
             .clear_env(&["PATH", "DEVELOPER_DIR", "LD_LIBRARY_PATH"])
@@ -493,7 +776,6 @@ impl Backend for GCC {
             ])
             .arg("--sysroot")
             .arg(&self.sysroot)
-            .args(["-o", target.to_str().unwrap()])
             .args([
                 "-C",
                 &format!("opt-level={}", self.codegen_opt.codegen_opt_level()),
@@ -502,6 +784,214 @@ impl Backend for GCC {
                 "-Z",
                 &format!("mir-opt-level={}", self.mir_opt.mir_opt_level()),
             ]);
-        run_compile_command(command, source).into()
+        self.options.apply(&mut command);
+        Some(command)
+    }
+}
+
+/// Runs programs with `mir::interpret` in-process, so it needs no toolchain. It only
+/// understands the programs that `mir::parse` does, i.e. generated ones
+pub struct Interpreter {
+    step_limit: usize,
+}
+
+impl Interpreter {
+    /// Far more than a generated program takes, since each function runs at most once
+    pub const DEFAULT_STEP_LIMIT: usize = 10_000_000;
+
+    pub fn new(step_limit: usize) -> Self {
+        Self { step_limit }
+    }
+}
+
+impl Backend for Interpreter {
+    fn is_reference(&self) -> bool {
+        true
+    }
+
+    fn execute(&self, source: &Source, _: &Path) -> ExecResult {
+        debug!("Interpreting {source}");
+        let (program, tcx) = match mir::parse::parse(&source.code()) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let output = interpreter_output(1, String::new(), e.to_string());
+                return Err(CompExecError::Failed(Stage::Compile, output));
+            }
+        };
+        match mir::interpret::run(&program, &tcx, self.step_limit) {
+            Ok(stdout) => Ok(interpreter_output(0, stdout, String::new())),
+            // Stands in for running out of time, like any other non-terminating program
+            Err(InterpError::StepLimit) => Err(CompExecError::Timeout(Stage::Execute)),
+            Err(e) => {
+                let output = interpreter_output(1, String::new(), e.to_string());
+                Err(CompExecError::Failed(Stage::Execute, output))
+            }
+        }
+    }
+}
+
+fn interpreter_output(code: i32, stdout: String, stderr: String) -> ProcessOutput {
+    ProcessOutput {
+        status: exit_status(code),
+        stdout: stdout.into(),
+        stderr: stderr.into(),
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw(code << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+/// Builds the whole program as one crate, even if it is split
+pub(crate) fn compile_single(
+    mut command: Command,
+    timeout: Option<Duration>,
+    source: &Source,
+    target: &Path,
+) -> ExecResult {
+    command.args(["-o", target.to_str().unwrap()]);
+    command.args(c_unit_args(source, target)?);
+    run_rustc(command, source, timeout)
+}
+
+/// Builds the lower crate of a split program as an rlib, then the binary crate
+/// linking against it
+fn compile_crates(
+    mut upper: Command,
+    mut lower: Command,
+    timeout: Option<Duration>,
+    source: &Source,
+    target: &Path,
+) -> ExecResult {
+    let c_unit = c_unit_args(source, target)?;
+
+    // rustc only accepts extern crates named lib*.rlib
+    let lib = target.with_file_name(format!(
+        "lib{}.rlib",
+        target.file_name().unwrap().to_str().unwrap()
+    ));
+    lower
+        .args(["--crate-type", "rlib", "--crate-name", "rustlantis_lower"])
+        .args(["--cfg", "rustlantis_lower"])
+        .args(["-o", lib.to_str().unwrap()])
+        .args(&c_unit);
+    run_rustc(lower, source, timeout)?;
+
+    upper
+        .args(["--cfg", "rustlantis_upper"])
+        .arg("--extern")
+        .arg(format!("rustlantis_lower={}", lib.to_str().unwrap()))
+        .args(["-o", target.to_str().unwrap()])
+        .args(&c_unit);
+    run_rustc(upper, source, timeout)
+}
+
+/// Builds the lower crate of a split program with one backend and the binary crate
+/// linking against it with another, so that they have to agree on the Rust ABI
+pub struct Mixed {
+    upper: Box<dyn Backend>,
+    lower: Box<dyn Backend>,
+}
+
+impl Mixed {
+    pub fn new(upper: Box<dyn Backend>, lower: Box<dyn Backend>) -> Self {
+        Self { upper, lower }
+    }
+}
+
+impl Backend for Mixed {
+    fn timeouts(&self) -> Timeouts {
+        self.upper.timeouts()
+    }
+
+    fn version(&self) -> Option<String> {
+        let upper = self.upper.version()?;
+        let lower = self.lower.version()?;
+        Some(format!("{upper} + {lower}"))
+    }
+
+    fn compile(&self, source: &Source, target: &Path) -> ExecResult {
+        let upper = self
+            .upper
+            .rustc_command()
+            .expect("upper crate backend uses rustc");
+        let lower = self
+            .lower
+            .rustc_command()
+            .expect("lower crate backend uses rustc");
+        compile_crates(upper, lower, self.timeouts().compile, source, target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::Outcome;
+
+    use super::*;
+
+    fn miri_error(code: i32, stderr: &str) -> ExecResult {
+        assert!(is_interpreter_error(stderr));
+        Err(CompExecError::Failed(
+            Stage::Execute,
+            interpreter_output(code, String::new(), stderr.to_string()),
+        ))
+    }
+
+    fn miri_exit(code: i32, stdout: &str, stderr: &str) -> ExecResult {
+        assert!(!is_interpreter_error(stderr));
+        Ok(interpreter_output(
+            code,
+            stdout.to_string(),
+            stderr.to_string(),
+        ))
+    }
+
+    #[test]
+    fn miri_outcomes() {
+        let ub = miri_error(
+            1,
+            "error: Undefined Behavior: using uninitialized data, but this operation requires \
+             initialized memory\n --> src/main.rs:5:5\n\nerror: aborting due to 1 previous error\n",
+        );
+        assert_eq!(Outcome::of(&ub), Outcome::UndefinedBehavior);
+
+        let unsupported = miri_error(
+            1,
+            "error: unsupported operation: can't call foreign function `foo` on OS `linux`\n\
+             \nerror: aborting due to 1 previous error\n",
+        );
+        assert_eq!(Outcome::of(&unsupported), Outcome::InterpreterError);
+
+        let type_error = miri_error(
+            1,
+            "error[E0308]: mismatched types\n\nerror: aborting due to 1 previous error\n",
+        );
+        assert_eq!(Outcome::of(&type_error), Outcome::CompileError);
+
+        let ice = miri_error(
+            101,
+            "error: internal compiler error: compiler/rustc_const_eval/src/interpret/step.rs\n",
+        );
+        assert_eq!(Outcome::of(&ice), Outcome::Ice);
+
+        // The program's own exit code and panics are passed through
+        let exit = miri_exit(3, "exiting\n", "");
+        assert_eq!(Outcome::of(&exit), Outcome::RuntimeCrash);
+        let panic = miri_exit(
+            101,
+            "",
+            "thread 'main' panicked at src/main.rs:3:5:\nattempt to add with overflow\n\
+             note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace\n",
+        );
+        assert_eq!(Outcome::of(&panic), Outcome::RuntimeCrash);
+        assert_eq!(Outcome::of(&miri_exit(0, "5\n", "")), Outcome::Success);
     }
 }
@@ -0,0 +1,565 @@
+//! Finding the optimisation pass that makes a backend disagree with the reference
+//! (Miri), by bisecting `-Zmir-opt-bisect-limit`, or LLVM's `-opt-bisect-limit` if
+//! the backend disagrees even without MIR optimisations
+
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::{debug, info};
+
+use crate::{
+    backends::{compile_single, Backend, CompExecError, ExecResult, Timeouts},
+    run_diff_test, BackendName, ExecResults, Source,
+};
+
+/// A backend with extra rustc flags, always building a single crate so that
+/// all passes run in one rustc invocation
+struct WithFlags<'a> {
+    backend: &'a dyn Backend,
+    flags: Vec<String>,
+}
+
+impl Backend for WithFlags<'_> {
+    fn rustc_command(&self) -> Option<Command> {
+        let mut command = self.backend.rustc_command()?;
+        command.args(&self.flags);
+        Some(command)
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        self.backend.timeouts()
+    }
+
+    fn compile(&self, source: &Source, target: &Path) -> ExecResult {
+        let timeout = self.timeouts().compile;
+        compile_single(self.rustc_command().unwrap(), timeout, source, target)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Pipeline {
+    Mir,
+    Llvm,
+}
+
+pub struct Bisection {
+    pub backend: BackendName,
+    pub pipeline: Pipeline,
+    /// Index of the first pass execution after which the backend disagrees
+    pub execution: usize,
+    pub pass: String,
+    /// The function (or module) the pass was running on
+    pub body: String,
+    /// For MIR passes, whether only disabling the pass with -Zmir-enable-passes
+    /// makes the backend agree
+    pub confirmed: Option<bool>,
+    /// For LLVM passes, the IR right before the pass ran
+    pub artifacts: Vec<PathBuf>,
+}
+
+impl fmt::Display for Bisection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pipeline = match self.pipeline {
+            Pipeline::Mir => "MIR",
+            Pipeline::Llvm => "LLVM",
+        };
+        write!(
+            f,
+            "{} disagrees after {pipeline} pass ({}) {} on {}",
+            self.backend, self.execution, self.pass, self.body
+        )?;
+        match self.confirmed {
+            Some(true) => write!(f, ", and agrees with -Zmir-enable-passes=-{}", self.pass)?,
+            Some(false) => write!(f, ", but still disagrees without it")?,
+            None => {}
+        }
+        for artifact in &self.artifacts {
+            write!(f, "\nIR before the pass: {}", artifact.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// The pass executions reported by -Zmir-opt-bisect-limit or LLVM's -opt-bisect-limit,
+/// which share a format, as (index, pass, body)
+fn pass_executions(stderr: &OsStr) -> Vec<(usize, String, String)> {
+    stderr
+        .to_string_lossy()
+        .lines()
+        .filter_map(|line| {
+            let line = line
+                .strip_prefix("BISECT: running pass (")
+                .or_else(|| line.strip_prefix("BISECT: NOT running pass ("))?;
+            let (execution, rest) = line.split_once(") ")?;
+            let (pass, body) = rest.split_once(" on ")?;
+            Some((execution.parse().ok()?, pass.to_owned(), body.to_owned()))
+        })
+        .collect()
+}
+
+/// Runs one backend with different flags, checking its output against the reference
+struct Bisector<'a> {
+    source: &'a Source,
+    backend: &'a dyn Backend,
+    expected: OsString,
+    target: PathBuf,
+}
+
+impl Bisector<'_> {
+    fn with_flags(&self, flags: &[String]) -> WithFlags<'_> {
+        WithFlags {
+            backend: self.backend,
+            flags: flags.to_vec(),
+        }
+    }
+
+    fn agrees(&self, flags: &[String]) -> bool {
+        self.with_flags(flags)
+            .execute(self.source, &self.target)
+            .is_ok_and(|out| out.stdout == self.expected)
+    }
+
+    /// The compiler's stderr, empty if it timed out
+    fn compile(&self, flags: &[String]) -> OsString {
+        match self.with_flags(flags).compile(self.source, &self.target) {
+            Ok(out) | Err(CompExecError::Failed(_, out)) => out.stderr,
+            Err(CompExecError::Timeout(_)) => OsString::new(),
+        }
+    }
+
+    /// Finds the first pass execution after which the backend disagrees. `limit`
+    /// gives the flags to only run the first n executions
+    fn search(
+        &self,
+        limit: impl Fn(usize) -> Vec<String>,
+        executions: usize,
+    ) -> Option<(usize, String, String)> {
+        let bad = first_bad(executions, |n| self.agrees(&limit(n)));
+        pass_executions(&self.compile(&limit(bad)))
+            .into_iter()
+            .find(|(execution, ..)| *execution == bad)
+    }
+}
+
+/// The smallest n for which running only the first n of `executions` passes doesn't
+/// agree, given that running none agrees and running all of them doesn't. Pass
+/// executions are numbered from 1, so this is also the culprit's number
+fn first_bad(executions: usize, mut agrees: impl FnMut(usize) -> bool) -> usize {
+    // The first `good` executions are fine, and `bad` aren't
+    let (mut good, mut bad) = (0, executions);
+    while bad - good > 1 {
+        let mid = (good + bad) / 2;
+        if agrees(mid) {
+            good = mid;
+        } else {
+            bad = mid;
+        }
+    }
+    bad
+}
+
+fn mir_limit(limit: usize) -> Vec<String> {
+    vec!["-Z".to_owned(), format!("mir-opt-bisect-limit={limit}")]
+}
+
+fn llvm_limit(limit: isize) -> Vec<String> {
+    // Multiple codegen units would be optimised in parallel, each with its own count
+    ["-Z", "mir-opt-level=0", "-C", "codegen-units=1", "-C"]
+        .map(String::from)
+        .into_iter()
+        .chain([format!("llvm-args=-opt-bisect-limit={limit}")])
+        .collect()
+}
+
+/// Whether a rustc invocation runs MIR or codegen optimisations, going by the last
+/// `opt-level` and `mir-opt-level` it sets
+fn optimises(command: &Command) -> bool {
+    let (mut opt_level, mut mir_opt_level) = ("0".to_owned(), "0".to_owned());
+    for arg in command.get_args() {
+        let arg = arg.to_string_lossy();
+        let arg = arg
+            .strip_prefix("-C")
+            .or_else(|| arg.strip_prefix("-Z"))
+            .unwrap_or(&arg);
+        if let Some(level) = arg.strip_prefix("opt-level=") {
+            opt_level = level.to_owned();
+        } else if let Some(level) = arg.strip_prefix("mir-opt-level=") {
+            mir_opt_level = level.to_owned();
+        }
+    }
+    opt_level != "0" || mir_opt_level != "0"
+}
+
+/// Bisects the passes of the first optimising rustc backend whose program ran but
+/// printed something else than the reference backend's, saving any IR into
+/// `artifacts`. Returns None if there is no such pair, for instance because the
+/// reference found UB or the backend didn't compile the program, or if the backend
+/// disagrees even without optimisations
+pub fn bisect_passes<'a>(
+    source: &Source,
+    backends: &HashMap<BackendName, Box<dyn Backend + 'a>>,
+    results: &ExecResults,
+    artifacts: &Path,
+) -> Option<Bisection> {
+    let expected = backends.iter().find_map(|(&name, b)| {
+        let out = results[name].as_ref().ok().filter(|_| b.is_reference())?;
+        Some(out.stdout.clone())
+    })?;
+
+    let mut suspects: Vec<_> = backends
+        .iter()
+        .filter(|(name, b)| {
+            b.rustc_command().is_some_and(|command| optimises(&command))
+                && results[**name]
+                    .as_ref()
+                    .is_ok_and(|out| out.stdout != expected)
+        })
+        .collect();
+    suspects.sort_by_key(|(name, _)| **name);
+    let (&name, backend) = *suspects.first()?;
+
+    let target_dir = tempfile::tempdir().unwrap();
+    let bisector = Bisector {
+        source,
+        backend: backend.as_ref(),
+        expected,
+        target: target_dir.path().join(name),
+    };
+    if bisector.agrees(&[]) {
+        debug!("{name} only disagrees when built as separate crates");
+        return None;
+    }
+
+    // With a limit of 0, rustc reports every MIR pass execution it skips
+    if bisector.agrees(&mir_limit(0)) {
+        info!("Bisecting MIR passes of {name}");
+        let executions = pass_executions(&bisector.compile(&mir_limit(0))).len();
+        let (execution, pass, body) = bisector.search(mir_limit, executions)?;
+        let disable = vec!["-Z".to_owned(), format!("mir-enable-passes=-{pass}")];
+        return Some(Bisection {
+            backend: name,
+            pipeline: Pipeline::Mir,
+            execution,
+            confirmed: Some(bisector.agrees(&disable)),
+            pass,
+            body,
+            artifacts: vec![],
+        });
+    }
+
+    // Only LLVM reports its passes, so other backends stop here
+    let executions = pass_executions(&bisector.compile(&llvm_limit(-1))).len();
+    if executions == 0 || bisector.agrees(&llvm_limit(-1)) || !bisector.agrees(&llvm_limit(0)) {
+        debug!("{name} disagrees without LLVM passes, or only with MIR passes");
+        return None;
+    }
+    info!("Bisecting LLVM passes of {name}");
+    let (execution, pass, body) =
+        bisector.search(|limit| llvm_limit(limit as isize), executions)?;
+
+    let stem = format!("{}-{name}-{pass}-{execution}", source.file_stem());
+    let mut saved = vec![];
+    // The whole module, as it was when the culprit was about to run
+    let mut module = llvm_limit(execution as isize - 1);
+    module.push("--emit=llvm-ir".to_owned());
+    let ir = target_dir.path().join("module.ll");
+    if bisector.with_flags(&module).compile(source, &ir).is_ok() {
+        let artifact = artifacts.join(format!("{stem}.ll"));
+        fs::copy(&ir, &artifact).expect("can save LLVM IR");
+        saved.push(artifact);
+    }
+    // Just the function, printed by LLVM right before the culprit ran on it
+    if let Some(function) = llvm_function(&body) {
+        let mut print = llvm_limit(execution as isize);
+        print.extend(
+            [
+                format!("llvm-args=-print-before={pass}"),
+                format!("llvm-args=-filter-print-funcs={function}"),
+            ]
+            .into_iter()
+            .flat_map(|arg| ["-C".to_owned(), arg]),
+        );
+        let stderr = bisector.compile(&print);
+        let stderr = stderr.to_string_lossy();
+        if let Some((_, dump)) = stderr.rsplit_once("*** IR Dump Before") {
+            let dump: String = dump
+                .lines()
+                .skip(1)
+                .filter(|line| !line.starts_with("BISECT: "))
+                .map(|line| format!("{line}\n"))
+                .collect();
+            let artifact = artifacts.join(format!("{stem}.before.ll"));
+            fs::write(&artifact, dump).expect("can save LLVM IR");
+            saved.push(artifact);
+        }
+    }
+
+    Some(Bisection {
+        backend: name,
+        pipeline: Pipeline::Llvm,
+        execution,
+        pass,
+        body,
+        confirmed: None,
+        artifacts: saved,
+    })
+}
+
+/// The function an LLVM pass ran on, from what -opt-bisect-limit printed it ran on.
+/// None for modules, loops and SCCs
+fn llvm_function(body: &str) -> Option<&str> {
+    body.strip_prefix("function (")
+        .and_then(|body| body.strip_suffix(')'))
+        .or_else(|| Some(body).filter(|body| !body.contains([' ', '['])))
+}
+
+/// Installed rustup toolchains that are dated nightlies, from `from` to `to` inclusive
+/// (as YYYY-MM-DD), oldest first
+pub fn installed_nightlies(from: Option<&str>, to: Option<&str>) -> Vec<String> {
+    let output = Command::new("rustup")
+        .args(["toolchain", "list"])
+        .output()
+        .expect("can run rustup toolchain list");
+    let mut nightlies: Vec<(String, String)> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let toolchain = line.split_whitespace().next()?;
+            let date = toolchain.strip_prefix("nightly-")?.get(..10)?;
+            let is_date = date.char_indices().all(|(i, c)| {
+                if i == 4 || i == 7 {
+                    c == '-'
+                } else {
+                    c.is_ascii_digit()
+                }
+            });
+            is_date.then(|| (date.to_owned(), toolchain.to_owned()))
+        })
+        .filter(|(date, _)| {
+            from.is_none_or(|from| date.as_str() >= from) && to.is_none_or(|to| date.as_str() <= to)
+        })
+        .collect();
+    nightlies.sort();
+    nightlies
+        .into_iter()
+        .map(|(_, toolchain)| toolchain)
+        .collect()
+}
+
+pub struct ToolchainBisection {
+    pub last_good: String,
+    pub first_bad: String,
+    /// What the backends of the first bad toolchain did
+    pub results: ExecResults,
+}
+
+impl fmt::Display for ToolchainBisection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "backends agree on {} but not on {}:\n{}",
+            self.last_good, self.first_bad, self.results
+        )
+    }
+}
+
+pub enum ToolchainBisectError {
+    NoToolchains,
+    /// The backends already disagree, or fail, on the oldest toolchain
+    FirstBad(String, Box<ExecResults>),
+    /// The backends still agree on the newest toolchain
+    LastGood(String),
+}
+
+impl fmt::Display for ToolchainBisectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolchainBisectError::NoToolchains => write!(f, "no toolchains to bisect"),
+            ToolchainBisectError::FirstBad(toolchain, results) => {
+                write!(f, "backends already don't pass on {toolchain}:\n{results}")
+            }
+            ToolchainBisectError::LastGood(toolchain) => {
+                write!(f, "backends still agree on {toolchain}")
+            }
+        }
+    }
+}
+
+/// Finds the first of `toolchains` (oldest first) on which the backends that
+/// `backends` sets up for it disagree
+pub fn bisect_toolchains<'a>(
+    source: &Source,
+    toolchains: &[String],
+    backends: impl Fn(&str) -> HashMap<BackendName, Box<dyn Backend + 'a>>,
+) -> Result<ToolchainBisection, ToolchainBisectError> {
+    let run = |toolchain: &String| {
+        info!("Difftesting {source} on {toolchain}");
+        let results = run_diff_test(source, &backends(toolchain));
+        let agree = results.all_same() && results.all_success();
+        (agree, results)
+    };
+
+    let (first, last) = match toolchains {
+        [] => return Err(ToolchainBisectError::NoToolchains),
+        [only] => (only, only),
+        [first, .., last] => (first, last),
+    };
+    let (agree, results) = run(first);
+    if !agree {
+        return Err(ToolchainBisectError::FirstBad(
+            first.clone(),
+            Box::new(results),
+        ));
+    }
+    let (agree, mut bad_results) = run(last);
+    if agree {
+        return Err(ToolchainBisectError::LastGood(last.clone()));
+    }
+
+    // toolchains[good] agrees, and toolchains[bad] doesn't
+    let (mut good, mut bad) = (0, toolchains.len() - 1);
+    while bad - good > 1 {
+        let mid = (good + bad) / 2;
+        let (agree, results) = run(&toolchains[mid]);
+        if agree {
+            good = mid;
+        } else {
+            bad = mid;
+            bad_results = results;
+        }
+    }
+    Ok(ToolchainBisection {
+        last_good: toolchains[good].clone(),
+        first_bad: toolchains[bad].clone(),
+        results: bad_results,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, process::ExitStatus};
+
+    use crate::backends::ProcessOutput;
+
+    use super::*;
+
+    /// Prints the same thing whatever the program
+    struct Fake(&'static str);
+
+    impl Backend for Fake {
+        fn execute(&self, _: &Source, _: &Path) -> ExecResult {
+            Ok(ProcessOutput {
+                status: ExitStatus::default(),
+                stdout: self.0.into(),
+                stderr: OsString::new(),
+            })
+        }
+    }
+
+    #[test]
+    fn mir_pass_executions() {
+        // From rustc -Zmir-opt-bisect-limit=2 -Copt-level=3 on two functions. Every
+        // pass execution is numbered, including the ones that don't run
+        let stderr = OsString::from(include_str!("../tests/inputs/mir_opt_bisect.stderr"));
+        let executions = pass_executions(&stderr);
+        let numbers: Vec<_> = executions.iter().map(|(n, ..)| *n).collect();
+        assert_eq!(numbers, (1..=64).collect::<Vec<_>>());
+        assert_eq!(
+            executions[1],
+            (
+                2,
+                "InstSimplify-before-inline".to_owned(),
+                "t[f279]::main".to_owned()
+            )
+        );
+        assert_eq!(
+            executions[47],
+            (48, "GVN".to_owned(), "t[f279]::fn1".to_owned())
+        );
+    }
+
+    #[test]
+    fn llvm_pass_executions() {
+        // Lines picked from rustc -Cllvm-args=-opt-bisect-limit=2, where a pass runs
+        // on a module, a function, a loop or a function in parentheses
+        let stderr = OsString::from(
+            "BISECT: running pass (1) annotation2metadata on [module]\n\
+             BISECT: NOT running pass (164) lower-expect on main\n\
+             BISECT: NOT running pass (727) loop-rotate on loop %<unnamed loop> in function \
+             _RINvNtCs8NwYtU1Mohg_4core3ptr9drop_glueSNtNtNtCsi4IsKQVxMg0_3std3ffi6os_str8\
+             OsStringECskOGwlKjLXpR_1t\n\
+             BISECT: NOT running pass (2842) mergeicmps on function (main)\n",
+        );
+        let executions = pass_executions(&stderr);
+        let numbers: Vec<_> = executions.iter().map(|(n, ..)| *n).collect();
+        assert_eq!(numbers, [1, 164, 727, 2842]);
+        let functions: Vec<_> = executions
+            .iter()
+            .map(|(_, _, body)| llvm_function(body))
+            .collect();
+        assert_eq!(functions, [None, Some("main"), None, Some("main")]);
+        assert_eq!(executions[2].1, "loop-rotate");
+    }
+
+    #[test]
+    fn search_first_bad() {
+        for culprit in 1..=10 {
+            let mut runs = vec![];
+            let bad = first_bad(10, |n| {
+                runs.push(n);
+                n < culprit
+            });
+            assert_eq!(bad, culprit);
+            // A binary search, never trying the known ends
+            assert!(runs.len() <= 4, "{runs:?}");
+            assert!(runs.iter().all(|n| (1..10).contains(n)), "{runs:?}");
+        }
+        assert_eq!(first_bad(1, |_| unreachable!()), 1);
+    }
+
+    #[test]
+    fn toolchain_bisection() {
+        let toolchains: Vec<String> = (1..=9)
+            .map(|day| format!("nightly-2024-01-0{day}"))
+            .collect();
+        let source = Source::Stdin(String::new());
+        let tested = RefCell::new(vec![]);
+        // `opt` miscompiles from `first_bad` on
+        let backends = |first_bad: &'static str| {
+            let tested = &tested;
+            move |toolchain: &str| {
+                tested.borrow_mut().push(toolchain.to_owned());
+                let mut backends: HashMap<BackendName, Box<dyn Backend>> = HashMap::default();
+                backends.insert("unopt", Box::new(Fake("5\n")));
+                let opt = if toolchain < first_bad { "5\n" } else { "6\n" };
+                backends.insert("opt", Box::new(Fake(opt)));
+                backends
+            }
+        };
+
+        let bisection = bisect_toolchains(&source, &toolchains, backends("nightly-2024-01-06"))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(bisection.last_good, "nightly-2024-01-05");
+        assert_eq!(bisection.first_bad, "nightly-2024-01-06");
+        assert!(!bisection.results.all_same());
+        // Both ends, then a binary search in between
+        assert!(tested.borrow().len() <= 5, "{:?}", tested.borrow());
+
+        assert!(matches!(
+            bisect_toolchains(&source, &toolchains, backends("nightly-2024-01-01")),
+            Err(ToolchainBisectError::FirstBad(toolchain, _)) if toolchain == "nightly-2024-01-01"
+        ));
+        assert!(matches!(
+            bisect_toolchains(&source, &toolchains, backends("nightly-2024-02-01")),
+            Err(ToolchainBisectError::LastGood(toolchain)) if toolchain == "nightly-2024-01-09"
+        ));
+        assert!(matches!(
+            bisect_toolchains(&source, &[], backends("nightly-2024-01-06")),
+            Err(ToolchainBisectError::NoToolchains)
+        ));
+    }
+}
@@ -2,19 +2,25 @@
 #![feature(let_chains)]
 
 pub mod backends;
+pub mod bisect;
+pub mod matrix;
+pub mod report;
+pub mod swarm;
+pub mod triage;
 
 // pub use backend;
 use std::{
     collections::{HashMap, HashSet},
     fmt::{self, Display},
+    fs,
     ops::Index,
     path::PathBuf,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use backends::{Backend, CompExecError, ExecResult};
 use colored::Colorize;
-use log::{debug, log_enabled};
+use log::debug;
 use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 
 pub type BackendName = &'static str;
@@ -24,6 +30,43 @@ pub enum Source {
     Stdin(String),
 }
 
+/// Opens the block comment in which `generate --c-ffi` embeds its C translation unit
+const C_UNIT_START: &str = "/* rustlantis-c-unit\n";
+/// Only present in programs generated with `generate --split`
+const SPLIT_CFG: &str = "#[cfg(rustlantis_upper)]";
+
+impl Source {
+    fn code(&self) -> String {
+        match self {
+            Source::File(path) => fs::read_to_string(path).expect("can read source file"),
+            Source::Stdin(code) => code.clone(),
+        }
+    }
+
+    /// A name for files derived from the program
+    pub fn file_stem(&self) -> String {
+        match self {
+            Source::File(path) => path.file_stem().map_or("source".to_owned(), |stem| {
+                stem.to_string_lossy().into_owned()
+            }),
+            Source::Stdin(_) => "stdin".to_owned(),
+        }
+    }
+
+    /// Whether the program can be built as a library crate and a binary crate
+    pub fn is_split(&self) -> bool {
+        self.code().contains(SPLIT_CFG)
+    }
+
+    /// The C translation unit embedded in the program, if there is one
+    pub fn c_unit(&self) -> Option<String> {
+        let code = self.code();
+        let (_, unit) = code.split_once(C_UNIT_START)?;
+        let (unit, _) = unit.split_once("*/")?;
+        Some(unit.to_owned())
+    }
+}
+
 impl Display for Source {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -36,6 +79,10 @@ impl Display for Source {
 pub struct ExecResults {
     // Equivalence classes of exec results and backends
     results: HashMap<ExecResult, HashSet<BackendName>>,
+    // Flags of the backends run in swarm mode
+    flags: HashMap<BackendName, Vec<String>>,
+    // How long each backend took to compile and run
+    durations: HashMap<BackendName, Duration>,
 }
 
 impl ExecResults {
@@ -53,7 +100,7 @@ impl ExecResults {
                 let eq = if let Ok(class_out) = class_result
                     && let Ok(out) = result
                 {
-                    class_out.stdout == out.stdout
+                    class_out.stdout == out.stdout && class_out.status.code() == out.status.code()
                 } else {
                     result == class_result
                 };
@@ -69,6 +116,8 @@ impl ExecResults {
 
         Self {
             results: eq_classes,
+            flags: HashMap::new(),
+            durations: HashMap::new(),
         }
     }
 
@@ -80,24 +129,17 @@ impl ExecResults {
         self.results.keys().all(|r| r.is_ok())
     }
 
-    pub fn has_ub(&self) -> Option<bool> {
+    /// Whether any backend (in practice, Miri or the interpreter) found undefined behaviour
+    pub fn has_ub(&self) -> bool {
         self.results
-            .iter()
-            .find_map(|(result, backends)| {
-                if backends.contains("miri") {
-                    Some(result)
-                } else {
-                    None
-                }
-            })
-            .map(|result| {
-                result.clone().is_err_and(|err| {
-                    err.0
-                        .stderr
-                        .to_string_lossy()
-                        .contains("Undefined Behavior")
-                })
-            })
+            .keys()
+            .any(|result| report::Outcome::of(result) == report::Outcome::UndefinedBehavior)
+    }
+
+    pub fn has_timeout(&self) -> bool {
+        self.results
+            .keys()
+            .any(|r| matches!(r, Err(CompExecError::Timeout(_))))
     }
 }
 
@@ -126,11 +168,22 @@ impl fmt::Display for ExecResults {
                     .collect::<String>()
                     .blue()
             ))?;
+            for name in names {
+                if let Some(flags) = self.flags.get(name) {
+                    f.write_fmt(format_args!("{name} flags: {}\n", flags.join(" ")))?;
+                }
+            }
             match result {
                 Ok(out) => {
                     f.write_fmt(format_args!("stdout:\n{}", out.stdout.to_string_lossy()))?;
                 }
-                Err(CompExecError(out)) => {
+                Err(CompExecError::Timeout(stage)) => {
+                    f.write_fmt(format_args!(
+                        "{}\n",
+                        format!("timed out while {stage}").red()
+                    ))?;
+                }
+                Err(CompExecError::Failed(_, out)) => {
                     f.write_fmt(format_args!("status: {}\n", out.status))?;
                     f.write_fmt(format_args!(
                         "stdout:\n{}================\n",
@@ -150,25 +203,35 @@ impl fmt::Display for ExecResults {
 
 pub fn run_diff_test<'a>(
     source: &Source,
-    backends: HashMap<BackendName, Box<dyn Backend + 'a>>,
+    backends: &HashMap<BackendName, Box<dyn Backend + 'a>>,
 ) -> ExecResults {
     let target_dir = tempfile::tempdir().unwrap();
-    let exec_results: HashMap<BackendName, ExecResult> = backends
+    let flags = backends
+        .iter()
+        .filter(|(_, b)| !b.swarm_flags().is_empty())
+        .map(|(&name, b)| (name, b.swarm_flags().to_vec()))
+        .collect();
+    let exec_results: HashMap<BackendName, (ExecResult, Duration)> = backends
         .par_iter()
         .map(|(&name, b)| {
             let target_path = target_dir.path().join(name);
-            let result = if log_enabled!(log::Level::Debug) {
-                let time = Instant::now();
-                let result = b.execute(source, &target_path);
-                let dur = time.elapsed();
-                debug!("{name} took {}s", dur.as_secs_f32());
-                result
-            } else {
-                b.execute(source, &target_path)
-            };
-            (name, result)
+            let time = Instant::now();
+            let result = b.execute(source, &target_path);
+            let dur = time.elapsed();
+            debug!("{name} took {}s", dur.as_secs_f32());
+            (name, (result, dur))
         })
         .collect();
 
-    ExecResults::from_exec_results(exec_results.iter())
+    let mut results = ExecResults::from_exec_results(
+        exec_results
+            .iter()
+            .map(|(name, (result, _))| (name, result)),
+    );
+    results.flags = flags;
+    results.durations = exec_results
+        .iter()
+        .map(|(name, (_, dur))| (*name, *dur))
+        .collect();
+    results
 }
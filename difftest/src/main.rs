@@ -1,13 +1,25 @@
 #![feature(iter_intersperse)]
 
 use core::panic;
-use std::{collections::HashMap, io::{self, Read}, path::PathBuf, process::ExitCode, str::FromStr};
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+    str::FromStr,
+};
 
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 use config::Config;
 use difftest::{
-    backends::{Backend, Cranelift, Miri, OptLevel, GCC, LLVM},
-    run_diff_test, BackendName, Source,
+    backends::{Backend, BackendOptions, Cranelift, OptLevel, LLVM},
+    bisect::{bisect_passes, bisect_toolchains, installed_nightlies},
+    matrix::{self, configured_timeouts},
+    report::Report,
+    run_diff_test,
+    swarm::{swarm, SwarmSpace},
+    triage::{self, Signature},
+    BackendName, Source,
 };
 use log::{debug, error, info};
 
@@ -16,82 +28,72 @@ fn main() -> ExitCode {
 
     let matches = Command::new("difftest")
         .arg(Arg::new("file").required(true))
+        .arg(
+            Arg::new("swarm")
+                .long("swarm")
+                .action(ArgAction::SetTrue)
+                .help("sample extra rustc flags for each backend from the [swarm] config"),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .help("with json, print each backend's outcome, version, flags and duration"),
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("bisect")
+                .about("find the first toolchain on which the backends disagree")
+                .arg(Arg::new("file").required(true))
+                .arg(
+                    Arg::new("toolchains")
+                        .long("toolchains")
+                        .value_delimiter(',')
+                        .help("toolchains to bisect, oldest first"),
+                )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .conflicts_with("toolchains")
+                        .help("bisect installed nightlies from this date (YYYY-MM-DD)"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .conflicts_with("toolchains")
+                        .help("bisect installed nightlies up to this date (YYYY-MM-DD)"),
+                )
+                .arg(
+                    Arg::new("cranelift")
+                        .long("cranelift")
+                        .action(ArgAction::SetTrue)
+                        .help("also difftest the Cranelift backend of each toolchain"),
+                ),
+        )
         .get_matches();
-    let source = matches.get_one::<String>("file").expect("required");
-
-    // Initialise backends
-    // TODO: extract this out into a function
-    let settings = Config::builder()
-        .add_source(config::File::with_name("config.toml").required(false))
-        .add_source(config::Environment::default())
-        .build()
-        .unwrap();
-
-    let mut backends: HashMap<BackendName, Box<dyn Backend>> = HashMap::default();
-    if let Ok(clif_dir) = settings.get_string("cranelift_dir") {
-        let clif = Cranelift::from_repo(clif_dir, OptLevel::Optimised, OptLevel::Unoptimised)
-            .expect("cranelift init failed");
-        backends.insert("cranelift-opt-only", Box::new(clif));
-    } else if let Ok(clif_toolchain) = settings.get_string("cranelift_toolchain") {
-        let clif =
-            Cranelift::from_rustup(&clif_toolchain, OptLevel::Optimised, OptLevel::Unoptimised)
-                .expect("cranelift init failed");
-        backends.insert("cranelift-opt-only", Box::new(clif));
+    if let Some(("bisect", matches)) = matches.subcommand() {
+        return bisect(matches);
     }
+    let source = read_source(matches.get_one::<String>("file").expect("required"));
 
-    let check_ub = settings
-        .get_string("miri_check_ub")
-        .map(|config| config == "true" || config == "1")
-        .unwrap_or(true);
-    if let Ok(miri_dir) = settings.get_string("miri_dir") {
-        let miri = Miri::from_repo(miri_dir, check_ub).expect("miri init failed");
-        if check_ub {
-            backends.insert("miri-checked", Box::new(miri));
-        } else {
-            backends.insert("miri-unchecked", Box::new(miri));
-        }
-    } else if let Ok(miri_toolchain) = settings.get_string("miri_toolchain") {
-        let miri = Miri::from_rustup(&miri_toolchain, check_ub).expect("miri init failed");
-        if check_ub {
-            backends.insert("miri-checked", Box::new(miri));
-        } else {
-            backends.insert("miri-unchecked", Box::new(miri));
-        }
-    }
+    // Initialise backends
+    let settings = settings();
 
-    if let Ok(cg_gcc) = settings.get_string("cg_gcc_dir") {
-        let cg_gcc = GCC::from_built_repo(cg_gcc, OptLevel::Optimised, OptLevel::Optimised);
-        match cg_gcc {
-            Ok(cg_gcc) => backends.insert("cg_gcc", Box::new(cg_gcc)),
-            Err(e) => panic!("rustc_codegen_gcc init failed\n{}", e.0),
+    let backends = match matrix::backends(&settings, source.is_split()) {
+        Ok(backends) => backends,
+        Err(e) => panic!("backend init failed\n{}", e.0),
+    };
+    let backends = if matches.get_flag("swarm") {
+        let space = match settings.get::<SwarmSpace>("swarm") {
+            Ok(space) => space,
+            Err(config::ConfigError::NotFound(_)) => SwarmSpace::default(),
+            Err(e) => panic!("invalid swarm config\n{e}"),
         };
-    }
-
-    let llvm_toolchain = settings.get_string("llvm_toolchain").ok();
-    backends.insert(
-        "llvm-opt",
-        Box::new(LLVM::new(
-            llvm_toolchain.clone(),
-            OptLevel::Optimised,
-            OptLevel::Optimised,
-        )),
-    );
-
-    backends.insert(
-        "llvm-opt-only",
-        Box::new(LLVM::new(
-            llvm_toolchain,
-            OptLevel::Optimised,
-            OptLevel::Unoptimised,
-        )),
-    );
-
-    let source = if source == "-" {
-        let mut code = String::new();
-        io::stdin().read_to_string(&mut code).expect("can read source code from stdin");
-        Source::Stdin(code)
+        swarm(backends, &space, &source)
     } else {
-        Source::File(PathBuf::from_str(source).expect("is valid path"))
+        backends
     };
 
     info!(
@@ -104,17 +106,118 @@ fn main() -> ExitCode {
             .collect::<String>()
     );
 
-    let results = run_diff_test(&source, backends);
+    let results = run_diff_test(&source, &backends);
+    if matches.get_one::<String>("format").unwrap() == "json" {
+        let report = Report::new(&source, &backends, &results);
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    }
     if results.all_same() && results.all_success() {
         info!("{} is all the same", source);
         debug!("{}", results);
         ExitCode::SUCCESS
     } else {
-        let results = results.to_string();
-        error!(
-            "{} didn't pass:\n{results}",
-            source,
-        );
+        error!("{source} didn't pass:\n{results}");
+        let signature = Signature::of(&results).expect("failures have a signature");
+        info!("Signature: {signature}");
+        if let Ok(buckets) = settings.get_string("buckets_dir") {
+            match triage::file(&PathBuf::from(&buckets), &signature, &source) {
+                Ok(bucket) => info!("Filed {source} under {bucket}"),
+                Err(e) => error!("can't file {source} under {buckets}: {e}"),
+            }
+        }
+        if settings.get_bool("bisect_passes").unwrap_or(false) {
+            let artifacts = settings
+                .get_string("artifacts_dir")
+                .unwrap_or(".".to_owned());
+            let artifacts = PathBuf::from(artifacts);
+            if let Some(bisection) = bisect_passes(&source, &backends, &results, &artifacts) {
+                error!("{bisection}");
+            }
+        }
         ExitCode::FAILURE
     }
 }
+
+fn settings() -> Config {
+    Config::builder()
+        .add_source(config::File::with_name("config.toml").required(false))
+        .add_source(config::Environment::default())
+        .build()
+        .unwrap()
+}
+
+fn read_source(file: &str) -> Source {
+    if file == "-" {
+        let mut code = String::new();
+        io::stdin()
+            .read_to_string(&mut code)
+            .expect("can read source code from stdin");
+        Source::Stdin(code)
+    } else {
+        Source::File(PathBuf::from_str(file).expect("is valid path"))
+    }
+}
+
+/// The bisect subcommand
+fn bisect(matches: &ArgMatches) -> ExitCode {
+    let source = read_source(matches.get_one::<String>("file").expect("required"));
+    let toolchains: Vec<String> = match matches.get_many::<String>("toolchains") {
+        Some(toolchains) => toolchains.cloned().collect(),
+        None => installed_nightlies(
+            matches.get_one::<String>("from").map(String::as_str),
+            matches.get_one::<String>("to").map(String::as_str),
+        ),
+    };
+    info!("Bisecting {} toolchains", toolchains.len());
+
+    let cranelift = matches.get_flag("cranelift");
+    let options = BackendOptions {
+        timeouts: configured_timeouts(&settings()),
+        ..Default::default()
+    };
+    let backends = |toolchain: &str| {
+        let mut backends: HashMap<BackendName, Box<dyn Backend>> = HashMap::default();
+        let toolchain = toolchain.to_owned();
+        if cranelift {
+            let clif =
+                Cranelift::from_rustup(&toolchain, OptLevel::Optimised, OptLevel::Unoptimised)
+                    .expect("cranelift init failed")
+                    .with_options(options.clone());
+            backends.insert("cranelift-opt-only", Box::new(clif));
+        }
+        backends.insert(
+            "llvm-opt",
+            Box::new(
+                LLVM::new(
+                    Some(toolchain.clone()),
+                    OptLevel::Optimised,
+                    OptLevel::Optimised,
+                )
+                .with_options(options.clone()),
+            ),
+        );
+        backends.insert(
+            "llvm-unopt",
+            Box::new(
+                LLVM::new(
+                    Some(toolchain),
+                    OptLevel::Unoptimised,
+                    OptLevel::Unoptimised,
+                )
+                .with_options(options.clone()),
+            ),
+        );
+        backends
+    };
+
+    match bisect_toolchains(&source, &toolchains, backends) {
+        Ok(bisection) => {
+            println!("{bisection}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! Backends declared in config.toml, one `[backends.NAME]` table each:
+//!
+//! ```toml
+//! [backends.llvm-os]
+//! kind = "llvm"
+//! toolchain = "nightly"
+//! opt_level = 2
+//! mir_opt_level = 1
+//! flags = ["-C", "opt-level=s", "-Zinline-mir"]
+//! env = { RUSTC_LOG = "warn" }
+//! compile_timeout = 120
+//! ```
+
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use config::Config;
+use serde::Deserialize;
+
+use crate::{
+    backends::{
+        Backend, BackendInitError, BackendOptions, Cranelift, Interpreter, Miri, Mixed, OptLevel,
+        Timeouts, GCC, LLVM,
+    },
+    BackendName,
+};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Llvm,
+    Cranelift,
+    Gcc,
+    Miri,
+    /// `mir::interpret`, which takes no toolchain or path
+    Interpreter,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BackendConfig {
+    pub kind: BackendKind,
+    /// Rustup toolchain to use
+    pub toolchain: Option<String>,
+    /// Repository to build the backend from, exclusive with `toolchain`
+    pub path: Option<PathBuf>,
+    /// -C opt-level, optimised if unset
+    pub opt_level: Option<usize>,
+    /// -Z mir-opt-level, optimised if unset
+    pub mir_opt_level: Option<usize>,
+    /// Fat LTO, only for LLVM
+    #[serde(default)]
+    pub lto: bool,
+    /// Whether Miri checks for UB
+    #[serde(default = "default_check_ub")]
+    pub check_ub: bool,
+    /// Extra rustc (or Miri) flags, after the backend's own
+    #[serde(default)]
+    pub flags: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Seconds, the top-level `compile_timeout` if unset
+    pub compile_timeout: Option<u64>,
+    /// Seconds, the top-level `run_timeout` if unset. Miri's whole run counts
+    pub run_timeout: Option<u64>,
+    /// Statements and terminators the interpreter runs before giving up, as if timed out
+    pub step_limit: Option<usize>,
+}
+
+pub type Backends = HashMap<BackendName, Box<dyn Backend>>;
+
+fn default_check_ub() -> bool {
+    true
+}
+
+fn opt_level(level: Option<usize>) -> OptLevel {
+    level.map_or(OptLevel::Optimised, OptLevel::Explicit)
+}
+
+/// The top-level `compile_timeout` and `run_timeout` of the config, in seconds
+pub fn configured_timeouts(settings: &Config) -> Timeouts {
+    let seconds = |key| settings.get::<u64>(key).ok().map(Duration::from_secs);
+    Timeouts {
+        compile: seconds("compile_timeout"),
+        run: seconds("run_timeout"),
+    }
+}
+
+impl BackendConfig {
+    pub fn init(&self, timeouts: Timeouts) -> Result<Box<dyn Backend>, BackendInitError> {
+        let codegen_opt = opt_level(self.opt_level);
+        let mir_opt = opt_level(self.mir_opt_level);
+        let options = BackendOptions {
+            flags: self.flags.clone(),
+            env: self.env.clone(),
+            timeouts: Timeouts {
+                compile: self
+                    .compile_timeout
+                    .map(Duration::from_secs)
+                    .or(timeouts.compile),
+                run: self.run_timeout.map(Duration::from_secs).or(timeouts.run),
+            },
+        };
+        if self.lto && !matches!(self.kind, BackendKind::Llvm) {
+            return Err(BackendInitError(
+                "lto is only supported by llvm backends".to_string(),
+            ));
+        }
+
+        let backend: Box<dyn Backend> = match (self.kind, &self.toolchain, &self.path) {
+            (_, Some(_), Some(_)) => {
+                return Err(BackendInitError(
+                    "toolchain and path are exclusive".to_string(),
+                ))
+            }
+            (BackendKind::Llvm, toolchain, None) => {
+                let llvm = LLVM::new(toolchain.clone(), codegen_opt, mir_opt).with_options(options);
+                if self.lto {
+                    Box::new(llvm.lto())
+                } else {
+                    Box::new(llvm)
+                }
+            }
+            (BackendKind::Cranelift, Some(toolchain), None) => Box::new(
+                Cranelift::from_rustup(toolchain, codegen_opt, mir_opt)?.with_options(options),
+            ),
+            (BackendKind::Cranelift, None, Some(path)) => {
+                Box::new(Cranelift::from_repo(path, codegen_opt, mir_opt)?.with_options(options))
+            }
+            (BackendKind::Gcc, None, Some(path)) => {
+                Box::new(GCC::from_built_repo(path, codegen_opt, mir_opt)?.with_options(options))
+            }
+            (BackendKind::Miri, Some(toolchain), None) => {
+                Box::new(Miri::from_rustup(toolchain, self.check_ub)?.with_options(options))
+            }
+            (BackendKind::Miri, None, Some(path)) => {
+                Box::new(Miri::from_repo(path, self.check_ub)?.with_options(options))
+            }
+            (BackendKind::Interpreter, None, None) => Box::new(Interpreter::new(
+                self.step_limit.unwrap_or(Interpreter::DEFAULT_STEP_LIMIT),
+            )),
+            (kind, _, _) => {
+                return Err(BackendInitError(format!(
+                    "{kind:?} backends need {}",
+                    match kind {
+                        BackendKind::Llvm => "a toolchain, not a path",
+                        BackendKind::Gcc => "the path to a built rustc_codegen_gcc",
+                        BackendKind::Cranelift | BackendKind::Miri => "a toolchain or a path",
+                        BackendKind::Interpreter => "neither a toolchain nor a path",
+                    }
+                )))
+            }
+        };
+        Ok(backend)
+    }
+}
+
+/// The backends in the `backends` table of the config, or None if there isn't one
+pub fn configured_backends(settings: &Config) -> Option<Result<Backends, BackendInitError>> {
+    let configs: HashMap<String, BackendConfig> = match settings.get("backends") {
+        Ok(configs) => configs,
+        Err(config::ConfigError::NotFound(_)) => return None,
+        Err(e) => return Some(Err(BackendInitError(format!("invalid backends: {e}")))),
+    };
+    let timeouts = configured_timeouts(settings);
+    Some(
+        configs
+            .into_iter()
+            .map(|(name, config)| {
+                let backend = config
+                    .init(timeouts)
+                    .map_err(|e| BackendInitError(format!("{name}: {}", e.0)))?;
+                // Backends live until the process exits
+                let name: BackendName = name.leak();
+                Ok((name, backend))
+            })
+            .collect(),
+    )
+}
+
+/// The backends set up by the top-level keys of the config, when it doesn't declare any
+pub fn default_backends(settings: &Config, split: bool) -> Result<Backends, BackendInitError> {
+    let mut backends: Backends = HashMap::default();
+    let options = BackendOptions {
+        timeouts: configured_timeouts(settings),
+        ..Default::default()
+    };
+    let clif = if let Ok(clif_dir) = settings.get_string("cranelift_dir") {
+        let clif = Cranelift::from_repo(clif_dir, OptLevel::Optimised, OptLevel::Unoptimised)
+            .map_err(|e| context("cranelift", e))?;
+        Some(clif.with_options(options.clone()))
+    } else if let Ok(clif_toolchain) = settings.get_string("cranelift_toolchain") {
+        let clif =
+            Cranelift::from_rustup(&clif_toolchain, OptLevel::Optimised, OptLevel::Unoptimised)
+                .map_err(|e| context("cranelift", e))?;
+        Some(clif.with_options(options.clone()))
+    } else {
+        None
+    };
+    if let Some(clif) = &clif {
+        backends.insert("cranelift-opt-only", Box::new(clif.clone()));
+    }
+
+    let check_ub = settings
+        .get_string("miri_check_ub")
+        .map(|config| config == "true" || config == "1")
+        .unwrap_or(true);
+    if let Ok(miri_dir) = settings.get_string("miri_dir") {
+        let miri = Miri::from_repo(miri_dir, check_ub)
+            .map_err(|e| context("miri", e))?
+            .with_options(options.clone());
+        if check_ub {
+            backends.insert("miri-checked", Box::new(miri));
+        } else {
+            backends.insert("miri-unchecked", Box::new(miri));
+        }
+    } else if let Ok(miri_toolchain) = settings.get_string("miri_toolchain") {
+        let miri = Miri::from_rustup(&miri_toolchain, check_ub)
+            .map_err(|e| context("miri", e))?
+            .with_options(options.clone());
+        if check_ub {
+            backends.insert("miri-checked", Box::new(miri));
+        } else {
+            backends.insert("miri-unchecked", Box::new(miri));
+        }
+    }
+
+    // Costs next to nothing, and checks for UB even without Miri. Opt-in, as programs
+    // that run longer than its step limit show up as timeout mismatches
+    let interpreter = settings
+        .get_string("interpreter")
+        .map(|config| config == "true" || config == "1")
+        .unwrap_or(false);
+    if interpreter {
+        let step_limit = settings
+            .get::<usize>("interpreter_step_limit")
+            .unwrap_or(Interpreter::DEFAULT_STEP_LIMIT);
+        backends.insert("interpreter", Box::new(Interpreter::new(step_limit)));
+    }
+
+    if let Ok(cg_gcc) = settings.get_string("cg_gcc_dir") {
+        let cg_gcc = GCC::from_built_repo(cg_gcc, OptLevel::Optimised, OptLevel::Optimised)
+            .map_err(|e| context("rustc_codegen_gcc", e))?;
+        backends.insert("cg_gcc", Box::new(cg_gcc.with_options(options.clone())));
+    }
+
+    let llvm_toolchain = settings.get_string("llvm_toolchain").ok();
+    let llvm_opt = LLVM::new(
+        llvm_toolchain.clone(),
+        OptLevel::Optimised,
+        OptLevel::Optimised,
+    )
+    .with_options(options.clone());
+    backends.insert("llvm-opt", Box::new(llvm_opt.clone()));
+
+    if split {
+        backends.insert("llvm-opt-lto", Box::new(llvm_opt.clone().lto()));
+    }
+
+    // Link crates built by different backends, which have to agree on the Rust ABI
+    if let Some(clif) = clif.filter(|_| split) {
+        backends.insert(
+            "llvm-cranelift-mixed",
+            Box::new(Mixed::new(
+                Box::new(llvm_opt.clone()),
+                Box::new(clif.clone()),
+            )),
+        );
+        backends.insert(
+            "cranelift-llvm-mixed",
+            Box::new(Mixed::new(Box::new(clif), Box::new(llvm_opt))),
+        );
+    }
+
+    backends.insert(
+        "llvm-opt-only",
+        Box::new(
+            LLVM::new(llvm_toolchain, OptLevel::Optimised, OptLevel::Unoptimised)
+                .with_options(options),
+        ),
+    );
+
+    Ok(backends)
+}
+
+fn context(backend: &str, e: BackendInitError) -> BackendInitError {
+    BackendInitError(format!("{backend}: {}", e.0))
+}
+
+/// The backends declared in the config, or the default ones. Only the default ones add
+/// LTO and mixed-backend variants for split programs; declared ones are used as they are
+pub fn backends(settings: &Config, split: bool) -> Result<Backends, BackendInitError> {
+    configured_backends(settings).unwrap_or_else(|| default_backends(settings, split))
+}
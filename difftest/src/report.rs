@@ -0,0 +1,200 @@
+//! What happened to each backend, classified for triage and printed by
+//! `difftest --format json`
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    process::ExitStatus,
+};
+
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    backends::{Backend, CompExecError, ExecResult, Stage},
+    triage::Signature,
+    BackendName, ExecResults, Source,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Compiled, and the program exited successfully
+    Success,
+    /// rustc rejected the program
+    CompileError,
+    /// rustc (or Miri) panicked
+    Ice,
+    /// The codegen backend aborted or was killed while compiling
+    BackendCrash,
+    LinkError,
+    /// Compiled, but the program exited unsuccessfully
+    RuntimeCrash,
+    /// Compiled, but the program was killed by this signal
+    Signal(i32),
+    Timeout(Stage),
+    /// Miri or the interpreter reported UB
+    UndefinedBehavior,
+    /// Miri or the interpreter stopped the program for another reason, such as an
+    /// unsupported operation
+    InterpreterError,
+}
+
+#[cfg(unix)]
+fn signal(status: ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(windows)]
+fn signal(_: ExitStatus) -> Option<i32> {
+    None
+}
+
+impl Outcome {
+    pub fn of(result: &ExecResult) -> Self {
+        let (stage, out) = match result {
+            Ok(out) if out.status.success() => return Outcome::Success,
+            Ok(out) => {
+                return signal(out.status).map_or(Outcome::RuntimeCrash, Outcome::Signal);
+            }
+            Err(CompExecError::Timeout(stage)) => return Outcome::Timeout(*stage),
+            Err(CompExecError::Failed(stage, out)) => (stage, out),
+        };
+
+        let stderr = out.stderr.to_string_lossy();
+        if stderr.contains("error: internal compiler error")
+            || stderr.contains("the compiler unexpectedly panicked")
+        {
+            return Outcome::Ice;
+        }
+        match stage {
+            // Only Miri and the interpreter fail while executing, programs' own exits are
+            // successful results
+            Stage::Execute if stderr.contains("Undefined Behavior") => Outcome::UndefinedBehavior,
+            Stage::Execute if stderr.contains("error[E") => Outcome::CompileError,
+            Stage::Execute => Outcome::InterpreterError,
+            Stage::Compile
+                if stderr.contains("error: linking with") || stderr.contains("error: linker `") =>
+            {
+                Outcome::LinkError
+            }
+            Stage::Compile if signal(out.status).is_some() || stderr.contains("LLVM ERROR") => {
+                Outcome::BackendCrash
+            }
+            Stage::Compile => Outcome::CompileError,
+        }
+    }
+
+    /// Whether the program got past compilation
+    pub fn compiled(&self) -> bool {
+        !matches!(
+            self,
+            Outcome::CompileError
+                | Outcome::Ice
+                | Outcome::BackendCrash
+                | Outcome::LinkError
+                | Outcome::Timeout(Stage::Compile)
+        )
+    }
+}
+
+fn hash(output: &impl Hash) -> String {
+    let mut hasher = DefaultHasher::new();
+    output.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackendReport {
+    pub outcome: Outcome,
+    pub compiled: bool,
+    /// Backends in the same class produced the same output
+    pub class: usize,
+    pub version: Option<String>,
+    /// The rustc arguments, including sampled swarm flags
+    pub flags: Vec<String>,
+    pub duration_secs: f64,
+    pub exit_code: Option<i32>,
+    pub stdout_hash: Option<String>,
+    pub stderr_hash: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub source: String,
+    pub all_same: bool,
+    pub all_success: bool,
+    /// What the failure is about, see [`Signature::of`]
+    pub signature: Option<String>,
+    /// The bucket under `buckets_dir` the failure is filed in
+    pub bucket: Option<String>,
+    pub backends: BTreeMap<BackendName, BackendReport>,
+}
+
+impl Report {
+    pub fn new<'a>(
+        source: &Source,
+        backends: &HashMap<BackendName, Box<dyn Backend + 'a>>,
+        results: &ExecResults,
+    ) -> Self {
+        // Number the classes by their first backend, so that they are stable across runs
+        let mut classes: Vec<_> = results
+            .results
+            .iter()
+            .map(|(result, names)| (names.iter().min().unwrap(), result, names))
+            .collect();
+        classes.sort_by_key(|(first, ..)| **first);
+
+        let versions: HashMap<BackendName, Option<String>> = backends
+            .par_iter()
+            .map(|(&name, backend)| (name, backend.version()))
+            .collect();
+
+        let mut reports = BTreeMap::new();
+        for (class, (_, result, names)) in classes.into_iter().enumerate() {
+            let outcome = Outcome::of(result);
+            let out = match result {
+                Ok(out) | Err(CompExecError::Failed(_, out)) => Some(out),
+                Err(CompExecError::Timeout(_)) => None,
+            };
+            for &name in names {
+                let flags = backends[name].rustc_command().map_or(vec![], |command| {
+                    command
+                        .get_args()
+                        .map(|arg| arg.to_string_lossy().into_owned())
+                        // The toolchain is in the version
+                        .filter(|arg| !arg.starts_with('+'))
+                        .collect()
+                });
+                reports.insert(
+                    name,
+                    BackendReport {
+                        outcome,
+                        compiled: outcome.compiled(),
+                        class,
+                        version: versions[name].clone(),
+                        flags,
+                        duration_secs: results
+                            .durations
+                            .get(name)
+                            .map_or(0.0, |duration| duration.as_secs_f64()),
+                        exit_code: out.and_then(|out| out.status.code()),
+                        stdout_hash: out.map(|out| hash(&out.stdout)),
+                        stderr_hash: out.map(|out| hash(&out.stderr)),
+                    },
+                );
+            }
+        }
+
+        let signature = Signature::of(results);
+        Self {
+            source: source.to_string(),
+            all_same: results.all_same(),
+            all_success: results.all_success(),
+            bucket: signature.as_ref().map(Signature::bucket),
+            signature: signature.map(|signature| signature.0),
+            backends: reports,
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Swarm testing: every rustc backend gets its own flags, sampled per program from
+//! the `[swarm]` table of the config:
+//!
+//! ```toml
+//! [swarm]
+//! opt_levels = ["1", "s"]
+//! target_features = ["+avx2", "-sse4.1"]
+//! panic_abort = false
+//! ```
+
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    process::Command,
+};
+
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::Deserialize;
+
+use crate::{
+    backends::{Backend, Timeouts},
+    BackendName, Source,
+};
+
+/// The flags to sample from. Empty lists and disabled toggles are never passed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SwarmSpace {
+    pub opt_levels: Vec<String>,
+    pub mir_opt_levels: Vec<usize>,
+    pub codegen_units: Vec<usize>,
+    pub debuginfo: Vec<usize>,
+    pub target_cpus: Vec<String>,
+    /// A random subset of these is enabled
+    pub target_features: Vec<String>,
+    pub inline_mir: bool,
+    pub panic_abort: bool,
+    pub overflow_checks: bool,
+}
+
+impl Default for SwarmSpace {
+    fn default() -> Self {
+        Self {
+            opt_levels: ["0", "1", "2", "3", "s", "z"].map(String::from).to_vec(),
+            mir_opt_levels: (0..=4).collect(),
+            codegen_units: vec![1, 2, 16],
+            debuginfo: vec![0, 1, 2],
+            target_cpus: ["generic", "native"].map(String::from).to_vec(),
+            target_features: vec![],
+            inline_mir: true,
+            panic_abort: true,
+            overflow_checks: true,
+        }
+    }
+}
+
+impl SwarmSpace {
+    pub fn sample(&self, rng: &mut impl Rng) -> Vec<String> {
+        let mut flags = vec![];
+        let mut push = |prefix: &str, flag: String| {
+            flags.push(prefix.to_owned());
+            flags.push(flag);
+        };
+
+        if let Some(level) = self.opt_levels.choose(rng) {
+            push("-C", format!("opt-level={level}"));
+        }
+        if let Some(level) = self.mir_opt_levels.choose(rng) {
+            push("-Z", format!("mir-opt-level={level}"));
+        }
+        if let Some(units) = self.codegen_units.choose(rng) {
+            push("-C", format!("codegen-units={units}"));
+        }
+        if let Some(level) = self.debuginfo.choose(rng) {
+            push("-C", format!("debuginfo={level}"));
+        }
+        if let Some(cpu) = self.target_cpus.choose(rng) {
+            push("-C", format!("target-cpu={cpu}"));
+        }
+        let amount = rng.gen_range(0..=self.target_features.len());
+        let features: Vec<&str> = self
+            .target_features
+            .choose_multiple(rng, amount)
+            .map(String::as_str)
+            .collect();
+        if !features.is_empty() {
+            push("-C", format!("target-feature={}", features.join(",")));
+        }
+        if self.inline_mir {
+            let inline = if rng.gen_bool(0.5) { "yes" } else { "no" };
+            push("-Z", format!("inline-mir={inline}"));
+        }
+        if self.panic_abort && rng.gen_bool(0.5) {
+            push("-C", "panic=abort".to_owned());
+        }
+        if self.overflow_checks {
+            let checks = if rng.gen_bool(0.5) { "yes" } else { "no" };
+            push("-C", format!("overflow-checks={checks}"));
+        }
+        flags
+    }
+}
+
+/// A rustc backend with sampled flags added after its own, which take precedence
+pub struct Swarm {
+    inner: Box<dyn Backend>,
+    flags: Vec<String>,
+}
+
+impl Backend for Swarm {
+    fn rustc_command(&self) -> Option<Command> {
+        let mut command = self.inner.rustc_command()?;
+        command.args(&self.flags);
+        Some(command)
+    }
+
+    fn swarm_flags(&self) -> &[String] {
+        &self.flags
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        self.inner.timeouts()
+    }
+}
+
+/// Samples flags for every backend that invokes rustc. The flags only depend on the
+/// program and the backend's name, so a rerun samples the same ones
+pub fn swarm(
+    backends: HashMap<BackendName, Box<dyn Backend>>,
+    space: &SwarmSpace,
+    source: &Source,
+) -> HashMap<BackendName, Box<dyn Backend>> {
+    backends
+        .into_iter()
+        .map(|(name, backend)| {
+            if backend.rustc_command().is_none() {
+                return (name, backend);
+            }
+            let mut hasher = DefaultHasher::new();
+            source.code().hash(&mut hasher);
+            name.hash(&mut hasher);
+            let mut rng = SmallRng::seed_from_u64(hasher.finish());
+            let flags = space.sample(&mut rng);
+            let swarm: Box<dyn Backend> = Box::new(Swarm {
+                inner: backend,
+                flags,
+            });
+            (name, swarm)
+        })
+        .collect()
+}
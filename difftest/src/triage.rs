@@ -0,0 +1,273 @@
+//! Deduplicating failures. Each failure gets a signature that doesn't depend on the
+//! program or the toolchain's paths, and repros with the same signature are filed
+//! into the same bucket under `buckets_dir`:
+//!
+//! ```text
+//! buckets/
+//!   3f0c8a2d91b4e657/
+//!     signature.txt
+//!     repro1-5a1f0e23c4b6d789.rs
+//!     repro7-0b2c4d6e8f1a3c5e.rs
+//! ```
+
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    backends::{CompExecError, ProcessOutput},
+    ExecResults, Source,
+};
+
+/// How many backtrace frames of an ICE go into its signature
+const FRAMES: usize = 3;
+
+/// Frames every ICE or panic goes through, which say nothing about the bug
+const PANIC_MACHINERY: &[&str] = &[
+    "std::",
+    "core::",
+    "alloc::",
+    "rust_begin_unwind",
+    "__rust",
+    "rustc_errors::",
+    "rustc_middle::util::bug::",
+    "rustc_middle::ty::context::tls::",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Signature(pub String);
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// FNV-1a, which unlike DefaultHasher is stable across Rust versions, so buckets
+/// survive rebuilding difftest
+fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Drops directories from paths, DefIds, and turns numbers (line numbers, local and
+/// node indices, addresses) into N, except in primitive types
+fn normalise(text: &str) -> String {
+    let mut words = vec![];
+    for word in text.split_whitespace() {
+        match word.rsplit_once('/') {
+            Some((_, file)) => words.push(file),
+            None => words.push(word),
+        }
+    }
+    let text = words.join(" ");
+
+    let mut normalised = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !c.is_ascii_digit() {
+            normalised.push(c);
+            continue;
+        }
+        let primitive = match normalised.as_bytes() {
+            [.., prev, b'i' | b'u' | b'f'] => !prev.is_ascii_alphanumeric() && *prev != b'_',
+            [b'i' | b'u' | b'f'] => true,
+            _ => false,
+        };
+        if primitive {
+            normalised.push(c);
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                normalised.push(digit);
+            }
+        } else {
+            // Addresses are hex
+            let hex = |c: &char| c.is_ascii_hexdigit() || *c == 'x';
+            while chars.next_if(hex).is_some() {}
+            normalised.push('N');
+        }
+    }
+
+    // DefId(0:N ~ crate[hash]::fnN) names the program's items
+    while let Some(start) = normalised.find("DefId(") {
+        let Some(len) = normalised[start..].find(')') else {
+            break;
+        };
+        normalised.replace_range(start..start + len + 1, "DefId");
+    }
+    normalised
+}
+
+/// The top frames of the backtrace, without the panic machinery and generic arguments
+fn frames(stderr: &str) -> Vec<String> {
+    let Some((_, backtrace)) = stderr.split_once("stack backtrace:") else {
+        return vec![];
+    };
+    backtrace
+        .lines()
+        .filter_map(|line| {
+            let (index, frame) = line.trim().split_once(": ")?;
+            index.parse::<usize>().ok()?;
+            // Symbol hashes and generic arguments
+            let frame = match frame.rsplit_once("::h") {
+                Some((frame, hash))
+                    if hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit()) =>
+                {
+                    frame
+                }
+                _ => frame,
+            };
+            let frame = frame.split_once("::<").map_or(frame, |(frame, _)| frame);
+            Some(frame)
+        })
+        .filter(|frame| {
+            let path = frame.trim_start_matches('<');
+            !PANIC_MACHINERY.iter().any(|skip| path.starts_with(skip))
+        })
+        .take(FRAMES)
+        .map(str::to_owned)
+        .collect()
+}
+
+/// The message of a crashed or failed compiler (or Miri), in order of preference: an
+/// LLVM assertion or error, an ICE, a panic's location and message, or the first error
+fn crash(out: &ProcessOutput) -> Signature {
+    let stderr = out.stderr.to_string_lossy();
+    let with_frames = |message: String| {
+        let frames = frames(&stderr);
+        if frames.is_empty() {
+            Signature(normalise(&message))
+        } else {
+            Signature(format!("{} in {}", normalise(&message), frames.join(" < ")))
+        }
+    };
+
+    if let Some(assertion) = stderr.lines().find(|line| line.contains("Assertion `")) {
+        return Signature(format!("LLVM assertion: {}", normalise(assertion)));
+    }
+    if let Some(error) = stderr.lines().find(|line| line.starts_with("LLVM ERROR: ")) {
+        return Signature(normalise(error));
+    }
+
+    let mut lines = stderr.lines();
+    if let Some(message) =
+        lines.find_map(|line| line.strip_prefix("error: internal compiler error: "))
+    {
+        // MIR validation failures continue on indented lines
+        let mut message = message.to_owned();
+        for details in lines.take_while(|line| line.starts_with("        ")) {
+            message.push(' ');
+            message.push_str(details.trim());
+        }
+        return with_frames(format!("ICE: {message}"));
+    }
+
+    // Panics without an ICE message, e.g. in Cranelift
+    let mut lines = stderr.lines();
+    if let Some((_, location)) = lines.find_map(|line| line.split_once("panicked at ")) {
+        let message = lines.next().unwrap_or_default();
+        return with_frames(format!("panicked at {location} {message}"));
+    }
+    if let Some(error) = stderr.lines().find(|line| line.starts_with("error")) {
+        return Signature(normalise(error));
+    }
+    Signature(format!("failed with {}", out.status))
+}
+
+impl Signature {
+    /// The signature of a failure: the crash of the first failing backend, or otherwise
+    /// which backends agree with each other. None if the backends all passed
+    pub fn of(results: &ExecResults) -> Option<Self> {
+        if results.all_same() && results.all_success() {
+            return None;
+        }
+
+        let mut classes: Vec<_> = results
+            .results
+            .iter()
+            .map(|(result, names)| {
+                let mut names: Vec<_> = names.iter().copied().collect();
+                names.sort();
+                (names, result)
+            })
+            .collect();
+        classes.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        for (names, result) in &classes {
+            match result {
+                Ok(_) => {}
+                Err(CompExecError::Timeout(stage)) => {
+                    return Some(Signature(format!(
+                        "timed out while {stage}: {}",
+                        names.join(", ")
+                    )));
+                }
+                Err(CompExecError::Failed(_, out)) => return Some(crash(out)),
+            }
+        }
+        let classes: Vec<_> = classes.iter().map(|(names, _)| names.join(", ")).collect();
+        Some(Signature(format!(
+            "output mismatch: {}",
+            classes.join(" | ")
+        )))
+    }
+
+    /// The name of the signature's bucket
+    pub fn bucket(&self) -> String {
+        format!("{:016x}", stable_hash(self.0.as_bytes()))
+    }
+}
+
+pub struct Bucket {
+    pub id: String,
+    pub path: PathBuf,
+    /// Whether this is the first repro of the signature
+    pub new: bool,
+    /// How many repros are in the bucket, including this one
+    pub repros: usize,
+}
+
+impl fmt::Display for Bucket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.new {
+            write!(f, "new bucket {}", self.path.display())
+        } else {
+            write!(f, "bucket {} ({} repros)", self.path.display(), self.repros)
+        }
+    }
+}
+
+/// Files the repro under its signature's bucket in `dir`. The same program is only
+/// filed once
+pub fn file(dir: &Path, signature: &Signature, source: &Source) -> io::Result<Bucket> {
+    let id = signature.bucket();
+    let path = dir.join(&id);
+    let new = !path.exists();
+    fs::create_dir_all(&path)?;
+    let signature_file = path.join("signature.txt");
+    if !signature_file.exists() {
+        fs::write(signature_file, format!("{signature}\n"))?;
+    }
+
+    let code = source.code();
+    let repro = format!(
+        "{}-{:016x}.rs",
+        source.file_stem(),
+        stable_hash(code.as_bytes())
+    );
+    fs::write(path.join(repro), code)?;
+
+    let mut repros = 0;
+    for entry in fs::read_dir(&path)? {
+        if entry?.path().extension().is_some_and(|ext| ext == "rs") {
+            repros += 1;
+        }
+    }
+    Ok(Bucket {
+        id,
+        path,
+        new,
+        repros,
+    })
+}
@@ -0,0 +1,20 @@
+#![feature(custom_mir, core_intrinsics)]
+
+extern crate core;
+use core::intrinsics::mir::*;
+
+// Fails MIR validation: SetDiscriminant on an integer
+#[custom_mir(dialect = "runtime", phase = "optimized")]
+pub fn simple(x: i32) -> i32 {
+    mir!(
+        {
+            RET = x;
+            SetDiscriminant(RET, 1);
+            Return()
+        }
+    )
+}
+
+pub fn main() {
+    println!("{}", simple(5));
+}
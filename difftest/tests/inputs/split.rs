@@ -0,0 +1,39 @@
+#![feature(custom_mir, core_intrinsics)]
+
+extern crate core;
+
+#[cfg(rustlantis_upper)]
+extern crate rustlantis_lower;
+#[cfg(rustlantis_upper)]
+use rustlantis_lower::lower;
+#[cfg(not(rustlantis_upper))]
+pub mod lower {
+    use core::intrinsics::mir::*;
+
+    #[derive(Debug, Copy, Clone)]
+    pub struct Pair {
+        pub a: i32,
+        pub b: i64,
+    }
+
+    #[inline]
+    #[custom_mir(dialect = "runtime", phase = "initial")]
+    pub fn swap(x: Pair) -> Pair {
+        mir!(
+            type RET = Pair;
+            {
+                RET.a = x.b as i32;
+                RET.b = x.a as i64;
+                Return()
+            }
+        )
+    }
+}
+#[cfg(not(rustlantis_lower))]
+use lower::*;
+
+#[cfg(not(rustlantis_lower))]
+pub fn main() {
+    let pair = swap(Pair { a: 5, b: -7 });
+    println!("{:?}", pair);
+}
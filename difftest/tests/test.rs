@@ -1,9 +1,17 @@
-use std::{collections::HashMap, path::PathBuf, str::FromStr};
+use std::{collections::HashMap, path::PathBuf, str::FromStr, time::Duration};
 
 use config::Config;
 use difftest::{
-    backends::{Backend, Cranelift, Miri, OptLevel, LLVM},
-    run_diff_test, Source,
+    backends::{
+        Backend, BackendOptions, CompExecError, Cranelift, Miri, Mixed, OptLevel, Stage, Timeouts,
+        LLVM,
+    },
+    matrix::configured_backends,
+    report::{Outcome, Report},
+    run_diff_test,
+    swarm::{swarm, SwarmSpace},
+    triage::{self, Signature},
+    Source,
 };
 
 #[test]
@@ -39,10 +47,243 @@ fn correct_mir() {
 
     let results = run_diff_test(
         &Source::File(PathBuf::from_str("tests/inputs/simple.rs").unwrap()),
-        backends,
+        &backends,
     );
     assert!(results.all_same());
     assert!(results["llvm"]
         .as_ref()
         .is_ok_and(|output| output.status.success() && output.stdout == "5\n"))
 }
+
+#[test]
+fn configured_matrix() {
+    let settings = Config::builder()
+        .add_source(config::File::from_str(
+            r#"
+            [backends.llvm-os]
+            kind = "llvm"
+            flags = ["-C", "opt-level=s"]
+
+            [backends.llvm-debug]
+            kind = "llvm"
+            opt_level = 0
+            mir_opt_level = 0
+            env = { RUSTC_LOG = "warn" }
+            "#,
+            config::FileFormat::Toml,
+        ))
+        .build()
+        .unwrap();
+
+    let backends = configured_backends(&settings)
+        .expect("declares backends")
+        .expect("backends can be initialised");
+    let results = run_diff_test(
+        &Source::File(PathBuf::from_str("tests/inputs/simple.rs").unwrap()),
+        &backends,
+    );
+    assert!(results.all_same());
+    assert!(results["llvm-os"]
+        .as_ref()
+        .is_ok_and(|output| output.status.success() && output.stdout == "5\n"))
+}
+
+#[test]
+fn swarm_flags() {
+    let mut backends: HashMap<&'static str, Box<dyn Backend>> = HashMap::default();
+    backends.insert(
+        "llvm",
+        Box::new(LLVM::new(None, OptLevel::Optimised, OptLevel::Optimised)),
+    );
+    let source = Source::File(PathBuf::from_str("tests/inputs/simple.rs").unwrap());
+    let space = SwarmSpace {
+        target_cpus: vec!["generic".to_string()],
+        ..Default::default()
+    };
+    let backends = swarm(backends, &space, &source);
+    assert!(backends["llvm"]
+        .swarm_flags()
+        .contains(&"target-cpu=generic".to_string()));
+
+    let results = run_diff_test(&source, &backends);
+    assert!(results["llvm"]
+        .as_ref()
+        .is_ok_and(|output| output.status.success() && output.stdout == "5\n"))
+}
+
+#[test]
+fn timeouts() {
+    let timeouts = |compile, run| BackendOptions {
+        timeouts: Timeouts { compile, run },
+        ..Default::default()
+    };
+    let mut backends: HashMap<&'static str, Box<dyn Backend>> = HashMap::default();
+    backends.insert(
+        "llvm",
+        Box::new(
+            LLVM::new(None, OptLevel::Optimised, OptLevel::Optimised)
+                .with_options(timeouts(None, Some(Duration::from_secs(1)))),
+        ),
+    );
+    backends.insert(
+        "llvm-unopt",
+        Box::new(
+            LLVM::new(None, OptLevel::Unoptimised, OptLevel::Unoptimised)
+                .with_options(timeouts(Some(Duration::ZERO), None)),
+        ),
+    );
+
+    let results = run_diff_test(
+        &Source::File(PathBuf::from_str("tests/inputs/hang.rs").unwrap()),
+        &backends,
+    );
+    assert!(results.has_timeout());
+    assert!(!results.all_same());
+    assert_eq!(results["llvm"], Err(CompExecError::Timeout(Stage::Execute)));
+    assert_eq!(
+        results["llvm-unopt"],
+        Err(CompExecError::Timeout(Stage::Compile))
+    );
+    assert_eq!(
+        Signature::of(&results).unwrap().0,
+        "timed out while executing: llvm"
+    );
+
+    // Swarmed backends keep their timeouts
+    let source = Source::File(PathBuf::from_str("tests/inputs/hang.rs").unwrap());
+    let backends = swarm(backends, &SwarmSpace::default(), &source);
+    let results = run_diff_test(&source, &backends);
+    assert_eq!(results["llvm"], Err(CompExecError::Timeout(Stage::Execute)));
+    assert_eq!(
+        results["llvm-unopt"],
+        Err(CompExecError::Timeout(Stage::Compile))
+    );
+}
+
+#[test]
+fn json_report() {
+    let mut backends: HashMap<&'static str, Box<dyn Backend>> = HashMap::default();
+    backends.insert(
+        "llvm",
+        Box::new(LLVM::new(None, OptLevel::Optimised, OptLevel::Optimised)),
+    );
+
+    let source = Source::File(PathBuf::from_str("tests/inputs/simple.rs").unwrap());
+    let results = run_diff_test(&source, &backends);
+    let report = serde_json::to_value(Report::new(&source, &backends, &results)).unwrap();
+    assert_eq!(report["all_same"], true);
+    assert_eq!(report["backends"]["llvm"]["outcome"], "success");
+    assert_eq!(report["backends"]["llvm"]["compiled"], true);
+    assert!(report["backends"]["llvm"]["version"]
+        .as_str()
+        .is_some_and(|version| version.starts_with("rustc")));
+
+    let source = Source::File(PathBuf::from_str("tests/inputs/invalid_mir.rs").unwrap());
+    let results = run_diff_test(&source, &backends);
+    assert_eq!(Outcome::of(&results["llvm"]), Outcome::CompileError);
+    let report = Report::new(&source, &backends, &results);
+    assert!(!report.backends["llvm"].compiled);
+}
+
+#[test]
+fn program_exit_status() {
+    let settings = Config::builder()
+        .add_source(config::File::with_name("config.toml").required(false))
+        .add_source(config::Environment::default())
+        .build()
+        .unwrap();
+
+    let mut backends: HashMap<&'static str, Box<dyn Backend>> = HashMap::default();
+    if let Ok(miri_toolchain) = settings.get_string("miri_toolchain") {
+        let miri = Miri::from_rustup(&miri_toolchain, true);
+        match miri {
+            Ok(miri) => backends.insert("miri", Box::new(miri)),
+            Err(e) => panic!("miri init failed\n{}", e.0),
+        };
+    } else {
+        // backends::tests::miri_outcomes covers Miri's canned output
+        eprintln!("miri_toolchain is not configured, only testing exit statuses under LLVM");
+    }
+    backends.insert(
+        "llvm",
+        Box::new(LLVM::new(None, OptLevel::Optimised, OptLevel::Optimised)),
+    );
+
+    let results = run_diff_test(
+        &Source::File(PathBuf::from_str("tests/inputs/exit.rs").unwrap()),
+        &backends,
+    );
+    // Under Miri the program's exit code isn't mistaken for an error
+    assert!(results.all_same());
+    assert_eq!(Outcome::of(&results["llvm"]), Outcome::RuntimeCrash);
+    assert!(results["llvm"]
+        .as_ref()
+        .is_ok_and(|output| output.status.code() == Some(3) && output.stdout == "exiting\n"))
+}
+
+#[test]
+fn ice_buckets() {
+    let mut backends: HashMap<&'static str, Box<dyn Backend>> = HashMap::default();
+    backends.insert(
+        "llvm",
+        Box::new(
+            LLVM::new(None, OptLevel::Optimised, OptLevel::Optimised).with_options(
+                BackendOptions {
+                    flags: vec!["-Zvalidate-mir".to_string()],
+                    ..Default::default()
+                },
+            ),
+        ),
+    );
+
+    // The same bug in a different program
+    let dir = tempfile::tempdir().unwrap();
+    let renamed = dir.path().join("renamed.rs");
+    let code = std::fs::read_to_string("tests/inputs/ice.rs").unwrap();
+    std::fs::write(&renamed, code.replace("simple", "renamed")).unwrap();
+
+    let buckets = dir.path().join("buckets");
+    let mut ids = vec![];
+    for source in [PathBuf::from_str("tests/inputs/ice.rs").unwrap(), renamed] {
+        let source = Source::File(source);
+        let results = run_diff_test(&source, &backends);
+        assert_eq!(Outcome::of(&results["llvm"]), Outcome::Ice);
+        let signature = Signature::of(&results).unwrap();
+        assert!(signature.0.starts_with("ICE: validate.rs"), "{signature}");
+        assert!(signature.0.contains("SetDiscriminant"), "{signature}");
+        ids.push(triage::file(&buckets, &signature, &source).unwrap());
+    }
+    assert_eq!(ids[0].id, ids[1].id);
+    assert!(ids[0].new && !ids[1].new);
+    assert_eq!(ids[1].repros, 2);
+}
+
+#[test]
+fn split_program() {
+    let source = Source::File(PathBuf::from_str("tests/inputs/split.rs").unwrap());
+    assert!(source.is_split());
+
+    let llvm_opt = || LLVM::new(None, OptLevel::Optimised, OptLevel::Optimised);
+    let llvm_unopt = || LLVM::new(None, OptLevel::Unoptimised, OptLevel::Unoptimised);
+    let mut backends: HashMap<&'static str, Box<dyn Backend>> = HashMap::default();
+    backends.insert("llvm-opt", Box::new(llvm_opt()));
+    backends.insert("llvm-opt-lto", Box::new(llvm_opt().lto()));
+    backends.insert(
+        "llvm-llvm-mixed",
+        Box::new(Mixed::new(Box::new(llvm_opt()), Box::new(llvm_unopt()))),
+    );
+
+    let results = run_diff_test(&source, &backends);
+    assert!(results.all_same(), "{results}");
+    assert!(results["llvm-llvm-mixed"]
+        .as_ref()
+        .is_ok_and(|output| output.stdout == "Pair { a: -7, b: 5 }\n"));
+
+    // The lower crate is built as a library of its own
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("split");
+    assert!(backends["llvm-llvm-mixed"]
+        .execute(&source, &target)
+        .is_ok());
+    assert!(dir.path().join("libsplit.rlib").exists());
+}
@@ -1,14 +1,22 @@
 use std::borrow::BorrowMut;
 
 use mir::{
-    syntax::{Callee, Mutability, Operand, Place, TyId, TyKind},
+    syntax::{
+        AtomicMethod, AtomicOrdering, AtomicTy, Callee, Mutability, Operand, Place, ProjectionElem,
+        TyId, TyKind,
+    },
     tyctxt::TyCtxt,
 };
-use rand::{seq::IteratorRandom, Rng};
+use rand::{
+    seq::{IteratorRandom, SliceRandom},
+    Rng,
+};
 
-use crate::{literal::GenLiteral, mem::BasicMemory, place_select::PlaceSelector};
+use crate::{
+    literal::GenLiteral, mem::BasicMemory, pgraph::ToPlaceIndex, place_select::PlaceSelector,
+};
 
-use super::{GenerationCtx, Result, SelectionError};
+use super::{GenerationCtx, Result, SelectionError, CONCURRENT_ATOMIC_PROBABILITY};
 
 pub trait CoreIntrinsic {
     fn name(&self) -> &'static str;
@@ -17,6 +25,10 @@ pub trait CoreIntrinsic {
 
     fn choose_operands(&self, ctx: &GenerationCtx, dest: &Place) -> Option<Vec<Operand>>;
 
+    fn callee(&self, _ctx: &GenerationCtx, _dest: &Place) -> Callee {
+        Callee::Intrinsic(self.name())
+    }
+
     fn generate_terminator(
         &self,
         ctx: &GenerationCtx,
@@ -28,7 +40,7 @@ pub trait CoreIntrinsic {
         let args = self
             .choose_operands(ctx, dest)
             .ok_or(SelectionError::Exhausted)?;
-        Ok((Callee::Intrinsic(self.name()), args))
+        Ok((self.callee(ctx, dest), args))
     }
 }
 
@@ -43,9 +55,9 @@ impl CoreIntrinsic for Fmaf64 {
     }
 
     fn choose_operands(&self, ctx: &GenerationCtx, dest: &Place) -> Option<Vec<Operand>> {
-        let a = ctx.choose_operand(&[TyCtxt::F64], dest).ok()?;
-        let b = ctx.choose_operand(&[TyCtxt::F64], dest).ok()?;
-        let c = ctx.choose_operand(&[TyCtxt::F64], dest).ok()?;
+        let a = ctx.choose_argument(&[TyCtxt::F64], dest).ok()?;
+        let b = ctx.choose_argument(&[TyCtxt::F64], dest).ok()?;
+        let c = ctx.choose_argument(&[TyCtxt::F64], dest).ok()?;
         Some(vec![a, b, c])
     }
 }
@@ -112,7 +124,7 @@ impl CoreIntrinsic for Bswap {
 
     fn choose_operands(&self, ctx: &GenerationCtx, dest: &Place) -> Option<Vec<Operand>> {
         let arg = ctx
-            .choose_operand(&[dest.ty(ctx.current_decls(), &ctx.tcx)], dest)
+            .choose_argument(&[dest.ty(ctx.current_decls(), &ctx.tcx)], dest)
             .ok()?;
         Some(vec![arg])
     }
@@ -127,7 +139,9 @@ impl CoreIntrinsic for Transmute {
     fn dest_type(&self, ty: TyId, tcx: &TyCtxt) -> bool {
         if ty.contains(tcx, |tcx, ty| match ty.kind(tcx) {
             // Tys with value validity contstraints
-            TyKind::Unit | TyKind::Bool | TyKind::Char | TyKind::RawPtr(..) | TyKind::Ref(..) => true, // TODO: pointer transmute
+            TyKind::Unit | TyKind::Bool | TyKind::Char | TyKind::RawPtr(..) | TyKind::Ref(..) => {
+                true
+            } // TODO: pointer transmute
             _ => false,
         }) {
             return false;
@@ -171,13 +185,315 @@ impl CoreIntrinsic for Transmute {
     }
 }
 
+/// Integer types with native atomic operations on all targets we test. 128-bit atomics
+/// need cmpxchg16b on x86_64 and lower to libcalls without it.
+fn is_atomic_int(ty: TyId, tcx: &TyCtxt) -> bool {
+    matches!(ty.kind(tcx), TyKind::Int(..) | TyKind::Uint(..))
+        && ty != TyCtxt::I128
+        && ty != TyCtxt::U128
+}
+
+/// Chooses an initialised place of one of the given types that can be accessed through
+/// a `*mut` pointer, and returns the pointer.
+fn choose_atomic_ptr(
+    ctx: &GenerationCtx,
+    tys: &[TyId],
+    dest: &Place,
+    write: bool,
+) -> Option<Place> {
+    let pointee = PlaceSelector::for_operand(ctx.tcx.clone())
+        .of_tys(tys)
+        .except(dest)
+        .into_iter_place(&ctx.pt)
+        .filter(|place| {
+            let Some((ProjectionElem::Deref, ptr_proj)) = place.projection().split_last() else {
+                return false;
+            };
+            let ptr = Place::from_projected(place.local(), ptr_proj);
+            if !matches!(
+                ptr.ty(ctx.current_decls(), &ctx.tcx).kind(&ctx.tcx),
+                TyKind::RawPtr(_, Mutability::Mut)
+            ) {
+                return false;
+            }
+            let local = place.local().to_place_index(&ctx.pt).expect("local exists");
+            let pidx = place.to_place_index(&ctx.pt).expect("place exists");
+            !write || ctx.pt.can_write_through(local, pidx)
+        })
+        .choose(&mut *ctx.rng.borrow_mut())?;
+    let (_, ptr_proj) = pointee
+        .projection()
+        .split_last()
+        .expect("pointee is a deref");
+    Some(Place::from_projected(pointee.local(), ptr_proj))
+}
+
+fn choose_ordering(ctx: &GenerationCtx, excluded: &[AtomicOrdering]) -> AtomicOrdering {
+    let orderings: Vec<AtomicOrdering> = AtomicOrdering::ALL
+        .into_iter()
+        .filter(|ord| !excluded.contains(ord))
+        .collect();
+    *orderings
+        .choose(&mut *ctx.rng.borrow_mut())
+        .expect("orderings are not empty")
+}
+
+pub(super) struct AtomicLoad;
+impl CoreIntrinsic for AtomicLoad {
+    fn name(&self) -> &'static str {
+        "atomic_load"
+    }
+
+    fn dest_type(&self, ty: TyId, tcx: &TyCtxt) -> bool {
+        is_atomic_int(ty, tcx)
+    }
+
+    fn choose_operands(&self, ctx: &GenerationCtx, dest: &Place) -> Option<Vec<Operand>> {
+        let ty = dest.ty(ctx.current_decls(), &ctx.tcx);
+        let ptr = choose_atomic_ptr(ctx, &[ty], dest, false)?;
+        Some(vec![Operand::Copy(ptr)])
+    }
+
+    fn callee(&self, ctx: &GenerationCtx, _: &Place) -> Callee {
+        let ordering = choose_ordering(ctx, &[AtomicOrdering::Release, AtomicOrdering::AcqRel]);
+        Callee::AtomicIntrinsic(self.name(), ordering)
+    }
+}
+
+pub(super) struct AtomicStore;
+impl CoreIntrinsic for AtomicStore {
+    fn name(&self) -> &'static str {
+        "atomic_store"
+    }
+
+    fn dest_type(&self, ty: TyId, _: &TyCtxt) -> bool {
+        ty == TyCtxt::UNIT
+    }
+
+    fn choose_operands(&self, ctx: &GenerationCtx, dest: &Place) -> Option<Vec<Operand>> {
+        let tys: Vec<TyId> = ctx
+            .tcx
+            .indices()
+            .filter(|ty| is_atomic_int(*ty, &ctx.tcx))
+            .collect();
+        let ptr = choose_atomic_ptr(ctx, &tys, dest, true)?;
+        let ty = ptr
+            .ty(ctx.current_decls(), &ctx.tcx)
+            .pointee_ty(&ctx.tcx)
+            .expect("is a pointer");
+        let val = ctx.choose_argument(&[ty], dest).ok()?;
+        Some(vec![Operand::Copy(ptr), val])
+    }
+
+    fn callee(&self, ctx: &GenerationCtx, _: &Place) -> Callee {
+        let ordering = choose_ordering(ctx, &[AtomicOrdering::Acquire, AtomicOrdering::AcqRel]);
+        Callee::AtomicIntrinsic(self.name(), ordering)
+    }
+}
+
+/// Atomic read-modify-write operations, returning the old value
+pub(super) struct AtomicRmw;
+impl AtomicRmw {
+    const COMMON_OPS: [&'static str; 7] = [
+        "atomic_xchg",
+        "atomic_xadd",
+        "atomic_xsub",
+        "atomic_and",
+        "atomic_nand",
+        "atomic_or",
+        "atomic_xor",
+    ];
+    const SIGNED_OPS: [&'static str; 2] = ["atomic_max", "atomic_min"];
+    const UNSIGNED_OPS: [&'static str; 2] = ["atomic_umax", "atomic_umin"];
+
+    pub(super) fn is_rmw(name: &str) -> bool {
+        Self::COMMON_OPS
+            .iter()
+            .chain(Self::SIGNED_OPS.iter())
+            .chain(Self::UNSIGNED_OPS.iter())
+            .any(|op| *op == name)
+    }
+}
+
+impl CoreIntrinsic for AtomicRmw {
+    fn name(&self) -> &'static str {
+        "atomic_xchg"
+    }
+
+    fn dest_type(&self, ty: TyId, tcx: &TyCtxt) -> bool {
+        is_atomic_int(ty, tcx)
+    }
+
+    fn choose_operands(&self, ctx: &GenerationCtx, dest: &Place) -> Option<Vec<Operand>> {
+        let ty = dest.ty(ctx.current_decls(), &ctx.tcx);
+        let ptr = choose_atomic_ptr(ctx, &[ty], dest, true)?;
+        let val = ctx.choose_argument(&[ty], dest).ok()?;
+        Some(vec![Operand::Copy(ptr), val])
+    }
+
+    fn callee(&self, ctx: &GenerationCtx, dest: &Place) -> Callee {
+        let ops = match dest.ty(ctx.current_decls(), &ctx.tcx).kind(&ctx.tcx) {
+            TyKind::Int(..) => Self::SIGNED_OPS,
+            _ => Self::UNSIGNED_OPS,
+        };
+        let name = *Self::COMMON_OPS
+            .iter()
+            .chain(ops.iter())
+            .choose(&mut *ctx.rng.borrow_mut())
+            .expect("ops are not empty");
+        Callee::AtomicIntrinsic(name, choose_ordering(ctx, &[]))
+    }
+}
+
+pub(super) struct AtomicFence;
+impl CoreIntrinsic for AtomicFence {
+    fn name(&self) -> &'static str {
+        "atomic_fence"
+    }
+
+    fn dest_type(&self, ty: TyId, _: &TyCtxt) -> bool {
+        ty == TyCtxt::UNIT
+    }
+
+    fn choose_operands(&self, _: &GenerationCtx, _: &Place) -> Option<Vec<Operand>> {
+        Some(vec![])
+    }
+
+    fn callee(&self, ctx: &GenerationCtx, _: &Place) -> Callee {
+        let name = *["atomic_fence", "atomic_singlethreadfence"]
+            .choose(&mut *ctx.rng.borrow_mut())
+            .unwrap();
+        // There is no such thing as a relaxed fence
+        let ordering = choose_ordering(ctx, &[AtomicOrdering::Relaxed]);
+        Callee::AtomicIntrinsic(name, ordering)
+    }
+}
+
+/// Methods of `AtomicI*`, `AtomicU*` and `AtomicPtr`, called through helper functions
+/// which make the atomic type with `from_ptr`. In threaded mode, read-modify-writes on
+/// integers may be done by several scoped threads at once.
+pub(super) struct TypedAtomic(&'static [AtomicMethod]);
+impl TypedAtomic {
+    const RMW: [AtomicMethod; 10] = [
+        AtomicMethod::Swap,
+        AtomicMethod::CompareExchange,
+        AtomicMethod::FetchAdd,
+        AtomicMethod::FetchSub,
+        AtomicMethod::FetchAnd,
+        AtomicMethod::FetchNand,
+        AtomicMethod::FetchOr,
+        AtomicMethod::FetchXor,
+        AtomicMethod::FetchMax,
+        AtomicMethod::FetchMin,
+    ];
+
+    fn atomic_ty(ty: TyId, tcx: &TyCtxt) -> Option<AtomicTy> {
+        if is_atomic_int(ty, tcx) {
+            Some(AtomicTy::Int(ty))
+        } else if matches!(ty.kind(tcx), TyKind::RawPtr(_, Mutability::Mut)) {
+            Some(AtomicTy::Ptr)
+        } else {
+            None
+        }
+    }
+
+    fn supports(method: AtomicMethod, ty: TyId, tcx: &TyCtxt) -> bool {
+        match Self::atomic_ty(ty, tcx) {
+            Some(AtomicTy::Int(_)) => true,
+            Some(AtomicTy::Ptr) => method.on_ptr(),
+            None => false,
+        }
+    }
+}
+
+impl CoreIntrinsic for TypedAtomic {
+    fn name(&self) -> &'static str {
+        self.0[0].name()
+    }
+
+    fn dest_type(&self, ty: TyId, tcx: &TyCtxt) -> bool {
+        if self.0 == [AtomicMethod::Store] {
+            ty == TyCtxt::UNIT
+        } else {
+            self.0.iter().any(|method| Self::supports(*method, ty, tcx))
+        }
+    }
+
+    fn choose_operands(&self, _: &GenerationCtx, _: &Place) -> Option<Vec<Operand>> {
+        unreachable!("operands depend on the method, which is chosen with them")
+    }
+
+    fn generate_terminator(
+        &self,
+        ctx: &GenerationCtx,
+        dest: &Place,
+    ) -> Result<(Callee, Vec<Operand>)> {
+        let dest_ty = dest.ty(ctx.current_decls(), &ctx.tcx);
+        if !self.dest_type(dest_ty, &ctx.tcx) {
+            return Err(SelectionError::Exhausted);
+        }
+        let method = *self
+            .0
+            .iter()
+            .filter(|method| {
+                **method == AtomicMethod::Store || Self::supports(**method, dest_ty, &ctx.tcx)
+            })
+            .choose(&mut *ctx.rng.borrow_mut())
+            .expect("dest type is supported");
+
+        let ptr = if method == AtomicMethod::Store {
+            let tys: Vec<TyId> = ctx
+                .tcx
+                .indices()
+                .filter(|ty| Self::atomic_ty(*ty, &ctx.tcx).is_some())
+                .collect();
+            choose_atomic_ptr(ctx, &tys, dest, true)
+        } else {
+            choose_atomic_ptr(ctx, &[dest_ty], dest, method != AtomicMethod::Load)
+        }
+        .ok_or(SelectionError::Exhausted)?;
+        let elem = ptr
+            .ty(ctx.current_decls(), &ctx.tcx)
+            .pointee_ty(&ctx.tcx)
+            .expect("is a pointer");
+        let mut args = vec![Operand::Copy(ptr)];
+        for _ in 0..method.operands() {
+            args.push(ctx.choose_argument(&[elem], dest)?);
+        }
+
+        let excluded: Vec<AtomicOrdering> = AtomicOrdering::ALL
+            .into_iter()
+            .filter(|ordering| method.forbids(*ordering))
+            .collect();
+        let ordering = choose_ordering(ctx, &excluded);
+        let ty = Self::atomic_ty(elem, &ctx.tcx).expect("pointee is atomic");
+        let callee = if ctx.threads
+            && method.is_rmw()
+            && matches!(ty, AtomicTy::Int(_))
+            && ctx.rng.borrow_mut().gen_bool(CONCURRENT_ATOMIC_PROBABILITY)
+        {
+            Callee::ConcurrentAtomic(ty, method, ordering)
+        } else {
+            Callee::Atomic(ty, method, ordering)
+        };
+        Ok((callee, args))
+    }
+}
+
 impl GenerationCtx {
     pub fn choose_intrinsic(&self, dest: &Place) -> Result<(Callee, Vec<Operand>)> {
-        let choices: [Box<dyn CoreIntrinsic>; 4] = [
+        let choices: [Box<dyn CoreIntrinsic>; 11] = [
             Box::new(Fmaf64),
             Box::new(ArithOffset),
             Box::new(Bswap),
             Box::new(Transmute),
+            Box::new(AtomicLoad),
+            Box::new(AtomicStore),
+            Box::new(AtomicRmw),
+            Box::new(AtomicFence),
+            Box::new(TypedAtomic(&[AtomicMethod::Load])),
+            Box::new(TypedAtomic(&[AtomicMethod::Store])),
+            Box::new(TypedAtomic(&TypedAtomic::RMW)),
         ];
 
         let intrinsic = self.make_choice(choices.iter(), Result::Ok)?;
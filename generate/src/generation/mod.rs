@@ -8,9 +8,10 @@ use index_vec::IndexVec;
 use log::{debug, trace};
 use mir::serialize::Serialize;
 use mir::syntax::{
-    AggregateKind, BasicBlock, BasicBlockData, BinOp, Body, Callee, Function, IntTy, Literal,
-    Local, LocalDecls, Mutability, Operand, Place, Program, ProjectionElem, Rvalue, Statement,
-    SwitchTargets, Terminator, TyId, TyKind, UnOp, VariantIdx,
+    Abi, AggregateKind, AtomicMethod, BasicBlock, BasicBlockData, BinOp, Body, Callee, CaptureMode,
+    CompoundConst, FnTrait, Function, IntTy, Literal, Local, LocalDecls, Mutability, Operand,
+    Place, Program, ProjectionElem, Rvalue, Statement, SwitchTargets, Terminator, TyId, TyKind,
+    UnOp, VariantIdx,
 };
 use mir::tyctxt::TyCtxt;
 use rand::seq::SliceRandom;
@@ -22,7 +23,7 @@ use crate::pgraph::{HasComplexity, PlaceGraph, PlaceIndex, PlaceOperand, ToPlace
 use crate::place_select::{PlaceSelector, Weight};
 use crate::ty::{seed_tys, TySelect};
 
-use self::intrinsics::{ArithOffset, Transmute};
+use self::intrinsics::{ArithOffset, AtomicRmw, AtomicStore, Transmute};
 use crate::generation::intrinsics::CoreIntrinsic;
 
 /// Max. number of statements & declarations in a bb
@@ -39,6 +40,23 @@ const MAX_FN_COUNT: usize = 20;
 const MAX_ARGS_COUNT: usize = 12;
 /// Expected proportion of variables to be dumped
 const VAR_DUMP_CHANCE: f32 = 0.5;
+/// Probability of a generated function being called through a closure
+const CLOSURE_CALL_PROBABILITY: f64 = 0.25;
+/// Probability of a generated function being run on a scoped thread, in threaded mode
+const WORKER_CALL_PROBABILITY: f64 = 0.2;
+/// Probability of an atomic read-modify-write on an integer being done by several scoped
+/// threads at once, in threaded mode
+const CONCURRENT_ATOMIC_PROBABILITY: f64 = 0.5;
+/// Probability of a generated function using a non-Rust calling convention
+const FOREIGN_ABI_PROBABILITY: f64 = 0.2;
+/// Max. number of arguments a function can have in ABI stress mode
+const ABI_STRESS_MAX_ARGS_COUNT: usize = 24;
+/// How much more likely Call terminators are in ABI stress mode
+const ABI_STRESS_CALL_WEIGHT_FACTOR: usize = 4;
+/// Weight of calls into the C translation unit, when enabled
+const FOREIGN_CALL_WEIGHT: usize = 10;
+/// Probability of a function in the library crate of a split program being `#[inline]`
+const CROSS_CRATE_INLINE_PROBABILITY: f64 = 0.5;
 
 #[derive(Debug)]
 pub enum SelectionError {
@@ -80,11 +98,34 @@ pub struct GenerationCtx {
     return_stack: Vec<Cursor>,
     saved_ctx: Vec<SavedCtx>,
     cursor: Cursor,
+    /// Make every function use a foreign ABI and take many arguments
+    abi_stress: bool,
+    /// Call functions defined in a generated C translation unit
+    c_ffi: bool,
+    /// Split the program into a library crate and a binary crate
+    split: bool,
+    /// Run some functions and atomic read-modify-writes on scoped threads
+    threads: bool,
 }
 
 // Operand
 impl GenerationCtx {
     fn choose_operand(&self, tys: &[TyId], excluded: &Place) -> Result<Operand> {
+        self.choose_operand_or_const(tys, excluded, true)
+    }
+
+    /// Chooses an operand for an intrinsic call, which is never a compound constant:
+    /// the PlaceGraph only tracks places and literals passed as arguments
+    fn choose_argument(&self, tys: &[TyId], excluded: &Place) -> Result<Operand> {
+        self.choose_operand_or_const(tys, excluded, false)
+    }
+
+    fn choose_operand_or_const(
+        &self,
+        tys: &[TyId],
+        excluded: &Place,
+        allow_compound: bool,
+    ) -> Result<Operand> {
         let operand: Result<Operand> = try {
             let (ppath, weights) = PlaceSelector::for_operand(self.tcx.clone())
                 .except(excluded)
@@ -106,7 +147,25 @@ impl GenerationCtx {
                 .copied()
                 .collect();
             if literalble.is_empty() {
-                Err(SelectionError::Exhausted)
+                if !allow_compound {
+                    return Err(SelectionError::Exhausted);
+                }
+                // Aggregates can still be built from a constant
+                let constructible: Vec<TyId> = tys
+                    .iter()
+                    .filter(|ty| <dyn RngCore>::is_const_constructible(**ty, &self.tcx))
+                    .copied()
+                    .collect();
+                let selected = constructible
+                    .iter()
+                    .choose(&mut *self.rng.borrow_mut())
+                    .ok_or(SelectionError::Exhausted)?;
+                let value = self
+                    .rng
+                    .borrow_mut()
+                    .gen_const(*selected, &self.tcx)
+                    .expect("can always generate a constant of a const constructible type");
+                Ok(Operand::Compound(CompoundConst::Block(value)))
             } else {
                 let selected = literalble
                     .iter()
@@ -422,6 +481,23 @@ impl GenerationCtx {
         Ok(agg)
     }
 
+    fn generate_compound_const(&self, lhs: &Place) -> Result<Rvalue> {
+        let target_ty = lhs.ty(self.current_decls(), &self.tcx);
+        if !matches!(
+            target_ty.kind(&self.tcx),
+            TyKind::Tuple(..) | TyKind::Array(..) | TyKind::Adt(..)
+        ) || !<dyn RngCore>::is_const_constructible(target_ty, &self.tcx)
+        {
+            return Err(SelectionError::Exhausted);
+        }
+        let value = self
+            .rng
+            .borrow_mut()
+            .gen_const(target_ty, &self.tcx)
+            .expect("can always generate a constant of a const constructible type");
+        Ok(Rvalue::Use(Operand::Compound(CompoundConst::Block(value))))
+    }
+
     // fn generate_len(&self, cur_stmt: &mut Statement) -> Result<()> {
     //     todo!()
     // }
@@ -444,6 +520,7 @@ impl GenerationCtx {
             (Self::generate_address_of, 4),
             (Self::generate_ref, 4),
             (Self::generate_aggregate, 2),
+            (Self::generate_compound_const, 1),
         ];
 
         let (choices, weights): (
@@ -566,7 +643,7 @@ impl GenerationCtx {
         let (choices, weights): (Vec<fn(&GenerationCtx) -> Result<Statement>>, Vec<usize>) =
             choices_and_weights.into_iter().unzip();
 
-        let statement = self
+        let mut statement = self
             .make_choice_weighted(
                 choices.into_iter(),
                 WeightedIndex::new(weights).expect("weights are valid"),
@@ -574,6 +651,10 @@ impl GenerationCtx {
             )
             .expect("deadend");
 
+        if let Statement::Assign(_, rvalue) = &mut statement {
+            self.hoist_consts(rvalue);
+        }
+
         // We're generating a new var
         if matches!(statement, Statement::Nop) {
             let ty = self
@@ -588,6 +669,23 @@ impl GenerationCtx {
         self.post_generation(&statement);
         self.current_bb_mut().insert_statement(statement);
     }
+
+    /// Turns some of the inline const blocks in an rvalue into uses of const items
+    fn hoist_consts(&mut self, rvalue: &mut Rvalue) {
+        let operands = match rvalue {
+            Rvalue::Use(operand) => std::slice::from_mut(operand),
+            Rvalue::Aggregate(_, operands) => operands.as_raw_slice_mut(),
+            _ => return,
+        };
+        for operand in operands {
+            if let Operand::Compound(CompoundConst::Block(value)) = operand {
+                if self.rng.get_mut().gen_bool(0.5) {
+                    let item = self.program.push_const(value.clone());
+                    *operand = Operand::Compound(CompoundConst::Item(item, value.clone()));
+                }
+            }
+        }
+    }
 }
 
 enum TerminatorParams {
@@ -605,6 +703,10 @@ enum TerminatorParams {
         args: Vec<Operand>,
         return_place: Place,
     },
+    ForeignCall {
+        args: Vec<Operand>,
+        return_place: Place,
+    },
 }
 // Terminator
 impl GenerationCtx {
@@ -760,7 +862,7 @@ impl GenerationCtx {
             })?;
 
         // TODO: if return place has a ref, don't generate 0 argument as this can never be valid
-        let args_count = self.rng.borrow_mut().gen_range(0..=MAX_ARGS_COUNT);
+        let args_count = self.rng.borrow_mut().gen_range(0..=self.max_args_count());
         let mut selector = PlaceSelector::for_argument(self.tcx.clone())
             .having_moved(return_place.to_place_index(&self.pt).unwrap());
         let mut args = vec![];
@@ -812,13 +914,46 @@ impl GenerationCtx {
         });
 
         let public = self.rng.get_mut().gen_bool(0.5);
+        let closure = self
+            .rng
+            .get_mut()
+            .gen_bool(CLOSURE_CALL_PROBABILITY)
+            .then(|| {
+                let capture = *[CaptureMode::None, CaptureMode::ByRef, CaptureMode::ByMove]
+                    .choose(self.rng.get_mut())
+                    .unwrap();
+                // Either call it directly, or pass it to a generic function calling it
+                // through a trait it implements
+                let params: Vec<TyId> = args
+                    .iter()
+                    .map(|arg| arg.ty(self.current_decls(), &self.tcx))
+                    .collect();
+                let kind = capture.closure_trait(&params, &self.tcx);
+                let via = self.rng.get_mut().gen_bool(0.5).then(|| {
+                    FnTrait::ALL
+                        .into_iter()
+                        .filter(|via| kind.callable_through(*via))
+                        .choose(self.rng.get_mut())
+                        .unwrap()
+                });
+                (capture, via)
+            });
+        // A worker is joined as soon as it is spawned, so it never runs concurrently with
+        // anything and the output stays deterministic
+        let worker = closure.is_none()
+            && self.threads
+            && self.rng.get_mut().gen_bool(WORKER_CALL_PROBABILITY);
 
         // We don't know the name of the new function here, so we save the current cursor and write the terminator after frame switch
         let caller_cursor = self.cursor;
         let new_fn = self.enter_new_fn(&args, &return_place, public);
         self.program.functions[caller_cursor.function].basic_blocks[caller_cursor.basic_block]
             .set_terminator(Terminator::Call {
-                callee: Callee::Generated(new_fn),
+                callee: match closure {
+                    Some((capture, via)) => Callee::Closure(new_fn, capture, via),
+                    None if worker => Callee::Worker(new_fn),
+                    None => Callee::Generated(new_fn),
+                },
                 destination: return_place,
                 target: target_bb,
                 args,
@@ -846,6 +981,86 @@ impl GenerationCtx {
         })
     }
 
+    fn generate_foreign_call_params(&self) -> Result<TerminatorParams> {
+        let (return_places, weights) = PlaceSelector::for_lhs(self.tcx.clone())
+            .of_ty(TyCtxt::U64)
+            .into_weighted(&self.pt)
+            .ok_or(SelectionError::Exhausted)?;
+
+        let return_place = self.make_choice_weighted(
+            return_places.into_iter(),
+            weights,
+            |ppath: crate::pgraph::PlacePath| Result::Ok(ppath.to_place(&self.pt)),
+        )?;
+
+        // The callee hashes the bits of its arguments, and NaNs coming out of arithmetics
+        // don't have deterministic bits, so floats are only passed as literals, and not in
+        // structs
+        let c_tys: Vec<TyId> = self
+            .tcx
+            .indices()
+            .filter(|ty| {
+                ty.c_type(&self.tcx).is_some()
+                    && !(ty.kind(&self.tcx).is_adt()
+                        && ty.contains(&self.tcx, |_, ty| ty == TyCtxt::F32 || ty == TyCtxt::F64))
+            })
+            .collect();
+        let args_count = self.rng.borrow_mut().gen_range(1..=self.max_args_count());
+        let mut args = vec![];
+        for _ in 0..args_count {
+            let ty = *c_tys.choose(&mut *self.rng.borrow_mut()).unwrap();
+            let arg = if matches!(ty.kind(&self.tcx), TyKind::Float(..)) {
+                self.rng
+                    .borrow_mut()
+                    .gen_literal(ty, &self.tcx)
+                    .map(Operand::Constant)
+                    .ok_or(SelectionError::Exhausted)
+            } else {
+                self.choose_operand(&[ty], &return_place)
+            };
+            if let Ok(arg) = arg {
+                args.push(arg);
+            }
+        }
+        if args.is_empty() {
+            return Err(SelectionError::Exhausted);
+        }
+
+        Ok(TerminatorParams::ForeignCall { args, return_place })
+    }
+
+    fn add_foreign_call(&mut self, args: Vec<Operand>, return_place: Place) {
+        let arg_tys: Vec<TyId> = args
+            .iter()
+            .map(|arg| arg.ty(self.current_decls(), &self.tcx))
+            .collect();
+        let callee = Callee::Foreign(self.program.foreign_fn(&arg_tys));
+
+        let ret = return_place.to_place_index(&self.pt).expect("place exists");
+        self.pt.mark_place_init(ret);
+        self.pt
+            .place_written(&return_place, self.pt.accessing_tag(&return_place));
+        for arg in &args {
+            if let Operand::Move(place) = arg {
+                self.pt
+                    .mark_place_moved(place.to_place_index(&self.pt).expect("arg exists"));
+            }
+        }
+        // The checksum is computed on the other side of the boundary
+        if !matches!(callee, Callee::Atomic(..) | Callee::ConcurrentAtomic(..)) {
+            self.pt.assign_literal(ret, None);
+        }
+
+        let bb = self.add_new_bb();
+        self.current_bb_mut().set_terminator(Terminator::Call {
+            callee,
+            destination: return_place,
+            target: bb,
+            args,
+        });
+        self.enter_bb(bb);
+    }
+
     fn add_intrinsic_call(&mut self, callee: Callee, args: Vec<Operand>, return_place: Place) {
         let ret = return_place.to_place_index(&self.pt).expect("place exists");
         let arg_places: Vec<PlaceOperand> = args
@@ -856,11 +1071,37 @@ impl GenerationCtx {
         self.pt.mark_place_init(ret);
         self.pt
             .place_written(&return_place, self.pt.accessing_tag(&return_place));
-        let Callee::Intrinsic(intrinsic_name) = callee else {
-            panic!("callee is intrinsic");
+        let intrinsic_name = match callee {
+            Callee::Intrinsic(name) | Callee::AtomicIntrinsic(name, _) => name,
+            // Methods of atomic types are told apart by the method instead
+            Callee::Atomic(..) | Callee::ConcurrentAtomic(..) => "",
+            _ => panic!("callee is intrinsic"),
         };
 
-        if intrinsic_name == ArithOffset.name() {
+        if let Callee::Atomic(_, method, _) | Callee::ConcurrentAtomic(_, method, _) = callee {
+            let mut pointee = args[0].place().expect("first operand is pointer").clone();
+            pointee.project(ProjectionElem::Deref);
+            // Everything but a store returns the old value of the pointee
+            if method != AtomicMethod::Store {
+                self.pt.copy_place(ret, &pointee);
+            }
+            if method != AtomicMethod::Load {
+                self.pt
+                    .place_written(&pointee, self.pt.accessing_tag(&pointee));
+                // Concurrent swaps all store the same value, and the rest are only ever
+                // concurrent on integers, so we don't need to know what they store
+                match (method, arg_places[1]) {
+                    (
+                        AtomicMethod::Store | AtomicMethod::Swap,
+                        PlaceOperand::Copy(p) | PlaceOperand::Move(p),
+                    ) => self.pt.copy_place(&pointee, p),
+                    (AtomicMethod::Store | AtomicMethod::Swap, PlaceOperand::Constant(lit)) => {
+                        self.pt.assign_literal(&pointee, Some(lit))
+                    }
+                    _ => self.pt.assign_literal(&pointee, None),
+                }
+            }
+        } else if intrinsic_name == ArithOffset.name() {
             let PlaceOperand::Copy(ptr) = arg_places[0] else {
                 unreachable!("first operand is pointer");
             };
@@ -883,6 +1124,19 @@ impl GenerationCtx {
             if let PlaceOperand::Copy(src) | PlaceOperand::Move(src) = arg_places[0] {
                 self.pt.transmute_place(ret, src)
             }
+        } else if intrinsic_name == AtomicStore.name() || AtomicRmw::is_rmw(intrinsic_name) {
+            let mut pointee = args[0].place().expect("first operand is pointer").clone();
+            pointee.project(ProjectionElem::Deref);
+            let new_val = match arg_places[1] {
+                _ if intrinsic_name != AtomicStore.name() && intrinsic_name != "atomic_xchg" => {
+                    None
+                }
+                PlaceOperand::Copy(p) | PlaceOperand::Move(p) => self.pt.known_val(p).copied(),
+                PlaceOperand::Constant(lit) => Some(lit),
+            };
+            self.pt
+                .place_written(&pointee, self.pt.accessing_tag(&pointee));
+            self.pt.assign_literal(&pointee, new_val);
         }
 
         for op in arg_places {
@@ -891,7 +1145,9 @@ impl GenerationCtx {
             }
         }
 
-        self.pt.assign_literal(ret, None);
+        if !matches!(callee, Callee::Atomic(..) | Callee::ConcurrentAtomic(..)) {
+            self.pt.assign_literal(ret, None);
+        }
 
         let bb = self.add_new_bb();
         self.current_bb_mut().set_terminator(Terminator::Call {
@@ -935,7 +1191,16 @@ impl GenerationCtx {
             (Self::generate_intrinsic_call_params, 20),
             (
                 Self::generate_call_params,
-                MAX_FN_COUNT.saturating_sub(self.program.functions.len()),
+                MAX_FN_COUNT.saturating_sub(self.program.functions.len())
+                    * if self.abi_stress {
+                        ABI_STRESS_CALL_WEIGHT_FACTOR
+                    } else {
+                        1
+                    },
+            ),
+            (
+                Self::generate_foreign_call_params,
+                if self.c_ffi { FOREIGN_CALL_WEIGHT } else { 0 },
             ),
         ];
         let (choices, weights): (
@@ -955,6 +1220,9 @@ impl GenerationCtx {
                 args,
                 return_place,
             } => self.add_intrinsic_call(callee, args, return_place),
+            TerminatorParams::ForeignCall { args, return_place } => {
+                self.add_foreign_call(args, return_place)
+            }
             TerminatorParams::SwitchInt {
                 discriminator,
                 discriminator_value,
@@ -1063,6 +1331,7 @@ impl GenerationCtx {
             .collect::<Vec<_>>();
         let return_ty = return_dest.ty(self.current_decls(), &self.tcx);
         let mut body = Body::new(&args_ty, return_ty, public);
+        body.abi = self.choose_abi();
 
         let starting_bb = body.new_basic_block(BasicBlockData::new());
         let new_fn = self.program.push_fn(body);
@@ -1090,6 +1359,7 @@ impl GenerationCtx {
     fn enter_fn0(&mut self, args_ty: &[TyId], return_ty: TyId, args: &[Literal]) {
         self.program.set_entry_args(args);
         let mut body = Body::new(args_ty, return_ty, true);
+        body.abi = self.choose_abi();
 
         let starting_bb = body.new_basic_block(BasicBlockData::new());
         let new_fn = self.program.push_fn(body);
@@ -1110,6 +1380,26 @@ impl GenerationCtx {
             .enter_fn0(&self.program.functions[self.cursor.function]);
     }
 
+    fn choose_abi(&mut self) -> Abi {
+        let rng = self.rng.get_mut();
+        if !self.abi_stress && !rng.gen_bool(FOREIGN_ABI_PROBABILITY) {
+            return Abi::Rust;
+        }
+        let mut abis = vec![Abi::C, Abi::System];
+        if cfg!(target_arch = "x86_64") {
+            abis.extend([Abi::SysV64, Abi::Win64]);
+        }
+        *abis.choose(rng).unwrap()
+    }
+
+    fn max_args_count(&self) -> usize {
+        if self.abi_stress {
+            ABI_STRESS_MAX_ARGS_COUNT
+        } else {
+            MAX_ARGS_COUNT
+        }
+    }
+
     // Returns from the currnt function. Returns false if we're returning from
     // fn0. True otherwise
     fn exit_fn(&mut self) -> bool {
@@ -1182,9 +1472,16 @@ impl GenerationCtx {
         }
     }
 
-    pub fn new(seed: u64, debug_dump: bool) -> Self {
+    pub fn new(
+        seed: u64,
+        debug_dump: bool,
+        abi_stress: bool,
+        c_ffi: bool,
+        split: bool,
+        threads: bool,
+    ) -> Self {
         let rng = RefCell::new(Box::new(rand::rngs::SmallRng::seed_from_u64(seed)));
-        let tcx = Rc::new(seed_tys(&mut *rng.borrow_mut()));
+        let tcx = Rc::new(seed_tys(&mut *rng.borrow_mut(), abi_stress || c_ffi));
         let ty_weights = TySelect::new(&tcx);
         // TODO: don't zero-initialize current_function and current_bb
         Self {
@@ -1199,6 +1496,10 @@ impl GenerationCtx {
                 basic_block: BasicBlock::new(0),
             },
             saved_ctx: vec![],
+            abi_stress,
+            c_ffi,
+            split,
+            threads,
         }
     }
 
@@ -1234,7 +1535,8 @@ impl GenerationCtx {
 
     fn generate_fn0(&mut self) {
         self.save_ctx();
-        let args_count = self.rng.get_mut().gen_range(0..=MAX_ARGS_COUNT);
+        let max_args_count = self.max_args_count();
+        let args_count = self.rng.get_mut().gen_range(0..=max_args_count);
         let arg_tys: Vec<TyId> = self
             .tcx
             .indices()
@@ -1293,12 +1595,31 @@ impl GenerationCtx {
             }
         }
 
+        if self.split {
+            self.split_crates();
+        }
+
         // Remove the Rc to self.tcx, so we can own it
         drop(self.pt);
 
         (self.program, Rc::into_inner(self.tcx).unwrap())
     }
 
+    /// Moves the later functions into the library crate, which only ever calls
+    /// functions created after it
+    fn split_crates(&mut self) {
+        let rng = self.rng.get_mut();
+        let fn_count = self.program.functions.len();
+        // fn0 is called from main, so it stays in the binary crate
+        let split = Function::new(rng.gen_range(1..cmp::max(fn_count, 2)));
+        for (func, body) in self.program.functions.iter_mut_enumerated() {
+            if func >= split {
+                body.inline = rng.gen_bool(CROSS_CRATE_INLINE_PROBABILITY);
+            }
+        }
+        self.program.split = Some(split);
+    }
+
     fn post_generation(&mut self, stmt: &Statement) {
         // We must evaluate the places first before updating any PlaceGraph state,
         // as the updates may affect projections
@@ -1366,6 +1687,11 @@ impl GenerationCtx {
                                 pt.assign_literal(lhs, Some(*lit));
                             }));
                         }
+                        Operand::Compound(c) => {
+                            actions.push(Box::new(move |pt| {
+                                pt.assign_const(lhs, c.value());
+                            }));
+                        }
                     },
                     agg @ Rvalue::Aggregate(agg_kind, ..) => {
                         if self.pt.ty(lhs).kind(&self.tcx).is_enum() {
@@ -1389,6 +1715,11 @@ impl GenerationCtx {
                                         pt.assign_literal(target, Some(*lit));
                                     }));
                                 }
+                                Operand::Compound(c) => {
+                                    actions.push(Box::new(move |pt| {
+                                        pt.assign_const(target, c.value());
+                                    }));
+                                }
                             }
                         }
                     }
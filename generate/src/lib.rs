@@ -0,0 +1,173 @@
+#![feature(exact_size_is_empty)]
+#![feature(iter_advance_by)]
+#![feature(variant_count)]
+#![feature(test)]
+#![feature(let_chains)]
+#![feature(try_blocks)]
+#![feature(box_patterns)]
+
+mod generation;
+mod literal;
+mod mem;
+mod pgraph;
+mod place_select;
+mod ty;
+
+use mir::{serialize::CallSynatx, syntax::Program, tyctxt::TyCtxt};
+
+use crate::generation::GenerationCtx;
+
+/// What to generate, as set by the flags of `generate`
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Print values instead of hashing them (slow)
+    pub debug_dump: bool,
+    pub call_syntax: CallSynatx,
+    pub abi_stress: bool,
+    pub c_ffi: bool,
+    pub split: bool,
+    pub threads: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            debug_dump: false,
+            call_syntax: CallSynatx::V4,
+            abi_stress: false,
+            c_ffi: false,
+            split: false,
+            threads: false,
+        }
+    }
+}
+
+/// The program generated from the seed, and its types
+pub fn generate_program(seed: u64, options: &Options) -> (Program, TyCtxt) {
+    let genctxt = GenerationCtx::new(
+        seed,
+        options.debug_dump,
+        options.abi_stress,
+        options.c_ffi,
+        options.split,
+        options.threads,
+    );
+    let (program, tcx) = genctxt.generate();
+    // A generator bug is much easier to track down here than from a compiler error
+    if cfg!(debug_assertions) {
+        mir::validate::validate(&program, &tcx)
+            .unwrap_or_else(|err| panic!("seed {seed} generated an invalid program: {err}"));
+    }
+    (program, tcx)
+}
+
+/// The source code of a generated program
+pub fn serialize(program: &Program, tcx: &TyCtxt, call_syntax: CallSynatx) -> String {
+    let mut code = program.serialize(tcx, call_syntax);
+    code.push('\n');
+    // A split program defines its ADTs in the library crate
+    if program.split.is_none() {
+        code.push_str(&tcx.serialize());
+        code.push('\n');
+    }
+    code
+}
+
+/// The source code of the program generated from the seed
+pub fn generate(seed: u64, options: &Options) -> String {
+    let (program, tcx) = generate_program(seed, options);
+    serialize(&program, &tcx, options.call_syntax)
+}
+
+#[cfg(test)]
+mod tests {
+    use mir::{ast, interpret, parse::parse, serialize::CallSynatx, syntax::Abi};
+
+    use crate::{generate_program, serialize, Options};
+
+    /// Far more statements and terminators than a generated program runs, since each
+    /// function is called at most once
+    const STEP_LIMIT: usize = 1_000_000;
+
+    #[test]
+    fn parse_generated() {
+        let syntaxes = [
+            CallSynatx::V1,
+            CallSynatx::V2,
+            CallSynatx::V3,
+            CallSynatx::V4,
+        ];
+        for (seed, call_syntax) in syntaxes.into_iter().enumerate() {
+            let options = Options {
+                debug_dump: seed % 2 == 1,
+                call_syntax,
+                abi_stress: true,
+                c_ffi: true,
+                split: seed < 2,
+                threads: true,
+            };
+            let (generated, tcx) = generate_program(seed as u64, &options);
+            let code = serialize(&generated, &tcx, call_syntax);
+            let (program, tcx) = parse(&code).unwrap_or_else(|e| panic!("seed {seed}: {e}"));
+            assert_eq!(program.functions.len(), generated.functions.len());
+            assert_eq!(program.split, generated.split);
+            assert_eq!(program.use_debug_dumper, options.debug_dump);
+            assert_eq!(serialize(&program, &tcx, call_syntax), code, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn ast_generated() {
+        let options = Options {
+            c_ffi: true,
+            split: true,
+            ..Default::default()
+        };
+        let (program, tcx) = generate_program(0, &options);
+        let code = serialize(&program, &tcx, options.call_syntax);
+
+        let json = ast::to_json(&program, &tcx);
+        let (program, tcx) = ast::from_json(&json).unwrap();
+        assert_eq!(serialize(&program, &tcx, options.call_syntax), code);
+        assert_eq!(ast::to_json(&program, &tcx), json);
+
+        let (program, tcx) = ast::from_binary(&ast::to_binary(&program, &tcx)).unwrap();
+        assert_eq!(serialize(&program, &tcx, options.call_syntax), code);
+    }
+
+    #[test]
+    fn abi_stress() {
+        let options = Options {
+            abi_stress: true,
+            ..Default::default()
+        };
+        let (program, tcx) = generate_program(0, &options);
+        assert!(program.functions.iter().all(|body| body.abi != Abi::Rust));
+        assert!(tcx
+            .indices()
+            .any(|ty| ty.kind(&tcx).is_adt() && tcx.meta(ty).repr_c));
+
+        // Without ABI stress, ADTs are never repr(C)
+        let (_, tcx) = generate_program(0, &Options::default());
+        assert!(tcx
+            .indices()
+            .all(|ty| !ty.kind(&tcx).is_adt() || !tcx.meta(ty).repr_c));
+    }
+
+    #[test]
+    fn interpret_generated() {
+        for seed in 0..4 {
+            let options = Options {
+                debug_dump: seed % 2 == 1,
+                abi_stress: seed >= 2,
+                threads: seed % 2 == 0,
+                ..Default::default()
+            };
+            let (program, tcx) = generate_program(seed, &options);
+            // A generated program runs to completion without UB
+            if let Err(err) = interpret::run(&program, &tcx, STEP_LIMIT) {
+                panic!("seed {seed}: {err}");
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use index_vec::IndexVec;
 use mir::{
-    syntax::{FloatTy, IntTy, Literal, TyId, TyKind, UintTy},
+    syntax::{
+        AggregateKind, ConstValue, FloatTy, IntTy, Literal, TyId, TyKind, UintTy, VariantIdx,
+    },
     tyctxt::TyCtxt,
 };
 use rand::{seq::SliceRandom, Rng, RngCore};
@@ -74,6 +77,53 @@ pub trait GenLiteral: Rng {
         };
         Some(lit)
     }
+    /// Whether a value of this type can be written out in full as a constant expression
+    fn is_const_constructible(ty: TyId, tcx: &TyCtxt) -> bool {
+        match ty.kind(tcx) {
+            TyKind::Unit => true,
+            TyKind::Tuple(tys) => tys.iter().all(|ty| Self::is_const_constructible(*ty, tcx)),
+            TyKind::Array(ty, _) => Self::is_const_constructible(*ty, tcx),
+            TyKind::Adt(adt) => adt
+                .variants
+                .iter()
+                .flat_map(|variant| variant.fields.iter())
+                .all(|ty| Self::is_const_constructible(*ty, tcx)),
+            _ => Self::is_literalble(ty, tcx),
+        }
+    }
+    fn gen_const(&mut self, ty: TyId, tcx: &TyCtxt) -> Option<ConstValue> {
+        let value = match ty.kind(tcx) {
+            TyKind::Unit => ConstValue::Aggregate(ty, AggregateKind::Tuple, IndexVec::new()),
+            TyKind::Tuple(tys) => ConstValue::Aggregate(
+                ty,
+                AggregateKind::Tuple,
+                tys.iter()
+                    .map(|ty| self.gen_const(*ty, tcx))
+                    .collect::<Option<_>>()?,
+            ),
+            TyKind::Array(elem, len) => ConstValue::Aggregate(
+                ty,
+                AggregateKind::Array(*elem),
+                (0..*len)
+                    .map(|_| self.gen_const(*elem, tcx))
+                    .collect::<Option<_>>()?,
+            ),
+            TyKind::Adt(adt) => {
+                let variant = VariantIdx::new(self.gen_range(0..adt.variants.len()));
+                ConstValue::Aggregate(
+                    ty,
+                    AggregateKind::Adt(ty, variant),
+                    adt.variants[variant]
+                        .fields
+                        .iter()
+                        .map(|ty| self.gen_const(*ty, tcx))
+                        .collect::<Option<_>>()?,
+                )
+            }
+            _ => ConstValue::Scalar(self.gen_literal(ty, tcx)?),
+        };
+        Some(value)
+    }
     fn gen_literal_non_zero(&mut self, ty: TyId, tcx: &TyCtxt) -> Option<Literal> {
         self.gen_literal(ty, tcx).map(|lit| match lit {
             Literal::Uint(n, t) => {
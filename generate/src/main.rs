@@ -1,25 +1,12 @@
-#![feature(exact_size_is_empty)]
-#![feature(iter_advance_by)]
-#![feature(variant_count)]
-#![feature(test)]
-#![feature(let_chains)]
-#![feature(try_blocks)]
-#![feature(box_patterns)]
+use std::{
+    io::{self, Write},
+    time::Instant,
+};
 
-mod generation;
-mod literal;
-mod mem;
-mod place_select;
-mod pgraph;
-mod ty;
-
-use std::time::Instant;
-
-use clap::{arg, command, value_parser, Arg};
+use clap::{arg, command, value_parser, Arg, ArgAction};
+use generate::{generate_program, serialize, Options};
 use log::{debug, info};
 
-use crate::generation::GenerationCtx;
-
 fn main() {
     env_logger::init();
     let matches = command!()
@@ -30,6 +17,29 @@ fn main() {
                 .value_parser(["v1", "v2", "v3", "v4"])
                 .default_value("v4")
                 .help("switch between different versions of Call syntaxes"),
+            Arg::new("abi-stress")
+                .long("abi-stress")
+                .action(ArgAction::SetTrue)
+                .help("generate functions with foreign ABIs and many by-value arguments"),
+            Arg::new("c-ffi")
+                .long("c-ffi")
+                .action(ArgAction::SetTrue)
+                .help("generate calls into an embedded C translation unit"),
+            Arg::new("split")
+                .long("split")
+                .action(ArgAction::SetTrue)
+                .help("generate a program that can also be built as a library and a binary crate"),
+            Arg::new("threads")
+                .long("threads")
+                .action(ArgAction::SetTrue)
+                .help("run some functions and atomic read-modify-writes on scoped threads"),
+            Arg::new("emit")
+                .long("emit")
+                .value_parser(["rust", "ast-json", "ast-bin"])
+                .default_value("rust")
+                .help(
+                    "print the program as Rust source, or as its MIR in JSON or a binary encoding",
+                ),
             arg!(<seed> "generation seed").value_parser(value_parser!(u64)),
         ])
         .get_matches();
@@ -40,11 +50,23 @@ fn main() {
     let debug_dump = matches.get_one::<bool>("debug").copied().unwrap_or(false);
     info!("Generating a program with seed {seed}");
     let call_syntax = matches.get_one::<String>("call-syntax").unwrap();
-    let genctxt = GenerationCtx::new(seed, debug_dump);
+    let options = Options {
+        debug_dump,
+        call_syntax: call_syntax.as_str().into(),
+        abi_stress: matches.get_flag("abi-stress"),
+        c_ffi: matches.get_flag("c-ffi"),
+        split: matches.get_flag("split"),
+        threads: matches.get_flag("threads"),
+    };
     let time = Instant::now();
-    let (program, tcx) = genctxt.generate();
-    println!("{}", program.serialize(&tcx, call_syntax.as_str().into()));
-    println!("{}", tcx.serialize());
+    let (program, tcx) = generate_program(seed, &options);
+    match matches.get_one::<String>("emit").unwrap().as_str() {
+        "ast-json" => println!("{}", mir::ast::to_json(&program, &tcx)),
+        "ast-bin" => io::stdout()
+            .write_all(&mir::ast::to_binary(&program, &tcx))
+            .expect("can write to stdout"),
+        _ => print!("{}", serialize(&program, &tcx, options.call_syntax)),
+    }
     let dur = time.elapsed();
     debug!("took {}s to generate", dur.as_secs_f32());
 }
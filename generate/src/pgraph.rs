@@ -9,8 +9,8 @@ use bimap::BiHashMap;
 use index_vec::IndexVec;
 use mir::{
     syntax::{
-        Body, FieldIdx, Literal, Local, Mutability, Operand, Place, ProjectionElem, Rvalue, TyId,
-        TyKind, UintTy, VariantIdx,
+        AggregateKind, Body, ConstValue, FieldIdx, Literal, Local, Mutability, Operand, Place,
+        ProjectionElem, Rvalue, TyId, TyKind, UintTy, VariantIdx,
     },
     tyctxt::TyCtxt,
 };
@@ -87,6 +87,9 @@ impl PlaceOperand {
                 PlaceOperand::Move(index)
             }
             Operand::Constant(lit) => PlaceOperand::Constant(*lit),
+            Operand::Compound(..) => {
+                unreachable!("compound constants are not chosen as arguments")
+            }
         }
     }
 }
@@ -1059,6 +1062,49 @@ impl PlaceGraph {
         }
     }
 
+    /// Records the known values of a constant assigned to a place. The place must already be
+    /// marked as init.
+    pub fn assign_const(&mut self, p: impl ToPlaceIndex, val: &ConstValue) {
+        let p = p.to_place_index(self).expect("place exists");
+        match val {
+            ConstValue::Scalar(lit) => self.assign_literal(p, Some(*lit)),
+            ConstValue::Aggregate(_, kind, fields) => {
+                self.assign_literal(p, None);
+                if let AggregateKind::Adt(ty, vid) = kind
+                    && ty.kind(&self.tcx).is_enum()
+                {
+                    // This uninits the fields, so they need to be marked as init again
+                    self.assign_discriminant(p, Some(*vid));
+                }
+                for (fid, field) in fields.iter_enumerated() {
+                    let proj = match kind {
+                        AggregateKind::Array(..) => ProjectionElem::ConstantIndex {
+                            offset: fid.index() as u64,
+                        },
+                        AggregateKind::Tuple => ProjectionElem::TupleField(fid),
+                        AggregateKind::Adt(ty, vid) => {
+                            let TyKind::Adt(adt) = ty.kind(&self.tcx) else {
+                                panic!("not an adt")
+                            };
+                            if adt.is_enum() {
+                                ProjectionElem::DowncastField(
+                                    *vid,
+                                    fid,
+                                    adt.variants[*vid].fields[fid],
+                                )
+                            } else {
+                                ProjectionElem::Field(fid)
+                            }
+                        }
+                    };
+                    let target = self.project_from_node(p, proj).expect("field exists");
+                    self.mark_place_init(target);
+                    self.assign_const(target, field);
+                }
+            }
+        }
+    }
+
     /// Return destinations of all currently active frames
     pub fn return_dest_stack(&self) -> impl Iterator<Item = PlaceIndex> + '_ {
         // Skip fn0 which is a dummy
@@ -1462,7 +1508,7 @@ impl HasComplexity for Operand {
     fn complexity(&self, pt: &PlaceGraph) -> usize {
         match self {
             Operand::Copy(place) | Operand::Move(place) => place.complexity(pt),
-            Operand::Constant(_) => 1,
+            Operand::Constant(_) | Operand::Compound(_) => 1,
         }
     }
 }
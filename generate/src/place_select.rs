@@ -386,7 +386,7 @@ mod tests {
     use super::PlaceSelector;
 
     fn build_pt(rng: &mut impl Rng) -> (PlaceGraph, Rc<TyCtxt>) {
-        let tcx = Rc::new(seed_tys(rng));
+        let tcx = Rc::new(seed_tys(rng, false));
         let mut pt = PlaceGraph::new(tcx.clone());
         let ty_weights = TySelect::new(&tcx);
         for i in 0..=32 {
@@ -160,7 +160,7 @@ fn new_composite(tcx: &mut TyCtxt, rng: &mut impl Rng) {
     }
 }
 
-fn new_adt(tcx: &mut TyCtxt, rng: &mut impl Rng) {
+fn new_adt(tcx: &mut TyCtxt, rng: &mut impl Rng, repr_c: bool) {
     // TODO: recursive types
     let variant_count = rng.gen_range(1..=ADT_MAX_VARIANTS);
 
@@ -184,12 +184,16 @@ fn new_adt(tcx: &mut TyCtxt, rng: &mut impl Rng) {
         false
     };
 
-    let meta = AdtMeta { copy };
+    // Only drawn when asked for, so that other programs stay the same for a seed
+    let repr_c = repr_c && rng.gen_bool(0.5);
+
+    let meta = AdtMeta { copy, repr_c };
 
     tcx.push_adt(adt, meta);
 }
 
-pub fn seed_tys<R: Rng>(rng: &mut R) -> TyCtxt {
+/// Seeds the type context. `repr_c` allows ADTs to be `#[repr(C)]`
+pub fn seed_tys<R: Rng>(rng: &mut R, repr_c: bool) -> TyCtxt {
     // Seed with primitives
     let mut tcx: TyCtxt = TyCtxt::from_primitives();
 
@@ -206,7 +210,7 @@ pub fn seed_tys<R: Rng>(rng: &mut R) -> TyCtxt {
     choices.shuffle(rng);
     for choice in choices {
         match choice {
-            Kind::Adt => new_adt(&mut tcx, rng),
+            Kind::Adt => new_adt(&mut tcx, rng, repr_c),
             Kind::Structural => new_composite(&mut tcx, rng),
         }
     }
@@ -225,8 +229,22 @@ mod tests {
     #[test]
     fn tys_unique() {
         let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
-        let tcx = seed_tys(&mut rng);
+        let tcx = seed_tys(&mut rng, false);
         let set: HashSet<TyId> = tcx.indices().collect();
         assert!(set.len() == tcx.len())
     }
+
+    #[test]
+    fn repr_c_opt_in() {
+        let adts = |repr_c| {
+            let mut rng = rand::rngs::SmallRng::seed_from_u64(0);
+            let tcx = seed_tys(&mut rng, repr_c);
+            tcx.indices()
+                .filter(|ty| ty.kind(&tcx).is_adt())
+                .map(|ty| tcx.meta(ty).repr_c)
+                .collect::<Vec<_>>()
+        };
+        assert!(adts(false).iter().all(|repr_c| !repr_c));
+        assert!(adts(true).iter().any(|repr_c| *repr_c));
+    }
 }
@@ -0,0 +1,492 @@
+//! Control flow and dataflow analyses over a `Body`. Control starts at `bb0`, and
+//! blocks it can't reach are left out of the orders and have no dominator.
+
+use index_vec::IndexVec;
+
+use crate::syntax::*;
+
+/// Whether something holds for each local of a body
+pub type LocalSet = IndexVec<Local, bool>;
+
+/// The blocks that can jump to each block, including unreachable ones
+pub fn predecessors(body: &Body) -> IndexVec<BasicBlock, Vec<BasicBlock>> {
+    let mut preds = IndexVec::from_vec(vec![vec![]; body.basic_blocks.len()]);
+    for (bb, data) in body.basic_blocks.iter_enumerated() {
+        for succ in data.terminator().successors() {
+            if !preds[succ].contains(&bb) {
+                preds[succ].push(bb);
+            }
+        }
+    }
+    preds
+}
+
+/// The reachable blocks, each before its successors unless the edge is a back edge
+pub fn reverse_postorder(body: &Body) -> Vec<BasicBlock> {
+    let mut visited = IndexVec::from_vec(vec![false; body.basic_blocks.len()]);
+    let mut postorder = vec![];
+    // Each block with the successors it has yet to visit
+    let mut stack = vec![];
+    if !body.basic_blocks.is_empty() {
+        let entry = BasicBlock::new(0);
+        visited[entry] = true;
+        stack.push((entry, body.basic_blocks[entry].terminator().successors()));
+    }
+    while let Some((bb, succs)) = stack.last_mut() {
+        match succs.pop() {
+            Some(succ) if !visited[succ] => {
+                visited[succ] = true;
+                let succs = body.basic_blocks[succ].terminator().successors();
+                stack.push((succ, succs));
+            }
+            Some(_) => {}
+            None => {
+                postorder.push(*bb);
+                stack.pop();
+            }
+        }
+    }
+    postorder.reverse();
+    postorder
+}
+
+/// Whether control can get to each block
+pub fn reachable(body: &Body) -> IndexVec<BasicBlock, bool> {
+    let mut reachable = IndexVec::from_vec(vec![false; body.basic_blocks.len()]);
+    for bb in reverse_postorder(body) {
+        reachable[bb] = true;
+    }
+    reachable
+}
+
+/// The dominator tree of the reachable blocks, as computed by Cooper, Harvey and
+/// Kennedy's "A Simple, Fast Dominance Algorithm"
+pub struct Dominators {
+    /// None for the entry block and unreachable blocks
+    idoms: IndexVec<BasicBlock, Option<BasicBlock>>,
+    /// The position of each reachable block in reverse postorder
+    order: IndexVec<BasicBlock, Option<usize>>,
+}
+
+impl Dominators {
+    pub fn new(body: &Body) -> Self {
+        let rpo = reverse_postorder(body);
+        let preds = predecessors(body);
+        let blocks = body.basic_blocks.len();
+        let mut order = IndexVec::from_vec(vec![None; blocks]);
+        for (i, bb) in rpo.iter().enumerate() {
+            order[*bb] = Some(i);
+        }
+
+        // The entry block is its own dominator while the tree is built
+        let mut idoms: IndexVec<BasicBlock, Option<BasicBlock>> =
+            IndexVec::from_vec(vec![None; blocks]);
+        let Some(&entry) = rpo.first() else {
+            return Dominators { idoms, order };
+        };
+        idoms[entry] = Some(entry);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &bb in &rpo[1..] {
+                let mut processed = preds[bb].iter().filter(|pred| idoms[**pred].is_some());
+                let first = *processed
+                    .next()
+                    .expect("a reachable block has a processed predecessor");
+                let idom = processed.fold(first, |idom, pred| {
+                    Self::intersect(&idoms, &order, idom, *pred)
+                });
+                if idoms[bb] != Some(idom) {
+                    idoms[bb] = Some(idom);
+                    changed = true;
+                }
+            }
+        }
+        idoms[entry] = None;
+        Dominators { idoms, order }
+    }
+
+    /// The closest common dominator of two blocks, while the entry is its own
+    fn intersect(
+        idoms: &IndexVec<BasicBlock, Option<BasicBlock>>,
+        order: &IndexVec<BasicBlock, Option<usize>>,
+        mut a: BasicBlock,
+        mut b: BasicBlock,
+    ) -> BasicBlock {
+        while a != b {
+            while order[a] > order[b] {
+                a = idoms[a].expect("processed");
+            }
+            while order[b] > order[a] {
+                b = idoms[b].expect("processed");
+            }
+        }
+        a
+    }
+
+    /// The closest strict dominator of the block, None for the entry block and
+    /// unreachable blocks
+    pub fn immediate_dominator(&self, bb: BasicBlock) -> Option<BasicBlock> {
+        self.idoms[bb]
+    }
+
+    /// Whether every path from the entry to `b` goes through `a`. A reachable block
+    /// dominates itself, and an unreachable one is dominated by nothing.
+    pub fn dominates(&self, a: BasicBlock, mut b: BasicBlock) -> bool {
+        if self.order[a].is_none() || self.order[b].is_none() {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idoms[b] {
+                Some(idom) => b = idom,
+                None => return false,
+            }
+        }
+    }
+
+    pub fn is_reachable(&self, bb: BasicBlock) -> bool {
+        self.order[bb].is_some()
+    }
+}
+
+/// Marks the locals the place reads as live. That's the local itself when it is
+/// dereferenced or read from, and any index.
+fn live_read(place: &Place, whole: bool, live: &mut LocalSet) {
+    for proj in place.projection() {
+        if let ProjectionElem::Index(local) = proj {
+            live[*local] = true;
+        }
+    }
+    if whole || place.projection().iter().any(ProjectionElem::is_deref) {
+        live[place.local()] = true;
+    }
+}
+
+fn live_operand(operand: &Operand, live: &mut LocalSet) {
+    if let Some(place) = operand.place() {
+        live_read(place, true, live);
+    }
+}
+
+/// A write to all of a local kills it, while a write to part of one leaves it as it is
+fn live_write(place: &Place, live: &mut LocalSet) {
+    if place.projection().is_empty() {
+        live[place.local()] = false;
+    } else {
+        live_read(place, false, live);
+    }
+}
+
+/// The locals live before the statement, given those live after it
+pub fn live_statement(statement: &Statement, live: &mut LocalSet) {
+    match statement {
+        Statement::Assign(place, rvalue) => {
+            live_write(place, live);
+            match rvalue {
+                Rvalue::Use(operand) | Rvalue::UnaryOp(_, operand) | Rvalue::Cast(operand, _) => {
+                    live_operand(operand, live)
+                }
+                Rvalue::BinaryOp(_, l, r) | Rvalue::CheckedBinaryOp(_, l, r) => {
+                    live_operand(l, live);
+                    live_operand(r, live);
+                }
+                Rvalue::Len(place) | Rvalue::Discriminant(place) => live_read(place, true, live),
+                Rvalue::AddressOf(_, place) | Rvalue::Ref(_, place) => {
+                    live_read(place, false, live)
+                }
+                Rvalue::Aggregate(_, operands) => {
+                    for operand in operands {
+                        live_operand(operand, live);
+                    }
+                }
+            }
+        }
+        Statement::StorageLive(local) | Statement::StorageDead(local) => live[*local] = false,
+        Statement::Deinit(place) | Statement::SetDiscriminant(place, _) => live_write(place, live),
+        Statement::Retag(place) => live_read(place, true, live),
+        Statement::Nop => {}
+    }
+}
+
+/// The locals live before the terminator, given those live after it
+pub fn live_terminator(terminator: &Terminator, live: &mut LocalSet) {
+    match terminator {
+        Terminator::Return => live[Local::new(0)] = true,
+        Terminator::Drop { place, .. } => live_read(place, true, live),
+        Terminator::Call {
+            destination, args, ..
+        } => {
+            live_write(destination, live);
+            for arg in args {
+                live_operand(arg, live);
+            }
+        }
+        Terminator::SwitchInt { discr, .. } => live_operand(discr, live),
+        Terminator::Hole | Terminator::Goto { .. } | Terminator::Unreachable => {}
+    }
+}
+
+/// The locals that may still be read at the start of each block. A local whose
+/// address is taken anywhere counts as live everywhere, as it can be read through
+/// the pointer.
+pub fn liveness(body: &Body) -> IndexVec<BasicBlock, LocalSet> {
+    let locals = body.local_decls.len();
+    let empty: LocalSet = IndexVec::from_vec(vec![false; locals]);
+    let mut live_in = IndexVec::from_vec(vec![empty.clone(); body.basic_blocks.len()]);
+
+    let mut borrowed = empty.clone();
+    for data in &body.basic_blocks {
+        for statement in data.statements() {
+            if let Statement::Assign(_, Rvalue::AddressOf(_, place) | Rvalue::Ref(_, place)) =
+                statement
+            {
+                borrowed[place.local()] = true;
+            }
+        }
+    }
+
+    // Liveness flows backwards, so blocks are visited in postorder, followed by the
+    // unreachable ones
+    let reachable = reachable(body);
+    let mut order = reverse_postorder(body);
+    order.reverse();
+    order.extend(body.basic_blocks.indices().filter(|bb| !reachable[*bb]));
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &bb in &order {
+            let data = &body.basic_blocks[bb];
+            let mut live = borrowed.clone();
+            for succ in data.terminator().successors() {
+                for (live, succ_live) in live.iter_mut().zip(&live_in[succ]) {
+                    *live |= *succ_live;
+                }
+            }
+            live_terminator(data.terminator(), &mut live);
+            for statement in data.statements().iter().rev() {
+                live_statement(statement, &mut live);
+            }
+            for (live, borrowed) in live.iter_mut().zip(&borrowed) {
+                *live |= *borrowed;
+            }
+            if live != live_in[bb] {
+                live_in[bb] = live;
+                changed = true;
+            }
+        }
+    }
+    live_in
+}
+
+/// Marks the local a write to the place initializes, unless it is behind a pointer
+fn init_write(place: &Place, init: &mut LocalSet) {
+    if !place.projection().iter().any(ProjectionElem::is_deref) {
+        init[place.local()] = true;
+    }
+}
+
+/// The locals initialized after the statement, given those initialized before it.
+/// Writing to any part of a local counts as initializing it.
+pub fn init_statement(statement: &Statement, init: &mut LocalSet) {
+    match statement {
+        Statement::Assign(place, rvalue) => {
+            // Writes through the pointer can't be tracked, so from here on the local
+            // counts as initialized
+            if let Rvalue::AddressOf(_, borrowed) | Rvalue::Ref(_, borrowed) = rvalue {
+                init_write(borrowed, init);
+            }
+            init_write(place, init);
+        }
+        Statement::StorageLive(local) | Statement::StorageDead(local) => init[*local] = false,
+        Statement::Deinit(place) => {
+            if place.projection().is_empty() {
+                init[place.local()] = false;
+            }
+        }
+        Statement::SetDiscriminant(place, _) => init_write(place, init),
+        Statement::Retag(_) | Statement::Nop => {}
+    }
+}
+
+/// The locals initialized after the terminator, given those initialized before it
+pub fn init_terminator(terminator: &Terminator, init: &mut LocalSet) {
+    if let Terminator::Call { destination, .. } = terminator {
+        init_write(destination, init);
+    }
+}
+
+/// The locals initialized on every path to the start of each block, starting with
+/// the parameters. None for unreachable blocks.
+pub fn initialized(body: &Body) -> IndexVec<BasicBlock, Option<LocalSet>> {
+    let mut init_in = IndexVec::from_vec(vec![None; body.basic_blocks.len()]);
+    let rpo = reverse_postorder(body);
+    let Some(&entry) = rpo.first() else {
+        return init_in;
+    };
+    let mut start: LocalSet = IndexVec::from_vec(vec![false; body.local_decls.len()]);
+    for arg in body.args_iter() {
+        start[arg] = true;
+    }
+    init_in[entry] = Some(start);
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &bb in &rpo {
+            let Some(mut init) = init_in[bb].clone() else {
+                continue;
+            };
+            let data = &body.basic_blocks[bb];
+            for statement in data.statements() {
+                init_statement(statement, &mut init);
+            }
+            init_terminator(data.terminator(), &mut init);
+            for succ in data.terminator().successors() {
+                match &mut init_in[succ] {
+                    Some(succ_init) => {
+                        for (succ_init, init) in succ_init.iter_mut().zip(&init) {
+                            if *succ_init && !*init {
+                                *succ_init = false;
+                                changed = true;
+                            }
+                        }
+                    }
+                    succ_init @ None => {
+                        *succ_init = Some(init.clone());
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+    init_in
+}
+
+#[cfg(test)]
+mod tests {
+    use index_vec::IndexVec;
+
+    use crate::{parse::parse, syntax::*};
+
+    use super::{initialized, liveness, predecessors, reachable, reverse_postorder, Dominators};
+
+    /// A diamond from bb0 to bb3, which loops on itself before returning, and an
+    /// unreachable bb5
+    fn body() -> Body {
+        let (mut program, _) = parse(
+            r#"
+            #[custom_mir(dialect = "runtime", phase = "initial")]
+            pub fn fn0(mut _1: i32) -> i32 {
+            mir! {
+            type RET = i32;
+            let _2: i32;
+            let _3: i32;
+            {
+            _2 = _1;
+            match _1 {
+            0 => bb1,
+            _ => bb2
+            }
+            }
+            bb1 = {
+            _3 = 1_i32;
+            Goto(bb3)
+            }
+            bb2 = {
+            Goto(bb3)
+            }
+            bb3 = {
+            RET = _2;
+            match _2 {
+            0 => bb3,
+            _ => bb4
+            }
+            }
+            bb4 = {
+            Return()
+            }
+            bb5 = {
+            _2 = _3;
+            Goto(bb4)
+            }
+            }
+            }
+            "#,
+        )
+        .unwrap();
+        program.functions.remove(Function::new(0))
+    }
+
+    fn bbs(indices: &[usize]) -> Vec<BasicBlock> {
+        indices.iter().copied().map(BasicBlock::new).collect()
+    }
+
+    /// The locals a set holds for
+    fn locals(set: &IndexVec<Local, bool>) -> Vec<usize> {
+        set.iter_enumerated()
+            .filter(|(_, holds)| **holds)
+            .map(|(local, _)| local.index())
+            .collect()
+    }
+
+    #[test]
+    fn control_flow() {
+        let body = body();
+        let preds = predecessors(&body);
+        assert_eq!(preds[BasicBlock::new(3)], bbs(&[1, 2, 3]));
+        assert_eq!(preds[BasicBlock::new(4)], bbs(&[3, 5]));
+
+        let rpo = reverse_postorder(&body);
+        assert_eq!(rpo.len(), 5);
+        assert_eq!(rpo[0], BasicBlock::new(0));
+        assert_eq!(rpo[3..], bbs(&[3, 4]));
+        let reachable = reachable(&body);
+        assert_eq!(reachable.iter().filter(|r| **r).count(), 5);
+        assert!(!reachable[BasicBlock::new(5)]);
+
+        let doms = Dominators::new(&body);
+        let idoms: Vec<Option<usize>> = body
+            .basic_blocks
+            .indices()
+            .map(|bb| doms.immediate_dominator(bb).map(|idom| idom.index()))
+            .collect();
+        assert_eq!(idoms, [None, Some(0), Some(0), Some(0), Some(3), None]);
+        let [bb0, bb1, bb3, bb4, bb5] = [0, 1, 3, 4, 5].map(BasicBlock::new);
+        assert!(doms.dominates(bb0, bb4));
+        assert!(doms.dominates(bb3, bb3));
+        assert!(!doms.dominates(bb1, bb3));
+        assert!(!doms.dominates(bb5, bb4));
+        assert!(!doms.is_reachable(bb5));
+    }
+
+    #[test]
+    fn dataflow() {
+        let body = body();
+        let live: Vec<Vec<usize>> = liveness(&body).iter().map(locals).collect();
+        // _3 is written in bb1 but only read from the unreachable bb5, and RET is read
+        // when the function returns
+        assert_eq!(
+            live,
+            [vec![1], vec![2], vec![2], vec![2], vec![0], vec![0, 3]]
+        );
+
+        let init: Vec<Option<Vec<usize>>> = initialized(&body)
+            .iter()
+            .map(|init| init.as_ref().map(locals))
+            .collect();
+        assert_eq!(
+            init,
+            [
+                Some(vec![1]),
+                Some(vec![1, 2]),
+                Some(vec![1, 2]),
+                Some(vec![1, 2]),
+                Some(vec![0, 1, 2]),
+                None
+            ]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! A program and its types as data instead of Rust source, for tools that want the
+//! structure: JSON to read, or a compact binary encoding to store many programs
+
+use bincode::Options;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    parse::intern,
+    syntax::{
+        AtomicMethod, AtomicOrdering, AtomicTy, Callee, CaptureMode, FnTrait, ForeignFunction,
+        Function, Program,
+    },
+    tyctxt::TyCtxt,
+};
+
+/// A program is meaningless without its types, so the two always travel together
+#[derive(Serialize)]
+struct Ast<'a> {
+    program: &'a Program,
+    tcx: &'a TyCtxt,
+}
+
+#[derive(Deserialize)]
+struct OwnedAst {
+    program: Program,
+    tcx: TyCtxt,
+}
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+}
+
+pub fn to_json(program: &Program, tcx: &TyCtxt) -> String {
+    serde_json::to_string(&Ast { program, tcx }).expect("programs serialize")
+}
+
+pub fn from_json(json: &str) -> serde_json::Result<(Program, TyCtxt)> {
+    let ast: OwnedAst = serde_json::from_str(json)?;
+    Ok((ast.program, ast.tcx))
+}
+
+pub fn to_binary(program: &Program, tcx: &TyCtxt) -> Vec<u8> {
+    bincode_options()
+        .serialize(&Ast { program, tcx })
+        .expect("programs serialize")
+}
+
+pub fn from_binary(bytes: &[u8]) -> bincode::Result<(Program, TyCtxt)> {
+    let ast: OwnedAst = bincode_options().deserialize(bytes)?;
+    Ok((ast.program, ast.tcx))
+}
+
+/// Floats are stored as their bits, as JSON has no NaNs or infinities
+pub(crate) mod float_bits {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(f: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        f.to_bits().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        u64::deserialize(deserializer).map(f64::from_bits)
+    }
+}
+
+/// `Callee` with owned names, which are interned to get the `&'static str`s back
+#[derive(Deserialize)]
+#[serde(rename = "Callee")]
+enum OwnedCallee {
+    Generated(Function),
+    Named(String),
+    Intrinsic(String),
+    AtomicIntrinsic(String, AtomicOrdering),
+    Closure(Function, CaptureMode, Option<FnTrait>),
+    Foreign(ForeignFunction),
+    Atomic(AtomicTy, AtomicMethod, AtomicOrdering),
+    ConcurrentAtomic(AtomicTy, AtomicMethod, AtomicOrdering),
+    Worker(Function),
+}
+
+impl<'de> Deserialize<'de> for Callee {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(match OwnedCallee::deserialize(deserializer)? {
+            OwnedCallee::Generated(func) => Callee::Generated(func),
+            OwnedCallee::Named(name) => Callee::Named(intern(&name)),
+            OwnedCallee::Intrinsic(name) => Callee::Intrinsic(intern(&name)),
+            OwnedCallee::AtomicIntrinsic(name, ordering) => {
+                Callee::AtomicIntrinsic(intern(&name), ordering)
+            }
+            OwnedCallee::Closure(func, capture, via) => Callee::Closure(func, capture, via),
+            OwnedCallee::Foreign(func) => Callee::Foreign(func),
+            OwnedCallee::Atomic(ty, method, ordering) => Callee::Atomic(ty, method, ordering),
+            OwnedCallee::ConcurrentAtomic(ty, method, ordering) => {
+                Callee::ConcurrentAtomic(ty, method, ordering)
+            }
+            OwnedCallee::Worker(func) => Callee::Worker(func),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use index_vec::IndexVec;
+
+    use crate::{serialize::CallSynatx, syntax::*, tyctxt::TyCtxt};
+
+    use super::{from_binary, from_json, to_binary, to_json};
+
+    #[test]
+    fn ast_round_trip() {
+        let tcx = TyCtxt::from_primitives();
+        let mut program = Program::new(false);
+        let mut body = Body::new(&[TyCtxt::F32], TyCtxt::F32, false);
+        let nan = Literal::Float(f64::from_bits(0x7ff8_0000_dead_beef), FloatTy::F32);
+        body.new_basic_block(BasicBlockData {
+            statements: vec![Statement::Assign(
+                Place::RETURN_SLOT,
+                Rvalue::Aggregate(
+                    AggregateKind::Tuple,
+                    IndexVec::from_vec(vec![Operand::Constant(Literal::Int(
+                        i128::MIN,
+                        IntTy::I128,
+                    ))]),
+                ),
+            )],
+            terminator: Terminator::Call {
+                callee: Callee::Intrinsic("fmaf32"),
+                destination: Place::RETURN_SLOT,
+                target: BasicBlock::new(1),
+                args: vec![Operand::Constant(nan)],
+            },
+        });
+        program.push_fn(body);
+        program.set_entry_args(&[Literal::Float(f64::NEG_INFINITY, FloatTy::F32)]);
+
+        let json = to_json(&program, &tcx);
+        let (from_json, json_tcx) = from_json(&json).unwrap();
+        assert_eq!(to_json(&from_json, &json_tcx), json);
+        let bytes = to_binary(&program, &tcx);
+        let (from_binary, binary_tcx) = from_binary(&bytes).unwrap();
+        assert_eq!(to_json(&from_binary, &binary_tcx), json);
+
+        let Terminator::Call { callee, args, .. } =
+            from_json.functions[Function::new(0)].basic_blocks[BasicBlock::new(0)].terminator()
+        else {
+            panic!("not a call");
+        };
+        assert!(matches!(callee, Callee::Intrinsic("fmaf32")));
+        let [Operand::Constant(Literal::Float(f, FloatTy::F32))] = args.as_slice() else {
+            panic!("not a float");
+        };
+        assert_eq!(f.to_bits(), 0x7ff8_0000_dead_beef);
+        assert_eq!(
+            from_binary.serialize(&binary_tcx, CallSynatx::V4),
+            program.serialize(&tcx, CallSynatx::V4)
+        );
+    }
+}
@@ -0,0 +1,1415 @@
+//! An interpreter that runs a `Program` directly and prints what the compiled program
+//! would, as a cheap oracle that needs neither rustc nor Miri
+
+use std::{
+    cmp::Ordering,
+    fmt::{self, Write},
+    hash::{DefaultHasher, Hasher},
+    ops::{Add, Div, Mul, Rem, Sub},
+};
+
+use index_vec::IndexVec;
+
+use crate::{serialize::Serialize, syntax::*, tyctxt::TyCtxt, validate::Location};
+
+/// How a program failed to run to completion
+#[derive(Debug)]
+pub enum InterpError {
+    /// The program ran out of steps, so it probably doesn't terminate
+    StepLimit,
+    UndefinedBehavior {
+        location: Location,
+        message: String,
+    },
+    /// The program does something the interpreter can't model, such as comparing addresses
+    Unsupported {
+        location: Location,
+        message: String,
+    },
+}
+
+impl fmt::Display for InterpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpError::StepLimit => write!(f, "the program didn't finish within the step limit"),
+            InterpError::UndefinedBehavior { location, message } => {
+                write!(f, "Undefined Behavior at {location}: {message}")
+            }
+            InterpError::Unsupported { location, message } => {
+                write!(f, "unsupported operation at {location}: {message}")
+            }
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, InterpError>;
+
+/// Runs the program from its first function with the entry arguments, and returns what it
+/// prints: the `dump_var` lines with the debug dumper, or else the final hash line.
+///
+/// A step is one statement or terminator. Programs that take more than `step_limit` steps
+/// are stopped with [`InterpError::StepLimit`].
+pub fn run(program: &Program, tcx: &TyCtxt, step_limit: usize) -> Result<String> {
+    let mut interp = Interpreter {
+        program,
+        tcx,
+        allocs: vec![],
+        stack: vec![],
+        hasher: DefaultHasher::new(),
+        stdout: String::new(),
+        steps: step_limit,
+    };
+    let args = program
+        .entry_args
+        .iter()
+        .map(|lit| Value::Scalar(*lit))
+        .collect();
+    interp
+        .push_frame(Function::new(0), args, None)
+        .map_err(|fault| fault.at(Location::EntryArgs))?;
+    while !interp.stack.is_empty() {
+        interp.step()?;
+    }
+    if !program.use_debug_dumper {
+        writeln!(interp.stdout, "hash: {}", interp.hasher.finish()).unwrap();
+    }
+    Ok(interp.stdout)
+}
+
+/// Calls nested deeper than this are reported rather than risking our own stack
+const MAX_DEPTH: usize = 10_000;
+
+type AllocId = usize;
+
+#[derive(Clone, Debug)]
+enum Value {
+    Uninit,
+    Scalar(Literal),
+    Ptr(Pointer),
+    Tuple(Vec<Value>),
+    Array(Vec<Value>),
+    Adt(VariantIdx, Vec<Value>),
+}
+
+impl Value {
+    const UNIT: Self = Value::Tuple(vec![]);
+
+    fn aggregate(kind: AggregateKind, fields: Vec<Value>) -> Self {
+        match kind {
+            AggregateKind::Array(_) => Value::Array(fields),
+            AggregateKind::Tuple => Value::Tuple(fields),
+            AggregateKind::Adt(_, variant) => Value::Adt(variant, fields),
+        }
+    }
+
+    fn constant(value: &ConstValue) -> Self {
+        match value {
+            ConstValue::Scalar(lit) => Value::Scalar(*lit),
+            ConstValue::Aggregate(_, kind, fields) => {
+                Value::aggregate(*kind, fields.iter().map(Value::constant).collect())
+            }
+        }
+    }
+
+    fn scalar(self) -> Eval<Literal> {
+        match self {
+            Value::Scalar(lit) => Ok(lit),
+            Value::Uninit => ub("using an uninitialized value"),
+            _ => unsupported("expected a scalar"),
+        }
+    }
+
+    fn pointer(self) -> Eval<Pointer> {
+        match self {
+            Value::Ptr(ptr) => ptr.in_bounds(),
+            Value::Uninit => ub("using an uninitialized pointer"),
+            _ => unsupported("expected a pointer"),
+        }
+    }
+}
+
+/// How to get from a value to one of its parts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Step {
+    /// A field of a tuple or struct
+    Field(usize),
+    /// A field of an enum variant
+    Variant(VariantIdx, usize),
+    Index(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Pointer {
+    alloc: AllocId,
+    path: Vec<Step>,
+    /// In elements, as added by `arith_offset`
+    offset: isize,
+}
+
+impl Pointer {
+    fn to_alloc(alloc: AllocId) -> Self {
+        Pointer {
+            alloc,
+            path: vec![],
+            offset: 0,
+        }
+    }
+
+    /// The same pointer with the offset folded into an array index
+    fn in_bounds(mut self) -> Eval<Self> {
+        if self.offset == 0 {
+            return Ok(self);
+        }
+        if let Some(Step::Index(index)) = self.path.last_mut() {
+            if let Some(moved) = index.checked_add_signed(self.offset) {
+                *index = moved;
+                self.offset = 0;
+                return Ok(self);
+            }
+        }
+        ub(format!(
+            "dereferencing a pointer {} elements out of bounds",
+            self.offset
+        ))
+    }
+}
+
+struct Allocation {
+    ty: TyId,
+    value: Value,
+}
+
+struct Frame {
+    func: Function,
+    locals: IndexVec<Local, Option<AllocId>>,
+    bb: BasicBlock,
+    statement: usize,
+    /// Where the caller wants the return value, and where it carries on
+    ret: Option<(Place, BasicBlock)>,
+}
+
+/// An error before it is given the location it happened at
+enum Fault {
+    Ub(String),
+    Unsupported(String),
+}
+
+impl Fault {
+    fn at(self, location: Location) -> InterpError {
+        match self {
+            Fault::Ub(message) => InterpError::UndefinedBehavior { location, message },
+            Fault::Unsupported(message) => InterpError::Unsupported { location, message },
+        }
+    }
+}
+
+type Eval<T> = std::result::Result<T, Fault>;
+
+fn ub<T>(message: impl Into<String>) -> Eval<T> {
+    Err(Fault::Ub(message.into()))
+}
+
+fn unsupported<T>(message: impl Into<String>) -> Eval<T> {
+    Err(Fault::Unsupported(message.into()))
+}
+
+struct Interpreter<'a> {
+    program: &'a Program,
+    tcx: &'a TyCtxt,
+    /// Every local of every call gets its own allocation, which is never reused
+    allocs: Vec<Option<Allocation>>,
+    stack: Vec<Frame>,
+    hasher: DefaultHasher,
+    stdout: String,
+    steps: usize,
+}
+
+impl Interpreter<'_> {
+    fn frame(&self) -> &Frame {
+        self.stack.last().expect("a function is running")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.stack.last_mut().expect("a function is running")
+    }
+
+    fn body(&self) -> &Body {
+        &self.program.functions[self.frame().func]
+    }
+
+    fn step(&mut self) -> Result<()> {
+        if self.steps == 0 {
+            return Err(InterpError::StepLimit);
+        }
+        self.steps -= 1;
+
+        let program = self.program;
+        let Frame {
+            func,
+            bb,
+            statement,
+            ..
+        } = *self.frame();
+        let Some(data) = program.functions[func].basic_blocks.get(bb) else {
+            let message = format!("{} doesn't exist", bb.identifier());
+            return Err(Fault::Unsupported(message).at(Location::Terminator(func, bb)));
+        };
+        match data.statements().get(statement) {
+            Some(stmt) => {
+                self.statement(stmt)
+                    .map_err(|fault| fault.at(Location::Statement(func, bb, statement)))?;
+                self.frame_mut().statement += 1;
+            }
+            None => self
+                .terminator(data.terminator())
+                .map_err(|fault| fault.at(Location::Terminator(func, bb)))?,
+        }
+        Ok(())
+    }
+
+    fn allocate(&mut self, ty: TyId) -> AllocId {
+        self.allocs.push(Some(Allocation {
+            ty,
+            value: Value::Uninit,
+        }));
+        self.allocs.len() - 1
+    }
+
+    fn push_frame(
+        &mut self,
+        func: Function,
+        args: Vec<Value>,
+        ret: Option<(Place, BasicBlock)>,
+    ) -> Eval<()> {
+        let program = self.program;
+        let Some(body) = program.functions.get(func) else {
+            return unsupported(format!("{} doesn't exist", func.identifier()));
+        };
+        if body.args_iter().len() != args.len() {
+            return unsupported(format!(
+                "{} takes {} arguments, but {} are given",
+                func.identifier(),
+                body.args_iter().len(),
+                args.len()
+            ));
+        }
+        if self.stack.len() == MAX_DEPTH {
+            return unsupported("the call stack is too deep");
+        }
+        let locals: IndexVec<Local, Option<AllocId>> = body
+            .local_decls
+            .iter()
+            .map(|decl| Some(self.allocate(decl.ty)))
+            .collect();
+        for (arg, value) in body.args_iter().zip(args) {
+            let alloc = locals[arg].expect("just allocated");
+            self.allocs[alloc].as_mut().expect("just allocated").value = value;
+        }
+        self.stack.push(Frame {
+            func,
+            locals,
+            bb: BasicBlock::new(0),
+            statement: 0,
+            ret,
+        });
+        Ok(())
+    }
+
+    fn goto(&mut self, target: BasicBlock) -> Eval<()> {
+        let frame = self.frame_mut();
+        frame.bb = target;
+        frame.statement = 0;
+        Ok(())
+    }
+
+    fn local(&self, local: Local) -> Eval<AllocId> {
+        match self.frame().locals.get(local) {
+            Some(Some(alloc)) => Ok(*alloc),
+            Some(None) => ub(format!(
+                "{} is used outside its storage",
+                local.identifier()
+            )),
+            None => unsupported(format!("{} isn't declared", local.identifier())),
+        }
+    }
+
+    fn place(&self, place: &Place) -> Eval<Pointer> {
+        let mut ptr = Pointer::to_alloc(self.local(place.local())?);
+        for proj in place.projection() {
+            match proj {
+                ProjectionElem::Deref => ptr = self.read(&ptr)?.pointer()?,
+                ProjectionElem::TupleField(field) | ProjectionElem::Field(field) => {
+                    ptr.path.push(Step::Field(field.index()))
+                }
+                ProjectionElem::DowncastField(variant, field, _) => {
+                    ptr.path.push(Step::Variant(*variant, field.index()))
+                }
+                ProjectionElem::Index(local) => {
+                    let index = self.read(&Pointer::to_alloc(self.local(*local)?))?;
+                    let Literal::Uint(index, UintTy::Usize) = index.scalar()? else {
+                        return unsupported("indexing with a non-usize");
+                    };
+                    ptr.path.push(Step::Index(index as usize));
+                }
+                ProjectionElem::ConstantIndex { offset } => {
+                    ptr.path.push(Step::Index(*offset as usize))
+                }
+            }
+        }
+        Ok(ptr)
+    }
+
+    fn read(&self, ptr: &Pointer) -> Eval<Value> {
+        let Some(alloc) = &self.allocs[ptr.alloc] else {
+            return ub("reading through a dangling pointer");
+        };
+        let mut value = &alloc.value;
+        for step in &ptr.path {
+            value = match (*step, value) {
+                (_, Value::Uninit) => return Ok(Value::Uninit),
+                (Step::Field(field), Value::Tuple(fields) | Value::Adt(_, fields)) => {
+                    fields.get(field)
+                }
+                (Step::Variant(variant, field), Value::Adt(active, fields)) => {
+                    if variant != *active {
+                        return ub(format!(
+                            "reading {} while {} is active",
+                            variant.identifier(),
+                            active.identifier()
+                        ));
+                    }
+                    fields.get(field)
+                }
+                (Step::Index(index), Value::Array(elems)) => {
+                    let Some(elem) = elems.get(index) else {
+                        return ub(format!("index {index} is out of bounds"));
+                    };
+                    Some(elem)
+                }
+                _ => None,
+            }
+            .map_or_else(|| unsupported("projecting a field that doesn't exist"), Ok)?;
+        }
+        Ok(value.clone())
+    }
+
+    fn write(&mut self, ptr: &Pointer, new: Value) -> Eval<()> {
+        let tcx = self.tcx;
+        let Some(alloc) = &mut self.allocs[ptr.alloc] else {
+            return ub("writing through a dangling pointer");
+        };
+        let mut ty = alloc.ty;
+        let mut value = &mut alloc.value;
+        for step in &ptr.path {
+            // Writing part of a value gives the rest of it a shape
+            let reshape = match (*step, &*value) {
+                (_, Value::Uninit) => true,
+                (Step::Variant(variant, _), Value::Adt(active, _)) => variant != *active,
+                _ => false,
+            };
+            if reshape {
+                *value = uninit_parts(tcx, ty, *step)?;
+            }
+            ty = part_ty(tcx, ty, *step)?;
+            value = match (*step, value) {
+                (Step::Field(field), Value::Tuple(fields) | Value::Adt(_, fields))
+                | (Step::Variant(_, field), Value::Adt(_, fields)) => fields.get_mut(field),
+                (Step::Index(index), Value::Array(elems)) => {
+                    let len = elems.len();
+                    let Some(elem) = elems.get_mut(index) else {
+                        return ub(format!("index {index} is out of bounds for length {len}"));
+                    };
+                    Some(elem)
+                }
+                _ => None,
+            }
+            .map_or_else(|| unsupported("projecting a field that doesn't exist"), Ok)?;
+        }
+        *value = new;
+        Ok(())
+    }
+
+    fn operand(&self, operand: &Operand) -> Eval<Value> {
+        match operand {
+            Operand::Copy(place) | Operand::Move(place) => self.read(&self.place(place)?),
+            Operand::Constant(lit) => Ok(Value::Scalar(*lit)),
+            Operand::Compound(value) => Ok(Value::constant(value.value())),
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) -> Eval<()> {
+        match stmt {
+            Statement::Assign(place, rvalue) => {
+                let value = self.rvalue(rvalue)?;
+                let ptr = self.place(place)?;
+                self.write(&ptr, value)
+            }
+            Statement::StorageLive(local) => {
+                let ty = self.body().local_decls[*local].ty;
+                let alloc = self.allocate(ty);
+                if let Some(old) = self.frame_mut().locals[*local].replace(alloc) {
+                    self.allocs[old] = None;
+                }
+                Ok(())
+            }
+            Statement::StorageDead(local) => {
+                if let Some(old) = self.frame_mut().locals[*local].take() {
+                    self.allocs[old] = None;
+                }
+                Ok(())
+            }
+            Statement::Deinit(place) => {
+                let ptr = self.place(place)?;
+                self.write(&ptr, Value::Uninit)
+            }
+            Statement::SetDiscriminant(place, variant) => {
+                let variant = VariantIdx::new(*variant as usize);
+                let ptr = self.place(place)?;
+                if let Value::Adt(active, _) = self.read(&ptr)? {
+                    if active == variant {
+                        return Ok(());
+                    }
+                }
+                let ty = place.ty(&self.body().local_decls, self.tcx);
+                let parts = uninit_parts(self.tcx, ty, Step::Variant(variant, 0))?;
+                self.write(&ptr, parts)
+            }
+            Statement::Retag(_) | Statement::Nop => Ok(()),
+        }
+    }
+
+    fn rvalue(&self, rvalue: &Rvalue) -> Eval<Value> {
+        let value = match rvalue {
+            Rvalue::Use(operand) => self.operand(operand)?,
+            Rvalue::UnaryOp(op, operand) => {
+                Value::Scalar(unary_op(*op, self.operand(operand)?.scalar()?)?)
+            }
+            Rvalue::BinaryOp(BinOp::Offset, ptr, offset) => {
+                let mut ptr = self.operand(ptr)?.pointer()?;
+                let Literal::Int(offset, IntTy::Isize) = self.operand(offset)?.scalar()? else {
+                    return unsupported("offsetting by a non-isize");
+                };
+                ptr.offset = ptr.offset.wrapping_add(offset as isize);
+                Value::Ptr(ptr)
+            }
+            Rvalue::BinaryOp(op, l, r) => {
+                let (l, r) = (self.operand(l)?, self.operand(r)?);
+                if matches!(l, Value::Ptr(..)) || matches!(r, Value::Ptr(..)) {
+                    return unsupported("comparing pointers");
+                }
+                Value::Scalar(binary_op(*op, l.scalar()?, r.scalar()?)?)
+            }
+            Rvalue::CheckedBinaryOp(op, l, r) => {
+                let (l, r) = (self.operand(l)?.scalar()?, self.operand(r)?.scalar()?);
+                let (result, overflowed) = checked_op(*op, l, r)?;
+                Value::Tuple(vec![
+                    Value::Scalar(result),
+                    Value::Scalar(Literal::Bool(overflowed)),
+                ])
+            }
+            Rvalue::Cast(operand, ty) => {
+                Value::Scalar(cast(self.tcx, self.operand(operand)?.scalar()?, *ty)?)
+            }
+            Rvalue::Len(place) => match place.ty(&self.body().local_decls, self.tcx).kind(self.tcx)
+            {
+                TyKind::Array(_, len) => Value::Scalar(Literal::Uint(*len as u128, UintTy::Usize)),
+                _ => return unsupported("taking the length of a non-array"),
+            },
+            Rvalue::Discriminant(place) => match self.read(&self.place(place)?)? {
+                Value::Adt(variant, _) => {
+                    Value::Scalar(Literal::Int(variant.index() as i128, IntTy::Isize))
+                }
+                Value::Uninit => return ub("reading the discriminant of an uninitialized value"),
+                _ => return unsupported("reading the discriminant of a non-ADT"),
+            },
+            Rvalue::AddressOf(_, place) | Rvalue::Ref(_, place) => Value::Ptr(self.place(place)?),
+            Rvalue::Aggregate(kind, operands) => {
+                let fields = operands
+                    .iter()
+                    .map(|operand| self.operand(operand))
+                    .collect::<Eval<_>>()?;
+                Value::aggregate(*kind, fields)
+            }
+        };
+        Ok(value)
+    }
+
+    fn terminator(&mut self, term: &Terminator) -> Eval<()> {
+        match term {
+            Terminator::Hole => unsupported("the terminator is not set"),
+            Terminator::Return => {
+                let frame = self.stack.pop().expect("a function is running");
+                let ret = match frame.locals[Local::RET] {
+                    Some(alloc) => self.read(&Pointer::to_alloc(alloc))?,
+                    None => return ub("RET is returned outside its storage"),
+                };
+                for alloc in frame.locals.iter().flatten() {
+                    self.allocs[*alloc] = None;
+                }
+                match frame.ret {
+                    Some((destination, target)) => {
+                        let ptr = self.place(&destination)?;
+                        self.write(&ptr, ret)?;
+                        self.goto(target)
+                    }
+                    None => Ok(()),
+                }
+            }
+            Terminator::Goto { target } | Terminator::Drop { target, .. } => self.goto(*target),
+            Terminator::Unreachable => ub("entering unreachable code"),
+            Terminator::Call {
+                callee,
+                destination,
+                target,
+                args,
+            } => self.call(callee, destination, *target, args),
+            Terminator::SwitchInt { discr, targets } => {
+                let (bits, width) = match self.operand(discr)?.scalar()? {
+                    Literal::Int(value, ty) => (value as u128, int_width(ty)),
+                    Literal::Uint(value, ty) => (value, uint_width(ty)),
+                    Literal::Bool(value) => (value as u128, 8),
+                    Literal::Char(value) => (value as u128, 32),
+                    Literal::Float(..) => return unsupported("switching on a float"),
+                };
+                let target = targets
+                    .branches
+                    .iter()
+                    .find(|(value, _)| zext(*value, width) == zext(bits, width))
+                    .map_or(targets.otherwise, |(_, bb)| *bb);
+                self.goto(target)
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        callee: &Callee,
+        destination: &Place,
+        target: BasicBlock,
+        args: &[Operand],
+    ) -> Eval<()> {
+        let values: Vec<Value> = args
+            .iter()
+            .map(|arg| self.operand(arg))
+            .collect::<Eval<_>>()?;
+        let result = match *callee {
+            Callee::Generated(func) | Callee::Closure(func, ..) | Callee::Worker(func) => {
+                return self.push_frame(func, values, Some((destination.clone(), target)));
+            }
+            Callee::Named("dump_var") => {
+                self.dump_var(args, values)?;
+                Value::UNIT
+            }
+            Callee::Named(name) => return unsupported(format!("calling {name}")),
+            Callee::Intrinsic(name) => {
+                let dest_ty = destination.ty(&self.body().local_decls, self.tcx);
+                self.intrinsic(name, values, dest_ty)?
+            }
+            Callee::AtomicIntrinsic(name, _) => self.atomic(name, values)?,
+            Callee::Atomic(_, method, _) => self.atomic(atomic_intrinsic(method), values)?,
+            Callee::ConcurrentAtomic(_, method, _) => {
+                // The workers all do the same operation, so their order doesn't matter
+                let old = self.atomic("atomic_load", values[..1].to_vec())?;
+                for _ in 0..Program::ATOMIC_WORKERS {
+                    self.atomic(atomic_intrinsic(method), values.clone())?;
+                }
+                old
+            }
+            Callee::Foreign(_) => {
+                let h = foreign_hash(values)?;
+                Value::Scalar(Literal::Uint(
+                    h.wrapping_mul(31).wrapping_add(h) as u128,
+                    UintTy::U64,
+                ))
+            }
+        };
+        let ptr = self.place(destination)?;
+        self.write(&ptr, result)?;
+        self.goto(target)
+    }
+
+    fn dump_var(&mut self, args: &[Operand], values: Vec<Value>) -> Eval<()> {
+        let tys: Vec<TyId> = args
+            .iter()
+            .map(|arg| arg.ty(&self.body().local_decls, self.tcx))
+            .collect();
+        if !self.program.use_debug_dumper {
+            for (ty, value) in tys.into_iter().zip(&values) {
+                self.hash(ty, value)?;
+            }
+            return Ok(());
+        }
+
+        // The format of `Program::DEBUG_DUMPER`
+        let index = |value: &Value| match value {
+            Value::Scalar(Literal::Uint(index, UintTy::Usize)) => Ok(*index),
+            _ => unsupported("dump_var takes usize indices"),
+        };
+        let [func, vars @ ..] = &values[..] else {
+            return unsupported("dump_var takes a function index");
+        };
+        let mut line = format!("fn{}:", index(func)?);
+        for (i, (var, ty)) in vars.chunks(2).zip(tys[1..].chunks(2)).enumerate() {
+            let ([var, val], [_, ty]) = (var, ty) else {
+                return unsupported("dump_var takes pairs of indices and values");
+            };
+            if i > 0 {
+                line.push('\n');
+            }
+            write!(line, "_{} = ", index(var)?).unwrap();
+            self.debug(*ty, val, &mut line)?;
+        }
+        line.push('\n');
+        self.stdout.push_str(&line);
+        Ok(())
+    }
+
+    /// Feeds the value to the hasher exactly as its `Hash` impl would
+    fn hash(&mut self, ty: TyId, value: &Value) -> Eval<()> {
+        let tcx = self.tcx;
+        match (ty.kind(tcx), value) {
+            (TyKind::Unit, _) => {}
+            (_, Value::Uninit) => return ub("hashing an uninitialized value"),
+            (TyKind::Tuple(tys), Value::Tuple(fields)) => {
+                for (ty, field) in tys.iter().zip(fields) {
+                    self.hash(*ty, field)?;
+                }
+            }
+            (TyKind::Array(elem_ty, _), Value::Array(elems)) => {
+                self.hasher.write_usize(elems.len());
+                for elem in elems {
+                    self.hash(*elem_ty, elem)?;
+                }
+            }
+            (_, Value::Scalar(lit)) => match *lit {
+                Literal::Bool(value) => self.hasher.write_u8(value as u8),
+                Literal::Char(value) => self.hasher.write_u32(value as u32),
+                Literal::Int(value, ty) => match ty {
+                    IntTy::Isize => self.hasher.write_isize(value as isize),
+                    IntTy::I8 => self.hasher.write_i8(value as i8),
+                    IntTy::I16 => self.hasher.write_i16(value as i16),
+                    IntTy::I32 => self.hasher.write_i32(value as i32),
+                    IntTy::I64 => self.hasher.write_i64(value as i64),
+                    IntTy::I128 => self.hasher.write_i128(value),
+                },
+                Literal::Uint(value, ty) => match ty {
+                    UintTy::Usize => self.hasher.write_usize(value as usize),
+                    UintTy::U8 => self.hasher.write_u8(value as u8),
+                    UintTy::U16 => self.hasher.write_u16(value as u16),
+                    UintTy::U32 => self.hasher.write_u32(value as u32),
+                    UintTy::U64 => self.hasher.write_u64(value as u64),
+                    UintTy::U128 => self.hasher.write_u128(value),
+                },
+                Literal::Float(..) => return unsupported("floats can't be hashed"),
+            },
+            _ => return unsupported(format!("{} can't be hashed", ty.serialize(tcx))),
+        }
+        Ok(())
+    }
+
+    /// Formats the value as its derived `Debug` impl would
+    fn debug(&self, ty: TyId, value: &Value, out: &mut String) -> Eval<()> {
+        let tcx = self.tcx;
+        match (ty.kind(tcx), value) {
+            (TyKind::Unit, _) => out.push_str("()"),
+            (_, Value::Uninit) => return ub("printing an uninitialized value"),
+            (TyKind::Tuple(tys), Value::Tuple(fields)) => {
+                out.push('(');
+                for (i, (ty, field)) in tys.iter().zip(fields).enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.debug(*ty, field, out)?;
+                }
+                if fields.len() == 1 {
+                    out.push(',');
+                }
+                out.push(')');
+            }
+            (TyKind::Array(elem_ty, _), Value::Array(elems)) => {
+                out.push('[');
+                for (i, elem) in elems.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    self.debug(*elem_ty, elem, out)?;
+                }
+                out.push(']');
+            }
+            (TyKind::Adt(adt), Value::Adt(variant, fields)) => {
+                if adt.is_enum() {
+                    out.push_str(&variant.identifier());
+                } else {
+                    out.push_str(&ty.type_name());
+                }
+                if !fields.is_empty() {
+                    out.push_str(" { ");
+                    for (i, (ty, field)) in
+                        adt.variants[*variant].fields.iter().zip(fields).enumerate()
+                    {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        write!(out, "{}: ", FieldIdx::new(i).identifier()).unwrap();
+                        self.debug(*ty, field, out)?;
+                    }
+                    out.push_str(" }");
+                }
+            }
+            (_, Value::Scalar(lit)) => match *lit {
+                Literal::Bool(value) => write!(out, "{value}").unwrap(),
+                Literal::Char(value) => write!(out, "{value:?}").unwrap(),
+                Literal::Int(value, _) => write!(out, "{value}").unwrap(),
+                Literal::Uint(value, _) => write!(out, "{value}").unwrap(),
+                Literal::Float(value, FloatTy::F32) => write!(out, "{:?}", value as f32).unwrap(),
+                Literal::Float(value, FloatTy::F64) => write!(out, "{value:?}").unwrap(),
+            },
+            _ => return unsupported(format!("{} can't be printed", ty.serialize(tcx))),
+        }
+        Ok(())
+    }
+
+    fn intrinsic(&mut self, name: &str, args: Vec<Value>, dest_ty: TyId) -> Eval<Value> {
+        let mut args = args.into_iter();
+        let mut arg = || {
+            args.next()
+                .map_or_else(|| unsupported("missing argument"), Ok)
+        };
+        let value = match name {
+            "fmaf64" => {
+                let (a, b, c) = (arg()?.scalar()?, arg()?.scalar()?, arg()?.scalar()?);
+                let (Literal::Float(a, _), Literal::Float(b, _), Literal::Float(c, _)) = (a, b, c)
+                else {
+                    return unsupported("fmaf64 of non-floats");
+                };
+                Value::Scalar(Literal::Float(a.mul_add(b, c), FloatTy::F64))
+            }
+            "arith_offset" => {
+                let Value::Ptr(mut ptr) = arg()? else {
+                    return unsupported("arith_offset of a non-pointer");
+                };
+                let Literal::Int(offset, IntTy::Isize) = arg()?.scalar()? else {
+                    return unsupported("arith_offset by a non-isize");
+                };
+                ptr.offset = ptr.offset.wrapping_add(offset as isize);
+                Value::Ptr(ptr)
+            }
+            "bswap" => Value::Scalar(match arg()?.scalar()? {
+                Literal::Int(value, ty) => {
+                    let width = int_width(ty);
+                    Literal::Int(sext(value.swap_bytes() >> (128 - width), width), ty)
+                }
+                Literal::Uint(value, ty) => {
+                    Literal::Uint(value.swap_bytes() >> (128 - uint_width(ty)), ty)
+                }
+                _ => return unsupported("bswap of a non-integer"),
+            }),
+            "transmute" => {
+                let mut bytes = vec![];
+                to_bytes(&arg()?, &mut bytes)?;
+                from_bytes(self.tcx, dest_ty, &bytes)?
+            }
+            _ => return unsupported(format!("calling the {name} intrinsic")),
+        };
+        Ok(value)
+    }
+
+    fn atomic(&mut self, name: &str, args: Vec<Value>) -> Eval<Value> {
+        let mut args = args.into_iter();
+        if name == "atomic_fence" || name == "atomic_singlethreadfence" {
+            return Ok(Value::UNIT);
+        }
+        let Some(ptr) = args.next() else {
+            return unsupported("missing argument");
+        };
+        let ptr = ptr.pointer()?;
+        let old = self.read(&ptr)?;
+        if name == "atomic_load" {
+            return Ok(old);
+        }
+        let Some(operand) = args.next() else {
+            return unsupported("missing argument");
+        };
+        if name == "atomic_store" {
+            self.write(&ptr, operand)?;
+            return Ok(Value::UNIT);
+        }
+        // Pointers are only ever exchanged, so they don't have to be scalars
+        if name == "atomic_xchg" {
+            self.write(&ptr, operand)?;
+            return Ok(old);
+        }
+        let (a, b) = (old.clone().scalar()?, operand.scalar()?);
+        let new = match name {
+            "atomic_cxchg" => {
+                let Some(new) = args.next() else {
+                    return unsupported("missing argument");
+                };
+                if compare(a, b)? == Some(Ordering::Equal) {
+                    new.scalar()?
+                } else {
+                    a
+                }
+            }
+            "atomic_xadd" => binary_op(BinOp::Add, a, b)?,
+            "atomic_xsub" => binary_op(BinOp::Sub, a, b)?,
+            "atomic_and" => binary_op(BinOp::BitAnd, a, b)?,
+            "atomic_nand" => unary_op(UnOp::Not, binary_op(BinOp::BitAnd, a, b)?)?,
+            "atomic_or" => binary_op(BinOp::BitOr, a, b)?,
+            "atomic_xor" => binary_op(BinOp::BitXor, a, b)?,
+            "atomic_max" | "atomic_umax" => {
+                if compare(a, b)? == Some(Ordering::Less) {
+                    b
+                } else {
+                    a
+                }
+            }
+            "atomic_min" | "atomic_umin" => {
+                if compare(a, b)? == Some(Ordering::Greater) {
+                    b
+                } else {
+                    a
+                }
+            }
+            _ => return unsupported(format!("calling the {name} intrinsic")),
+        };
+        self.write(&ptr, Value::Scalar(new))?;
+        Ok(old)
+    }
+}
+
+/// The atomic intrinsic doing what the method of an atomic type does
+fn atomic_intrinsic(method: AtomicMethod) -> &'static str {
+    match method {
+        AtomicMethod::Load => "atomic_load",
+        AtomicMethod::Store => "atomic_store",
+        AtomicMethod::Swap => "atomic_xchg",
+        AtomicMethod::CompareExchange => "atomic_cxchg",
+        AtomicMethod::FetchAdd => "atomic_xadd",
+        AtomicMethod::FetchSub => "atomic_xsub",
+        AtomicMethod::FetchAnd => "atomic_and",
+        AtomicMethod::FetchNand => "atomic_nand",
+        AtomicMethod::FetchOr => "atomic_or",
+        AtomicMethod::FetchXor => "atomic_xor",
+        AtomicMethod::FetchMax => "atomic_max",
+        AtomicMethod::FetchMin => "atomic_min",
+    }
+}
+
+/// The value of the part of `ty` that `step` leads to, with everything uninitialized
+fn uninit_parts(tcx: &TyCtxt, ty: TyId, step: Step) -> Eval<Value> {
+    let uninit = |count: usize| vec![Value::Uninit; count];
+    let parts = match (ty.kind(tcx), step) {
+        (TyKind::Tuple(tys), Step::Field(_)) => Value::Tuple(uninit(tys.len())),
+        (TyKind::Adt(adt), Step::Field(_)) if !adt.is_enum() => {
+            Value::Adt(VariantIdx::new(0), uninit(adt.variants[0].fields.len()))
+        }
+        (TyKind::Adt(adt), Step::Variant(variant, _)) => match adt.variants.get(variant) {
+            Some(def) => Value::Adt(variant, uninit(def.fields.len())),
+            None => return unsupported(format!("{} doesn't exist", variant.identifier())),
+        },
+        (TyKind::Array(_, len), Step::Index(_)) => Value::Array(uninit(*len)),
+        _ => return unsupported(format!("can't project into {}", ty.serialize(tcx))),
+    };
+    Ok(parts)
+}
+
+/// The type of the part of `ty` that `step` leads to
+fn part_ty(tcx: &TyCtxt, ty: TyId, step: Step) -> Eval<TyId> {
+    let part = match (ty.kind(tcx), step) {
+        (TyKind::Tuple(tys), Step::Field(field)) => tys.get(field),
+        (TyKind::Adt(adt), Step::Field(field)) => adt.variants[0].fields.get(field),
+        (TyKind::Adt(adt), Step::Variant(variant, field)) => adt
+            .variants
+            .get(variant)
+            .and_then(|def| def.fields.get(field)),
+        (TyKind::Array(elem, _), Step::Index(_)) => Some(elem),
+        _ => None,
+    };
+    part.copied().map_or_else(
+        || unsupported(format!("can't project into {}", ty.serialize(tcx))),
+        Ok,
+    )
+}
+
+fn int_width(ty: IntTy) -> u32 {
+    match ty {
+        IntTy::Isize => isize::BITS,
+        IntTy::I8 => 8,
+        IntTy::I16 => 16,
+        IntTy::I32 => 32,
+        IntTy::I64 => 64,
+        IntTy::I128 => 128,
+    }
+}
+
+fn uint_width(ty: UintTy) -> u32 {
+    match ty {
+        UintTy::Usize => usize::BITS,
+        UintTy::U8 => 8,
+        UintTy::U16 => 16,
+        UintTy::U32 => 32,
+        UintTy::U64 => 64,
+        UintTy::U128 => 128,
+    }
+}
+
+/// Sign-extends the low `width` bits
+fn sext(value: i128, width: u32) -> i128 {
+    let shift = 128 - width;
+    (value << shift) >> shift
+}
+
+/// Zero-extends the low `width` bits
+fn zext(value: u128, width: u32) -> u128 {
+    if width == 128 {
+        value
+    } else {
+        value & ((1 << width) - 1)
+    }
+}
+
+fn unary_op(op: UnOp, operand: Literal) -> Eval<Literal> {
+    let result = match (op, operand) {
+        (UnOp::Not, Literal::Bool(value)) => Literal::Bool(!value),
+        (UnOp::Not, Literal::Int(value, ty)) => Literal::Int(!value, ty),
+        (UnOp::Not, Literal::Uint(value, ty)) => Literal::Uint(zext(!value, uint_width(ty)), ty),
+        (UnOp::Neg, Literal::Int(value, ty)) => {
+            Literal::Int(sext(value.wrapping_neg(), int_width(ty)), ty)
+        }
+        (UnOp::Neg, Literal::Float(value, ty)) => Literal::Float(-value, ty),
+        _ => return unsupported(format!("{} can't be applied to {operand:?}", op.symbol())),
+    };
+    Ok(result)
+}
+
+fn compare(l: Literal, r: Literal) -> Eval<Option<Ordering>> {
+    let ordering = match (l, r) {
+        (Literal::Int(l, _), Literal::Int(r, _)) => l.partial_cmp(&r),
+        (Literal::Uint(l, _), Literal::Uint(r, _)) => l.partial_cmp(&r),
+        // f32s are exact as f64s, so they compare the same
+        (Literal::Float(l, _), Literal::Float(r, _)) => l.partial_cmp(&r),
+        (Literal::Bool(l), Literal::Bool(r)) => l.partial_cmp(&r),
+        (Literal::Char(l), Literal::Char(r)) => l.partial_cmp(&r),
+        _ => return unsupported(format!("can't compare {l:?} and {r:?}")),
+    };
+    Ok(ordering)
+}
+
+/// Integer arithmetic wraps, like MIR's unchecked operators
+fn binary_op(op: BinOp, l: Literal, r: Literal) -> Eval<Literal> {
+    let ordering = || compare(l, r);
+    let result = match (op, l, r) {
+        (BinOp::Eq, ..) => Literal::Bool(ordering()? == Some(Ordering::Equal)),
+        (BinOp::Ne, ..) => Literal::Bool(ordering()? != Some(Ordering::Equal)),
+        (BinOp::Lt, ..) => Literal::Bool(ordering()? == Some(Ordering::Less)),
+        (BinOp::Le, ..) => Literal::Bool(matches!(
+            ordering()?,
+            Some(Ordering::Less | Ordering::Equal)
+        )),
+        (BinOp::Gt, ..) => Literal::Bool(ordering()? == Some(Ordering::Greater)),
+        (BinOp::Ge, ..) => Literal::Bool(matches!(
+            ordering()?,
+            Some(Ordering::Greater | Ordering::Equal)
+        )),
+        (BinOp::Shl | BinOp::Shr, Literal::Int(..) | Literal::Uint(..), _) => shift(op, l, r)?,
+        (_, Literal::Int(l, ty), Literal::Int(r, _)) => {
+            let width = int_width(ty);
+            let min = i128::MIN >> (128 - width);
+            let result = match op {
+                BinOp::Div | BinOp::Rem if r == 0 => return ub("dividing by zero"),
+                BinOp::Div | BinOp::Rem if l == min && r == -1 => {
+                    return ub("overflow in division")
+                }
+                BinOp::Div => l / r,
+                BinOp::Rem => l % r,
+                BinOp::BitXor => l ^ r,
+                BinOp::BitAnd => l & r,
+                BinOp::BitOr => l | r,
+                BinOp::Add => l.wrapping_add(r),
+                BinOp::Sub => l.wrapping_sub(r),
+                BinOp::Mul => l.wrapping_mul(r),
+                _ => return unsupported(format!("{} can't be applied to integers", op.symbol())),
+            };
+            Literal::Int(sext(result, width), ty)
+        }
+        (_, Literal::Uint(l, ty), Literal::Uint(r, _)) => {
+            let result = match op {
+                BinOp::Div | BinOp::Rem if r == 0 => return ub("dividing by zero"),
+                BinOp::Div => l / r,
+                BinOp::Rem => l % r,
+                BinOp::BitXor => l ^ r,
+                BinOp::BitAnd => l & r,
+                BinOp::BitOr => l | r,
+                BinOp::Add => l.wrapping_add(r),
+                BinOp::Sub => l.wrapping_sub(r),
+                BinOp::Mul => l.wrapping_mul(r),
+                _ => return unsupported(format!("{} can't be applied to integers", op.symbol())),
+            };
+            Literal::Uint(zext(result, uint_width(ty)), ty)
+        }
+        (_, Literal::Float(l, FloatTy::F32), Literal::Float(r, FloatTy::F32)) => {
+            let result = float_arith(op, l as f32, r as f32)?;
+            Literal::Float(result as f64, FloatTy::F32)
+        }
+        (_, Literal::Float(l, FloatTy::F64), Literal::Float(r, FloatTy::F64)) => {
+            Literal::Float(float_arith(op, l, r)?, FloatTy::F64)
+        }
+        (BinOp::BitXor, Literal::Bool(l), Literal::Bool(r)) => Literal::Bool(l ^ r),
+        (BinOp::BitAnd, Literal::Bool(l), Literal::Bool(r)) => Literal::Bool(l & r),
+        (BinOp::BitOr, Literal::Bool(l), Literal::Bool(r)) => Literal::Bool(l | r),
+        _ => {
+            return unsupported(format!(
+                "{} can't be applied to {l:?} and {r:?}",
+                op.symbol()
+            ))
+        }
+    };
+    Ok(result)
+}
+
+fn float_arith<F>(op: BinOp, l: F, r: F) -> Eval<F>
+where
+    F: Add<Output = F> + Sub<Output = F> + Mul<Output = F> + Div<Output = F> + Rem<Output = F>,
+{
+    match op {
+        BinOp::Add => Ok(l + r),
+        BinOp::Sub => Ok(l - r),
+        BinOp::Mul => Ok(l * r),
+        BinOp::Div => Ok(l / r),
+        BinOp::Rem => Ok(l % r),
+        _ => unsupported(format!("{} can't be applied to floats", op.symbol())),
+    }
+}
+
+/// Shifts by the amount modulo the width, like MIR's unchecked shifts
+fn shift(op: BinOp, l: Literal, r: Literal) -> Eval<Literal> {
+    let amount = match r {
+        Literal::Int(amount, _) => amount as u128,
+        Literal::Uint(amount, _) => amount,
+        _ => return unsupported(format!("shifting by {r:?}")),
+    };
+    let left = matches!(op, BinOp::Shl);
+    let result = match l {
+        Literal::Int(value, ty) => {
+            let width = int_width(ty);
+            let amount = (amount % width as u128) as u32;
+            let shifted = if left {
+                value << amount
+            } else {
+                value >> amount
+            };
+            Literal::Int(sext(shifted, width), ty)
+        }
+        Literal::Uint(value, ty) => {
+            let width = uint_width(ty);
+            let amount = (amount % width as u128) as u32;
+            let shifted = if left {
+                value << amount
+            } else {
+                value >> amount
+            };
+            Literal::Uint(zext(shifted, width), ty)
+        }
+        _ => return unsupported(format!("shifting {l:?}")),
+    };
+    Ok(result)
+}
+
+/// The wrapped result, and whether the exact result didn't fit
+fn checked_op(op: BinOp, l: Literal, r: Literal) -> Eval<(Literal, bool)> {
+    let result = binary_op(op, l, r)?;
+    let overflowed = match (op, l, r) {
+        (BinOp::Add | BinOp::Sub | BinOp::Mul, Literal::Int(l, ty), Literal::Int(r, _)) => {
+            let exact = match op {
+                BinOp::Add => l.checked_add(r),
+                BinOp::Sub => l.checked_sub(r),
+                _ => l.checked_mul(r),
+            };
+            exact.is_none_or(|exact| sext(exact, int_width(ty)) != exact)
+        }
+        (BinOp::Add | BinOp::Sub | BinOp::Mul, Literal::Uint(l, ty), Literal::Uint(r, _)) => {
+            let exact = match op {
+                BinOp::Add => l.checked_add(r),
+                BinOp::Sub => l.checked_sub(r),
+                _ => l.checked_mul(r),
+            };
+            exact.is_none_or(|exact| zext(exact, uint_width(ty)) != exact)
+        }
+        (BinOp::Shl | BinOp::Shr, Literal::Int(_, ty), r) => shift_overflows(r, int_width(ty)),
+        (BinOp::Shl | BinOp::Shr, Literal::Uint(_, ty), r) => shift_overflows(r, uint_width(ty)),
+        _ => return unsupported(format!("{} can't be checked", op.symbol())),
+    };
+    Ok((result, overflowed))
+}
+
+fn shift_overflows(amount: Literal, width: u32) -> bool {
+    match amount {
+        Literal::Int(amount, _) => !(0..width as i128).contains(&amount),
+        Literal::Uint(amount, _) => amount >= width as u128,
+        _ => false,
+    }
+}
+
+/// An `as` cast
+fn cast(tcx: &TyCtxt, value: Literal, target: TyId) -> Eval<Literal> {
+    let result = match (value, target.kind(tcx)) {
+        (Literal::Bool(value), TyKind::Int(..) | TyKind::Uint(..)) => {
+            return cast(tcx, Literal::Uint(value as u128, UintTy::U8), target)
+        }
+        (Literal::Char(value), TyKind::Int(..) | TyKind::Uint(..)) => {
+            return cast(tcx, Literal::Uint(value as u128, UintTy::U32), target)
+        }
+        (Literal::Uint(value, UintTy::U8), TyKind::Char) => Literal::Char(value as u8 as char),
+        (Literal::Int(value, _), TyKind::Int(ty)) => Literal::Int(sext(value, int_width(*ty)), *ty),
+        (Literal::Int(value, _), TyKind::Uint(ty)) => {
+            Literal::Uint(zext(value as u128, uint_width(*ty)), *ty)
+        }
+        (Literal::Uint(value, _), TyKind::Int(ty)) => {
+            Literal::Int(sext(value as i128, int_width(*ty)), *ty)
+        }
+        (Literal::Uint(value, _), TyKind::Uint(ty)) => {
+            Literal::Uint(zext(value, uint_width(*ty)), *ty)
+        }
+        // Converting from the 128-bit value rounds the same as from the original type
+        (Literal::Int(value, _), TyKind::Float(FloatTy::F32)) => {
+            Literal::Float(value as f32 as f64, FloatTy::F32)
+        }
+        (Literal::Int(value, _), TyKind::Float(FloatTy::F64)) => {
+            Literal::Float(value as f64, FloatTy::F64)
+        }
+        (Literal::Uint(value, _), TyKind::Float(FloatTy::F32)) => {
+            Literal::Float(value as f32 as f64, FloatTy::F32)
+        }
+        (Literal::Uint(value, _), TyKind::Float(FloatTy::F64)) => {
+            Literal::Float(value as f64, FloatTy::F64)
+        }
+        (Literal::Float(value, _), TyKind::Float(FloatTy::F32)) => {
+            Literal::Float(value as f32 as f64, FloatTy::F32)
+        }
+        (Literal::Float(value, _), TyKind::Float(FloatTy::F64)) => {
+            Literal::Float(value, FloatTy::F64)
+        }
+        // Float to int casts saturate, so saturating to 128 bits first doesn't change them
+        (Literal::Float(value, _), TyKind::Int(ty)) => {
+            let width = int_width(*ty);
+            let max = i128::MAX >> (128 - width);
+            Literal::Int((value as i128).clamp(-max - 1, max), *ty)
+        }
+        (Literal::Float(value, _), TyKind::Uint(ty)) => {
+            Literal::Uint((value as u128).min(zext(u128::MAX, uint_width(*ty))), *ty)
+        }
+        _ => return unsupported(format!("can't cast {value:?} to {}", target.serialize(tcx))),
+    };
+    Ok(result)
+}
+
+/// The FNV-1a hash computed by the mirror of a foreign function, over the scalars of
+/// its arguments in field order
+fn foreign_hash(args: Vec<Value>) -> Eval<u64> {
+    fn scalars(value: Value, out: &mut Vec<Value>) {
+        match value {
+            Value::Adt(_, fields) => fields.into_iter().for_each(|field| scalars(field, out)),
+            value => out.push(value),
+        }
+    }
+    let mut flattened = vec![];
+    for arg in args {
+        scalars(arg, &mut flattened);
+    }
+
+    let mut h: u64 = 0xcbf29ce484222325;
+    for arg in flattened {
+        let bits = match arg.scalar()? {
+            Literal::Int(value, _) => value as u64,
+            Literal::Uint(value, _) => value as u64,
+            Literal::Bool(value) => value as u64,
+            Literal::Char(value) => value as u64,
+            Literal::Float(value, FloatTy::F32) => (value as f32).to_bits() as u64,
+            Literal::Float(value, FloatTy::F64) => value.to_bits(),
+        };
+        h = (h ^ bits).wrapping_mul(0x100000001b3);
+    }
+    Ok(h)
+}
+
+/// Transmutes go through little-endian bytes, as on every target we test
+fn to_bytes(value: &Value, bytes: &mut Vec<u8>) -> Eval<()> {
+    match value {
+        Value::Uninit => return ub("transmuting an uninitialized value"),
+        Value::Scalar(lit) => match *lit {
+            Literal::Bool(value) => bytes.push(value as u8),
+            Literal::Char(value) => bytes.extend((value as u32).to_le_bytes()),
+            Literal::Int(value, ty) => {
+                bytes.extend(&value.to_le_bytes()[..int_width(ty) as usize / 8])
+            }
+            Literal::Uint(value, ty) => {
+                bytes.extend(&value.to_le_bytes()[..uint_width(ty) as usize / 8])
+            }
+            Literal::Float(value, FloatTy::F32) => {
+                bytes.extend((value as f32).to_bits().to_le_bytes())
+            }
+            Literal::Float(value, FloatTy::F64) => bytes.extend(value.to_bits().to_le_bytes()),
+        },
+        Value::Array(elems) => {
+            for elem in elems {
+                to_bytes(elem, bytes)?;
+            }
+        }
+        _ => return unsupported("transmuting a value without a fixed layout"),
+    }
+    Ok(())
+}
+
+fn from_bytes(tcx: &TyCtxt, ty: TyId, bytes: &[u8]) -> Eval<Value> {
+    let mut buf = [0; 16];
+    let value = match ty.kind(tcx) {
+        TyKind::Unit if bytes.is_empty() => Value::UNIT,
+        TyKind::Int(int_ty) if bytes.len() == int_width(*int_ty) as usize / 8 => {
+            buf[..bytes.len()].copy_from_slice(bytes);
+            let value = i128::from_le_bytes(buf);
+            Value::Scalar(Literal::Int(sext(value, int_width(*int_ty)), *int_ty))
+        }
+        TyKind::Uint(uint_ty) if bytes.len() == uint_width(*uint_ty) as usize / 8 => {
+            buf[..bytes.len()].copy_from_slice(bytes);
+            Value::Scalar(Literal::Uint(u128::from_le_bytes(buf), *uint_ty))
+        }
+        TyKind::Float(FloatTy::F32) if bytes.len() == 4 => {
+            buf[..4].copy_from_slice(bytes);
+            let value = f32::from_bits(u128::from_le_bytes(buf) as u32);
+            Value::Scalar(Literal::Float(value as f64, FloatTy::F32))
+        }
+        TyKind::Float(FloatTy::F64) if bytes.len() == 8 => {
+            buf[..8].copy_from_slice(bytes);
+            let value = f64::from_bits(u128::from_le_bytes(buf) as u64);
+            Value::Scalar(Literal::Float(value, FloatTy::F64))
+        }
+        TyKind::Bool if bytes.len() == 1 => match bytes[0] {
+            0 | 1 => Value::Scalar(Literal::Bool(bytes[0] == 1)),
+            byte => return ub(format!("{byte} is not a valid bool")),
+        },
+        TyKind::Char if bytes.len() == 4 => {
+            let value = u32::from_le_bytes(bytes.try_into().expect("4 bytes"));
+            match char::from_u32(value) {
+                Some(value) => Value::Scalar(Literal::Char(value)),
+                None => return ub(format!("{value:#x} is not a valid char")),
+            }
+        }
+        TyKind::Array(elem, len) if *len > 0 && bytes.len().is_multiple_of(*len) => Value::Array(
+            bytes
+                .chunks(bytes.len() / len)
+                .map(|chunk| from_bytes(tcx, *elem, chunk))
+                .collect::<Eval<_>>()?,
+        ),
+        TyKind::Array(_, 0) if bytes.is_empty() => Value::Array(vec![]),
+        _ => {
+            return unsupported(format!(
+                "can't transmute {} bytes into {}",
+                bytes.len(),
+                ty.serialize(tcx)
+            ))
+        }
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{DefaultHasher, Hash, Hasher};
+
+    use crate::parse::parse;
+
+    use super::{run, InterpError};
+
+    const STEP_LIMIT: usize = 1000;
+
+    /// A function whose first block is `bb0`, and which dumps `_1` to `_4`
+    fn program(bb0: &str) -> String {
+        format!(
+            r#"
+            #[custom_mir(dialect = "runtime", phase = "initial")]
+            pub fn fn0() -> () {{
+            mir! {{
+            type RET = ();
+            let _1: i32;
+            let _2: (i32, bool);
+            let _3: [u8; 2];
+            let _4: ();
+            let _5: u8;
+            let _6: u8;
+            {{
+            _1 = 2147483647_i32;
+            _2 = Checked(_1 + 1_i32);
+            _5 = _2.0 as u8;
+            _3 = [_5, 3_u8];
+            {bb0}
+            }}
+            bb1 = {{
+            Return()
+            }}
+            }}
+            }}
+            "#
+        )
+    }
+
+    fn error(bb0: &str) -> String {
+        let (program, tcx) = parse(&program(bb0)).unwrap();
+        run(&program, &tcx, STEP_LIMIT).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn run_hash() {
+        let (program, tcx) = parse(&program(
+            "Call(RET = dump_var(Move(_1), Move(_2), Move(_3), Move(_4)), ReturnTo(bb1), \
+            UnwindUnreachable())",
+        ))
+        .unwrap();
+
+        let mut hasher = DefaultHasher::new();
+        i32::MAX.hash(&mut hasher);
+        (i32::MIN, true).hash(&mut hasher);
+        // Unit writes nothing to the hasher
+        [0_u8, 3].hash(&mut hasher);
+        assert_eq!(
+            run(&program, &tcx, STEP_LIMIT).unwrap(),
+            format!("hash: {}\n", hasher.finish())
+        );
+    }
+
+    #[test]
+    fn run_debug() {
+        let (mut program, tcx) = parse(&program(
+            "Call(RET = dump_var(0_usize, 1_usize, Move(_1), 2_usize, Move(_2), 3_usize, \
+            Move(_3), 4_usize, Move(_4)), ReturnTo(bb1), UnwindUnreachable())",
+        ))
+        .unwrap();
+        program.use_debug_dumper = true;
+        assert_eq!(
+            run(&program, &tcx, STEP_LIMIT).unwrap(),
+            "fn0:_1 = 2147483647\n_2 = (-2147483648, true)\n_3 = [0, 3]\n_4 = ()\n"
+        );
+    }
+
+    #[test]
+    fn run_errors() {
+        assert_eq!(
+            error("_6 = _5 / _6;\nGoto(bb1)"),
+            "Undefined Behavior at fn0 bb0[4]: using an uninitialized value"
+        );
+        assert_eq!(
+            error("_6 = 0_u8;\n_6 = _5 / _6;\nGoto(bb1)"),
+            "Undefined Behavior at fn0 bb0[5]: dividing by zero"
+        );
+        assert_eq!(
+            error("Unreachable()"),
+            "Undefined Behavior at fn0 bb0 terminator: entering unreachable code"
+        );
+
+        let (program, tcx) = parse(&program("Goto(bb0)")).unwrap();
+        assert!(matches!(
+            run(&program, &tcx, STEP_LIMIT),
+            Err(InterpError::StepLimit)
+        ));
+    }
+}
@@ -1,6 +1,12 @@
 #![feature(iter_intersperse)]
 #![feature(box_patterns)]
 
+pub mod analysis;
+pub mod ast;
+pub mod interpret;
+pub mod parse;
 pub mod serialize;
 pub mod syntax;
 pub mod tyctxt;
+pub mod validate;
+pub mod visit;
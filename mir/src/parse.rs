@@ -0,0 +1,1769 @@
+//! Parser for the custom MIR dialect that `Program::serialize` emits, so that generated,
+//! reduced or hand-edited programs can be turned back into a `Program` and its `TyCtxt`
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Mutex,
+};
+
+use index_vec::IndexVec;
+
+use crate::{
+    syntax::*,
+    tyctxt::{AdtMeta, TyCtxt},
+};
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+type Result<T> = std::result::Result<T, ParseError>;
+
+/// Parses a program in any call syntax, along with the ADT definitions that
+/// `TyCtxt::serialize` appends to it.
+///
+/// ADTs keep the ids their names give, so a parsed program serializes back to the
+/// same text. Only what the serializer writes is recovered: locals declared in the
+/// body are mutable, and every function of the lower crate of a split program is public.
+pub fn parse(src: &str) -> Result<(Program, TyCtxt)> {
+    let mut parser = Parser::new(tokenize(src)?);
+    parser.reserve_adts()?;
+    parser.items(Pass::Adts, false)?;
+    parser.expect_end()?;
+    parser.pos = 0;
+    parser.items(Pass::Items, false)?;
+    parser.expect_end()?;
+    parser.finish()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    /// Digits with an optional fraction, and the type suffix if there is one
+    Number(String, Option<String>),
+    Char(char),
+    Str(String),
+    Lifetime(String),
+    Punct(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "`{ident}`"),
+            Token::Number(digits, Some(suffix)) => write!(f, "`{digits}_{suffix}`"),
+            Token::Number(digits, None) => write!(f, "`{digits}`"),
+            Token::Char(c) => write!(f, "{c:?}"),
+            Token::Str(str) => write!(f, "{str:?}"),
+            Token::Lifetime(lifetime) => write!(f, "`'{lifetime}`"),
+            Token::Punct(punct) => write!(f, "`{punct}`"),
+        }
+    }
+}
+
+/// Longer punctuation comes first, so that it is matched greedily
+const PUNCTS: [&str; 36] = [
+    "::", "->", "=>", "==", "!=", "<=", ">=", "<<", ">>", ":", ";", ",", ".", "=", "<", ">", "!",
+    "#", "(", ")", "[", "]", "{", "}", "+", "-", "*", "/", "%", "^", "&", "|", "?", "@", "$", "~",
+];
+
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens: Vec<(Token, usize)> = vec![];
+    let mut line = 1;
+    let mut i = 0;
+    let error = |line, message: String| ParseError { line, message };
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c == '\n' {
+            line += 1;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '/' && next == Some('*') {
+            let start = line;
+            let mut depth = 0;
+            loop {
+                match (chars.get(i), chars.get(i + 1)) {
+                    (Some('/'), Some('*')) => {
+                        depth += 1;
+                        i += 2;
+                    }
+                    (Some('*'), Some('/')) => {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    (Some(c), _) => {
+                        if *c == '\n' {
+                            line += 1;
+                        }
+                        i += 1;
+                    }
+                    (None, _) => return Err(error(start, "unterminated comment".to_owned())),
+                }
+            }
+            continue;
+        }
+
+        let start = i;
+        let token = if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            Token::Ident(chars[start..i].iter().collect())
+        } else if c.is_ascii_digit() {
+            let digit = |i: usize| chars.get(i).is_some_and(char::is_ascii_digit);
+            while digit(i) || (chars.get(i) == Some(&'_') && digit(i + 1)) {
+                i += 1;
+            }
+            // In `_1.0.1`, the fields are separate numbers
+            let after_dot = matches!(tokens.last(), Some((Token::Punct("."), _)));
+            if !after_dot && chars.get(i) == Some(&'.') && digit(i + 1) {
+                i += 1;
+                while digit(i) {
+                    i += 1;
+                }
+            }
+            let digits: String = chars[start..i].iter().filter(|c| **c != '_').collect();
+            let suffix = if chars.get(i) == Some(&'_')
+                && chars.get(i + 1).is_some_and(|c| c.is_alphabetic())
+            {
+                i += 1;
+                let start = i;
+                while i < chars.len() && chars[i].is_alphanumeric() {
+                    i += 1;
+                }
+                Some(chars[start..i].iter().collect())
+            } else {
+                None
+            };
+            Token::Number(digits, suffix)
+        } else if c == '\'' {
+            match (next, chars.get(i + 2)) {
+                (Some('\\'), _) => {
+                    let (c, len) = escape(&chars[i + 1..])
+                        .ok_or_else(|| error(line, "invalid escape".to_owned()))?;
+                    i += 1 + len;
+                    if chars.get(i) != Some(&'\'') {
+                        return Err(error(line, "unterminated character literal".to_owned()));
+                    }
+                    i += 1;
+                    Token::Char(c)
+                }
+                (Some(c), Some('\'')) => {
+                    i += 3;
+                    Token::Char(c)
+                }
+                _ => {
+                    i += 1;
+                    while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                        i += 1;
+                    }
+                    Token::Lifetime(chars[start + 1..i].iter().collect())
+                }
+            }
+        } else if c == '"' {
+            let start = line;
+            let mut str = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    Some('"') => break,
+                    Some('\\') => {
+                        let (c, len) = escape(&chars[i..])
+                            .ok_or_else(|| error(line, "invalid escape".to_owned()))?;
+                        str.push(c);
+                        i += len;
+                    }
+                    Some(c) => {
+                        if *c == '\n' {
+                            line += 1;
+                        }
+                        str.push(*c);
+                        i += 1;
+                    }
+                    None => return Err(error(start, "unterminated string".to_owned())),
+                }
+            }
+            i += 1;
+            Token::Str(str)
+        } else {
+            let punct = PUNCTS
+                .iter()
+                .find(|punct| {
+                    let punct: Vec<char> = punct.chars().collect();
+                    chars[i..].starts_with(&punct)
+                })
+                .ok_or_else(|| error(line, format!("unexpected character {c:?}")))?;
+            i += punct.len();
+            Token::Punct(punct)
+        };
+        tokens.push((token, line));
+    }
+    Ok(tokens)
+}
+
+/// The character a backslash escape stands for, and how long the escape is
+fn escape(chars: &[char]) -> Option<(char, usize)> {
+    let c = match chars.get(1)? {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        '\\' => '\\',
+        '\'' => '\'',
+        '"' => '"',
+        'u' => {
+            let end = chars.iter().position(|c| *c == '}')?;
+            let hex: String = chars.get(3..end)?.iter().collect();
+            return Some((
+                char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?,
+                end + 1,
+            ));
+        }
+        _ => return None,
+    };
+    Some((c, 2))
+}
+
+/// The index in a name like `fn3` or `_3`
+fn numbered(name: &str, prefix: &str) -> Option<usize> {
+    let digits = name.strip_prefix(prefix)?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// The type, method, ordering and concurrency of an atomic helper, from its name after
+/// `atomic_`
+fn atomic_helper(name: &str) -> Option<(AtomicTy, AtomicMethod, AtomicOrdering, bool)> {
+    let (name, concurrent) = match name.strip_suffix("_concurrently") {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    let (ty, name) = name.split_once('_')?;
+    let ty = match ty {
+        "ptr" => AtomicTy::Ptr,
+        "isize" => AtomicTy::Int(TyCtxt::ISIZE),
+        "i8" => AtomicTy::Int(TyCtxt::I8),
+        "i16" => AtomicTy::Int(TyCtxt::I16),
+        "i32" => AtomicTy::Int(TyCtxt::I32),
+        "i64" => AtomicTy::Int(TyCtxt::I64),
+        "usize" => AtomicTy::Int(TyCtxt::USIZE),
+        "u8" => AtomicTy::Int(TyCtxt::U8),
+        "u16" => AtomicTy::Int(TyCtxt::U16),
+        "u32" => AtomicTy::Int(TyCtxt::U32),
+        "u64" => AtomicTy::Int(TyCtxt::U64),
+        _ => return None,
+    };
+    AtomicMethod::ALL.into_iter().find_map(|method| {
+        let ordering = name.strip_prefix(method.name())?.strip_prefix('_')?;
+        let ordering = AtomicOrdering::ALL
+            .into_iter()
+            .find(|o| o.snake_case() == ordering)?;
+        Some((ty, method, ordering, concurrent))
+    })
+}
+
+/// Callee names are `&'static str`, so each distinct parsed one is leaked once
+pub(crate) fn intern(name: &str) -> &'static str {
+    static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+    let mut names = NAMES.lock().unwrap();
+    if let Some(name) = names.get(name) {
+        return name;
+    }
+    let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+    names.insert(name);
+    name
+}
+
+/// ADTs can be used before they are defined, so their definitions are parsed in a
+/// pass of their own before everything else
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Pass {
+    Adts,
+    Items,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    tcx: TyCtxt,
+    /// Placeholders between the ADTs, which structural types can be put in
+    holes: BTreeSet<TyId>,
+    functions: BTreeMap<usize, Body>,
+    consts: BTreeMap<usize, ConstValue>,
+    foreign_functions: BTreeMap<usize, Vec<TyId>>,
+    entry_args: Vec<Literal>,
+    use_debug_dumper: bool,
+    split: Option<usize>,
+}
+
+impl Parser {
+    fn new(tokens: Vec<(Token, usize)>) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            tcx: TyCtxt::from_primitives(),
+            holes: BTreeSet::new(),
+            functions: BTreeMap::new(),
+            consts: BTreeMap::new(),
+            foreign_functions: BTreeMap::new(),
+            entry_args: vec![],
+            use_debug_dumper: false,
+            split: None,
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        let line = self
+            .tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line);
+        ParseError {
+            line,
+            message: message.into(),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + n).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.error("unexpected end of input"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T> {
+        Err(match self.peek() {
+            Some(token) => self.error(format!("expected {expected}, found {token}")),
+            None => self.error(format!("expected {expected}, found end of input")),
+        })
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+
+    fn is_ident(&self, ident: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(i)) if i == ident)
+    }
+
+    /// Whether a call of `name` comes next
+    fn is_call(&self, name: &str) -> bool {
+        matches!(
+            (self.peek(), self.peek_nth(1)),
+            (Some(Token::Ident(ident)), Some(Token::Punct("("))) if ident == name
+        )
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = self.is_punct(punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_ident(&mut self, ident: &str) -> bool {
+        let found = self.is_ident(ident);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<()> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("`{punct}`"))
+        }
+    }
+
+    fn expect_ident(&mut self, ident: &str) -> Result<()> {
+        if self.eat_ident(ident) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("`{ident}`"))
+        }
+    }
+
+    /// Expects a path like `core::intrinsics`
+    fn expect_path(&mut self, path: &str) -> Result<()> {
+        for (i, segment) in path.split("::").enumerate() {
+            if i > 0 {
+                self.expect_punct("::")?;
+            }
+            self.expect_ident(segment)?;
+        }
+        Ok(())
+    }
+
+    fn expect_end(&self) -> Result<()> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => self.unexpected("an item"),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Token::Ident(ident)) => {
+                let ident = ident.clone();
+                self.pos += 1;
+                Ok(ident)
+            }
+            _ => self.unexpected("an identifier"),
+        }
+    }
+
+    /// An identifier like `fn3`, returning the index
+    fn numbered(&mut self, prefix: &str) -> Result<usize> {
+        let index = match self.peek() {
+            Some(Token::Ident(ident)) => numbered(ident, prefix),
+            _ => None,
+        };
+        match index {
+            Some(index) => {
+                self.pos += 1;
+                Ok(index)
+            }
+            None => self.unexpected(&format!("`{prefix}N`")),
+        }
+    }
+
+    /// A number without a type suffix
+    fn index(&mut self) -> Result<usize> {
+        let index = match self.peek() {
+            Some(Token::Number(digits, None)) => digits.parse().ok(),
+            _ => None,
+        };
+        match index {
+            Some(index) => {
+                self.pos += 1;
+                Ok(index)
+            }
+            None => self.unexpected("an index"),
+        }
+    }
+
+    /// Skips a bracketed token tree
+    fn skip_group(&mut self) -> Result<()> {
+        let mut depth = 0usize;
+        loop {
+            match self.next()? {
+                Token::Punct("(" | "[" | "{") => depth += 1,
+                Token::Punct(")" | "]" | "}") => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Skips to just after the next `;` outside of brackets
+    fn skip_statement(&mut self) -> Result<()> {
+        loop {
+            match self.peek() {
+                Some(Token::Punct(";")) => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(Token::Punct("(" | "[" | "{")) => self.skip_group()?,
+                _ => {
+                    self.next()?;
+                }
+            }
+        }
+    }
+
+    /// Skips a function signature, and its body if it has one. Also skips other items
+    /// ending in a `;` or a block, such as tuple structs and impls.
+    fn skip_fn(&mut self) -> Result<()> {
+        loop {
+            match self.peek() {
+                Some(Token::Punct(";")) => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(Token::Punct("{")) => return self.skip_group(),
+                Some(Token::Punct("(" | "[")) => self.skip_group()?,
+                _ => {
+                    self.next()?;
+                }
+            }
+        }
+    }
+
+    /// Sets aside the ids that the ADT names give, filling in placeholders up to them
+    fn reserve_adts(&mut self) -> Result<()> {
+        let mut adts = BTreeSet::new();
+        for window in self.tokens.windows(2) {
+            let [(Token::Ident(keyword), _), (Token::Ident(name), line)] = window else {
+                continue;
+            };
+            let index = match numbered(name, "Adt") {
+                Some(index) if keyword == "struct" || keyword == "enum" => index,
+                _ => continue,
+            };
+            if index < self.tcx.len() || !adts.insert(TyId::new(index)) {
+                return Err(ParseError {
+                    line: *line,
+                    message: format!("{name} is already a type"),
+                });
+            }
+        }
+        if let Some(last) = adts.last() {
+            while self.tcx.len() <= last.index() {
+                let id = self.tcx.push(TyKind::Unit);
+                if !adts.contains(&id) {
+                    self.holes.insert(id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The id of a structural type, adding it if it is new
+    fn intern(&mut self, kind: TyKind) -> TyId {
+        if let Some((id, _)) = self
+            .tcx
+            .iter_enumerated()
+            .find(|(id, existing)| !self.holes.contains(id) && **existing == kind)
+        {
+            return id;
+        }
+        match self.holes.pop_first() {
+            Some(id) => {
+                *self.tcx.kind_mut(id) = kind;
+                id
+            }
+            None => self.tcx.push(kind),
+        }
+    }
+
+    fn ty(&mut self) -> Result<TyId> {
+        let kind = match self.next()? {
+            Token::Punct("(") => {
+                let mut elems = vec![];
+                let mut trailing_comma = false;
+                while !self.eat_punct(")") {
+                    elems.push(self.ty()?);
+                    trailing_comma = self.eat_punct(",");
+                    if !trailing_comma && !self.is_punct(")") {
+                        return self.unexpected("`,` or `)`");
+                    }
+                }
+                match elems.as_slice() {
+                    [] => return Ok(TyCtxt::UNIT),
+                    [elem] if !trailing_comma => return Ok(*elem),
+                    _ => TyKind::Tuple(elems),
+                }
+            }
+            Token::Punct("*") => {
+                let mutability = if self.eat_ident("mut") {
+                    Mutability::Mut
+                } else {
+                    self.expect_ident("const")?;
+                    Mutability::Not
+                };
+                TyKind::RawPtr(self.ty()?, mutability)
+            }
+            Token::Punct("&") => {
+                if let Some(Token::Lifetime(_)) = self.peek() {
+                    self.pos += 1;
+                }
+                let mutability = if self.eat_ident("mut") {
+                    Mutability::Mut
+                } else {
+                    Mutability::Not
+                };
+                TyKind::Ref(self.ty()?, mutability)
+            }
+            Token::Punct("[") => {
+                let elem = self.ty()?;
+                self.expect_punct(";")?;
+                let len = self.index()?;
+                self.expect_punct("]")?;
+                TyKind::Array(elem, len)
+            }
+            Token::Ident(name) => {
+                return Ok(match name.as_str() {
+                    "bool" => TyCtxt::BOOL,
+                    "char" => TyCtxt::CHAR,
+                    "isize" => TyCtxt::ISIZE,
+                    "i8" => TyCtxt::I8,
+                    "i16" => TyCtxt::I16,
+                    "i32" => TyCtxt::I32,
+                    "i64" => TyCtxt::I64,
+                    "i128" => TyCtxt::I128,
+                    "usize" => TyCtxt::USIZE,
+                    "u8" => TyCtxt::U8,
+                    "u16" => TyCtxt::U16,
+                    "u32" => TyCtxt::U32,
+                    "u64" => TyCtxt::U64,
+                    "u128" => TyCtxt::U128,
+                    "f32" => TyCtxt::F32,
+                    "f64" => TyCtxt::F64,
+                    _ => {
+                        self.pos -= 1;
+                        self.adt()?
+                    }
+                });
+            }
+            _ => {
+                self.pos -= 1;
+                return self.unexpected("a type");
+            }
+        };
+        Ok(self.intern(kind))
+    }
+
+    /// A use of an ADT by name
+    fn adt(&mut self) -> Result<TyId> {
+        let index = self.numbered("Adt")?;
+        let ty = TyId::new(index);
+        if index >= self.tcx.len() || self.holes.contains(&ty) {
+            self.pos -= 1;
+            return Err(self.error(format!("Adt{index} is not defined")));
+        }
+        Ok(ty)
+    }
+
+    fn attribute(&mut self) -> Result<Vec<Token>> {
+        self.expect_punct("#")?;
+        self.eat_punct("!");
+        let start = self.pos;
+        if !self.is_punct("[") {
+            return self.unexpected("`[`");
+        }
+        self.skip_group()?;
+        Ok(self.tokens[start + 1..self.pos - 1]
+            .iter()
+            .map(|(token, _)| token.clone())
+            .collect())
+    }
+
+    /// Items up to the end of the input or of the enclosing module
+    fn items(&mut self, pass: Pass, lower: bool) -> Result<()> {
+        let mut attrs = vec![];
+        loop {
+            match self.peek() {
+                None | Some(Token::Punct("}")) => return Ok(()),
+                Some(Token::Punct("#")) => attrs.push(self.attribute()?),
+                _ => {
+                    self.item(pass, lower, &attrs)?;
+                    attrs.clear();
+                }
+            }
+        }
+    }
+
+    fn item(&mut self, pass: Pass, lower: bool, attrs: &[Vec<Token>]) -> Result<()> {
+        let mut public = false;
+        let mut abi = Abi::Rust;
+        loop {
+            if self.eat_ident("pub") {
+                public = true;
+            } else if self.eat_ident("unsafe") {
+            } else if self.eat_ident("extern") {
+                match self.peek() {
+                    Some(Token::Str(name)) => {
+                        abi = match name.as_str() {
+                            "Rust" => Abi::Rust,
+                            "C" => Abi::C,
+                            "system" => Abi::System,
+                            "sysv64" => Abi::SysV64,
+                            "win64" => Abi::Win64,
+                            _ => return Err(self.error(format!("unknown ABI {name:?}"))),
+                        };
+                        self.pos += 1;
+                    }
+                    Some(Token::Ident(crate_)) if crate_ == "crate" => {
+                        return self.skip_statement();
+                    }
+                    _ => abi = Abi::C,
+                }
+            } else {
+                break;
+            }
+        }
+        // An `extern` block
+        if self.eat_punct("{") {
+            self.items(pass, lower)?;
+            return self.expect_punct("}");
+        }
+
+        match self.ident()?.as_str() {
+            "fn" => {
+                let name = self.ident()?;
+                if pass == Pass::Adts {
+                    return self.skip_fn();
+                }
+                if let Some(index) = numbered(&name, "fn") {
+                    self.function(index, public, abi, lower, attrs)
+                } else if let Some(index) = numbered(&name, "cfn") {
+                    self.foreign_function(index)
+                } else if name == "main" {
+                    self.main()
+                } else {
+                    // The debug dumper also takes the function and the variable numbers
+                    if name == "dump_var"
+                        && matches!(self.peek_nth(1), Some(Token::Ident(param)) if param == "f")
+                    {
+                        self.use_debug_dumper = true;
+                    }
+                    self.skip_fn()
+                }
+            }
+            "const" => {
+                let name = self.ident()?;
+                match numbered(&name, "CONST") {
+                    Some(index) if pass == Pass::Items => self.const_item(index),
+                    _ => self.skip_statement(),
+                }
+            }
+            keyword @ ("struct" | "enum") => {
+                let enum_ = keyword == "enum";
+                let name = self.ident()?;
+                match numbered(&name, "Adt") {
+                    Some(index) if pass == Pass::Adts => self.adt_def(index, enum_, attrs),
+                    Some(_) => self.skip_group(),
+                    // Helpers of the generated program, such as `Unshared`
+                    None => self.skip_fn(),
+                }
+            }
+            "impl" => self.skip_fn(),
+            "mod" => {
+                self.ident()?;
+                self.expect_punct("{")?;
+                self.items(pass, true)?;
+                self.expect_punct("}")
+            }
+            "use" | "static" => self.skip_statement(),
+            keyword => {
+                self.pos -= 1;
+                Err(self.error(format!("unexpected `{keyword}`")))
+            }
+        }
+    }
+
+    fn adt_def(&mut self, index: usize, enum_: bool, attrs: &[Vec<Token>]) -> Result<()> {
+        let ident = |name: &str| Token::Ident(name.to_owned());
+        let meta = AdtMeta {
+            copy: attrs.iter().any(|attr| {
+                attr.first() == Some(&ident("derive")) && attr.contains(&ident("Copy"))
+            }),
+            repr_c: attrs.iter().any(|attr| {
+                *attr
+                    == [
+                        ident("repr"),
+                        Token::Punct("("),
+                        ident("C"),
+                        Token::Punct(")"),
+                    ]
+            }),
+        };
+
+        self.expect_punct("{")?;
+        let mut variants = IndexVec::new();
+        if enum_ {
+            while !self.eat_punct("}") {
+                let vid = self.numbered("Variant")?;
+                if vid != variants.len() {
+                    self.pos -= 1;
+                    return Err(self.error(format!("expected Variant{}", variants.len())));
+                }
+                self.expect_punct("{")?;
+                variants.push(self.variant_def()?);
+                if !self.eat_punct(",") && !self.is_punct("}") {
+                    return self.unexpected("`,` or `}`");
+                }
+            }
+        } else {
+            variants.push(self.variant_def()?);
+        }
+        self.tcx
+            .define_adt(TyId::new(index), Adt { variants }, meta);
+        Ok(())
+    }
+
+    /// The fields of a variant, after the opening brace
+    fn variant_def(&mut self) -> Result<VariantDef> {
+        let mut fields = IndexVec::new();
+        while !self.eat_punct("}") {
+            self.eat_ident("pub");
+            self.field_name(fields.len())?;
+            self.expect_punct(":")?;
+            fields.push(self.ty()?);
+            if !self.eat_punct(",") && !self.is_punct("}") {
+                return self.unexpected("`,` or `}`");
+            }
+        }
+        Ok(VariantDef { fields })
+    }
+
+    /// Fields have to be written in order, since their names are their indices
+    fn field_name(&mut self, expected: usize) -> Result<()> {
+        if self.numbered("fld")? != expected {
+            self.pos -= 1;
+            return Err(self.error(format!("expected fld{expected}")));
+        }
+        Ok(())
+    }
+
+    fn const_item(&mut self, index: usize) -> Result<()> {
+        self.expect_punct(":")?;
+        self.ty()?;
+        self.expect_punct("=")?;
+        let value = self.const_value()?;
+        self.expect_punct(";")?;
+        if self.consts.insert(index, value).is_some() {
+            return Err(self.error(format!("CONST{index} is defined twice")));
+        }
+        Ok(())
+    }
+
+    fn const_value(&mut self) -> Result<ConstValue> {
+        if let Some(lit) = self.literal()? {
+            return Ok(ConstValue::Scalar(lit));
+        }
+        if self.eat_punct("[") {
+            let fields = IndexVec::from_vec(self.list("]", Self::const_value)?);
+            let Some(first) = fields.first() else {
+                return Err(self.error("empty arrays have no type"));
+            };
+            let elem = first.ty();
+            let ty = self.intern(TyKind::Array(elem, fields.len()));
+            Ok(ConstValue::Aggregate(
+                ty,
+                AggregateKind::Array(elem),
+                fields,
+            ))
+        } else if self.eat_punct("(") {
+            let fields = IndexVec::from_vec(self.list(")", Self::const_value)?);
+            let ty = if fields.is_empty() {
+                TyCtxt::UNIT
+            } else {
+                self.intern(TyKind::Tuple(fields.iter().map(|f| f.ty()).collect()))
+            };
+            Ok(ConstValue::Aggregate(ty, AggregateKind::Tuple, fields))
+        } else {
+            let (ty, variant) = self.adt_path()?;
+            self.expect_punct("{")?;
+            let mut fields = IndexVec::new();
+            while !self.eat_punct("}") {
+                self.field_name(fields.len())?;
+                self.expect_punct(":")?;
+                fields.push(self.const_value()?);
+                if !self.eat_punct(",") && !self.is_punct("}") {
+                    return self.unexpected("`,` or `}`");
+                }
+            }
+            Ok(ConstValue::Aggregate(
+                ty,
+                AggregateKind::Adt(ty, variant),
+                fields,
+            ))
+        }
+    }
+
+    /// `AdtN` or `AdtN::VariantM`
+    fn adt_path(&mut self) -> Result<(TyId, VariantIdx)> {
+        let ty = self.adt()?;
+        let variant = if self.eat_punct("::") {
+            self.numbered("Variant")?
+        } else {
+            0
+        };
+        Ok((ty, VariantIdx::new(variant)))
+    }
+
+    /// Comma separated elements up to `close`, which a single element tuple may precede
+    fn list<T>(
+        &mut self,
+        close: &str,
+        mut element: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let mut elems = vec![];
+        while !self.eat_punct(close) {
+            elems.push(element(self)?);
+            if !self.eat_punct(",") && !self.is_punct(close) {
+                return self.unexpected(&format!("`,` or `{close}`"));
+            }
+        }
+        Ok(elems)
+    }
+
+    /// A literal, if one comes next
+    fn literal(&mut self) -> Result<Option<Literal>> {
+        let lit = match (self.peek(), self.peek_nth(1), self.peek_nth(2)) {
+            (Some(Token::Number(digits, suffix)), _, _) => {
+                let lit = self.number(digits, suffix.as_deref(), false)?;
+                self.pos += 1;
+                lit
+            }
+            (Some(Token::Punct("-")), Some(Token::Number(digits, suffix)), _) => {
+                let lit = self.number(digits, suffix.as_deref(), true)?;
+                self.pos += 2;
+                lit
+            }
+            (
+                Some(Token::Punct("(")),
+                Some(Token::Punct("-")),
+                Some(Token::Number(digits, suffix)),
+            ) => {
+                let lit = self.number(digits, suffix.as_deref(), true)?;
+                self.pos += 3;
+                self.expect_punct(")")?;
+                lit
+            }
+            (Some(Token::Ident(b)), _, _) if b == "true" || b == "false" => {
+                let lit = Literal::Bool(b == "true");
+                self.pos += 1;
+                lit
+            }
+            (Some(Token::Char(c)), _, _) => {
+                let lit = Literal::Char(*c);
+                self.pos += 1;
+                lit
+            }
+            (Some(Token::Ident(ty)), Some(Token::Punct("::")), Some(Token::Ident(value)))
+                if ty == "f32" || ty == "f64" =>
+            {
+                let value = match value.as_str() {
+                    "NAN" => f64::NAN,
+                    "INFINITY" => f64::INFINITY,
+                    "NEG_INFINITY" => f64::NEG_INFINITY,
+                    _ => return Ok(None),
+                };
+                let ty = if ty == "f32" {
+                    FloatTy::F32
+                } else {
+                    FloatTy::F64
+                };
+                self.pos += 3;
+                Literal::Float(value, ty)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(lit))
+    }
+
+    fn number(&self, digits: &str, suffix: Option<&str>, negative: bool) -> Result<Literal> {
+        let invalid = || self.error(format!("invalid literal {digits}"));
+        let int = |ty| {
+            let value: u128 = digits.parse().map_err(|_| invalid())?;
+            let value = if negative {
+                if value > 1 << 127 {
+                    return Err(invalid());
+                }
+                (value as i128).wrapping_neg()
+            } else {
+                i128::try_from(value).map_err(|_| invalid())?
+            };
+            Ok(Literal::Int(value, ty))
+        };
+        let uint = |ty| {
+            if negative {
+                return Err(invalid());
+            }
+            Ok(Literal::Uint(digits.parse().map_err(|_| invalid())?, ty))
+        };
+        let float = |ty| {
+            let value: f64 = digits.parse().map_err(|_| invalid())?;
+            Ok(Literal::Float(if negative { -value } else { value }, ty))
+        };
+        match suffix {
+            Some("isize") => int(IntTy::Isize),
+            Some("i8") => int(IntTy::I8),
+            Some("i16") => int(IntTy::I16),
+            Some("i32") => int(IntTy::I32),
+            Some("i64") => int(IntTy::I64),
+            Some("i128") => int(IntTy::I128),
+            Some("usize") => uint(UintTy::Usize),
+            Some("u8") => uint(UintTy::U8),
+            Some("u16") => uint(UintTy::U16),
+            Some("u32") => uint(UintTy::U32),
+            Some("u64") => uint(UintTy::U64),
+            Some("u128") => uint(UintTy::U128),
+            Some("f32") => float(FloatTy::F32),
+            Some("f64") => float(FloatTy::F64),
+            _ => Err(self.error(format!("literal {digits} needs a type suffix"))),
+        }
+    }
+
+    fn foreign_function(&mut self, index: usize) -> Result<()> {
+        self.expect_punct("(")?;
+        let params = self.list(")", |parser: &mut Self| {
+            parser.ident()?;
+            parser.expect_punct(":")?;
+            parser.ty()
+        })?;
+        // Declared both as an import and as a stand-in, with the same parameters
+        self.foreign_functions.entry(index).or_insert(params);
+        self.skip_fn()
+    }
+
+    fn main(&mut self) -> Result<()> {
+        self.expect_punct("(")?;
+        self.expect_punct(")")?;
+        self.expect_punct("{")?;
+        self.numbered("fn")?;
+        self.expect_punct("(")?;
+        self.entry_args = self.list(")", |parser: &mut Self| {
+            let black_box = parser.eat_ident("std");
+            if black_box {
+                parser.expect_punct("::")?;
+                parser.expect_path("hint::black_box")?;
+                parser.expect_punct("(")?;
+            }
+            let Some(lit) = parser.literal()? else {
+                return parser.unexpected("a literal");
+            };
+            if black_box {
+                parser.expect_punct(")")?;
+            }
+            Ok(lit)
+        })?;
+        // The rest prints the hash
+        let mut depth = 1usize;
+        while depth > 0 {
+            match self.next()? {
+                Token::Punct("(" | "[" | "{") => depth += 1,
+                Token::Punct(")" | "]" | "}") => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn function(
+        &mut self,
+        index: usize,
+        public: bool,
+        abi: Abi,
+        lower: bool,
+        attrs: &[Vec<Token>],
+    ) -> Result<()> {
+        self.expect_punct("(")?;
+        let params = self.list(")", |parser: &mut Self| {
+            let mutability = if parser.eat_ident("mut") {
+                Mutability::Mut
+            } else {
+                Mutability::Not
+            };
+            let local = parser.ident()?;
+            parser.expect_punct(":")?;
+            Ok((local, mutability, parser.ty()?))
+        })?;
+        self.expect_punct("->")?;
+        let return_ty = self.ty()?;
+
+        let arg_tys: Vec<TyId> = params.iter().map(|(_, _, ty)| *ty).collect();
+        let mut body = Body::new(&arg_tys, return_ty, public);
+        let args: Vec<Local> = body.args_iter().collect();
+        for ((name, mutability, _), arg) in params.iter().zip(args) {
+            if *name != arg.identifier() {
+                return Err(self.error(format!("expected {} as parameter", arg.identifier())));
+            }
+            body.local_decls[arg].mutability = *mutability;
+        }
+        body.abi = abi;
+        body.inline = attrs
+            .iter()
+            .any(|attr| *attr == [Token::Ident("inline".to_owned())]);
+
+        self.expect_punct("{")?;
+        self.expect_ident("mir")?;
+        self.expect_punct("!")?;
+        self.expect_punct("{")?;
+        self.expect_ident("type")?;
+        self.expect_ident("RET")?;
+        self.expect_punct("=")?;
+        if self.ty()? != return_ty {
+            return Err(self.error("RET has a different type than the function returns"));
+        }
+        self.expect_punct(";")?;
+        while self.eat_ident("let") {
+            let expected = Local::new(body.local_decls.len());
+            if self.ident()? != expected.identifier() {
+                self.pos -= 1;
+                return Err(self.error(format!("expected {}", expected.identifier())));
+            }
+            self.expect_punct(":")?;
+            let ty = self.ty()?;
+            self.expect_punct(";")?;
+            body.declare_new_var(Mutability::Mut, ty);
+        }
+
+        self.expect_punct("{")?;
+        let bb = self.basic_block(&body)?;
+        body.new_basic_block(bb);
+        while !self.eat_punct("}") {
+            let index = self.numbered("bb")?;
+            if index != body.basic_blocks.len() {
+                self.pos -= 1;
+                return Err(self.error(format!("expected bb{}", body.basic_blocks.len())));
+            }
+            self.expect_punct("=")?;
+            self.expect_punct("{")?;
+            let bb = self.basic_block(&body)?;
+            body.new_basic_block(bb);
+        }
+        self.expect_punct("}")?;
+
+        if self.functions.insert(index, body).is_some() {
+            return Err(self.error(format!("fn{index} is defined twice")));
+        }
+        if lower {
+            self.split = Some(self.split.map_or(index, |split| split.min(index)));
+        }
+        Ok(())
+    }
+
+    /// The statements and the terminator of a basic block, after the opening brace
+    fn basic_block(&mut self, body: &Body) -> Result<BasicBlockData> {
+        let mut bb = BasicBlockData::new();
+        loop {
+            if let Some(term) = self.terminator(body)? {
+                bb.set_terminator(term);
+                self.eat_punct(";");
+                self.expect_punct("}")?;
+                return Ok(bb);
+            }
+            bb.insert_statement(self.statement(body)?);
+            self.expect_punct(";")?;
+        }
+    }
+
+    fn local(&mut self, body: &Body) -> Result<Local> {
+        let local = if self.eat_ident("RET") {
+            Local::RET
+        } else {
+            Local::new(self.numbered("_")?)
+        };
+        if local.index() >= body.local_decls.len() {
+            self.pos -= 1;
+            return Err(self.error(format!("{} is not declared", local.identifier())));
+        }
+        Ok(local)
+    }
+
+    fn basic_block_id(&mut self) -> Result<BasicBlock> {
+        Ok(BasicBlock::new(self.numbered("bb")?))
+    }
+
+    fn place(&mut self, body: &Body) -> Result<Place> {
+        let mut place = if self.eat_ident("place") {
+            self.expect_punct("!")?;
+            self.expect_punct("(")?;
+            let place = self.place(body)?;
+            self.expect_punct(")")?;
+            place
+        } else if self.eat_punct("(") {
+            self.expect_punct("*")?;
+            let mut place = self.place(body)?;
+            self.expect_punct(")")?;
+            place.project(ProjectionElem::Deref);
+            place
+        } else if self.eat_ident("Field") {
+            self.expect_punct("::")?;
+            self.expect_punct("<")?;
+            let ty = self.ty()?;
+            self.expect_punct(">")?;
+            self.expect_punct("(")?;
+            self.expect_ident("Variant")?;
+            self.expect_punct("(")?;
+            let mut place = self.place(body)?;
+            self.expect_punct(",")?;
+            let variant = VariantIdx::new(self.index()?);
+            self.expect_punct(")")?;
+            self.expect_punct(",")?;
+            let field = FieldIdx::new(self.index()?);
+            self.expect_punct(")")?;
+            place.project(ProjectionElem::DowncastField(variant, field, ty));
+            place
+        } else {
+            Place::from_local(self.local(body)?)
+        };
+
+        loop {
+            if self.eat_punct(".") {
+                let proj = match self.peek() {
+                    Some(Token::Number(..)) => {
+                        ProjectionElem::TupleField(FieldIdx::new(self.index()?))
+                    }
+                    _ => ProjectionElem::Field(FieldIdx::new(self.numbered("fld")?)),
+                };
+                place.project(proj);
+            } else if self.eat_punct("[") {
+                let proj = match self.peek() {
+                    Some(Token::Number(..)) => ProjectionElem::ConstantIndex {
+                        offset: self.index()? as u64,
+                    },
+                    _ => ProjectionElem::Index(self.local(body)?),
+                };
+                self.expect_punct("]")?;
+                place.project(proj);
+            } else {
+                return Ok(place);
+            }
+        }
+    }
+
+    fn operand(&mut self, body: &Body) -> Result<Operand> {
+        if self.eat_ident("Move") {
+            self.expect_punct("(")?;
+            let place = self.place(body)?;
+            self.expect_punct(")")?;
+            return Ok(Operand::Move(place));
+        }
+        if self.eat_ident("const") {
+            self.expect_punct("{")?;
+            let value = self.const_value()?;
+            self.expect_punct("}")?;
+            return Ok(Operand::Compound(CompoundConst::Block(value)));
+        }
+        let item = match self.peek() {
+            Some(Token::Ident(name)) => numbered(name, "CONST"),
+            _ => None,
+        };
+        if let Some(index) = item {
+            // Items come before the functions using them
+            let Some(value) = self.consts.get(&index) else {
+                return Err(self.error(format!("CONST{index} is not defined before its use")));
+            };
+            let operand =
+                Operand::Compound(CompoundConst::Item(ConstItem::new(index), value.clone()));
+            self.pos += 1;
+            return Ok(operand);
+        }
+        if let Some(lit) = self.literal()? {
+            return Ok(Operand::Constant(lit));
+        }
+        Ok(Operand::Copy(self.place(body)?))
+    }
+
+    fn bin_op(&mut self) -> Option<BinOp> {
+        let op = match self.peek() {
+            Some(Token::Punct(punct)) => match *punct {
+                "+" => BinOp::Add,
+                "-" => BinOp::Sub,
+                "*" => BinOp::Mul,
+                "/" => BinOp::Div,
+                "%" => BinOp::Rem,
+                "^" => BinOp::BitXor,
+                "&" => BinOp::BitAnd,
+                "|" => BinOp::BitOr,
+                "<<" => BinOp::Shl,
+                ">>" => BinOp::Shr,
+                "==" => BinOp::Eq,
+                "<" => BinOp::Lt,
+                "<=" => BinOp::Le,
+                "!=" => BinOp::Ne,
+                ">=" => BinOp::Ge,
+                ">" => BinOp::Gt,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.pos += 1;
+        Some(op)
+    }
+
+    /// The right hand side of an assignment to `dest`
+    fn rvalue(&mut self, body: &Body, dest: &Place) -> Result<Rvalue> {
+        if self.is_call("Checked") {
+            self.pos += 2;
+            let a = self.operand(body)?;
+            let Some(op) = self.bin_op() else {
+                return self.unexpected("a binary operator");
+            };
+            let b = self.operand(body)?;
+            self.expect_punct(")")?;
+            return Ok(Rvalue::CheckedBinaryOp(op, a, b));
+        }
+        if self.is_call("Offset") {
+            self.pos += 2;
+            let a = self.operand(body)?;
+            self.expect_punct(",")?;
+            let b = self.operand(body)?;
+            self.expect_punct(")")?;
+            return Ok(Rvalue::BinaryOp(BinOp::Offset, a, b));
+        }
+        if self.is_call("Len") || self.is_call("Discriminant") {
+            let len = self.is_call("Len");
+            self.pos += 2;
+            let place = self.place(body)?;
+            self.expect_punct(")")?;
+            return Ok(if len {
+                Rvalue::Len(place)
+            } else {
+                Rvalue::Discriminant(place)
+            });
+        }
+        if self.eat_ident("core") {
+            self.expect_punct("::")?;
+            self.expect_ident("ptr")?;
+            self.expect_punct("::")?;
+            let mutability = if self.eat_ident("addr_of_mut") {
+                Mutability::Mut
+            } else {
+                self.expect_ident("addr_of")?;
+                Mutability::Not
+            };
+            self.expect_punct("!")?;
+            self.expect_punct("(")?;
+            let place = self.place(body)?;
+            self.expect_punct(")")?;
+            return Ok(Rvalue::AddressOf(mutability, place));
+        }
+        if self.eat_punct("&") {
+            let mutability = if self.eat_ident("mut") {
+                Mutability::Mut
+            } else {
+                Mutability::Not
+            };
+            return Ok(Rvalue::Ref(mutability, self.place(body)?));
+        }
+        if self.eat_punct("!") {
+            return Ok(Rvalue::UnaryOp(UnOp::Not, self.operand(body)?));
+        }
+        if self.eat_punct("-") {
+            return Ok(Rvalue::UnaryOp(UnOp::Neg, self.operand(body)?));
+        }
+        if self.eat_punct("[") {
+            let TyKind::Array(elem, _) = dest.ty(&body.local_decls, &self.tcx).kind(&self.tcx)
+            else {
+                return Err(self.error("an array is assigned to a place that isn't one"));
+            };
+            let elem = *elem;
+            let operands = self.list("]", |parser: &mut Self| parser.operand(body))?;
+            return Ok(Rvalue::Aggregate(
+                AggregateKind::Array(elem),
+                IndexVec::from_vec(operands),
+            ));
+        }
+        // Unless it is a dereference or a negative literal
+        if self.is_punct("(")
+            && !matches!(
+                (self.peek_nth(1), self.peek_nth(2)),
+                (Some(Token::Punct("*")), _) | (Some(Token::Punct("-")), Some(Token::Number(..)))
+            )
+        {
+            self.pos += 1;
+            let operands = self.list(")", |parser: &mut Self| parser.operand(body))?;
+            return Ok(Rvalue::Aggregate(
+                AggregateKind::Tuple,
+                IndexVec::from_vec(operands),
+            ));
+        }
+        if matches!(self.peek(), Some(Token::Ident(name)) if numbered(name, "Adt").is_some()) {
+            let (ty, variant) = self.adt_path()?;
+            self.expect_punct("{")?;
+            let mut operands = IndexVec::new();
+            while !self.eat_punct("}") {
+                self.field_name(operands.len())?;
+                self.expect_punct(":")?;
+                operands.push(self.operand(body)?);
+                if !self.eat_punct(",") && !self.is_punct("}") {
+                    return self.unexpected("`,` or `}`");
+                }
+            }
+            return Ok(Rvalue::Aggregate(AggregateKind::Adt(ty, variant), operands));
+        }
+
+        let a = self.operand(body)?;
+        if self.eat_ident("as") {
+            return Ok(Rvalue::Cast(a, self.ty()?));
+        }
+        match self.bin_op() {
+            Some(op) => Ok(Rvalue::BinaryOp(op, a, self.operand(body)?)),
+            None => Ok(Rvalue::Use(a)),
+        }
+    }
+
+    fn statement(&mut self, body: &Body) -> Result<Statement> {
+        let name = match (self.peek(), self.peek_nth(1)) {
+            (Some(Token::Ident(name)), Some(Token::Punct("("))) => name.clone(),
+            _ => String::new(),
+        };
+        let stmt = match name.as_str() {
+            "StorageLive" | "StorageDead" | "Deinit" | "SetDiscriminant" | "Retag" => {
+                self.pos += 2;
+                let stmt = match name.as_str() {
+                    "StorageLive" => Statement::StorageLive(self.local(body)?),
+                    "StorageDead" => Statement::StorageDead(self.local(body)?),
+                    "Deinit" => Statement::Deinit(self.place(body)?),
+                    "Retag" => Statement::Retag(self.place(body)?),
+                    _ => {
+                        let place = self.place(body)?;
+                        self.expect_punct(",")?;
+                        let discr = self.index()?;
+                        let discr = u32::try_from(discr)
+                            .map_err(|_| self.error("discriminant out of range"))?;
+                        Statement::SetDiscriminant(place, discr)
+                    }
+                };
+                self.expect_punct(")")?;
+                stmt
+            }
+            _ => {
+                let place = self.place(body)?;
+                self.expect_punct("=")?;
+                let rvalue = self.rvalue(body, &place)?;
+                Statement::Assign(place, rvalue)
+            }
+        };
+        Ok(stmt)
+    }
+
+    /// The terminator of a basic block, if one comes next
+    fn terminator(&mut self, body: &Body) -> Result<Option<Terminator>> {
+        let Some(Token::Ident(name)) = self.peek().cloned() else {
+            return Ok(None);
+        };
+        if name == "match" {
+            self.pos += 1;
+            let discr = self.operand(body)?;
+            self.expect_punct("{")?;
+            let mut branches = vec![];
+            while !self.eat_ident("_") {
+                let value = match self.peek() {
+                    Some(Token::Number(digits, None)) => digits.parse().ok(),
+                    _ => None,
+                };
+                let Some(value) = value else {
+                    return self.unexpected("a value or `_`");
+                };
+                self.pos += 1;
+                self.expect_punct("=>")?;
+                branches.push((value, self.basic_block_id()?));
+                self.expect_punct(",")?;
+            }
+            self.expect_punct("=>")?;
+            let otherwise = self.basic_block_id()?;
+            self.eat_punct(",");
+            self.expect_punct("}")?;
+            return Ok(Some(Terminator::SwitchInt {
+                discr,
+                targets: SwitchTargets {
+                    branches,
+                    otherwise,
+                },
+            }));
+        }
+        if !matches!(self.peek_nth(1), Some(Token::Punct("("))) {
+            return Ok(None);
+        }
+        let term = match name.as_str() {
+            "Return" => {
+                self.pos += 2;
+                Terminator::Return
+            }
+            "Unreachable" => {
+                self.pos += 2;
+                Terminator::Unreachable
+            }
+            "Goto" => {
+                self.pos += 2;
+                Terminator::Goto {
+                    target: self.basic_block_id()?,
+                }
+            }
+            "Drop" => {
+                self.pos += 2;
+                let place = self.place(body)?;
+                self.expect_punct(",")?;
+                Terminator::Drop {
+                    place,
+                    target: self.basic_block_id()?,
+                }
+            }
+            "Call" => {
+                self.pos += 2;
+                let destination = self.place(body)?;
+                let (callee, args, target) = if self.eat_punct(",") {
+                    // Call(dest, bb, f(args))
+                    let target = self.basic_block_id()?;
+                    self.expect_punct(",")?;
+                    let (callee, args) = self.call(body)?;
+                    (callee, args, target)
+                } else {
+                    self.expect_punct("=")?;
+                    let (callee, args) = self.call(body)?;
+                    self.expect_punct(",")?;
+                    let target = if self.eat_ident("ReturnTo") {
+                        self.expect_punct("(")?;
+                        let target = self.basic_block_id()?;
+                        self.expect_punct(")")?;
+                        target
+                    } else {
+                        self.basic_block_id()?
+                    };
+                    if self.eat_punct(",") {
+                        self.expect_ident("UnwindUnreachable")?;
+                        self.expect_punct("(")?;
+                        self.expect_punct(")")?;
+                    }
+                    (callee, args, target)
+                };
+                Terminator::Call {
+                    callee,
+                    destination,
+                    target,
+                    args,
+                }
+            }
+            _ => return Ok(None),
+        };
+        self.expect_punct(")")?;
+        Ok(Some(term))
+    }
+
+    /// A callee and its arguments
+    fn call(&mut self, body: &Body) -> Result<(Callee, Vec<Operand>)> {
+        let name = self.ident()?;
+        let callee = if name == "core" {
+            self.expect_punct("::")?;
+            self.expect_ident("intrinsics")?;
+            self.expect_punct("::")?;
+            let name = intern(&self.ident()?);
+            if self.eat_punct("::") {
+                self.expect_punct("<")?;
+                while self.eat_ident("_") {
+                    self.expect_punct(",")?;
+                }
+                self.expect_punct("{")?;
+                self.expect_path("core::intrinsics::AtomicOrdering")?;
+                self.expect_punct("::")?;
+                let ordering = self.ident()?;
+                let Some(ordering) = AtomicOrdering::ALL
+                    .into_iter()
+                    .find(|o| o.name() == ordering)
+                else {
+                    self.pos -= 1;
+                    return Err(self.error(format!("unknown ordering {ordering}")));
+                };
+                self.expect_punct("}")?;
+                self.expect_punct(">")?;
+                Callee::AtomicIntrinsic(name, ordering)
+            } else {
+                Callee::Intrinsic(name)
+            }
+        } else if let Some(index) = numbered(&name, "fn") {
+            Callee::Generated(Function::new(index))
+        } else if let Some(index) = name
+            .strip_suffix("_on_worker")
+            .and_then(|func| numbered(func, "fn"))
+        {
+            Callee::Worker(Function::new(index))
+        } else if let Some(helper) = name.strip_prefix("atomic_") {
+            let Some((ty, method, ordering, concurrent)) = atomic_helper(helper) else {
+                self.pos -= 1;
+                return Err(self.error(format!("unknown atomic helper {name}")));
+            };
+            if concurrent {
+                Callee::ConcurrentAtomic(ty, method, ordering)
+            } else {
+                Callee::Atomic(ty, method, ordering)
+            }
+        } else if let Some(index) = numbered(&name, "cfn") {
+            Callee::Foreign(ForeignFunction::new(index))
+        } else if let Some((index, shim)) = name
+            .split_once("_closure_")
+            .and_then(|(func, shim)| Some((numbered(func, "fn")?, shim)))
+        {
+            let (capture, via) = match shim.split_once("_via_") {
+                Some((capture, via)) => {
+                    match FnTrait::ALL.into_iter().find(|t| t.snake_case() == via) {
+                        Some(via) => (capture, Some(via)),
+                        None => {
+                            self.pos -= 1;
+                            return Err(self.error(format!("unknown closure {name}")));
+                        }
+                    }
+                }
+                None => (shim, None),
+            };
+            let capture = match capture {
+                "plain" => CaptureMode::None,
+                "by_ref" => CaptureMode::ByRef,
+                "by_move" => CaptureMode::ByMove,
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error(format!("unknown closure {name}")));
+                }
+            };
+            Callee::Closure(Function::new(index), capture, via)
+        } else {
+            Callee::Named(intern(&name))
+        };
+        self.expect_punct("(")?;
+        let args = self.list(")", |parser: &mut Self| parser.operand(body))?;
+        Ok((callee, args))
+    }
+
+    /// Numbered items have to be contiguous
+    fn contiguous<I: index_vec::Idx, T>(
+        &self,
+        items: BTreeMap<usize, T>,
+        prefix: &str,
+    ) -> Result<IndexVec<I, T>> {
+        let mut vec = IndexVec::new();
+        for (index, item) in items {
+            if index != vec.len() {
+                return Err(self.error(format!("{prefix}{} is missing", vec.len())));
+            }
+            vec.push(item);
+        }
+        Ok(vec)
+    }
+
+    fn finish(mut self) -> Result<(Program, TyCtxt)> {
+        if self.functions.is_empty() {
+            return Err(self.error("the program has no functions"));
+        }
+        let functions = std::mem::take(&mut self.functions);
+        let consts = std::mem::take(&mut self.consts);
+        let foreign_functions = std::mem::take(&mut self.foreign_functions);
+        let program = Program {
+            functions: self.contiguous(functions, "fn")?,
+            consts: self.contiguous(consts, "CONST")?,
+            foreign_functions: self.contiguous(foreign_functions, "cfn")?,
+            entry_args: self.entry_args,
+            use_debug_dumper: self.use_debug_dumper,
+            split: self.split.map(Function::new),
+        };
+        Ok((program, self.tcx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{syntax::*, tyctxt::TyCtxt};
+
+    use super::parse;
+
+    const ENUM_FN: &str = r#"
+        #[custom_mir(dialect = "runtime", phase = "initial")]
+        pub fn fn0(mut _1: Adt17) -> i32 {
+        mir! {
+        type RET = i32;
+        let _2: (i32, bool);
+        {
+        place!(Field::<i32>(Variant(_1, 1), 0)) = 5_i32;
+        _2 = Checked(Field::<i32>(Variant(_1, 1), 0) + (-1_i32));
+        Call(RET, bb1, fn0(Move(_1)))
+        }
+        bb1 = {
+        match _2.1 {
+        1 => bb2,
+        _ => bb2
+        }
+        }
+        bb2 = {
+        Return()
+        }
+        }
+        }
+        pub enum Adt17 {
+        Variant0{
+        fld0: u8,
+        },
+        Variant1{
+        fld0: i32,
+        }}
+    "#;
+
+    #[test]
+    fn parse_enum_fn() {
+        let (program, tcx) = parse(ENUM_FN).unwrap();
+        let adt = TyId::new(17);
+        assert!(adt.kind(&tcx).is_enum());
+        assert!(!tcx.meta(adt).copy);
+
+        let body = &program.functions[Function::new(0)];
+        assert_eq!(body.args_iter().len(), 1);
+        let pair = body.local_decls[Local::new(2)].ty;
+        assert_eq!(
+            pair.tuple_elems(&tcx),
+            Some(&[TyCtxt::I32, TyCtxt::BOOL][..])
+        );
+
+        let bb0 = &body.basic_blocks[BasicBlock::new(0)];
+        let Statement::Assign(place, _) = &bb0.statements()[0] else {
+            panic!("not an assignment");
+        };
+        assert_eq!(
+            place.projection(),
+            &[ProjectionElem::DowncastField(
+                VariantIdx::new(1),
+                FieldIdx::new(0),
+                TyCtxt::I32
+            )]
+        );
+        let Statement::Assign(_, Rvalue::CheckedBinaryOp(BinOp::Add, _, rhs)) =
+            &bb0.statements()[1]
+        else {
+            panic!("not a checked addition");
+        };
+        assert!(matches!(
+            rhs,
+            Operand::Constant(Literal::Int(-1, IntTy::I32))
+        ));
+        assert!(matches!(
+            bb0.terminator(),
+            Terminator::Call {
+                callee: Callee::Generated(_),
+                target,
+                ..
+            } if target.index() == 1
+        ));
+    }
+
+    #[test]
+    fn parse_error_line() {
+        let src = ENUM_FN.replace("Move(_1)", "Move(_3)");
+        let e = parse(&src).err().expect("_3 is not declared");
+        assert_eq!(e.line, 10);
+        assert_eq!(e.message, "_3 is not declared");
+    }
+}
@@ -1,3 +1,8 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    iter,
+};
+
 use crate::{syntax::*, tyctxt::TyCtxt};
 
 #[derive(Debug, Clone, Copy)]
@@ -151,12 +156,60 @@ impl Serialize for Literal {
     }
 }
 
+impl Serialize for ConstValue {
+    fn serialize(&self, tcx: &TyCtxt) -> String {
+        match self {
+            ConstValue::Scalar(lit) => lit.serialize(tcx),
+            ConstValue::Aggregate(ty, kind, fields) => match kind {
+                AggregateKind::Array(_) => {
+                    let list: String = fields
+                        .iter()
+                        .map(|field| field.serialize(tcx))
+                        .intersperse(", ".to_owned())
+                        .collect();
+                    format!("[{list}]")
+                }
+                AggregateKind::Tuple => match fields.len() {
+                    0 => "()".to_owned(),
+                    1 => format!("({},)", fields.first().unwrap().serialize(tcx)),
+                    _ => format!(
+                        "({})",
+                        fields
+                            .iter()
+                            .map(|field| field.serialize(tcx))
+                            .intersperse(", ".to_owned())
+                            .collect::<String>()
+                    ),
+                },
+                AggregateKind::Adt(_, variant) => {
+                    let list: String = fields
+                        .iter_enumerated()
+                        .map(|(fid, field)| {
+                            format!("{}: {}", fid.identifier(), field.serialize(tcx))
+                        })
+                        .intersperse(", ".to_owned())
+                        .collect();
+                    if ty.kind(tcx).is_enum() {
+                        format!("{}::{} {{ {list} }}", ty.type_name(), variant.identifier())
+                    } else {
+                        format!("{} {{ {list} }}", ty.type_name())
+                    }
+                }
+            },
+        }
+    }
+}
+
 impl Serialize for Operand {
     fn serialize(&self, tcx: &TyCtxt) -> String {
         match self {
             Operand::Copy(place) => place.serialize_value(tcx),
             Operand::Move(place) => format!("Move({})", place.serialize_value(tcx)),
             Operand::Constant(lit) => lit.serialize(tcx),
+            Operand::Compound(CompoundConst::Item(item, _)) => item.identifier(),
+            Operand::Compound(CompoundConst::Block(value)) => {
+                format!("const {{ {} }}", value.serialize(tcx))
+            }
         }
     }
 }
@@ -281,29 +334,36 @@ impl Terminator {
                     Callee::Generated(func) => func.identifier(),
                     Callee::Named(func) => func.to_string(),
                     Callee::Intrinsic(func) => format!("core::intrinsics::{func}"),
+                    Callee::Closure(func, capture, via) => func.closure_identifier(*capture, *via),
+                    Callee::Atomic(ty, method, ordering) => {
+                        atomic_identifier(*ty, *method, *ordering, false, tcx)
+                    }
+                    Callee::ConcurrentAtomic(ty, method, ordering) => {
+                        atomic_identifier(*ty, *method, *ordering, true, tcx)
+                    }
+                    Callee::Worker(func) => func.worker_identifier(),
+                    Callee::Foreign(func) => func.identifier(),
+                    Callee::AtomicIntrinsic(func, ordering) => {
+                        // Arithmetic read-modify-writes take the operand type separately
+                        let ty_params = match *func {
+                            "atomic_fence" | "atomic_singlethreadfence" => 0,
+                            "atomic_xadd" | "atomic_xsub" | "atomic_and" | "atomic_nand"
+                            | "atomic_or" | "atomic_xor" => 2,
+                            _ => 1,
+                        };
+                        format!(
+                            "core::intrinsics::{func}::<{}{{ core::intrinsics::AtomicOrdering::{} }}>",
+                            "_, ".repeat(ty_params),
+                            ordering.name()
+                        )
+                    }
                 };
-                match call_syntax {
-                    CallSynatx::V1 => format!(
-                        "Call({}, {}, {fn_name}({args_list}))",
-                        destination.serialize_place(tcx),
-                        target.identifier(),
-                    ),
-                    CallSynatx::V2 => format!(
-                        "Call({} = {fn_name}({args_list}), {})",
-                        destination.serialize_place(tcx),
-                        target.identifier(),
-                    ),
-                    CallSynatx::V3 => format!(
-                        "Call({} = {fn_name}({args_list}), {}, UnwindUnreachable())",
-                        destination.serialize_place(tcx),
-                        target.identifier(),
-                    ),
-                    CallSynatx::V4 => format!(
-                        "Call({} = {fn_name}({args_list}), ReturnTo({}), UnwindUnreachable())",
-                        destination.serialize_place(tcx),
-                        target.identifier(),
-                    ),
-                }
+                serialize_call(
+                    call_syntax,
+                    &destination.serialize_place(tcx),
+                    &format!("{fn_name}({args_list})"),
+                    &target.identifier(),
+                )
             }
             Terminator::SwitchInt { discr, targets } => {
                 let arms = targets.match_arms();
@@ -367,37 +427,456 @@ impl Body {
 }
 
 impl Program {
+    /// A plain Rust function with the same signature as `func`, which calls it from a closure.
+    ///
+    /// `#[custom_mir]` can't be put on closures, so the generated function is the body of
+    /// the closure, and the shim builds the closure out of the places the caller passes it.
+    /// Capturing closures find them in a nested tuple, so that capture analysis captures
+    /// each one through its own field projections. By-ref closures reborrow the places
+    /// behind reference arguments, capturing the caller's places by reference, and move
+    /// out everything else; `move` closures take ownership of the whole environment.
+    fn closure_shim(
+        &self,
+        func: Function,
+        capture: CaptureMode,
+        via: Option<FnTrait>,
+        tcx: &TyCtxt,
+    ) -> String {
+        let body = &self.functions[func];
+        let args: Vec<String> = body.args_iter().map(|arg| arg.identifier()).collect();
+        let params: String = body
+            .args_decl_iter()
+            .map(|(arg, decl)| format!("{}: {}", arg.identifier(), decl.ty.serialize(tcx)))
+            .intersperse(", ".to_owned())
+            .collect();
+        let ret = body.return_ty().serialize(tcx);
+        let args_list = args.join(", ");
+        let call = |args: &[String]| match via {
+            Some(via) => format!("call_{}(c, {})", via.snake_case(), tuple(args)),
+            None => format!("c({})", args.join(", ")),
+        };
+
+        let (closure, invocation) = match capture {
+            CaptureMode::None => (
+                format!(
+                    "let c = |{params}| -> {ret} {{ {}({args_list}) }};",
+                    func.identifier()
+                ),
+                call(&args),
+            ),
+            CaptureMode::ByRef | CaptureMode::ByMove if args.is_empty() => (
+                format!("let c = || -> {ret} {{ {}() }};", func.identifier()),
+                call(&[]),
+            ),
+            CaptureMode::ByRef | CaptureMode::ByMove => {
+                let (env, paths) = nested_tuple(&args);
+                let fields: Vec<String> = paths
+                    .iter()
+                    .zip(body.args_decl_iter())
+                    .map(|(path, (_, decl))| match decl.ty.kind(tcx) {
+                        TyKind::Ref(_, Mutability::Not) if capture == CaptureMode::ByRef => {
+                            format!("&*env{path}")
+                        }
+                        TyKind::Ref(_, Mutability::Mut) if capture == CaptureMode::ByRef => {
+                            format!("&mut *env{path}")
+                        }
+                        _ => format!("env{path}"),
+                    })
+                    .collect();
+                let args_fields: String = (0..args.len())
+                    .map(|i| format!("args.{i}"))
+                    .intersperse(", ".to_owned())
+                    .collect();
+                let prefix = if capture == CaptureMode::ByMove {
+                    "move "
+                } else {
+                    ""
+                };
+                let params: Vec<TyId> = body.args_decl_iter().map(|(_, decl)| decl.ty).collect();
+                // Calling an FnMut closure directly borrows it mutably
+                let binding =
+                    if via.is_none() && capture.closure_trait(&params, tcx) == FnTrait::FnMut {
+                        "let mut c"
+                    } else {
+                        "let c"
+                    };
+                (
+                    format!(
+                        "let env = {env};\n\
+                        {binding} = {prefix}|| -> {ret} {{ let args = {}; {}({args_fields}) }};",
+                        tuple(&fields),
+                        func.identifier()
+                    ),
+                    call(&[]),
+                )
+            }
+        };
+        format!(
+            "{}fn {}({params}) -> {ret} {{\n{closure}\n{invocation}\n}}\n",
+            if self.is_lower(func) { "pub " } else { "" },
+            func.closure_identifier(capture, via)
+        )
+    }
+
+    /// A generic function calling the closure it is given through `via`. Unlike the
+    /// closures themselves, it is custom MIR, so the call goes through the trait method.
+    fn closure_caller(via: FnTrait, public: bool, call_syntax: CallSynatx) -> String {
+        let name = via.name();
+        let (receiver, method) = match via {
+            FnTrait::Fn => (Some("&"), "call"),
+            FnTrait::FnMut => (Some("&mut "), "call_mut"),
+            FnTrait::FnOnce => (None, "call_once"),
+        };
+        let (decl, stmt, callee) = match receiver {
+            Some(borrow) => (
+                format!("let _3: {borrow}F;\n"),
+                format!("_3 = {borrow}_1;\n"),
+                "_3",
+            ),
+            None => (String::new(), String::new(), "_1"),
+        };
+        format!(
+            "{}\n{}fn call_{}<A: core::marker::Tuple, F: {name}<A>>(_1: F, _2: A) -> F::Output {{\n\
+            mir! {{\n{decl}{{\n{stmt}{}\n}}\nbb1 = {{\nReturn()\n}}\n}}\n}}\n",
+            Program::FUNCTION_ATTRIBUTE,
+            if public { "pub " } else { "" },
+            via.snake_case(),
+            serialize_call(
+                call_syntax,
+                "RET",
+                &format!("<F as {name}<A>>::{method}(Move({callee}), Move(_2))"),
+                "bb1",
+            ),
+        )
+    }
+
+    /// A plain Rust function with the same signature as `func`, which calls it on a scoped
+    /// thread and waits for it to finish
+    fn worker_shim(&self, func: Function, tcx: &TyCtxt) -> String {
+        let body = &self.functions[func];
+        let args: Vec<String> = body.args_iter().map(|arg| arg.identifier()).collect();
+        let params: String = body
+            .args_decl_iter()
+            .map(|(arg, decl)| format!("{}: {}", arg.identifier(), decl.ty.serialize(tcx)))
+            .intersperse(", ".to_owned())
+            .collect();
+        let args_fields: String = (0..args.len())
+            .map(|i| format!("args.0.{i}"))
+            .intersperse(", ".to_owned())
+            .collect();
+        format!(
+            "{}fn {}({params}) -> {} {{\n\
+            let args = Unshared({});\n\
+            std::thread::scope(|s| s.spawn(move || {{ let args = args; Unshared({}({args_fields})) }}).join().unwrap()).0\n}}\n",
+            if self.is_lower(func) { "pub " } else { "" },
+            func.worker_identifier(),
+            body.return_ty().serialize(tcx),
+            tuple(&args),
+            func.identifier()
+        )
+    }
+
+    /// Every callee of a call terminator in the program
+    fn callees(&self) -> impl Iterator<Item = Callee> + '_ {
+        self.functions
+            .iter()
+            .flat_map(|body| body.basic_blocks.iter())
+            .filter_map(|bb| match bb.terminator() {
+                Terminator::Call { callee, .. } => Some(*callee),
+                _ => None,
+            })
+    }
+
+    /// ADTs that have to be defined in the lower crate, since something there uses them
+    fn lower_adts(&self, tcx: &TyCtxt) -> HashSet<TyId> {
+        let mut adts = HashSet::new();
+        for (_, body) in self
+            .functions
+            .iter_enumerated()
+            .filter(|(func, _)| self.is_lower(*func))
+        {
+            for decl in &body.local_decls {
+                decl.ty.collect_adts(tcx, &mut adts);
+            }
+        }
+        for value in &self.consts {
+            value.ty().collect_adts(tcx, &mut adts);
+        }
+        // Foreign functions are declared in the lower crate
+        for ty in self.foreign_functions.iter().flatten() {
+            ty.collect_adts(tcx, &mut adts);
+        }
+        adts
+    }
+
+    /// The Rust side of a foreign function: its import from the C translation unit,
+    /// the mirror that C calls back into, and a pure Rust stand-in for when there
+    /// is no C compiler (e.g. under Miri).
+    ///
+    /// Both sides compute the same FNV-1a hash over the bits of the arguments. The
+    /// foreign function returns its own hash combined with the one from the mirror.
+    fn foreign_fn_decls(&self, func: ForeignFunction, params: &[TyId], tcx: &TyCtxt) -> String {
+        let params_list: String = params
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| format!("a{i}: {}", ty.serialize(tcx)))
+            .intersperse(", ".to_owned())
+            .collect();
+        let args_list: String = (0..params.len())
+            .map(|i| format!("a{i}"))
+            .intersperse(", ".to_owned())
+            .collect();
+        let hash: String = params
+            .iter()
+            .enumerate()
+            .flat_map(|(i, ty)| {
+                ty.c_scalars(tcx).into_iter().map(move |(path, ty)| {
+                    let bits = match ty.kind(tcx) {
+                        TyKind::Float(..) => format!("a{i}{path}.to_bits() as u64"),
+                        _ => format!("a{i}{path} as u64"),
+                    };
+                    format!("h = (h ^ {bits}).wrapping_mul(0x100000001b3);\n")
+                })
+            })
+            .collect();
+        let name = func.identifier();
+        let mirror = func.mirror_identifier();
+        let vis = if self.split.is_some() { "pub " } else { "" };
+        format!(
+            "#[cfg(rustlantis_cc)]
+            extern \"C\" {{
+                {vis}fn {name}({params_list}) -> u64;
+            }}
+            #[cfg(not(rustlantis_cc))]
+            {vis}unsafe extern \"C\" fn {name}({params_list}) -> u64 {{
+                let h = {mirror}({args_list});
+                h.wrapping_mul(31).wrapping_add(h)
+            }}
+            #[no_mangle]
+            pub extern \"C\" fn {mirror}({params_list}) -> u64 {{
+                let mut h: u64 = 0xcbf29ce484222325;
+                {hash}h
+            }}\n"
+        )
+    }
+
+    /// The C translation unit defining all foreign functions, and the structs they take
+    pub fn c_unit(&self, tcx: &TyCtxt) -> String {
+        let mut unit = Program::C_HEADER.to_string();
+        let mut structs = vec![];
+        for ty in self.foreign_functions.iter().flatten() {
+            c_structs(*ty, tcx, &mut structs);
+        }
+        for ty in structs {
+            let TyKind::Adt(adt) = ty.kind(tcx) else {
+                panic!("not an adt");
+            };
+            let fields: String = adt.variants[VariantIdx::new(0)]
+                .fields
+                .iter_enumerated()
+                .map(|(fid, ty)| {
+                    let c_ty = ty.c_type(tcx).expect("type has a C equivalent");
+                    format!("    {c_ty} {};\n", fid.identifier())
+                })
+                .collect();
+            unit += &format!("struct {} {{\n{fields}}};\n", ty.type_name());
+        }
+        unit.extend(
+            self.foreign_functions
+                .iter_enumerated()
+                .map(|(func, params)| {
+                    let params_list: String = params
+                        .iter()
+                        .enumerate()
+                        .map(|(i, ty)| {
+                            format!("{} a{i}", ty.c_type(tcx).expect("type has a C equivalent"))
+                        })
+                        .intersperse(", ".to_owned())
+                        .collect();
+                    let params_list = if params.is_empty() {
+                        "void".to_owned()
+                    } else {
+                        params_list
+                    };
+                    let args_list: String = (0..params.len())
+                        .map(|i| format!("a{i}"))
+                        .intersperse(", ".to_owned())
+                        .collect();
+                    let hash: String = params
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(i, ty)| {
+                            ty.c_scalars(tcx).into_iter().map(move |(path, ty)| {
+                                let bits = match ty.kind(tcx) {
+                                    TyKind::Float(FloatTy::F32) => format!("f32_bits(a{i}{path})"),
+                                    TyKind::Float(FloatTy::F64) => format!("f64_bits(a{i}{path})"),
+                                    _ => format!("(uint64_t)a{i}{path}"),
+                                };
+                                format!("    h = (h ^ {bits}) * 0x100000001b3ull;\n")
+                            })
+                        })
+                        .collect();
+                    format!(
+                        "uint64_t {mirror}({params_list});\n\
+                uint64_t {name}({params_list}) {{\n    \
+                uint64_t h = 0xcbf29ce484222325ull;\n\
+                {hash}    \
+                return h * 31 + {mirror}({args_list});\n}}\n",
+                        name = func.identifier(),
+                        mirror = func.mirror_identifier(),
+                    )
+                }),
+        );
+        unit
+    }
+
     pub fn serialize(&self, tcx: &TyCtxt, call_syntax: CallSynatx) -> String {
-        let mut program = Program::HEADER.to_string();
+        let split = self.split.is_some();
+        // Keeps the items of the upper crate out of the lower crate
+        let upper_cfg = if split {
+            "#[cfg(not(rustlantis_lower))]\n"
+        } else {
+            ""
+        };
+        // Without a split, every item goes into `upper`
+        let mut lower = String::new();
+        let mut upper = String::new();
+        let mut emit = |in_lower: bool, item: &str| {
+            if in_lower {
+                lower += item;
+            } else {
+                upper += upper_cfg;
+                upper += item;
+            }
+        };
+
         if self.use_debug_dumper {
-            program += Program::DEBUG_DUMPER;
+            emit(split, Program::DEBUG_DUMPER);
         } else {
-            program += Program::DUMPER;
+            emit(split, Program::DUMPER);
+        }
+        for (item, value) in self.consts.iter_enumerated() {
+            emit(
+                split,
+                &format!(
+                    "{}const {}: {} = {};\n",
+                    if split { "pub " } else { "" },
+                    item.identifier(),
+                    value.ty().serialize(tcx),
+                    value.serialize(tcx)
+                ),
+            );
         }
-        program.extend(self.functions.iter_enumerated().map(|(idx, body)| {
+        let closures: BTreeSet<(Function, CaptureMode, Option<FnTrait>)> = self
+            .callees()
+            .filter_map(|callee| match callee {
+                Callee::Closure(func, capture, via) => Some((func, capture, via)),
+                _ => None,
+            })
+            .collect();
+        for (idx, body) in self.functions.iter_enumerated() {
+            // By-ref closures pass on reborrows, which don't live for 'static
+            let reborrowed = closures
+                .iter()
+                .any(|&(func, capture, _)| func == idx && capture == CaptureMode::ByRef);
             let args_list: String = body
                 .args_iter()
                 .map(|arg| {
                     let decl = &body.local_decls[arg];
+                    let ty = match decl.ty.kind(tcx) {
+                        TyKind::Ref(ty, mutability) if reborrowed => {
+                            format!("&{}{}", mutability.prefix_str(), ty.serialize(tcx))
+                        }
+                        _ => decl.ty.serialize(tcx),
+                    };
                     format!(
                         "{}{}: {}",
                         decl.mutability.prefix_str(),
                         arg.identifier(),
-                        decl.ty.serialize(tcx)
+                        ty
                     )
                 })
                 .intersperse(",".to_string())
                 .collect();
-            format!(
-                "{}\n{}fn {}({}) -> {} {{\n{}\n}}\n",
-                Program::FUNCTION_ATTRIBUTE,
-                if body.public { "pub " } else { "" },
-                idx.identifier(),
-                args_list,
-                body.return_ty().serialize(tcx),
-                body.serialize(tcx, call_syntax)
-            )
-        }));
+            emit(
+                self.is_lower(idx),
+                &format!(
+                    "{}{}\n{}{}fn {}({}) -> {} {{\n{}\n}}\n",
+                    if body.inline { "#[inline]\n" } else { "" },
+                    Program::FUNCTION_ATTRIBUTE,
+                    // Functions in the lower crate are called from the upper one
+                    if body.public || self.is_lower(idx) {
+                        "pub "
+                    } else {
+                        ""
+                    },
+                    body.abi.extern_prefix(),
+                    idx.identifier(),
+                    args_list,
+                    body.return_ty().serialize(tcx),
+                    body.serialize(tcx, call_syntax)
+                ),
+            );
+        }
+        let callers: BTreeSet<FnTrait> = closures.iter().filter_map(|(_, _, via)| *via).collect();
+        for &via in &callers {
+            emit(split, &Program::closure_caller(via, split, call_syntax));
+        }
+        for (func, capture, via) in closures {
+            emit(
+                self.is_lower(func),
+                &self.closure_shim(func, capture, via, tcx),
+            );
+        }
+        let workers: BTreeSet<Function> = self
+            .callees()
+            .filter_map(|callee| match callee {
+                Callee::Worker(func) => Some(func),
+                _ => None,
+            })
+            .collect();
+        if !workers.is_empty() {
+            emit(split, Program::UNSHARED);
+        }
+        for func in workers {
+            emit(self.is_lower(func), &self.worker_shim(func, tcx));
+        }
+        let atomics: BTreeSet<(AtomicTy, AtomicMethod, AtomicOrdering, bool)> = self
+            .callees()
+            .filter_map(|callee| match callee {
+                Callee::Atomic(ty, method, ordering) => Some((ty, method, ordering, false)),
+                Callee::ConcurrentAtomic(ty, method, ordering) => {
+                    Some((ty, method, ordering, true))
+                }
+                _ => None,
+            })
+            .collect();
+        for (ty, method, ordering, concurrent) in atomics {
+            emit(
+                split,
+                &atomic_helper(ty, method, ordering, concurrent, split, tcx),
+            );
+        }
+        for (func, params) in self.foreign_functions.iter_enumerated() {
+            emit(split, &self.foreign_fn_decls(func, params, tcx));
+        }
+
+        let mut program = if callers.is_empty() {
+            Program::HEADER.to_string()
+        } else {
+            Program::CLOSURE_FEATURES.to_string() + Program::HEADER
+        };
+        if split {
+            let lower_adts = self.lower_adts(tcx);
+            program += Program::LOWER_CRATE_START;
+            program += &lower;
+            program += &tcx.serialize_adts("", |adt| lower_adts.contains(&adt));
+            program += Program::LOWER_CRATE_END;
+            // The rest are only used by the upper crate, and can live there
+            program += &tcx.serialize_adts(upper_cfg, |adt| !lower_adts.contains(&adt));
+        }
+        program += &upper;
+
         let arg_list: String = self
             .entry_args
             .iter()
@@ -414,6 +893,13 @@ impl Program {
 
         let hash_printer = if self.use_debug_dumper {
             ""
+        } else if split {
+            r#"
+                unsafe {
+                    use std::hash::Hasher;
+                    println!("hash: {}", H.finish());
+                }
+            "#
         } else {
             r#"
                 unsafe {
@@ -423,20 +909,172 @@ impl Program {
         };
 
         program.push_str(&format!(
-            "pub fn main() {{
+            "{upper_cfg}pub fn main() {{
                 {first_fn}({arg_list});
                 {hash_printer}
             }}"
         ));
+        if !self.foreign_functions.is_empty() {
+            program += "\n";
+            program += Program::C_UNIT_START;
+            program += &self.c_unit(tcx);
+            program += "*/\n";
+        }
         program
     }
 }
 
+/// A call terminator in the syntax of the toolchain being targeted
+fn serialize_call(call_syntax: CallSynatx, destination: &str, call: &str, target: &str) -> String {
+    match call_syntax {
+        CallSynatx::V1 => format!("Call({destination}, {target}, {call})"),
+        CallSynatx::V2 => format!("Call({destination} = {call}, {target})"),
+        CallSynatx::V3 => format!("Call({destination} = {call}, {target}, UnwindUnreachable())"),
+        CallSynatx::V4 => {
+            format!("Call({destination} = {call}, ReturnTo({target}), UnwindUnreachable())")
+        }
+    }
+}
+
+fn atomic_identifier(
+    ty: AtomicTy,
+    method: AtomicMethod,
+    ordering: AtomicOrdering,
+    concurrent: bool,
+    tcx: &TyCtxt,
+) -> String {
+    let ty = match ty {
+        AtomicTy::Int(ty) => ty.serialize(tcx),
+        AtomicTy::Ptr => "ptr".to_owned(),
+    };
+    format!(
+        "atomic_{ty}_{}_{}{}",
+        method.name(),
+        ordering.snake_case(),
+        if concurrent { "_concurrently" } else { "" }
+    )
+}
+
+/// A plain Rust function doing an atomic operation on the pointee of its first argument,
+/// through `from_ptr` of the atomic type. Concurrent ones have scoped threads do it and
+/// return the value from before them.
+fn atomic_helper(
+    ty: AtomicTy,
+    method: AtomicMethod,
+    ordering: AtomicOrdering,
+    concurrent: bool,
+    public: bool,
+    tcx: &TyCtxt,
+) -> String {
+    let (generics, elem, atomic) = match ty {
+        AtomicTy::Int(ty) => {
+            let elem = ty.serialize(tcx);
+            let mut name = elem.chars();
+            let first = name.next().expect("type has a name").to_ascii_uppercase();
+            let atomic = format!("core::sync::atomic::Atomic{first}{}", name.as_str());
+            ("", elem, atomic)
+        }
+        AtomicTy::Ptr => (
+            "<T>",
+            "*mut T".to_owned(),
+            "core::sync::atomic::AtomicPtr::<T>".to_owned(),
+        ),
+    };
+    let order =
+        |ordering: AtomicOrdering| format!("core::sync::atomic::Ordering::{}", ordering.name());
+    let (params, call) = match method {
+        AtomicMethod::Load => (String::new(), format!("atomic.load({})", order(ordering))),
+        AtomicMethod::CompareExchange => (
+            format!(", current: {elem}, new: {elem}"),
+            format!(
+                "match atomic.compare_exchange(current, new, {}, {}) {{ Ok(old) | Err(old) => old }}",
+                order(ordering),
+                order(ordering.failure())
+            ),
+        ),
+        method => (
+            format!(", val: {elem}"),
+            format!("atomic.{}(val, {})", method.name(), order(ordering)),
+        ),
+    };
+    let ret = if method == AtomicMethod::Store {
+        "()".to_owned()
+    } else {
+        elem.clone()
+    };
+    let body = if concurrent {
+        format!(
+            "let old = atomic.load(core::sync::atomic::Ordering::Relaxed);\n\
+            std::thread::scope(|s| {{\n\
+            for _ in 0..{} {{\n\
+            s.spawn(|| {call});\n\
+            }}\n\
+            }});\n\
+            old",
+            Program::ATOMIC_WORKERS
+        )
+    } else {
+        call
+    };
+    format!(
+        "{}fn {}{generics}(ptr: *mut {elem}{params}) -> {ret} {{\n\
+        let atomic = unsafe {{ {atomic}::from_ptr(ptr) }};\n\
+        {body}\n}}\n",
+        if public { "pub " } else { "" },
+        atomic_identifier(ty, method, ordering, concurrent, tcx),
+    )
+}
+
+/// A tuple expression of the values, with the trailing comma of 1-tuples
+fn tuple(values: &[String]) -> String {
+    match values {
+        [only] => format!("({only},)"),
+        values => format!("({})", values.join(", ")),
+    }
+}
+
+/// Nests the values in tuples of two, as `(a, (b, c))`, and returns the tuple along with
+/// the field projections to each value
+fn nested_tuple(values: &[String]) -> (String, Vec<String>) {
+    match values {
+        [] => ("()".to_owned(), vec![]),
+        [only] => (format!("({only},)"), vec![".0".to_owned()]),
+        [first, second] => (
+            format!("({first}, {second})"),
+            vec![".0".to_owned(), ".1".to_owned()],
+        ),
+        [first, rest @ ..] => {
+            let (rest, paths) = nested_tuple(rest);
+            let paths = iter::once(".0".to_owned())
+                .chain(paths.into_iter().map(|path| format!(".1{path}")))
+                .collect();
+            (format!("({first}, {rest})"), paths)
+        }
+    }
+}
+
+/// Collects the structs that a C type is made of, each after the structs it contains
+fn c_structs(ty: TyId, tcx: &TyCtxt, structs: &mut Vec<TyId>) {
+    if let TyKind::Adt(adt) = ty.kind(tcx) {
+        for field in &adt.variants[VariantIdx::new(0)].fields {
+            c_structs(*field, tcx, structs);
+        }
+        if !structs.contains(&ty) {
+            structs.push(ty);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{syntax::*, tyctxt::TyCtxt};
+    use index_vec::IndexVec;
 
-    use super::Serialize;
+    use crate::{
+        syntax::*,
+        tyctxt::{AdtMeta, TyCtxt},
+    };
+
+    use super::{atomic_helper, CallSynatx, Serialize};
 
     #[test]
     fn serialize_body() {
@@ -451,6 +1089,358 @@ mod tests {
         });
     }
 
+    #[test]
+    fn serialize_const() {
+        let mut tcx = TyCtxt::from_primitives();
+        let tuple = tcx.push(TyKind::Tuple(vec![TyCtxt::I8, TyCtxt::BOOL]));
+        let value = ConstValue::Aggregate(
+            tuple,
+            AggregateKind::Tuple,
+            IndexVec::from_vec(vec![
+                ConstValue::Scalar(Literal::Int(-1, IntTy::I8)),
+                ConstValue::Scalar(Literal::Bool(true)),
+            ]),
+        );
+        assert_eq!(value.serialize(&tcx), "((-1_i8), true)");
+
+        let block = Operand::Compound(CompoundConst::Block(value.clone()));
+        assert_eq!(block.serialize(&tcx), "const { ((-1_i8), true) }");
+
+        let item = Operand::Compound(CompoundConst::Item(ConstItem::new(3), value));
+        assert_eq!(item.serialize(&tcx), "CONST3");
+    }
+
+    #[test]
+    fn serialize_atomic_call() {
+        let tcx = TyCtxt::from_primitives();
+        let call = Terminator::Call {
+            destination: Place::RETURN_SLOT,
+            target: BasicBlock::new(1),
+            callee: Callee::AtomicIntrinsic("atomic_xadd", AtomicOrdering::SeqCst),
+            args: vec![
+                Operand::Copy(Place::from_local(Local::new(1))),
+                Operand::Constant(Literal::Uint(1, UintTy::U32)),
+            ],
+        };
+        assert_eq!(
+            call.serialize(&tcx, CallSynatx::V1),
+            "Call(RET, bb1, core::intrinsics::atomic_xadd::<_, _, { core::intrinsics::AtomicOrdering::SeqCst }>(_1, 1_u32))"
+        );
+
+        let fence = Terminator::Call {
+            destination: Place::RETURN_SLOT,
+            target: BasicBlock::new(1),
+            callee: Callee::AtomicIntrinsic("atomic_fence", AtomicOrdering::Acquire),
+            args: vec![],
+        };
+        assert_eq!(
+            fence.serialize(&tcx, CallSynatx::V1),
+            "Call(RET, bb1, core::intrinsics::atomic_fence::<{ core::intrinsics::AtomicOrdering::Acquire }>())"
+        );
+    }
+
+    #[test]
+    fn serialize_closure_shim() {
+        let mut tcx = TyCtxt::from_primitives();
+        let mut program = Program::new(false);
+        let func = program.push_fn(Body::new(&[TyCtxt::I32], TyCtxt::BOOL, false));
+        assert_eq!(
+            program.closure_shim(func, CaptureMode::ByRef, None, &tcx),
+            "fn fn0_closure_by_ref(_1: i32) -> bool {\n\
+            let env = (_1,);\n\
+            let c = || -> bool { let args = (env.0,); fn0(args.0) };\n\
+            c()\n}\n"
+        );
+        assert_eq!(
+            program.closure_shim(func, CaptureMode::None, None, &tcx),
+            "fn fn0_closure_plain(_1: i32) -> bool {\n\
+            let c = |_1: i32| -> bool { fn0(_1) };\n\
+            c(_1)\n}\n"
+        );
+        // Arguments are passed on through the trait as a tuple
+        assert_eq!(
+            program.closure_shim(func, CaptureMode::None, Some(FnTrait::Fn), &tcx),
+            "fn fn0_closure_plain_via_fn(_1: i32) -> bool {\n\
+            let c = |_1: i32| -> bool { fn0(_1) };\n\
+            call_fn(c, (_1,))\n}\n"
+        );
+        // Each capture has its own path into the environment
+        let func = program.push_fn(Body::new(
+            &[TyCtxt::I32, TyCtxt::BOOL, TyCtxt::U8],
+            TyCtxt::UNIT,
+            false,
+        ));
+        assert_eq!(
+            program.closure_shim(func, CaptureMode::ByMove, None, &tcx),
+            "fn fn1_closure_by_move(_1: i32, _2: bool, _3: u8) -> () {\n\
+            let env = (_1, (_2, _3));\n\
+            let c = move || -> () { let args = (env.0, env.1.0, env.1.1); \
+            fn1(args.0, args.1, args.2) };\n\
+            c()\n}\n"
+        );
+        // By-ref closures capture the places behind references
+        let shared = tcx.push(TyKind::Ref(TyCtxt::I32, Mutability::Not));
+        let unique = tcx.push(TyKind::Ref(TyCtxt::U8, Mutability::Mut));
+        let func = program.push_fn(Body::new(&[unique, shared], TyCtxt::UNIT, false));
+        assert_eq!(
+            program.closure_shim(func, CaptureMode::ByRef, Some(FnTrait::FnMut), &tcx),
+            "fn fn2_closure_by_ref_via_fn_mut(_1: &'static mut u8, _2: &'static i32) -> () {\n\
+            let env = (_1, _2);\n\
+            let c = || -> () { let args = (&mut *env.0, &*env.1); fn2(args.0, args.1) };\n\
+            call_fn_mut(c, ())\n}\n"
+        );
+    }
+
+    #[test]
+    fn serialize_closure_caller() {
+        assert_eq!(
+            Program::closure_caller(FnTrait::FnMut, false, CallSynatx::V4),
+            "#[custom_mir(dialect = \"runtime\", phase = \"initial\")]\n\
+            fn call_fn_mut<A: core::marker::Tuple, F: FnMut<A>>(_1: F, _2: A) -> F::Output {\n\
+            mir! {\n\
+            let _3: &mut F;\n\
+            {\n\
+            _3 = &mut _1;\n\
+            Call(RET = <F as FnMut<A>>::call_mut(Move(_3), Move(_2)), ReturnTo(bb1), UnwindUnreachable())\n\
+            }\n\
+            bb1 = {\n\
+            Return()\n\
+            }\n\
+            }\n\
+            }\n"
+        );
+    }
+
+    #[test]
+    fn closure_traits() {
+        let mut tcx = TyCtxt::from_primitives();
+        let unique = tcx.push(TyKind::Ref(TyCtxt::U8, Mutability::Mut));
+        let shared = tcx.push(TyKind::Ref(TyCtxt::U8, Mutability::Not));
+        assert_eq!(
+            CaptureMode::None.closure_trait(&[unique], &tcx),
+            FnTrait::Fn
+        );
+        assert_eq!(
+            CaptureMode::ByRef.closure_trait(&[shared], &tcx),
+            FnTrait::Fn
+        );
+        assert_eq!(
+            CaptureMode::ByRef.closure_trait(&[unique], &tcx),
+            FnTrait::FnMut
+        );
+        // Moving the reference out of a move closure's environment consumes it
+        assert_eq!(
+            CaptureMode::ByMove.closure_trait(&[unique], &tcx),
+            FnTrait::FnOnce
+        );
+        assert_eq!(
+            CaptureMode::ByMove.closure_trait(&[shared], &tcx),
+            FnTrait::Fn
+        );
+        assert!(FnTrait::Fn.callable_through(FnTrait::FnOnce));
+        assert!(!FnTrait::FnMut.callable_through(FnTrait::Fn));
+    }
+
+    #[test]
+    fn serialize_worker_shim() {
+        let tcx = TyCtxt::from_primitives();
+        let mut program = Program::new(false);
+        let func = program.push_fn(Body::new(&[TyCtxt::I32], TyCtxt::BOOL, false));
+        assert_eq!(
+            program.worker_shim(func, &tcx),
+            "fn fn0_on_worker(_1: i32) -> bool {\n\
+            let args = Unshared((_1,));\n\
+            std::thread::scope(|s| s.spawn(move || { let args = args; Unshared(fn0(args.0.0)) }).join().unwrap()).0\n}\n"
+        );
+    }
+
+    #[test]
+    fn serialize_atomic_helper() {
+        let tcx = TyCtxt::from_primitives();
+        assert_eq!(
+            atomic_helper(
+                AtomicTy::Ptr,
+                AtomicMethod::Swap,
+                AtomicOrdering::Release,
+                false,
+                false,
+                &tcx
+            ),
+            "fn atomic_ptr_swap_release<T>(ptr: *mut *mut T, val: *mut T) -> *mut T {\n\
+            let atomic = unsafe { core::sync::atomic::AtomicPtr::<T>::from_ptr(ptr) };\n\
+            atomic.swap(val, core::sync::atomic::Ordering::Release)\n}\n"
+        );
+        assert_eq!(
+            atomic_helper(
+                AtomicTy::Int(TyCtxt::U32),
+                AtomicMethod::CompareExchange,
+                AtomicOrdering::Release,
+                false,
+                true,
+                &tcx
+            ),
+            "pub fn atomic_u32_compare_exchange_release(ptr: *mut u32, current: u32, new: u32) -> u32 {\n\
+            let atomic = unsafe { core::sync::atomic::AtomicU32::from_ptr(ptr) };\n\
+            match atomic.compare_exchange(current, new, core::sync::atomic::Ordering::Release, core::sync::atomic::Ordering::Relaxed) { Ok(old) | Err(old) => old }\n}\n"
+        );
+        // Every worker does the same read-modify-write, so the result doesn't depend on
+        // how they interleave
+        assert_eq!(
+            atomic_helper(
+                AtomicTy::Int(TyCtxt::I8),
+                AtomicMethod::FetchAdd,
+                AtomicOrdering::AcqRel,
+                true,
+                false,
+                &tcx
+            ),
+            "fn atomic_i8_fetch_add_acq_rel_concurrently(ptr: *mut i8, val: i8) -> i8 {\n\
+            let atomic = unsafe { core::sync::atomic::AtomicI8::from_ptr(ptr) };\n\
+            let old = atomic.load(core::sync::atomic::Ordering::Relaxed);\n\
+            std::thread::scope(|s| {\n\
+            for _ in 0..4 {\n\
+            s.spawn(|| atomic.fetch_add(val, core::sync::atomic::Ordering::AcqRel));\n\
+            }\n\
+            });\n\
+            old\n}\n"
+        );
+    }
+
+    #[test]
+    fn serialize_c_unit() {
+        let tcx = TyCtxt::from_primitives();
+        let mut program = Program::new(false);
+        let func = program.foreign_fn(&[TyCtxt::I8, TyCtxt::F32]);
+        assert_eq!(program.foreign_fn(&[TyCtxt::I8, TyCtxt::F32]), func);
+        let unit = program.c_unit(&tcx);
+        assert!(unit.contains("uint64_t cfn0_mirror(int8_t a0, float a1);"));
+        assert!(unit.contains("uint64_t cfn0(int8_t a0, float a1) {"));
+        assert!(unit.contains("h = (h ^ (uint64_t)a0) * 0x100000001b3ull;"));
+        assert!(unit.contains("h = (h ^ f32_bits(a1)) * 0x100000001b3ull;"));
+    }
+
+    #[test]
+    fn serialize_c_structs() {
+        let mut tcx = TyCtxt::from_primitives();
+        let struct_of = |tcx: &mut TyCtxt, fields: &[TyId], repr_c| {
+            let variant = VariantDef {
+                fields: IndexVec::from_iter(fields.iter().copied()),
+            };
+            let adt = Adt {
+                variants: IndexVec::from_iter([variant]),
+            };
+            tcx.push_adt(adt, AdtMeta { copy: true, repr_c })
+        };
+        let inner = struct_of(&mut tcx, &[TyCtxt::U8, TyCtxt::BOOL], true);
+        let outer = struct_of(&mut tcx, &[TyCtxt::I32, inner], true);
+        let rust = struct_of(&mut tcx, &[TyCtxt::I32], false);
+        let with_char = struct_of(&mut tcx, &[TyCtxt::CHAR], true);
+        let empty = struct_of(&mut tcx, &[], true);
+        assert_eq!(
+            outer.c_type(&tcx),
+            Some(format!("struct {}", outer.type_name()))
+        );
+        assert_eq!(rust.c_type(&tcx), None);
+        assert_eq!(with_char.c_type(&tcx), None);
+        assert_eq!(empty.c_type(&tcx), None);
+
+        let mut program = Program::new(false);
+        let func = program.foreign_fn(&[outer, TyCtxt::I8]);
+        let unit = program.c_unit(&tcx);
+        let inner_def = format!(
+            "struct {} {{\n    uint8_t fld0;\n    bool fld1;\n}};\n",
+            inner.type_name()
+        );
+        let outer_def = format!(
+            "struct {} {{\n    int32_t fld0;\n    struct {} fld1;\n}};\n",
+            outer.type_name(),
+            inner.type_name()
+        );
+        // Structs are defined before they are used
+        let inner_at = unit.find(&inner_def).expect("inner struct is defined");
+        let outer_at = unit.find(&outer_def).expect("outer struct is defined");
+        assert!(inner_at < outer_at);
+        assert!(unit.contains(&format!(
+            "uint64_t cfn0(struct {} a0, int8_t a1) {{",
+            outer.type_name()
+        )));
+        // Both sides hash the scalars in field order
+        let decls = program.foreign_fn_decls(func, &[outer, TyCtxt::I8], &tcx);
+        for (c, rust) in [
+            ("(uint64_t)a0.fld0", "a0.fld0 as u64"),
+            ("(uint64_t)a0.fld1.fld0", "a0.fld1.fld0 as u64"),
+            ("(uint64_t)a0.fld1.fld1", "a0.fld1.fld1 as u64"),
+            ("(uint64_t)a1", "a1 as u64"),
+        ] {
+            assert!(
+                unit.contains(&format!("h = (h ^ {c}) * 0x100000001b3ull;")),
+                "{unit}"
+            );
+            assert!(decls.contains(&format!("h = (h ^ {rust}).wrapping_mul(0x100000001b3);")));
+        }
+    }
+
+    #[test]
+    fn serialize_extern_fn() {
+        let mut tcx = TyCtxt::from_primitives();
+        let variant = VariantDef {
+            fields: IndexVec::from_iter([TyCtxt::I128, TyCtxt::F32, TyCtxt::U8]),
+        };
+        let adt = Adt {
+            variants: IndexVec::from_iter([variant]),
+        };
+        let adt = tcx.push_adt(
+            adt,
+            AdtMeta {
+                copy: true,
+                repr_c: true,
+            },
+        );
+        let mut program = Program::new(false);
+        let mut body = Body::new(&[adt, TyCtxt::I128, TyCtxt::F64], adt, false);
+        body.new_basic_block(BasicBlockData {
+            statements: vec![],
+            terminator: Terminator::Return,
+        });
+        body.abi = Abi::SysV64;
+        program.push_fn(body);
+        let name = adt.type_name();
+        assert!(tcx.serialize().contains(&format!(
+            "#[repr(C)]\n#[derive(Debug,Copy,Clone)]\npub struct {name} {{"
+        )));
+        assert!(program.serialize(&tcx, CallSynatx::V4).contains(&format!(
+            "extern \"sysv64\" fn fn0(mut _1: {name},mut _2: i128,mut _3: f64) -> {name} {{"
+        )));
+    }
+
+    #[test]
+    fn serialize_split() {
+        let tcx = TyCtxt::from_primitives();
+        let mut program = Program::new(false);
+        let mut body = Body::new(&[], TyCtxt::UNIT, false);
+        body.new_basic_block(BasicBlockData {
+            statements: vec![],
+            terminator: Terminator::Return,
+        });
+        program.push_fn(body.clone());
+        body.inline = true;
+        let lower = program.push_fn(body);
+        program.split = Some(lower);
+        let serialized = program.serialize(&tcx, CallSynatx::V4);
+        let (lower_crate, upper_crate) = serialized
+            .split_once(Program::LOWER_CRATE_END)
+            .expect("has a lower crate");
+        assert!(lower_crate.contains(&format!(
+            "#[inline]\n{}\npub fn fn1(",
+            Program::FUNCTION_ATTRIBUTE
+        )));
+        assert!(!lower_crate.contains("fn fn0("));
+        assert!(upper_crate.contains(&format!(
+            "#[cfg(not(rustlantis_lower))]\n{}\nfn fn0(",
+            Program::FUNCTION_ATTRIBUTE
+        )));
+    }
+
     #[test]
     fn serialize_literal() {
         let tcx = TyCtxt::from_primitives();
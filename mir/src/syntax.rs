@@ -1,30 +1,64 @@
-use std::num::TryFromIntError;
+use std::{collections::HashSet, num::TryFromIntError};
 
 use index_vec::{define_index_type, IndexVec};
+use serde::{Deserialize, Serialize};
 use smallvec::SmallVec;
 
 use crate::tyctxt::TyCtxt;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Program {
     pub functions: IndexVec<Function, Body>,
+    pub consts: IndexVec<ConstItem, ConstValue>,
+    /// Parameter types of functions defined in the C translation unit
+    pub foreign_functions: IndexVec<ForeignFunction, Vec<TyId>>,
     pub entry_args: Vec<Literal>,
     pub use_debug_dumper: bool,
+    /// The first function of the lower crate, if the program is split into a library
+    /// crate and a binary crate linking against it
+    pub split: Option<Function>,
 }
 
 pub type LocalDecls = IndexVec<Local, LocalDecl>;
 
 define_index_type! {pub struct Function = u32;}
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Body {
     pub basic_blocks: IndexVec<BasicBlock, BasicBlockData>,
     pub local_decls: LocalDecls,
     arg_count: usize,
     pub public: bool,
+    pub abi: Abi,
+    /// Whether the function is marked `#[inline]`
+    pub inline: bool,
+}
+
+/// Calling convention of a function
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Abi {
+    Rust,
+    C,
+    System,
+    /// Only available on x86_64
+    SysV64,
+    /// Only available on x86_64
+    Win64,
+}
+
+impl Abi {
+    pub fn extern_prefix(&self) -> &'static str {
+        match self {
+            Abi::Rust => "",
+            Abi::C => "extern \"C\" ",
+            Abi::System => "extern \"system\" ",
+            Abi::SysV64 => "extern \"sysv64\" ",
+            Abi::Win64 => "extern \"win64\" ",
+        }
+    }
 }
 
 define_index_type! {pub struct BasicBlock = u32;}
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct BasicBlockData {
     pub(crate) statements: Vec<Statement>,
     pub(crate) terminator: Terminator,
@@ -51,10 +85,22 @@ impl BasicBlockData {
     pub fn terminator(&self) -> &Terminator {
         &self.terminator
     }
+
+    pub fn terminator_mut(&mut self) -> &mut Terminator {
+        &mut self.terminator
+    }
+
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    pub fn statements_mut(&mut self) -> &mut Vec<Statement> {
+        &mut self.statements
+    }
 }
 
 define_index_type! {pub struct Local = u32;}
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct LocalDecl {
     /// Whether this is a mutable binding (i.e., `let x` or `let mut x`).
     ///
@@ -79,10 +125,10 @@ impl VariantIdx {
     }
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub struct Place {
-    local: Local,
-    projection: SmallVec<[ProjectionElem; 4]>,
+    pub(crate) local: Local,
+    pub(crate) projection: SmallVec<[ProjectionElem; 4]>,
 }
 impl Place {
     pub const fn from_local(local: Local) -> Self {
@@ -126,7 +172,7 @@ impl From<Local> for Place {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum ProjectionElem {
     Deref,
     /// This should be the same as Field, but to allow for context free serialization
@@ -148,14 +194,250 @@ impl ProjectionElem {
     }
 }
 
-#[derive(Clone, Copy)]
+// Deserialized in `ast`, since the names can't borrow from the input
+#[derive(Clone, Copy, Serialize)]
 pub enum Callee {
     Generated(Function),
     Named(&'static str),
     Intrinsic(&'static str),
+    /// An atomic intrinsic, which takes its memory ordering as a const generic argument
+    AtomicIntrinsic(&'static str, AtomicOrdering),
+    /// A generated function called from inside a closure, which is either invoked
+    /// directly or passed to a generic function calling it through the trait.
+    Closure(Function, CaptureMode, Option<FnTrait>),
+    /// A function defined in the C translation unit, which checksums its arguments
+    Foreign(ForeignFunction),
+    /// A method of an atomic type, called through a helper function that views the
+    /// pointee of its first argument as one
+    Atomic(AtomicTy, AtomicMethod, AtomicOrdering),
+    /// An atomic read-modify-write done by `Program::ATOMIC_WORKERS` scoped threads at
+    /// once, returning the value from before. Every worker does the same operation, so
+    /// the final value doesn't depend on the order they run in.
+    ConcurrentAtomic(AtomicTy, AtomicMethod, AtomicOrdering),
+    /// A generated function run on a scoped thread, which the caller joins straight away
+    Worker(Function),
+}
+
+/// How a closure gets hold of the arguments it passes on to its function
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum CaptureMode {
+    /// The arguments are closure parameters
+    None,
+    /// The arguments are captured by a non-move closure
+    ByRef,
+    /// The arguments are captured by a move closure
+    ByMove,
+}
+
+impl CaptureMode {
+    /// The most specific closure trait that the shim's closure implements, given the
+    /// parameters of the function it calls. By-ref closures reborrow through captured
+    /// references, and both capturing modes move out whatever else isn't `Copy`.
+    pub fn closure_trait(self, params: &[TyId], tcx: &TyCtxt) -> FnTrait {
+        let reborrowed = |ty: &TyId| self == CaptureMode::ByRef && ty.is_ref(tcx);
+        // `is_copy` doesn't count pointers, but shared references and raw pointers are
+        // copied out of the environment all the same
+        let copied = |ty: &TyId| {
+            ty.is_copy(tcx)
+                || matches!(
+                    ty.kind(tcx),
+                    TyKind::Ref(_, Mutability::Not) | TyKind::RawPtr(..)
+                )
+        };
+        if self == CaptureMode::None {
+            // Arguments are passed at the call rather than captured
+            FnTrait::Fn
+        } else if params.iter().any(|ty| !copied(ty) && !reborrowed(ty)) {
+            FnTrait::FnOnce
+        } else if params
+            .iter()
+            .any(|ty| reborrowed(ty) && matches!(ty.kind(tcx), TyKind::Ref(_, Mutability::Mut)))
+        {
+            FnTrait::FnMut
+        } else {
+            FnTrait::Fn
+        }
+    }
+}
+
+/// The closure traits, from the one that can be called the most freely
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum FnTrait {
+    Fn,
+    FnMut,
+    FnOnce,
+}
+
+impl FnTrait {
+    pub const ALL: [FnTrait; 3] = [FnTrait::Fn, FnTrait::FnMut, FnTrait::FnOnce];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FnTrait::Fn => "Fn",
+            FnTrait::FnMut => "FnMut",
+            FnTrait::FnOnce => "FnOnce",
+        }
+    }
+
+    /// Suffix of the closure shim, and of the generic function calling through the trait
+    pub fn snake_case(self) -> &'static str {
+        match self {
+            FnTrait::Fn => "fn",
+            FnTrait::FnMut => "fn_mut",
+            FnTrait::FnOnce => "fn_once",
+        }
+    }
+
+    /// Whether a closure implementing `self` can be called through `via`
+    pub fn callable_through(self, via: FnTrait) -> bool {
+        via >= self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AtomicOrdering {
+    Relaxed,
+    Release,
+    Acquire,
+    AcqRel,
+    SeqCst,
+}
+
+impl AtomicOrdering {
+    pub const ALL: [AtomicOrdering; 5] = [
+        AtomicOrdering::Relaxed,
+        AtomicOrdering::Release,
+        AtomicOrdering::Acquire,
+        AtomicOrdering::AcqRel,
+        AtomicOrdering::SeqCst,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AtomicOrdering::Relaxed => "Relaxed",
+            AtomicOrdering::Release => "Release",
+            AtomicOrdering::Acquire => "Acquire",
+            AtomicOrdering::AcqRel => "AcqRel",
+            AtomicOrdering::SeqCst => "SeqCst",
+        }
+    }
+
+    pub fn snake_case(&self) -> &'static str {
+        match self {
+            AtomicOrdering::Relaxed => "relaxed",
+            AtomicOrdering::Release => "release",
+            AtomicOrdering::Acquire => "acquire",
+            AtomicOrdering::AcqRel => "acq_rel",
+            AtomicOrdering::SeqCst => "seq_cst",
+        }
+    }
+
+    /// The strongest ordering a failed compare-exchange can have after succeeding with `self`
+    pub fn failure(&self) -> AtomicOrdering {
+        match self {
+            AtomicOrdering::Relaxed | AtomicOrdering::Release => AtomicOrdering::Relaxed,
+            AtomicOrdering::Acquire | AtomicOrdering::AcqRel => AtomicOrdering::Acquire,
+            AtomicOrdering::SeqCst => AtomicOrdering::SeqCst,
+        }
+    }
+}
+
+/// The type an atomic helper views its pointee as
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AtomicTy {
+    /// `AtomicI*` or `AtomicU*` of a primitive integer type
+    Int(TyId),
+    /// `AtomicPtr<T>` of a `*mut T`
+    Ptr,
+}
+
+/// The methods of the atomic types that generated programs call
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum AtomicMethod {
+    Load,
+    Store,
+    Swap,
+    CompareExchange,
+    FetchAdd,
+    FetchSub,
+    FetchAnd,
+    FetchNand,
+    FetchOr,
+    FetchXor,
+    FetchMax,
+    FetchMin,
+}
+
+impl AtomicMethod {
+    pub const ALL: [AtomicMethod; 12] = [
+        AtomicMethod::Load,
+        AtomicMethod::Store,
+        AtomicMethod::Swap,
+        AtomicMethod::CompareExchange,
+        AtomicMethod::FetchAdd,
+        AtomicMethod::FetchSub,
+        AtomicMethod::FetchAnd,
+        AtomicMethod::FetchNand,
+        AtomicMethod::FetchOr,
+        AtomicMethod::FetchXor,
+        AtomicMethod::FetchMax,
+        AtomicMethod::FetchMin,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AtomicMethod::Load => "load",
+            AtomicMethod::Store => "store",
+            AtomicMethod::Swap => "swap",
+            AtomicMethod::CompareExchange => "compare_exchange",
+            AtomicMethod::FetchAdd => "fetch_add",
+            AtomicMethod::FetchSub => "fetch_sub",
+            AtomicMethod::FetchAnd => "fetch_and",
+            AtomicMethod::FetchNand => "fetch_nand",
+            AtomicMethod::FetchOr => "fetch_or",
+            AtomicMethod::FetchXor => "fetch_xor",
+            AtomicMethod::FetchMax => "fetch_max",
+            AtomicMethod::FetchMin => "fetch_min",
+        }
+    }
+
+    /// Number of operands after the pointer
+    pub fn operands(self) -> usize {
+        match self {
+            AtomicMethod::Load => 0,
+            AtomicMethod::CompareExchange => 2,
+            _ => 1,
+        }
+    }
+
+    /// Whether the method reads the old value and writes a new one
+    pub fn is_rmw(self) -> bool {
+        !matches!(self, AtomicMethod::Load | AtomicMethod::Store)
+    }
+
+    /// Whether `AtomicPtr` has the method. Its arithmetic ones aren't stable.
+    pub fn on_ptr(self) -> bool {
+        matches!(
+            self,
+            AtomicMethod::Load | AtomicMethod::Store | AtomicMethod::Swap
+        )
+    }
+
+    /// Whether the method can't take `ordering`
+    pub fn forbids(self, ordering: AtomicOrdering) -> bool {
+        match self {
+            AtomicMethod::Load => {
+                matches!(ordering, AtomicOrdering::Release | AtomicOrdering::AcqRel)
+            }
+            AtomicMethod::Store => {
+                matches!(ordering, AtomicOrdering::Acquire | AtomicOrdering::AcqRel)
+            }
+            _ => false,
+        }
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Terminator {
     Hole,
     // define!("mir_return", fn Return() -> BasicBlock);
@@ -193,13 +475,13 @@ pub enum Terminator {
     },
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SwitchTargets {
     pub branches: Vec<(u128, BasicBlock)>,
     pub otherwise: BasicBlock,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Rvalue {
     Use(Operand),
     UnaryOp(UnOp, Operand),
@@ -216,7 +498,7 @@ pub enum Rvalue {
     Ref(Mutability, Place),
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum AggregateKind {
     /// The type is of the element
     Array(TyId),
@@ -225,22 +507,65 @@ pub enum AggregateKind {
     Adt(TyId, VariantIdx),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Literal {
     Uint(u128, UintTy),
     Int(i128, IntTy),
     Bool(bool),
     Char(char),
     // Every f32 can be expressed exactly as f64
-    Float(f64, FloatTy),
+    Float(#[serde(with = "crate::ast::float_bits")] f64, FloatTy),
+}
+
+define_index_type! {pub struct ConstItem = u32;}
+impl ConstItem {
+    pub fn identifier(&self) -> String {
+        format!("CONST{}", self.index())
+    }
+}
+
+/// A constant value of any type that can be written as a constant expression
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ConstValue {
+    Scalar(Literal),
+    /// The type of the whole aggregate, how it is built, and its field values
+    Aggregate(TyId, AggregateKind, IndexVec<FieldIdx, ConstValue>),
 }
 
-#[derive(Clone, Debug)]
+impl ConstValue {
+    pub fn ty(&self) -> TyId {
+        match self {
+            ConstValue::Scalar(lit) => lit.ty(),
+            ConstValue::Aggregate(ty, ..) => *ty,
+        }
+    }
+}
+
+/// A constant operand of a compound type, which has to go through CTFE
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum CompoundConst {
+    /// A use of a top level `const` item. The value is kept alongside so that
+    /// the operand is self-describing.
+    Item(ConstItem, ConstValue),
+    /// An inline `const { .. }` block
+    Block(ConstValue),
+}
+
+impl CompoundConst {
+    pub fn value(&self) -> &ConstValue {
+        match self {
+            CompoundConst::Item(_, value) | CompoundConst::Block(value) => value,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Operand {
     Copy(Place),
     // define!("mir_move", fn Move<T>(place: T) -> T);
     Move(Place),
     Constant(Literal),
+    Compound(CompoundConst),
     // TODO: the following
     // define!("mir_static", fn Static<T>(s: T) -> &'static T);
     // define!("mir_static_mut", fn StaticMut<T>(s: T) -> *mut T);
@@ -251,18 +576,19 @@ impl Operand {
         match self {
             Operand::Copy(place) | Operand::Move(place) => place.ty(local_decls, tcx),
             Operand::Constant(lit) => lit.ty(),
+            Operand::Compound(c) => c.value().ty(),
         }
     }
 
     pub fn place(&self) -> Option<&Place> {
         match self {
             Operand::Copy(place) | Operand::Move(place) => Some(place),
-            Operand::Constant(..) => None,
+            Operand::Constant(..) | Operand::Compound(..) => None,
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum Statement {
     Assign(Place, Rvalue),
     // define!("mir_storage_live", fn StorageLive<T>(local: T));
@@ -278,14 +604,14 @@ pub enum Statement {
     Nop,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum Mutability {
     // N.B. Order is deliberate, so that Not < Mut
     Not,
     Mut,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum IntTy {
     Isize,
     I8,
@@ -295,7 +621,7 @@ pub enum IntTy {
     I128,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum UintTy {
     Usize,
     U8,
@@ -305,7 +631,7 @@ pub enum UintTy {
     U128,
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
 pub enum FloatTy {
     F32,
     F64,
@@ -384,6 +710,28 @@ impl TyId {
         }
     }
 
+    /// Adds all ADTs this type refers to, including through references, to `adts`
+    pub fn collect_adts(self, tcx: &TyCtxt, adts: &mut HashSet<TyId>) {
+        match self.kind(tcx) {
+            TyKind::Adt(adt) => {
+                if adts.insert(self) {
+                    for ty in adt
+                        .variants
+                        .iter()
+                        .flat_map(|variant| variant.fields.iter())
+                    {
+                        ty.collect_adts(tcx, adts);
+                    }
+                }
+            }
+            TyKind::Tuple(elems) => elems.iter().for_each(|ty| ty.collect_adts(tcx, adts)),
+            TyKind::RawPtr(ty, _) | TyKind::Ref(ty, _) | TyKind::Array(ty, _) => {
+                ty.collect_adts(tcx, adts)
+            }
+            _ => {}
+        }
+    }
+
     pub fn contains<P>(self, tcx: &TyCtxt, predicate: P) -> bool
     where
         P: Fn(&TyCtxt, TyId) -> bool + Copy,
@@ -427,6 +775,53 @@ impl TyId {
         !self.contains(tcx, |tcx, ty| ty.is_any_ptr(tcx))
     }
 
+    /// The C type with the same ABI, for types that can cross the C boundary by value:
+    /// scalars, and `repr(C)` structs of them, which `Program::c_unit` defines
+    pub fn c_type(self, tcx: &TyCtxt) -> Option<String> {
+        let c_ty = match self.kind(tcx) {
+            TyKind::Bool => "bool",
+            TyKind::Int(IntTy::Isize) => "intptr_t",
+            TyKind::Int(IntTy::I8) => "int8_t",
+            TyKind::Int(IntTy::I16) => "int16_t",
+            TyKind::Int(IntTy::I32) => "int32_t",
+            TyKind::Int(IntTy::I64) => "int64_t",
+            TyKind::Uint(UintTy::Usize) => "uintptr_t",
+            TyKind::Uint(UintTy::U8) => "uint8_t",
+            TyKind::Uint(UintTy::U16) => "uint16_t",
+            TyKind::Uint(UintTy::U32) => "uint32_t",
+            TyKind::Uint(UintTy::U64) => "uint64_t",
+            TyKind::Float(FloatTy::F32) => "float",
+            TyKind::Float(FloatTy::F64) => "double",
+            TyKind::Adt(adt) if !adt.is_enum() && tcx.meta(self).repr_c => {
+                let fields = &adt.variants.first()?.fields;
+                // C has no empty structs
+                if fields.is_empty() || fields.iter().any(|ty| ty.c_type(tcx).is_none()) {
+                    return None;
+                }
+                return Some(format!("struct {}", self.type_name()));
+            }
+            _ => return None,
+        };
+        Some(c_ty.to_owned())
+    }
+
+    /// The scalars making up a value of a type with a C equivalent, in field order, as the
+    /// field accesses reaching them (empty for a scalar itself) and their types
+    pub fn c_scalars(self, tcx: &TyCtxt) -> Vec<(String, TyId)> {
+        match self.kind(tcx) {
+            TyKind::Adt(adt) => adt.variants[VariantIdx::new(0)]
+                .fields
+                .iter_enumerated()
+                .flat_map(|(fid, ty)| {
+                    ty.c_scalars(tcx)
+                        .into_iter()
+                        .map(move |(path, ty)| (format!(".{}{path}", fid.identifier()), ty))
+                })
+                .collect(),
+            _ => vec![(String::new(), self)],
+        }
+    }
+
     pub fn hashable(self, tcx: &TyCtxt) -> bool {
         // TODO: hash Adts maybe
         self.kind(tcx).is_structural()
@@ -454,7 +849,7 @@ impl TyId {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum TyKind {
     // Scalars
     Unit,
@@ -552,13 +947,13 @@ impl PartialEq for TyKind {
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Hash, Debug)]
+#[derive(PartialEq, Eq, Clone, Hash, Debug, Serialize, Deserialize)]
 pub struct VariantDef {
     /// Fields of this variant.
     pub fields: IndexVec<FieldIdx, TyId>,
 }
 
-#[derive(Clone, Hash, Debug)]
+#[derive(Clone, Hash, Debug, Serialize, Deserialize)]
 pub struct Adt {
     pub variants: IndexVec<VariantIdx, VariantDef>,
 }
@@ -574,7 +969,7 @@ impl Adt {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum BinOp {
     Add,
     Sub,
@@ -595,7 +990,7 @@ pub enum BinOp {
     Offset,
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub enum UnOp {
     Not,
     Neg,
@@ -719,7 +1114,7 @@ impl Program {
         "#[custom_mir(dialect = \"runtime\", phase = \"initial\")]";
     pub const HEADER: &'static str = "#![recursion_limit = \"1024\"]
     #![feature(custom_mir, core_intrinsics, lazy_get)]
-    #![allow(unused_parens, unused_assignments, overflowing_literals)]
+    #![allow(unused_parens, unused_assignments, overflowing_literals, improper_ctypes_definitions)]
     extern crate core;
     use core::intrinsics::mir::*;\n";
 
@@ -728,10 +1123,10 @@ impl Program {
     use std::hash::{Hash, Hasher};
     use std::sync::LazyLock;
 
-    static mut H: LazyLock<DefaultHasher> = LazyLock::new(|| DefaultHasher::new());
+    pub static mut H: LazyLock<DefaultHasher> = LazyLock::new(|| DefaultHasher::new());
 
     #[inline(never)]
-    fn dump_var(
+    pub fn dump_var(
         val0: impl Hash,
         val1: impl Hash,
         val2: impl Hash,
@@ -750,7 +1145,7 @@ impl Program {
     use std::fmt::Debug;
 
     #[inline(never)]
-    fn dump_var(
+    pub fn dump_var(
         f: usize,
         var0: usize, val0: impl Debug,
         var1: usize, val1: impl Debug,
@@ -763,14 +1158,49 @@ impl Program {
 
     // Fake "intrinsic"
     pub const DUMPER_CALL: Callee = Callee::Named("dump_var");
+    /// Lets the generic functions calling closures call them through the trait methods
+    pub const CLOSURE_FEATURES: &'static str =
+        "#![feature(fn_traits, unboxed_closures, tuple_trait)]\n";
     pub const DUMPER_ARITY: usize = 4;
+    /// Number of scoped threads doing a concurrent atomic operation
+    pub const ATOMIC_WORKERS: usize = 4;
+    /// Lets arguments and return values with raw pointers cross to a worker thread. The
+    /// caller waits for the worker, so only one thread uses them at any time.
+    pub const UNSHARED: &'static str = "
+    pub struct Unshared<T>(pub T);
+    unsafe impl<T> Send for Unshared<T> {}
+    ";
+    /// Opens the module holding the lower crate of a split program. Compiled with
+    /// `--cfg rustlantis_lower`, the file is the lower crate itself; compiled with
+    /// `--cfg rustlantis_upper`, it imports the module from there instead.
+    pub const LOWER_CRATE_START: &'static str = "#[cfg(rustlantis_upper)]
+    extern crate rustlantis_lower;
+    #[cfg(rustlantis_upper)]
+    use rustlantis_lower::lower;
+    #[cfg(not(rustlantis_upper))]
+    pub mod lower {
+    use core::intrinsics::mir::*;\n";
+    pub const LOWER_CRATE_END: &'static str = "}
+    #[cfg(not(rustlantis_lower))]
+    use lower::*;\n";
+    /// Opens the block comment holding the C translation unit
+    pub const C_UNIT_START: &'static str = "/* rustlantis-c-unit\n";
+    pub const C_HEADER: &'static str = "#include <stdbool.h>
+#include <stdint.h>
+#include <string.h>
+static uint64_t f32_bits(float f) { uint32_t b; memcpy(&b, &f, sizeof b); return b; }
+static uint64_t f64_bits(double f) { uint64_t b; memcpy(&b, &f, sizeof b); return b; }
+";
 
     // A new, empty function
     pub fn new(debug: bool) -> Self {
         Self {
             functions: IndexVec::default(),
+            consts: IndexVec::default(),
+            foreign_functions: IndexVec::default(),
             entry_args: vec![],
             use_debug_dumper: debug,
+            split: None,
         }
     }
 
@@ -778,6 +1208,23 @@ impl Program {
         self.functions.push(body)
     }
 
+    pub fn push_const(&mut self, value: ConstValue) -> ConstItem {
+        self.consts.push(value)
+    }
+
+    /// Returns the foreign function with these parameter types, declaring one if there is none
+    pub fn foreign_fn(&mut self, args: &[TyId]) -> ForeignFunction {
+        if let Some(existing) = self.foreign_functions.position(|tys| tys == args) {
+            return existing;
+        }
+        self.foreign_functions.push(args.to_vec())
+    }
+
+    /// Whether the function belongs to the lower crate of a split program
+    pub fn is_lower(&self, func: Function) -> bool {
+        self.split.is_some_and(|split| func >= split)
+    }
+
     pub fn set_entry_args(&mut self, args: &[Literal]) {
         self.entry_args = Vec::from(args);
     }
@@ -787,6 +1234,50 @@ impl Function {
     pub fn identifier(&self) -> String {
         format!("fn{}", self.index())
     }
+
+    pub fn closure_identifier(&self, capture: CaptureMode, via: Option<FnTrait>) -> String {
+        let capture = match capture {
+            CaptureMode::None => "plain",
+            CaptureMode::ByRef => "by_ref",
+            CaptureMode::ByMove => "by_move",
+        };
+        let via = via.map_or(String::new(), |via| format!("_via_{}", via.snake_case()));
+        format!("fn{}_closure_{capture}{via}", self.index())
+    }
+
+    pub fn worker_identifier(&self) -> String {
+        format!("fn{}_on_worker", self.index())
+    }
+}
+
+define_index_type! {pub struct ForeignFunction = u32;}
+impl ForeignFunction {
+    pub fn identifier(&self) -> String {
+        format!("cfn{}", self.index())
+    }
+
+    /// The Rust function the C side calls back into with the same arguments
+    pub fn mirror_identifier(&self) -> String {
+        format!("cfn{}_mirror", self.index())
+    }
+}
+
+impl Terminator {
+    /// The blocks control can go to next, in the order they are written
+    pub fn successors(&self) -> Vec<BasicBlock> {
+        match self {
+            Terminator::Goto { target }
+            | Terminator::Drop { target, .. }
+            | Terminator::Call { target, .. } => vec![*target],
+            Terminator::SwitchInt { targets, .. } => targets
+                .branches
+                .iter()
+                .map(|(_, bb)| *bb)
+                .chain([targets.otherwise])
+                .collect(),
+            Terminator::Return | Terminator::Unreachable | Terminator::Hole => vec![],
+        }
+    }
 }
 
 impl SwitchTargets {
@@ -834,6 +1325,8 @@ impl Body {
             local_decls: locals,
             public,
             arg_count: args.len(),
+            abi: Abi::Rust,
+            inline: false,
         }
     }
 
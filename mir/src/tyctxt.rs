@@ -1,4 +1,4 @@
-use std::{collections::HashMap, slice};
+use std::{collections::BTreeMap, slice};
 
 use index_vec::IndexVec;
 
@@ -7,9 +7,10 @@ use crate::{
     syntax::{Adt, TyId, TyKind},
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub struct AdtMeta {
     pub copy: bool,
+    pub repr_c: bool,
 }
 
 impl AdtMeta {
@@ -27,11 +28,20 @@ impl AdtMeta {
             format!("#[derive({list})]\n")
         }
     }
+
+    fn repr_attr(&self) -> &'static str {
+        if self.repr_c {
+            "#[repr(C)]\n"
+        } else {
+            ""
+        }
+    }
 }
 
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct TyCtxt {
     tys: IndexVec<TyId, TyKind>,
-    adt_meta: HashMap<TyId, AdtMeta>,
+    adt_meta: BTreeMap<TyId, AdtMeta>,
 }
 
 impl TyCtxt {
@@ -76,7 +86,7 @@ impl TyCtxt {
         let tys = IndexVec::from_iter(primitives);
         Self {
             tys,
-            adt_meta: HashMap::new(),
+            adt_meta: BTreeMap::new(),
         }
     }
 
@@ -91,6 +101,12 @@ impl TyCtxt {
         id
     }
 
+    /// Turns the placeholder at `ty` into an ADT, so that it can be given a fixed id
+    pub(crate) fn define_adt(&mut self, ty: TyId, adt: Adt, meta: AdtMeta) {
+        self.tys[ty] = TyKind::Adt(adt);
+        self.adt_meta.insert(ty, meta);
+    }
+
     pub fn meta(&self, ty: TyId) -> AdtMeta {
         self.adt_meta[&ty]
     }
@@ -99,6 +115,10 @@ impl TyCtxt {
         &self.tys[ty]
     }
 
+    pub fn kind_mut(&mut self, ty: TyId) -> &mut TyKind {
+        &mut self.tys[ty]
+    }
+
     pub fn indices(&self) -> impl Iterator<Item = TyId> {
         self.tys.indices()
     }
@@ -116,11 +136,22 @@ impl TyCtxt {
     }
 
     pub fn serialize(&self) -> String {
+        self.serialize_adts("", |_| true)
+    }
+
+    /// Definitions of the ADTs matching the filter, each preceded by `attr`
+    pub fn serialize_adts(&self, attr: &str, filter: impl Fn(TyId) -> bool) -> String {
         let mut str = String::new();
-        for (id, adt) in self.tys.iter_enumerated().filter(|(_, kind)| kind.is_adt()) {
+        for (id, adt) in self
+            .tys
+            .iter_enumerated()
+            .filter(|(id, kind)| kind.is_adt() && filter(*id))
+        {
             let TyKind::Adt(adt) = adt else {
                 panic!("not an adt");
             };
+            str += attr;
+            str += self.adt_meta[&id].repr_attr();
             str += &self.adt_meta[&id].derive_attrs();
             if adt.is_enum() {
                 let variants: String = adt
@@ -134,11 +165,13 @@ impl TyCtxt {
                 str += &format!("pub enum {} {{\n{variants}}}\n", id.type_name())
             } else {
                 let def = adt.variants.first().expect("has only one variant");
-                str += &format!(
-                    "pub struct {} {{\n{}}}\n",
-                    id.type_name(),
-                    def.serialize(self)
-                )
+                // Fields are accessed from the other crate when the program is split
+                let fields: String = def
+                    .fields
+                    .iter_enumerated()
+                    .map(|(fid, ty)| format!("pub {}: {},\n", fid.identifier(), ty.serialize(self)))
+                    .collect();
+                str += &format!("pub struct {} {{\n{fields}}}\n", id.type_name())
             }
         }
         str
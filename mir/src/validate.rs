@@ -0,0 +1,885 @@
+//! Static checks of a `Program` against its `TyCtxt`, so that a malformed program is
+//! caught where it is made rather than by a confusing compiler error in some backend
+
+use std::{collections::BTreeSet, fmt, iter};
+
+use crate::{
+    analysis::{self, LocalSet},
+    serialize::Serialize,
+    syntax::*,
+    tyctxt::TyCtxt,
+};
+
+/// Where in a program a check failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    EntryArgs,
+    /// The signature of a function defined in the C translation unit
+    Foreign(ForeignFunction),
+    Const(ConstItem),
+    Decls(Function),
+    Statement(Function, BasicBlock, usize),
+    Terminator(Function, BasicBlock),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::EntryArgs => write!(f, "entry args"),
+            Location::Foreign(func) => write!(f, "{} signature", func.identifier()),
+            Location::Const(item) => write!(f, "{}", item.identifier()),
+            Location::Decls(func) => write!(f, "{} decls", func.identifier()),
+            Location::Statement(func, bb, idx) => {
+                write!(f, "{} {}[{idx}]", func.identifier(), bb.identifier())
+            }
+            Location::Terminator(func, bb) => {
+                write!(f, "{} {} terminator", func.identifier(), bb.identifier())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ValidationError {
+    pub location: Location,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+type Result<T> = std::result::Result<T, ValidationError>;
+
+/// Type-checks every function and constant of the program, and checks that the
+/// control flow is complete and that no local is read before it is written.
+///
+/// Returns the first problem found.
+pub fn validate(program: &Program, tcx: &TyCtxt) -> Result<()> {
+    let mut v = Validator {
+        program,
+        tcx,
+        location: Location::EntryArgs,
+    };
+    v.entry_args()?;
+    for (item, value) in program.consts.iter_enumerated() {
+        v.location = Location::Const(item);
+        v.const_value(value)?;
+    }
+    for (func, body) in program.functions.iter_enumerated() {
+        v.body(func, body)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+struct Validator<'a> {
+    program: &'a Program,
+    tcx: &'a TyCtxt,
+    location: Location,
+}
+
+impl Validator<'_> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T> {
+        Err(ValidationError {
+            location: self.location,
+            message: message.into(),
+        })
+    }
+
+    fn expect_ty(&self, what: &str, ty: TyId, expected: TyId) -> Result<()> {
+        if same_ty(self.tcx, ty, expected) {
+            Ok(())
+        } else {
+            self.error(format!(
+                "{what} has type {}, expected {}",
+                ty.serialize(self.tcx),
+                expected.serialize(self.tcx)
+            ))
+        }
+    }
+
+    fn entry_args(&self) -> Result<()> {
+        let Some(fn0) = self.program.functions.first() else {
+            return self.error("there is no entry function");
+        };
+        let params: Vec<TyId> = fn0.args_decl_iter().map(|(_, decl)| decl.ty).collect();
+        if params.len() != self.program.entry_args.len() {
+            return self.error(format!(
+                "fn0 takes {} arguments, but {} are given",
+                params.len(),
+                self.program.entry_args.len()
+            ));
+        }
+        for (lit, param) in self.program.entry_args.iter().zip(params) {
+            self.expect_ty("entry argument", lit.ty(), param)?;
+        }
+        Ok(())
+    }
+
+    fn const_value(&self, value: &ConstValue) -> Result<()> {
+        match value {
+            ConstValue::Scalar(_) => Ok(()),
+            ConstValue::Aggregate(ty, kind, fields) => {
+                let tys: Vec<TyId> = fields.iter().map(ConstValue::ty).collect();
+                self.aggregate(*kind, &tys, *ty)?;
+                fields.iter().try_for_each(|field| self.const_value(field))
+            }
+        }
+    }
+
+    fn body(&mut self, func: Function, body: &Body) -> Result<()> {
+        self.location = Location::Decls(func);
+        if body.basic_blocks.is_empty() {
+            return self.error("the function has no basic blocks");
+        }
+        if let Some((local, _)) = body
+            .local_decls
+            .iter_enumerated()
+            .find(|(_, decl)| decl.ty.index() >= self.tcx.len())
+        {
+            return self.error(format!("{} has an undefined type", local.identifier()));
+        }
+
+        let mut body_validator = BodyValidator { v: *self, body };
+        for (bb, data) in body.basic_blocks.iter_enumerated() {
+            for (idx, stmt) in data.statements().iter().enumerate() {
+                body_validator.v.location = Location::Statement(func, bb, idx);
+                body_validator.statement(stmt)?;
+            }
+            body_validator.v.location = Location::Terminator(func, bb);
+            body_validator.terminator(data.terminator())?;
+        }
+        body_validator.init_before_use(func)
+    }
+
+    /// Checks an aggregate of values of types `tys` against the type it builds
+    fn aggregate(&self, kind: AggregateKind, tys: &[TyId], target: TyId) -> Result<()> {
+        match (kind, target.kind(self.tcx)) {
+            (AggregateKind::Array(elem), TyKind::Array(target_elem, len)) => {
+                self.expect_ty("array element type", elem, *target_elem)?;
+                if tys.len() != *len {
+                    return self.error(format!("{} elements for an array of {len}", tys.len()));
+                }
+                tys.iter()
+                    .try_for_each(|ty| self.expect_ty("array element", *ty, elem))
+            }
+            (AggregateKind::Tuple, TyKind::Unit) if tys.is_empty() => Ok(()),
+            (AggregateKind::Tuple, TyKind::Tuple(elems)) => {
+                if tys.len() != elems.len() {
+                    return self.error(format!(
+                        "{} fields for a tuple of {}",
+                        tys.len(),
+                        elems.len()
+                    ));
+                }
+                tys.iter()
+                    .zip(elems)
+                    .try_for_each(|(ty, elem)| self.expect_ty("tuple field", *ty, *elem))
+            }
+            (AggregateKind::Adt(adt_ty, variant), TyKind::Adt(adt)) => {
+                self.expect_ty("ADT aggregate", adt_ty, target)?;
+                let Some(def) = adt.variants.get(variant) else {
+                    return self.error(format!(
+                        "{} has no {}",
+                        target.serialize(self.tcx),
+                        variant.identifier()
+                    ));
+                };
+                if tys.len() != def.fields.len() {
+                    return self.error(format!(
+                        "{} fields for a variant with {}",
+                        tys.len(),
+                        def.fields.len()
+                    ));
+                }
+                tys.iter()
+                    .zip(&def.fields)
+                    .try_for_each(|(ty, field)| self.expect_ty("ADT field", *ty, *field))
+            }
+            _ => self.error(format!(
+                "{kind:?} aggregate can't build {}",
+                target.serialize(self.tcx)
+            )),
+        }
+    }
+}
+
+struct BodyValidator<'a> {
+    v: Validator<'a>,
+    body: &'a Body,
+}
+
+impl BodyValidator<'_> {
+    fn local_ty(&self, local: Local) -> Result<TyId> {
+        match self.body.local_decls.get(local) {
+            Some(decl) => Ok(decl.ty),
+            None => self
+                .v
+                .error(format!("{} is not declared", local.identifier())),
+        }
+    }
+
+    /// The type of a place, checking that each projection applies to the type before it
+    fn place_ty(&self, place: &Place) -> Result<TyId> {
+        let tcx = self.v.tcx;
+        let mut ty = self.local_ty(place.local())?;
+        for proj in place.projection() {
+            ty = match (proj, ty.kind(tcx)) {
+                (ProjectionElem::Deref, TyKind::RawPtr(pointee, _) | TyKind::Ref(pointee, _)) => {
+                    *pointee
+                }
+                (ProjectionElem::TupleField(fid), TyKind::Tuple(elems)) => {
+                    match elems.get(fid.index()) {
+                        Some(elem) => *elem,
+                        None => return self.v.error(format!("tuple has no field {}", fid.index())),
+                    }
+                }
+                (ProjectionElem::Field(fid), TyKind::Adt(adt)) if !adt.is_enum() => {
+                    match adt.variants[VariantIdx::new(0)].fields.get(*fid) {
+                        Some(field) => *field,
+                        None => return self.v.error(format!("struct has no {}", fid.identifier())),
+                    }
+                }
+                (ProjectionElem::DowncastField(vid, fid, field_ty), TyKind::Adt(adt)) => {
+                    match adt.variants.get(*vid).and_then(|def| def.fields.get(*fid)) {
+                        Some(field) => {
+                            self.v.expect_ty("downcast field", *field_ty, *field)?;
+                            *field
+                        }
+                        None => {
+                            return self.v.error(format!(
+                                "ADT has no {} in {}",
+                                fid.identifier(),
+                                vid.identifier()
+                            ));
+                        }
+                    }
+                }
+                (ProjectionElem::Index(idx), TyKind::Array(elem, _)) => {
+                    self.v
+                        .expect_ty("index", self.local_ty(*idx)?, TyCtxt::USIZE)?;
+                    *elem
+                }
+                (ProjectionElem::ConstantIndex { offset }, TyKind::Array(elem, len)) => {
+                    if *offset >= *len as u64 {
+                        return self
+                            .v
+                            .error(format!("index {offset} is out of bounds of [_; {len}]"));
+                    }
+                    *elem
+                }
+                _ => {
+                    return self.v.error(format!(
+                        "can't project {} from {}",
+                        place.serialize_place(tcx),
+                        ty.serialize(tcx)
+                    ));
+                }
+            };
+        }
+        Ok(ty)
+    }
+
+    fn operand_ty(&self, operand: &Operand) -> Result<TyId> {
+        match operand {
+            Operand::Copy(place) | Operand::Move(place) => self.place_ty(place),
+            Operand::Constant(lit) => Ok(lit.ty()),
+            Operand::Compound(compound) => {
+                if let CompoundConst::Item(item, value) = compound {
+                    let Some(item_value) = self.v.program.consts.get(*item) else {
+                        return self
+                            .v
+                            .error(format!("{} is not defined", item.identifier()));
+                    };
+                    self.v.expect_ty("constant", value.ty(), item_value.ty())?;
+                }
+                self.v.const_value(compound.value())?;
+                Ok(compound.value().ty())
+            }
+        }
+    }
+
+    fn statement(&self, stmt: &Statement) -> Result<()> {
+        match stmt {
+            Statement::Assign(place, rvalue) => {
+                let ty = self.place_ty(place)?;
+                self.rvalue(rvalue, ty)
+            }
+            Statement::StorageLive(local) | Statement::StorageDead(local) => {
+                self.local_ty(*local).map(|_| ())
+            }
+            Statement::Deinit(place) | Statement::Retag(place) => self.place_ty(place).map(|_| ()),
+            Statement::SetDiscriminant(place, variant) => {
+                let ty = self.place_ty(place)?;
+                match ty.kind(self.v.tcx) {
+                    TyKind::Adt(adt) if (*variant as usize) < adt.variants.len() => Ok(()),
+                    _ => self.v.error(format!(
+                        "{} has no variant {variant}",
+                        ty.serialize(self.v.tcx)
+                    )),
+                }
+            }
+            Statement::Nop => Ok(()),
+        }
+    }
+
+    /// Checks that the rvalue is well-typed and produces a value of type `target`
+    fn rvalue(&self, rvalue: &Rvalue, target: TyId) -> Result<()> {
+        let tcx = self.v.tcx;
+        match rvalue {
+            Rvalue::Use(operand) => self
+                .v
+                .expect_ty("operand", self.operand_ty(operand)?, target),
+            Rvalue::UnaryOp(op, operand) => {
+                let ty = self.operand_ty(operand)?;
+                let ok = match (op, ty.kind(tcx)) {
+                    (UnOp::Not, kind) => {
+                        matches!(kind, TyKind::Int(_) | TyKind::Uint(_) | TyKind::Bool)
+                    }
+                    (UnOp::Neg, kind) => matches!(kind, TyKind::Int(_) | TyKind::Float(_)),
+                };
+                if !ok {
+                    return self.v.error(format!(
+                        "{} can't be applied to {}",
+                        op.symbol(),
+                        ty.serialize(tcx)
+                    ));
+                }
+                self.v.expect_ty("unary operation", ty, target)
+            }
+            Rvalue::BinaryOp(op, l, r) => {
+                let ty = self.binary_op(*op, l, r)?;
+                self.v.expect_ty("binary operation", ty, target)
+            }
+            Rvalue::CheckedBinaryOp(op, l, r) => {
+                if !matches!(op, BinOp::Add | BinOp::Sub | BinOp::Mul) {
+                    return self.v.error(format!("{} can't be checked", op.symbol()));
+                }
+                let ty = self.binary_op(*op, l, r)?;
+                if !matches!(ty.kind(tcx), TyKind::Int(_) | TyKind::Uint(_)) {
+                    return self
+                        .v
+                        .error(format!("checked arithmetic on {}", ty.serialize(tcx)));
+                }
+                match target.tuple_elems(tcx) {
+                    Some(&[result, overflow]) => {
+                        self.v.expect_ty("checked result", ty, result)?;
+                        self.v.expect_ty("overflow flag", TyCtxt::BOOL, overflow)
+                    }
+                    _ => self.v.error(format!(
+                        "checked operation assigned to {}",
+                        target.serialize(tcx)
+                    )),
+                }
+            }
+            Rvalue::Cast(operand, ty) => {
+                let source = self.operand_ty(operand)?;
+                if !castable(tcx, source, *ty) {
+                    return self.v.error(format!(
+                        "{} can't be cast to {}",
+                        source.serialize(tcx),
+                        ty.serialize(tcx)
+                    ));
+                }
+                self.v.expect_ty("cast", *ty, target)
+            }
+            Rvalue::Len(place) => match self.place_ty(place)?.kind(tcx) {
+                TyKind::Array(..) => self.v.expect_ty("length", TyCtxt::USIZE, target),
+                _ => self.v.error("Len of a place that isn't an array"),
+            },
+            Rvalue::Discriminant(place) => match self.place_ty(place)?.kind(tcx) {
+                TyKind::Adt(..) => self.v.expect_ty("discriminant", TyCtxt::ISIZE, target),
+                _ => self.v.error("Discriminant of a place that isn't an ADT"),
+            },
+            Rvalue::AddressOf(mutability, place) => {
+                let pointee = self.place_ty(place)?;
+                match target.kind(tcx) {
+                    TyKind::RawPtr(ty, m) if m == mutability => {
+                        self.v.expect_ty("pointee", pointee, *ty)
+                    }
+                    _ => self.v.error(format!(
+                        "{}{} assigned to {}",
+                        mutability.ptr_prefix_str(),
+                        pointee.serialize(tcx),
+                        target.serialize(tcx)
+                    )),
+                }
+            }
+            Rvalue::Ref(mutability, place) => {
+                let referent = self.place_ty(place)?;
+                match target.kind(tcx) {
+                    TyKind::Ref(ty, m) if m == mutability => {
+                        self.v.expect_ty("referent", referent, *ty)
+                    }
+                    _ => self.v.error(format!(
+                        "&{}{} assigned to {}",
+                        mutability.prefix_str(),
+                        referent.serialize(tcx),
+                        target.serialize(tcx)
+                    )),
+                }
+            }
+            Rvalue::Aggregate(kind, operands) => {
+                let tys = operands
+                    .iter()
+                    .map(|operand| self.operand_ty(operand))
+                    .collect::<Result<Vec<_>>>()?;
+                self.v.aggregate(*kind, &tys, target)
+            }
+        }
+    }
+
+    /// The type of the result of a binary operation
+    fn binary_op(&self, op: BinOp, l: &Operand, r: &Operand) -> Result<TyId> {
+        use TyKind::*;
+        let tcx = self.v.tcx;
+        let (l, r) = (self.operand_ty(l)?, self.operand_ty(r)?);
+        let (l_kind, r_kind) = (l.kind(tcx), r.kind(tcx));
+        let same = same_ty(tcx, l, r);
+        let (ok, result) = match op {
+            BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Rem => {
+                (same && matches!(l_kind, Int(_) | Uint(_) | Float(_)), l)
+            }
+            BinOp::BitXor | BinOp::BitAnd | BinOp::BitOr => {
+                (same && matches!(l_kind, Int(_) | Uint(_) | Bool), l)
+            }
+            BinOp::Shl | BinOp::Shr => (
+                matches!(l_kind, Int(_) | Uint(_)) && matches!(r_kind, Int(_) | Uint(_)),
+                l,
+            ),
+            BinOp::Eq | BinOp::Lt | BinOp::Le | BinOp::Ne | BinOp::Ge | BinOp::Gt => (
+                same && matches!(
+                    l_kind,
+                    Bool | Char | Int(_) | Uint(_) | Float(_) | RawPtr(..)
+                ),
+                TyCtxt::BOOL,
+            ),
+            BinOp::Offset => (
+                l.is_raw_ptr(tcx) && matches!(r_kind, Int(IntTy::Isize) | Uint(UintTy::Usize)),
+                l,
+            ),
+        };
+        if ok {
+            Ok(result)
+        } else {
+            let name = match op {
+                BinOp::Offset => "Offset",
+                _ => op.symbol(),
+            };
+            self.v.error(format!(
+                "{name} can't be applied to {} and {}",
+                l.serialize(tcx),
+                r.serialize(tcx)
+            ))
+        }
+    }
+
+    fn target(&self, bb: BasicBlock) -> Result<()> {
+        if bb.index() < self.body.basic_blocks.len() {
+            Ok(())
+        } else {
+            self.v.error(format!("{} doesn't exist", bb.identifier()))
+        }
+    }
+
+    fn terminator(&self, term: &Terminator) -> Result<()> {
+        let tcx = self.v.tcx;
+        match term {
+            Terminator::Hole => self.v.error("the terminator is not set"),
+            Terminator::Return | Terminator::Unreachable => Ok(()),
+            Terminator::Goto { target } => self.target(*target),
+            Terminator::Drop { place, target } => {
+                self.place_ty(place)?;
+                self.target(*target)
+            }
+            Terminator::SwitchInt { discr, targets } => {
+                let ty = self.operand_ty(discr)?;
+                if !matches!(
+                    ty.kind(tcx),
+                    TyKind::Int(_) | TyKind::Uint(_) | TyKind::Char | TyKind::Bool
+                ) {
+                    return self
+                        .v
+                        .error(format!("can't switch on {}", ty.serialize(tcx)));
+                }
+                let mut values = BTreeSet::new();
+                for (value, bb) in &targets.branches {
+                    if !values.insert(value) {
+                        return self.v.error(format!("{value} has more than one target"));
+                    }
+                    self.target(*bb)?;
+                }
+                self.target(targets.otherwise)
+            }
+            Terminator::Call {
+                callee,
+                destination,
+                target,
+                args,
+            } => {
+                let ret = self.place_ty(destination)?;
+                let arg_tys = args
+                    .iter()
+                    .map(|arg| self.operand_ty(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                self.call(callee, &arg_tys, ret)?;
+                self.target(*target)
+            }
+        }
+    }
+
+    /// Checks the arguments and return type against the callee's signature, where
+    /// it is known
+    fn call(&self, callee: &Callee, args: &[TyId], ret: TyId) -> Result<()> {
+        let (params, callee_ret): (Vec<TyId>, TyId) = match callee {
+            Callee::Generated(func) | Callee::Closure(func, ..) | Callee::Worker(func) => {
+                let Some(body) = self.v.program.functions.get(*func) else {
+                    return self.v.error(format!("{} doesn't exist", func.identifier()));
+                };
+                let params: Vec<TyId> = body.args_decl_iter().map(|(_, decl)| decl.ty).collect();
+                if let Callee::Closure(_, capture, Some(via)) = callee {
+                    let kind = capture.closure_trait(&params, self.v.tcx);
+                    if !kind.callable_through(*via) {
+                        return self.v.error(format!(
+                            "the closure only implements {}, but is called through {}",
+                            kind.name(),
+                            via.name()
+                        ));
+                    }
+                }
+                (params, body.return_ty())
+            }
+            Callee::Foreign(func) => match self.v.program.foreign_functions.get(*func) {
+                Some(params) => (params.clone(), TyCtxt::U64),
+                None => return self.v.error(format!("{} doesn't exist", func.identifier())),
+            },
+            Callee::Named(name) if *name == "dump_var" => {
+                let arity = if self.v.program.use_debug_dumper {
+                    1 + Program::DUMPER_ARITY * 2
+                } else {
+                    Program::DUMPER_ARITY
+                };
+                if args.len() != arity {
+                    return self.v.error(format!(
+                        "dump_var takes {arity} arguments, but {} are given",
+                        args.len()
+                    ));
+                }
+                return Ok(());
+            }
+            Callee::Atomic(ty, method, ordering)
+            | Callee::ConcurrentAtomic(ty, method, ordering) => {
+                if method.forbids(*ordering) {
+                    return self
+                        .v
+                        .error(format!("{} can't be {}", method.name(), ordering.name()));
+                }
+                if matches!(callee, Callee::ConcurrentAtomic(..))
+                    && (!method.is_rmw() || *ty == AtomicTy::Ptr)
+                {
+                    return self
+                        .v
+                        .error("only read-modify-writes on integers are done concurrently");
+                }
+                // The helper takes a `*mut` to the atomic's value type, and then values of it
+                let Some(&ptr) = args.first() else {
+                    return self.v.error("an atomic helper takes a pointer");
+                };
+                let tcx = self.v.tcx;
+                let elem = match (ty, ptr.kind(tcx)) {
+                    (AtomicTy::Int(ty), _) => *ty,
+                    // Generic over the pointee of the stored pointers
+                    (AtomicTy::Ptr, TyKind::RawPtr(elem, _)) => *elem,
+                    (AtomicTy::Ptr, _) => ptr,
+                };
+                if *ty == AtomicTy::Ptr
+                    && !(method.on_ptr()
+                        && matches!(elem.kind(tcx), TyKind::RawPtr(_, Mutability::Mut)))
+                {
+                    return self.v.error(format!(
+                        "AtomicPtr can't {} {}",
+                        method.name(),
+                        elem.serialize(tcx)
+                    ));
+                }
+                if !matches!(ptr.kind(tcx), TyKind::RawPtr(pointee, Mutability::Mut) if *pointee == elem)
+                {
+                    return self.v.error(format!(
+                        "an atomic helper on {} takes a *mut of it, not {}",
+                        elem.serialize(tcx),
+                        ptr.serialize(tcx)
+                    ));
+                }
+                let ret = if *method == AtomicMethod::Store {
+                    TyCtxt::UNIT
+                } else {
+                    elem
+                };
+                let params = iter::once(ptr)
+                    .chain(iter::repeat(elem).take(method.operands()))
+                    .collect();
+                (params, ret)
+            }
+            // Intrinsics are generic, and the others are defined outside the program
+            Callee::Named(..) | Callee::Intrinsic(..) | Callee::AtomicIntrinsic(..) => {
+                return Ok(());
+            }
+        };
+        if args.len() != params.len() {
+            return self.v.error(format!(
+                "the callee takes {} arguments, but {} are given",
+                params.len(),
+                args.len()
+            ));
+        }
+        for (arg, param) in args.iter().zip(params) {
+            self.v.expect_ty("argument", *arg, param)?;
+        }
+        self.v.expect_ty("return place", ret, callee_ret)
+    }
+
+    /// Checks that every local is definitely initialized wherever it is read, where
+    /// writing to any part of a local initializes it
+    fn init_before_use(&mut self, func: Function) -> Result<()> {
+        let body = self.body;
+        // Unreachable blocks have no state, so they aren't checked
+        for (bb, init) in analysis::initialized(body).into_iter_enumerated() {
+            let Some(mut state) = init else {
+                continue;
+            };
+            let data = &body.basic_blocks[bb];
+            for (idx, stmt) in data.statements().iter().enumerate() {
+                self.v.location = Location::Statement(func, bb, idx);
+                self.statement_reads(stmt, &state)?;
+                analysis::init_statement(stmt, &mut state);
+            }
+            self.v.location = Location::Terminator(func, bb);
+            self.terminator_reads(data.terminator(), &state)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the locals a place reads are initialized. That's the local itself
+    /// when it is dereferenced or read from, and any index.
+    fn place_read(&self, place: &Place, whole: bool, state: &LocalSet) -> Result<()> {
+        let mut reads: Vec<Local> = place
+            .projection()
+            .iter()
+            .filter_map(|proj| match proj {
+                ProjectionElem::Index(idx) => Some(*idx),
+                _ => None,
+            })
+            .collect();
+        if whole || place.projection().iter().any(ProjectionElem::is_deref) {
+            reads.push(place.local());
+        }
+        for local in reads {
+            if !state[local] && !zero_sized(self.v.tcx, self.body.local_decls[local].ty) {
+                return self.v.error(format!(
+                    "{} is read before it is written",
+                    local.identifier()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn operand_read(&self, operand: &Operand, state: &LocalSet) -> Result<()> {
+        match operand.place() {
+            Some(place) => self.place_read(place, true, state),
+            None => Ok(()),
+        }
+    }
+
+    fn statement_reads(&self, stmt: &Statement, state: &LocalSet) -> Result<()> {
+        match stmt {
+            Statement::Assign(place, rvalue) => {
+                match rvalue {
+                    Rvalue::Use(operand)
+                    | Rvalue::UnaryOp(_, operand)
+                    | Rvalue::Cast(operand, _) => self.operand_read(operand, state)?,
+                    Rvalue::BinaryOp(_, l, r) | Rvalue::CheckedBinaryOp(_, l, r) => {
+                        self.operand_read(l, state)?;
+                        self.operand_read(r, state)?;
+                    }
+                    Rvalue::Len(place) | Rvalue::Discriminant(place) => {
+                        self.place_read(place, true, state)?
+                    }
+                    // Taking the address doesn't read the pointee
+                    Rvalue::AddressOf(_, place) | Rvalue::Ref(_, place) => {
+                        self.place_read(place, false, state)?
+                    }
+                    Rvalue::Aggregate(_, operands) => {
+                        for operand in operands {
+                            self.operand_read(operand, state)?;
+                        }
+                    }
+                }
+                self.place_read(place, false, state)
+            }
+            Statement::Deinit(place) | Statement::SetDiscriminant(place, _) => {
+                self.place_read(place, false, state)
+            }
+            Statement::Retag(place) => self.place_read(place, true, state),
+            Statement::StorageLive(_) | Statement::StorageDead(_) | Statement::Nop => Ok(()),
+        }
+    }
+
+    fn terminator_reads(&self, term: &Terminator, state: &LocalSet) -> Result<()> {
+        match term {
+            // RET isn't checked, as the decoy branches of a generated `SwitchInt` may
+            // return before it is written. They are never taken.
+            Terminator::Hole
+            | Terminator::Unreachable
+            | Terminator::Return
+            | Terminator::Goto { .. } => Ok(()),
+            Terminator::Drop { place, .. } => self.place_read(place, true, state),
+            Terminator::SwitchInt { discr, .. } => self.operand_read(discr, state),
+            Terminator::Call {
+                destination, args, ..
+            } => {
+                for arg in args {
+                    self.operand_read(arg, state)?;
+                }
+                self.place_read(destination, false, state)
+            }
+        }
+    }
+}
+
+/// Whether two types are the same. Structural types are compared by their structure,
+/// in case the same type was interned more than once, and ADTs by their id.
+fn same_ty(tcx: &TyCtxt, a: TyId, b: TyId) -> bool {
+    if a == b {
+        return true;
+    }
+    match (a.kind(tcx), b.kind(tcx)) {
+        (TyKind::Adt(..), _) | (_, TyKind::Adt(..)) => false,
+        (TyKind::RawPtr(a, am), TyKind::RawPtr(b, bm))
+        | (TyKind::Ref(a, am), TyKind::Ref(b, bm)) => am == bm && same_ty(tcx, *a, *b),
+        (TyKind::Array(a, alen), TyKind::Array(b, blen)) => alen == blen && same_ty(tcx, *a, *b),
+        (TyKind::Tuple(a), TyKind::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_ty(tcx, *a, *b))
+        }
+        (TyKind::Unit, TyKind::Tuple(elems)) | (TyKind::Tuple(elems), TyKind::Unit) => {
+            elems.is_empty()
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// Whether an `as` cast from `source` to `target` is allowed
+fn castable(tcx: &TyCtxt, source: TyId, target: TyId) -> bool {
+    use TyKind::*;
+    matches!(
+        (source.kind(tcx), target.kind(tcx)),
+        (Int(_) | Uint(_) | Float(_), Int(_) | Uint(_) | Float(_))
+            | (Bool | Char | RawPtr(..), Int(_) | Uint(_))
+            | (Uint(UintTy::U8), Char)
+            | (RawPtr(..) | Int(_) | Uint(_), RawPtr(..))
+    )
+}
+
+/// Values of types without any bytes are never uninitialized
+fn zero_sized(tcx: &TyCtxt, ty: TyId) -> bool {
+    match ty.kind(tcx) {
+        TyKind::Unit => true,
+        TyKind::Tuple(elems) => elems.iter().all(|elem| zero_sized(tcx, *elem)),
+        TyKind::Array(elem, len) => *len == 0 || zero_sized(tcx, *elem),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse::parse, syntax::*, tyctxt::TyCtxt};
+
+    use super::{validate, Location};
+
+    /// A function whose first block is `bb0`, with `_1` written and `_2` not
+    fn program(bb0: &str) -> String {
+        format!(
+            r#"
+            #[custom_mir(dialect = "runtime", phase = "initial")]
+            pub fn fn0() -> i32 {{
+            mir! {{
+            type RET = i32;
+            let _1: i32;
+            let _2: i32;
+            let _3: (i32, bool);
+            {{
+            _1 = 1_i32;
+            {bb0}
+            }}
+            bb1 = {{
+            Return()
+            }}
+            }}
+            }}
+            "#
+        )
+    }
+
+    fn error(bb0: &str) -> String {
+        let (program, tcx) = parse(&program(bb0)).unwrap();
+        validate(&program, &tcx).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn validate_ok() {
+        let (program, tcx) = parse(&program(
+            "_3 = Checked(_1 + _1);
+            RET = _3.0;
+            _2 = RET as i32;
+            match _1 {
+            0 => bb1,
+            1 => bb1,
+            _ => bb1
+            }",
+        ))
+        .unwrap();
+        validate(&program, &tcx).unwrap();
+    }
+
+    #[test]
+    fn validate_errors() {
+        assert_eq!(
+            error("match _1 {\n1 => bb1,\n1 => bb1,\n_ => bb1\n}"),
+            "fn0 bb0 terminator: 1 has more than one target"
+        );
+        assert_eq!(
+            error("RET = _1 + _2;\nGoto(bb1)"),
+            "fn0 bb0[1]: _2 is read before it is written"
+        );
+        assert_eq!(
+            error("RET = _1 + 1_i64;\nGoto(bb1)"),
+            "fn0 bb0[1]: + can't be applied to i32 and i64"
+        );
+        assert_eq!(
+            error("RET = _1.0;\nGoto(bb1)"),
+            "fn0 bb0[1]: can't project _1.0 from i32"
+        );
+        assert_eq!(
+            error("Call(RET, bb1, fn0(_1))"),
+            "fn0 bb0 terminator: the callee takes 0 arguments, but 1 are given"
+        );
+    }
+
+    #[test]
+    fn validate_hole() {
+        let tcx = TyCtxt::from_primitives();
+        let mut program = Program::new(false);
+        let mut body = Body::new(&[], TyCtxt::UNIT, false);
+        body.new_basic_block(BasicBlockData::new());
+        program.push_fn(body);
+        let err = validate(&program, &tcx).unwrap_err();
+        assert_eq!(
+            err.location,
+            Location::Terminator(Function::new(0), BasicBlock::new(0))
+        );
+        assert_eq!(err.message, "the terminator is not set");
+    }
+}
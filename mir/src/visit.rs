@@ -0,0 +1,527 @@
+//! Traversals of a `Program`, in the style of rustc's MIR visitors. Each `visit_*` method
+//! defaults to its `super_*` method, which visits the parts in the order they are
+//! serialized. An override that still wants the parts visited calls `super_*` itself.
+//!
+//! Types are visited where the program refers to them by `TyId`, and not through the
+//! `TyCtxt`.
+
+use crate::{syntax::*, validate::Location};
+
+pub trait Visitor {
+    fn visit_program(&mut self, program: &Program) {
+        self.super_program(program);
+    }
+
+    fn visit_body(&mut self, func: Function, body: &Body) {
+        self.super_body(func, body);
+    }
+
+    fn visit_local_decl(&mut self, local: Local, decl: &LocalDecl, location: Location) {
+        self.super_local_decl(local, decl, location);
+    }
+
+    fn visit_basic_block(&mut self, func: Function, bb: BasicBlock, data: &BasicBlockData) {
+        self.super_basic_block(func, bb, data);
+    }
+
+    fn visit_statement(&mut self, statement: &Statement, location: Location) {
+        self.super_statement(statement, location);
+    }
+
+    fn visit_terminator(&mut self, terminator: &Terminator, location: Location) {
+        self.super_terminator(terminator, location);
+    }
+
+    fn visit_rvalue(&mut self, rvalue: &Rvalue, location: Location) {
+        self.super_rvalue(rvalue, location);
+    }
+
+    fn visit_operand(&mut self, operand: &Operand, location: Location) {
+        self.super_operand(operand, location);
+    }
+
+    fn visit_place(&mut self, place: &Place, location: Location) {
+        self.super_place(place, location);
+    }
+
+    fn visit_callee(&mut self, _callee: &Callee, _location: Location) {}
+
+    fn visit_const_value(&mut self, value: &ConstValue, location: Location) {
+        self.super_const_value(value, location);
+    }
+
+    fn visit_literal(&mut self, _literal: &Literal, _location: Location) {}
+
+    /// Every use of a local: the base of a place, an index, or a storage marker
+    fn visit_local(&mut self, _local: Local, _location: Location) {}
+
+    fn visit_ty(&mut self, _ty: TyId, _location: Location) {}
+
+    fn super_program(&mut self, program: &Program) {
+        for literal in &program.entry_args {
+            self.visit_literal(literal, Location::EntryArgs);
+        }
+        for (func, params) in program.foreign_functions.iter_enumerated() {
+            for ty in params {
+                self.visit_ty(*ty, Location::Foreign(func));
+            }
+        }
+        for (item, value) in program.consts.iter_enumerated() {
+            self.visit_const_value(value, Location::Const(item));
+        }
+        for (func, body) in program.functions.iter_enumerated() {
+            self.visit_body(func, body);
+        }
+    }
+
+    fn super_body(&mut self, func: Function, body: &Body) {
+        for (local, decl) in body.local_decls.iter_enumerated() {
+            self.visit_local_decl(local, decl, Location::Decls(func));
+        }
+        for (bb, data) in body.basic_blocks.iter_enumerated() {
+            self.visit_basic_block(func, bb, data);
+        }
+    }
+
+    fn super_local_decl(&mut self, _local: Local, decl: &LocalDecl, location: Location) {
+        self.visit_ty(decl.ty, location);
+    }
+
+    fn super_basic_block(&mut self, func: Function, bb: BasicBlock, data: &BasicBlockData) {
+        for (i, statement) in data.statements.iter().enumerate() {
+            self.visit_statement(statement, Location::Statement(func, bb, i));
+        }
+        self.visit_terminator(&data.terminator, Location::Terminator(func, bb));
+    }
+
+    fn super_statement(&mut self, statement: &Statement, location: Location) {
+        match statement {
+            Statement::Assign(place, rvalue) => {
+                self.visit_place(place, location);
+                self.visit_rvalue(rvalue, location);
+            }
+            Statement::StorageLive(local) | Statement::StorageDead(local) => {
+                self.visit_local(*local, location)
+            }
+            Statement::Deinit(place)
+            | Statement::SetDiscriminant(place, _)
+            | Statement::Retag(place) => self.visit_place(place, location),
+            Statement::Nop => {}
+        }
+    }
+
+    fn super_terminator(&mut self, terminator: &Terminator, location: Location) {
+        match terminator {
+            Terminator::Drop { place, .. } => self.visit_place(place, location),
+            Terminator::Call {
+                callee,
+                destination,
+                args,
+                ..
+            } => {
+                self.visit_callee(callee, location);
+                self.visit_place(destination, location);
+                for arg in args {
+                    self.visit_operand(arg, location);
+                }
+            }
+            Terminator::SwitchInt { discr, .. } => self.visit_operand(discr, location),
+            Terminator::Hole
+            | Terminator::Return
+            | Terminator::Goto { .. }
+            | Terminator::Unreachable => {}
+        }
+    }
+
+    fn super_rvalue(&mut self, rvalue: &Rvalue, location: Location) {
+        match rvalue {
+            Rvalue::Use(operand) | Rvalue::UnaryOp(_, operand) => {
+                self.visit_operand(operand, location)
+            }
+            Rvalue::BinaryOp(_, lhs, rhs) | Rvalue::CheckedBinaryOp(_, lhs, rhs) => {
+                self.visit_operand(lhs, location);
+                self.visit_operand(rhs, location);
+            }
+            Rvalue::Cast(operand, ty) => {
+                self.visit_operand(operand, location);
+                self.visit_ty(*ty, location);
+            }
+            Rvalue::Len(place)
+            | Rvalue::Discriminant(place)
+            | Rvalue::AddressOf(_, place)
+            | Rvalue::Ref(_, place) => self.visit_place(place, location),
+            Rvalue::Aggregate(kind, operands) => {
+                if let AggregateKind::Array(ty) | AggregateKind::Adt(ty, _) = kind {
+                    self.visit_ty(*ty, location);
+                }
+                for operand in operands {
+                    self.visit_operand(operand, location);
+                }
+            }
+        }
+    }
+
+    /// A use of a const item visits the copy of its value the operand carries
+    fn super_operand(&mut self, operand: &Operand, location: Location) {
+        match operand {
+            Operand::Copy(place) | Operand::Move(place) => self.visit_place(place, location),
+            Operand::Constant(literal) => self.visit_literal(literal, location),
+            Operand::Compound(CompoundConst::Item(_, value) | CompoundConst::Block(value)) => {
+                self.visit_const_value(value, location)
+            }
+        }
+    }
+
+    fn super_place(&mut self, place: &Place, location: Location) {
+        self.visit_local(place.local, location);
+        for proj in &place.projection {
+            match proj {
+                ProjectionElem::Index(local) => self.visit_local(*local, location),
+                ProjectionElem::DowncastField(_, _, ty) => self.visit_ty(*ty, location),
+                ProjectionElem::Deref
+                | ProjectionElem::TupleField(_)
+                | ProjectionElem::Field(_)
+                | ProjectionElem::ConstantIndex { .. } => {}
+            }
+        }
+    }
+
+    fn super_const_value(&mut self, value: &ConstValue, location: Location) {
+        match value {
+            ConstValue::Scalar(literal) => self.visit_literal(literal, location),
+            ConstValue::Aggregate(ty, kind, fields) => {
+                self.visit_ty(*ty, location);
+                if let AggregateKind::Array(ty) | AggregateKind::Adt(ty, _) = kind {
+                    self.visit_ty(*ty, location);
+                }
+                for field in fields {
+                    self.visit_const_value(field, location);
+                }
+            }
+        }
+    }
+}
+
+/// `Visitor`, but with each part of the program borrowed mutably
+pub trait MutVisitor {
+    fn visit_program(&mut self, program: &mut Program) {
+        self.super_program(program);
+    }
+
+    fn visit_body(&mut self, func: Function, body: &mut Body) {
+        self.super_body(func, body);
+    }
+
+    fn visit_local_decl(&mut self, local: Local, decl: &mut LocalDecl, location: Location) {
+        self.super_local_decl(local, decl, location);
+    }
+
+    fn visit_basic_block(&mut self, func: Function, bb: BasicBlock, data: &mut BasicBlockData) {
+        self.super_basic_block(func, bb, data);
+    }
+
+    fn visit_statement(&mut self, statement: &mut Statement, location: Location) {
+        self.super_statement(statement, location);
+    }
+
+    fn visit_terminator(&mut self, terminator: &mut Terminator, location: Location) {
+        self.super_terminator(terminator, location);
+    }
+
+    fn visit_rvalue(&mut self, rvalue: &mut Rvalue, location: Location) {
+        self.super_rvalue(rvalue, location);
+    }
+
+    fn visit_operand(&mut self, operand: &mut Operand, location: Location) {
+        self.super_operand(operand, location);
+    }
+
+    fn visit_place(&mut self, place: &mut Place, location: Location) {
+        self.super_place(place, location);
+    }
+
+    fn visit_callee(&mut self, _callee: &mut Callee, _location: Location) {}
+
+    fn visit_const_value(&mut self, value: &mut ConstValue, location: Location) {
+        self.super_const_value(value, location);
+    }
+
+    fn visit_literal(&mut self, _literal: &mut Literal, _location: Location) {}
+
+    /// Every use of a local: the base of a place, an index, or a storage marker
+    fn visit_local(&mut self, _local: &mut Local, _location: Location) {}
+
+    fn visit_ty(&mut self, _ty: &mut TyId, _location: Location) {}
+
+    fn super_program(&mut self, program: &mut Program) {
+        for literal in &mut program.entry_args {
+            self.visit_literal(literal, Location::EntryArgs);
+        }
+        for (func, params) in program.foreign_functions.iter_mut_enumerated() {
+            for ty in params {
+                self.visit_ty(ty, Location::Foreign(func));
+            }
+        }
+        for (item, value) in program.consts.iter_mut_enumerated() {
+            self.visit_const_value(value, Location::Const(item));
+        }
+        for (func, body) in program.functions.iter_mut_enumerated() {
+            self.visit_body(func, body);
+        }
+    }
+
+    fn super_body(&mut self, func: Function, body: &mut Body) {
+        for (local, decl) in body.local_decls.iter_mut_enumerated() {
+            self.visit_local_decl(local, decl, Location::Decls(func));
+        }
+        for (bb, data) in body.basic_blocks.iter_mut_enumerated() {
+            self.visit_basic_block(func, bb, data);
+        }
+    }
+
+    fn super_local_decl(&mut self, _local: Local, decl: &mut LocalDecl, location: Location) {
+        self.visit_ty(&mut decl.ty, location);
+    }
+
+    fn super_basic_block(&mut self, func: Function, bb: BasicBlock, data: &mut BasicBlockData) {
+        for (i, statement) in data.statements.iter_mut().enumerate() {
+            self.visit_statement(statement, Location::Statement(func, bb, i));
+        }
+        self.visit_terminator(&mut data.terminator, Location::Terminator(func, bb));
+    }
+
+    fn super_statement(&mut self, statement: &mut Statement, location: Location) {
+        match statement {
+            Statement::Assign(place, rvalue) => {
+                self.visit_place(place, location);
+                self.visit_rvalue(rvalue, location);
+            }
+            Statement::StorageLive(local) | Statement::StorageDead(local) => {
+                self.visit_local(local, location)
+            }
+            Statement::Deinit(place)
+            | Statement::SetDiscriminant(place, _)
+            | Statement::Retag(place) => self.visit_place(place, location),
+            Statement::Nop => {}
+        }
+    }
+
+    fn super_terminator(&mut self, terminator: &mut Terminator, location: Location) {
+        match terminator {
+            Terminator::Drop { place, .. } => self.visit_place(place, location),
+            Terminator::Call {
+                callee,
+                destination,
+                args,
+                ..
+            } => {
+                self.visit_callee(callee, location);
+                self.visit_place(destination, location);
+                for arg in args {
+                    self.visit_operand(arg, location);
+                }
+            }
+            Terminator::SwitchInt { discr, .. } => self.visit_operand(discr, location),
+            Terminator::Hole
+            | Terminator::Return
+            | Terminator::Goto { .. }
+            | Terminator::Unreachable => {}
+        }
+    }
+
+    fn super_rvalue(&mut self, rvalue: &mut Rvalue, location: Location) {
+        match rvalue {
+            Rvalue::Use(operand) | Rvalue::UnaryOp(_, operand) => {
+                self.visit_operand(operand, location)
+            }
+            Rvalue::BinaryOp(_, lhs, rhs) | Rvalue::CheckedBinaryOp(_, lhs, rhs) => {
+                self.visit_operand(lhs, location);
+                self.visit_operand(rhs, location);
+            }
+            Rvalue::Cast(operand, ty) => {
+                self.visit_operand(operand, location);
+                self.visit_ty(ty, location);
+            }
+            Rvalue::Len(place)
+            | Rvalue::Discriminant(place)
+            | Rvalue::AddressOf(_, place)
+            | Rvalue::Ref(_, place) => self.visit_place(place, location),
+            Rvalue::Aggregate(kind, operands) => {
+                if let AggregateKind::Array(ty) | AggregateKind::Adt(ty, _) = kind {
+                    self.visit_ty(ty, location);
+                }
+                for operand in operands.iter_mut() {
+                    self.visit_operand(operand, location);
+                }
+            }
+        }
+    }
+
+    /// A use of a const item visits the copy of its value the operand carries
+    fn super_operand(&mut self, operand: &mut Operand, location: Location) {
+        match operand {
+            Operand::Copy(place) | Operand::Move(place) => self.visit_place(place, location),
+            Operand::Constant(literal) => self.visit_literal(literal, location),
+            Operand::Compound(CompoundConst::Item(_, value) | CompoundConst::Block(value)) => {
+                self.visit_const_value(value, location)
+            }
+        }
+    }
+
+    fn super_place(&mut self, place: &mut Place, location: Location) {
+        self.visit_local(&mut place.local, location);
+        for proj in place.projection.iter_mut() {
+            match proj {
+                ProjectionElem::Index(local) => self.visit_local(local, location),
+                ProjectionElem::DowncastField(_, _, ty) => self.visit_ty(ty, location),
+                ProjectionElem::Deref
+                | ProjectionElem::TupleField(_)
+                | ProjectionElem::Field(_)
+                | ProjectionElem::ConstantIndex { .. } => {}
+            }
+        }
+    }
+
+    fn super_const_value(&mut self, value: &mut ConstValue, location: Location) {
+        match value {
+            ConstValue::Scalar(literal) => self.visit_literal(literal, location),
+            ConstValue::Aggregate(ty, kind, fields) => {
+                self.visit_ty(ty, location);
+                if let AggregateKind::Array(ty) | AggregateKind::Adt(ty, _) = kind {
+                    self.visit_ty(ty, location);
+                }
+                for field in fields.iter_mut() {
+                    self.visit_const_value(field, location);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{parse::parse, serialize::CallSynatx, syntax::*, validate::Location};
+
+    use super::{MutVisitor, Visitor};
+
+    const PROGRAM: &str = r#"
+        #[custom_mir(dialect = "runtime", phase = "initial")]
+        pub fn fn0() -> i32 {
+        mir! {
+        type RET = i32;
+        let _1: i32;
+        let _2: [i32; 2];
+        let _3: usize;
+        let _4: i32;
+        {
+        _1 = 1_i32;
+        _3 = 0_usize;
+        _2 = [_1, 2_i32];
+        RET = _2[_3];
+        _4 = RET + 3_i32;
+        Return()
+        }
+        }
+        }
+        "#;
+
+    /// Where each local is used, and every literal
+    #[derive(Default)]
+    struct Uses {
+        locals: Vec<(Local, Location)>,
+        literals: Vec<Literal>,
+        tys: usize,
+    }
+
+    impl Visitor for Uses {
+        fn visit_local(&mut self, local: Local, location: Location) {
+            self.locals.push((local, location));
+        }
+
+        fn visit_literal(&mut self, literal: &Literal, _: Location) {
+            self.literals.push(*literal);
+        }
+
+        fn visit_ty(&mut self, _: TyId, _: Location) {
+            self.tys += 1;
+        }
+    }
+
+    #[test]
+    fn visit_program() {
+        let (program, _) = parse(PROGRAM).unwrap();
+        let mut uses = Uses::default();
+        uses.visit_program(&program);
+
+        let func = Function::new(0);
+        let bb0 = BasicBlock::new(0);
+        let at = |i| Location::Statement(func, bb0, i);
+        let locals: Vec<(usize, Location)> = uses
+            .locals
+            .iter()
+            .map(|(local, location)| (local.index(), *location))
+            .collect();
+        assert_eq!(
+            locals,
+            [
+                (1, at(0)),
+                (3, at(1)),
+                (2, at(2)),
+                (1, at(2)),
+                (0, at(3)),
+                (2, at(3)),
+                (3, at(3)),
+                (4, at(4)),
+                (0, at(4)),
+            ]
+        );
+        let literals: Vec<String> = uses
+            .literals
+            .iter()
+            .map(|literal| format!("{literal:?}"))
+            .collect();
+        assert_eq!(
+            literals,
+            [
+                "Int(1, I32)",
+                "Uint(0, Usize)",
+                "Int(2, I32)",
+                "Int(3, I32)"
+            ]
+        );
+        // The five local decls, and the array's element type
+        assert_eq!(uses.tys, 6);
+    }
+
+    /// Swaps two locals of the same type, and increments integer literals
+    struct Rewrite(Local, Local);
+
+    impl MutVisitor for Rewrite {
+        fn visit_local(&mut self, local: &mut Local, _: Location) {
+            if *local == self.0 {
+                *local = self.1;
+            } else if *local == self.1 {
+                *local = self.0;
+            }
+        }
+
+        fn visit_literal(&mut self, literal: &mut Literal, _: Location) {
+            if let Literal::Int(i, _) = literal {
+                *i += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn mut_visit_program() {
+        let (mut program, tcx) = parse(PROGRAM).unwrap();
+        Rewrite(Local::new(1), Local::new(4)).visit_program(&mut program);
+        let code = program.serialize(&tcx, CallSynatx::V4);
+        assert!(
+            code.contains(
+                "_4 = 2_i32;\n_3 = 0_usize;\n_2 = [_4,3_i32];\nRET = _2[_3];\n_1 = RET + 4_i32;"
+            ),
+            "{code}"
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! A campaign's progress, saved after every program so that a restarted campaign
+//! picks up where it left off
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::Path,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Seeds {
+    /// start..end, or from start on
+    Range { start: u64, end: Option<u64> },
+    /// Seeds drawn from a generator seeded with this
+    Random { seed: u64 },
+}
+
+/// SplitMix64, so that the nth random seed doesn't depend on the ones before it
+fn splitmix64(n: u64) -> u64 {
+    let mut z = n.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+impl Seeds {
+    /// The seed of the nth program, None past the end of the range
+    pub fn nth(&self, n: u64) -> Option<u64> {
+        match *self {
+            Seeds::Range { start, end } => {
+                let seed = start.checked_add(n)?;
+                end.is_none_or(|end| seed < end).then_some(seed)
+            }
+            Seeds::Random { seed } => Some(splitmix64(
+                seed.wrapping_add(n.wrapping_mul(0x9e3779b97f4a7c15)),
+            )),
+        }
+    }
+}
+
+impl FromStr for Seeds {
+    type Err = String;
+
+    /// A range like 0..1000, or 1000.. for no end
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| format!("{s} is not a range like 0..1000"))?;
+        let start = start.parse().map_err(|e| format!("invalid start: {e}"))?;
+        let end = match end {
+            "" => None,
+            end => Some(end.parse().map_err(|e| format!("invalid end: {e}"))?),
+        };
+        Ok(Seeds::Range { start, end })
+    }
+}
+
+/// The `generate` flags of every program in the campaign
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Generation {
+    pub abi_stress: bool,
+    pub c_ffi: bool,
+    pub split: bool,
+    /// Campaigns from before threaded mode don't have it
+    #[serde(default)]
+    pub threads: bool,
+}
+
+impl Generation {
+    pub fn options(&self, debug_dump: bool) -> generate::Options {
+        generate::Options {
+            debug_dump,
+            abi_stress: self.abi_stress,
+            c_ffi: self.c_ffi,
+            split: self.split,
+            threads: self.threads,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Campaign {
+    pub seeds: Seeds,
+    pub generation: Generation,
+    /// Every program before the nth has been tested
+    next: u64,
+    /// Programs after `next` that parallel workers already tested
+    done: BTreeSet<u64>,
+    pub tested: u64,
+    /// The seeds that failed, by signature
+    pub failures: BTreeMap<String, Vec<u64>>,
+    /// The next program to hand out to a worker
+    #[serde(skip)]
+    dispatched: u64,
+}
+
+impl Campaign {
+    pub fn new(seeds: Seeds, generation: Generation) -> Self {
+        Self {
+            seeds,
+            generation,
+            next: 0,
+            done: BTreeSet::new(),
+            tested: 0,
+            failures: BTreeMap::new(),
+            dispatched: 0,
+        }
+    }
+
+    /// The campaign saved at `path`, if there is one
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut campaign: Self = serde_json::from_str(&json)?;
+        // Programs that were being tested when the campaign stopped are tested again
+        campaign.dispatched = campaign.next;
+        Ok(Some(campaign))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        // Write, then rename, so a campaign killed halfway through saving isn't lost
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp, path)
+    }
+
+    /// Hands out the next untested program as (n, seed), None once there are no more
+    pub fn take(&mut self) -> Option<(u64, u64)> {
+        while self.done.contains(&self.dispatched) {
+            self.dispatched += 1;
+        }
+        let n = self.dispatched;
+        let seed = self.seeds.nth(n)?;
+        self.dispatched += 1;
+        Some((n, seed))
+    }
+
+    /// Records the nth program as tested, with the signature of its failure if it failed
+    pub fn complete(&mut self, n: u64, seed: u64, failure: Option<String>) {
+        self.tested += 1;
+        if let Some(signature) = failure {
+            self.failures.entry(signature).or_default().push(seed);
+        }
+        self.done.insert(n);
+        while self.done.remove(&self.next) {
+            self.next += 1;
+        }
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failures.values().map(Vec::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Campaign, Generation, Seeds};
+
+    #[test]
+    fn parse_seeds() {
+        assert_eq!(
+            "0..1000".parse(),
+            Ok(Seeds::Range {
+                start: 0,
+                end: Some(1000)
+            })
+        );
+        assert_eq!(
+            "1000..".parse(),
+            Ok(Seeds::Range {
+                start: 1000,
+                end: None
+            })
+        );
+        assert!("1000".parse::<Seeds>().is_err());
+        assert!("a..10".parse::<Seeds>().is_err());
+        assert!("0..b".parse::<Seeds>().is_err());
+    }
+
+    #[test]
+    fn nth_seed() {
+        let range = Seeds::Range {
+            start: 10,
+            end: Some(12),
+        };
+        assert_eq!(range.nth(0), Some(10));
+        assert_eq!(range.nth(1), Some(11));
+        assert_eq!(range.nth(2), None);
+
+        let open = Seeds::Range {
+            start: u64::MAX,
+            end: None,
+        };
+        assert_eq!(open.nth(0), Some(u64::MAX));
+        assert_eq!(open.nth(1), None);
+
+        // Random seeds are the same on every run, and differ between programs
+        let random = Seeds::Random { seed: 7 };
+        assert_eq!(random.nth(3), Seeds::Random { seed: 7 }.nth(3));
+        assert_ne!(random.nth(3), random.nth(4));
+        assert_ne!(random.nth(3), Seeds::Random { seed: 8 }.nth(3));
+    }
+
+    #[test]
+    fn complete_out_of_order() {
+        let seeds = Seeds::Range {
+            start: 100,
+            end: Some(105),
+        };
+        let mut campaign = Campaign::new(seeds, Generation::default());
+        let taken: Vec<_> = std::iter::from_fn(|| campaign.take()).collect();
+        assert_eq!(taken, [(0, 100), (1, 101), (2, 102), (3, 103), (4, 104)]);
+
+        campaign.complete(2, 102, None);
+        campaign.complete(1, 101, Some("crash".to_string()));
+        assert_eq!(campaign.next, 0);
+        assert_eq!(campaign.done.len(), 2);
+
+        // The done set shrinks as soon as the programs before it are tested
+        campaign.complete(0, 100, None);
+        assert_eq!(campaign.next, 3);
+        assert!(campaign.done.is_empty());
+
+        campaign.complete(4, 104, Some("crash".to_string()));
+        campaign.complete(3, 103, None);
+        assert_eq!(campaign.next, 5);
+        assert!(campaign.done.is_empty());
+        assert_eq!(campaign.tested, 5);
+        assert_eq!(campaign.failures["crash"], [101, 104]);
+        assert_eq!(campaign.failed(), 2);
+    }
+
+    #[test]
+    fn resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("campaign.json");
+        assert!(Campaign::load(&path).unwrap().is_none());
+
+        let mut campaign = Campaign::new(
+            Seeds::Range {
+                start: 0,
+                end: None,
+            },
+            Generation::default(),
+        );
+        for _ in 0..4 {
+            campaign.take();
+        }
+        campaign.complete(0, 0, None);
+        campaign.complete(2, 2, None);
+        campaign.save(&path).unwrap();
+
+        // Programs 1 and 3 were in flight, so they are handed out again, but not 2
+        let mut campaign = Campaign::load(&path).unwrap().unwrap();
+        assert_eq!(campaign.take(), Some((1, 1)));
+        assert_eq!(campaign.take(), Some((3, 3)));
+        assert_eq!(campaign.take(), Some((4, 4)));
+        assert_eq!(campaign.tested, 2);
+    }
+}
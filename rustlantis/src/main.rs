@@ -0,0 +1,449 @@
+mod campaign;
+mod reduce;
+
+use std::{
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use config::Config;
+use difftest::{
+    backends::{BackendOptions, Miri},
+    matrix::{self, Backends},
+    run_diff_test,
+    triage::{self, Signature},
+    Source,
+};
+use log::{debug, error, info};
+use mir::serialize::CallSynatx;
+
+use crate::{
+    campaign::{Campaign, Generation, Seeds},
+    reduce::Repro,
+};
+
+/// How often the fuzz subcommand prints its progress
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+fn main() -> ExitCode {
+    env_logger::init();
+
+    let matches = Command::new("rustlantis")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("fuzz")
+                .about("generate and difftest programs until the seeds run out")
+                .arg(
+                    Arg::new("seeds")
+                        .long("seeds")
+                        .value_parser(|s: &str| s.parse::<Seeds>())
+                        .help("seeds to test, like 0..1000 or 1000.. [default: random seeds]"),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .value_parser(value_parser!(usize))
+                        .help("programs to test in parallel [default: number of CPUs]"),
+                )
+                .arg(
+                    Arg::new("state")
+                        .long("state")
+                        .value_parser(value_parser!(PathBuf))
+                        .default_value("campaign.json")
+                        .help("where the campaign is saved, and resumed from if it exists"),
+                )
+                .arg(
+                    Arg::new("repros")
+                        .long("repros")
+                        .value_parser(value_parser!(PathBuf))
+                        .default_value("repros")
+                        .help("where failing programs are saved"),
+                )
+                .args(generation_args()),
+        )
+        .subcommand(
+            Command::new("reduce")
+                .about("reduce a failing program, keeping it failing the same way")
+                .arg(
+                    Arg::new("program")
+                        .required(true)
+                        .help("generation seed of the program, or the path to a .rs repro"),
+                )
+                .arg(
+                    Arg::new("debug")
+                        .short('d')
+                        .long("debug")
+                        .action(ArgAction::SetTrue)
+                        .help("reduce the debug version of the generated program"),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_parser(value_parser!(PathBuf))
+                        .help(
+                            "where the reduced program is saved \
+                             [default: <seed or file stem>-reduced.rs]",
+                        ),
+                )
+                .args(generation_args()),
+        )
+        .get_matches();
+    match matches.subcommand() {
+        Some(("fuzz", matches)) => fuzz(matches),
+        Some(("reduce", matches)) => reduce(matches),
+        _ => unreachable!("subcommand is required"),
+    }
+}
+
+/// The flags choosing what to generate
+fn generation_args() -> [Arg; 4] {
+    [
+        Arg::new("abi-stress")
+            .long("abi-stress")
+            .action(ArgAction::SetTrue)
+            .help("generate functions with foreign ABIs and many by-value arguments"),
+        Arg::new("c-ffi")
+            .long("c-ffi")
+            .action(ArgAction::SetTrue)
+            .help("generate calls into an embedded C translation unit"),
+        Arg::new("split")
+            .long("split")
+            .action(ArgAction::SetTrue)
+            .help("generate programs that are also built as a library and a binary crate"),
+        Arg::new("threads")
+            .long("threads")
+            .action(ArgAction::SetTrue)
+            .help("run some functions and atomic read-modify-writes on scoped threads"),
+    ]
+}
+
+fn generation(matches: &ArgMatches) -> Generation {
+    Generation {
+        abi_stress: matches.get_flag("abi-stress"),
+        c_ffi: matches.get_flag("c-ffi"),
+        split: matches.get_flag("split"),
+        threads: matches.get_flag("threads"),
+    }
+}
+
+/// The config, with `defaults` for keys that are unset
+fn settings(defaults: &[(&str, u64)]) -> Config {
+    let mut builder = Config::builder();
+    for (key, value) in defaults {
+        builder = builder.set_default(*key, *value).unwrap();
+    }
+    builder
+        .add_source(config::File::with_name("config.toml").required(false))
+        .add_source(config::Environment::default())
+        .build()
+        .unwrap()
+}
+
+/// The fuzz subcommand
+fn fuzz(matches: &ArgMatches) -> ExitCode {
+    let state = matches.get_one::<PathBuf>("state").unwrap();
+    let repros = matches.get_one::<PathBuf>("repros").unwrap();
+    let jobs = matches
+        .get_one::<usize>("jobs")
+        .copied()
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |jobs| jobs.get()));
+
+    let campaign = match Campaign::load(state) {
+        Ok(Some(campaign)) => {
+            info!(
+                "Resuming {} with {} programs tested and {} failures",
+                state.display(),
+                campaign.tested,
+                campaign.failed()
+            );
+            campaign
+        }
+        Ok(None) => {
+            let seeds = match matches.get_one::<Seeds>("seeds") {
+                Some(seeds) => *seeds,
+                None => Seeds::Random {
+                    seed: rand::random(),
+                },
+            };
+            Campaign::new(seeds, generation(matches))
+        }
+        Err(e) => {
+            error!("can't resume {}: {e}", state.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    info!("Fuzzing {:?} with {jobs} jobs", campaign.seeds);
+
+    let settings = settings(&[]);
+    let backends = match matrix::backends(&settings, campaign.generation.split) {
+        Ok(backends) => backends,
+        Err(e) => {
+            error!("backend init failed\n{}", e.0);
+            return ExitCode::FAILURE;
+        }
+    };
+    let buckets = settings.get_string("buckets_dir").ok().map(PathBuf::from);
+    if let Err(e) = fs::create_dir_all(repros) {
+        error!("can't create {}: {e}", repros.display());
+        return ExitCode::FAILURE;
+    }
+
+    let fuzzer = Fuzzer {
+        campaign: Mutex::new(campaign),
+        state,
+        repros,
+        buckets: buckets.as_deref(),
+        backends: &backends,
+    };
+    let start = (Instant::now(), fuzzer.campaign.lock().unwrap().tested);
+    let (running, stopped) = mpsc::channel::<()>();
+    thread::scope(|s| {
+        let fuzzer = &fuzzer;
+        s.spawn(move || {
+            // Until every worker has dropped its sender
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(STATUS_INTERVAL) {
+                fuzzer.status(start);
+            }
+        });
+        for _ in 0..jobs {
+            let running = running.clone();
+            s.spawn(move || {
+                fuzzer.work();
+                drop(running);
+            });
+        }
+        drop(running);
+    });
+
+    fuzzer.status(start);
+    let campaign = fuzzer.campaign.into_inner().unwrap();
+    for (signature, seeds) in &campaign.failures {
+        println!("{signature}: {seeds:?}");
+    }
+    if campaign.failures.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+struct Fuzzer<'a> {
+    campaign: Mutex<Campaign>,
+    state: &'a Path,
+    repros: &'a Path,
+    buckets: Option<&'a Path>,
+    backends: &'a Backends,
+}
+
+impl Fuzzer<'_> {
+    /// Prints the counters, with the throughput since `start` (a time and the number
+    /// of programs tested by then, so that resumed campaigns don't count earlier runs)
+    fn status(&self, (time, tested): (Instant, u64)) {
+        let campaign = self.campaign.lock().unwrap();
+        let elapsed = time.elapsed().as_secs_f64();
+        eprintln!(
+            "{} programs tested, {:.2}/s, {} failures, {} unique",
+            campaign.tested,
+            (campaign.tested - tested) as f64 / elapsed,
+            campaign.failed(),
+            campaign.failures.len()
+        );
+    }
+
+    /// Tests programs until the seeds run out
+    fn work(&self) {
+        loop {
+            let (n, seed, generation) = {
+                let mut campaign = self.campaign.lock().unwrap();
+                let Some((n, seed)) = campaign.take() else {
+                    return;
+                };
+                (n, seed, campaign.generation)
+            };
+            let failure = self.test(seed, generation);
+
+            let mut campaign = self.campaign.lock().unwrap();
+            campaign.complete(n, seed, failure.map(|signature| signature.0));
+            if let Err(e) = campaign.save(self.state) {
+                error!("can't save {}: {e}", self.state.display());
+            }
+        }
+    }
+
+    /// Generates and difftests a program, returning the signature of its failure
+    fn test(&self, seed: u64, generation: Generation) -> Option<Signature> {
+        let Some(code) = generate(seed, generation, false) else {
+            return Some(Signature("generator panicked".to_owned()));
+        };
+        let results = run_diff_test(&Source::Stdin(code.clone()), self.backends);
+        let Some(signature) = Signature::of(&results) else {
+            debug!("{seed} is all the same");
+            return None;
+        };
+        error!("{seed} didn't pass: {signature}\n{results}");
+
+        // A debug program is easier to minimise, if it still fails
+        let debug = generate(seed, generation, true).filter(|debug| {
+            let results = run_diff_test(&Source::Stdin(debug.clone()), self.backends);
+            !(results.all_same() && results.all_success())
+        });
+        let repro = match debug {
+            Some(debug) => self.save(&format!("{seed}-debug.rs"), &debug),
+            None => self.save(&format!("{seed}.rs"), &code),
+        };
+
+        if let (Some(repro), Some(buckets)) = (repro, self.buckets) {
+            match triage::file(buckets, &signature, &Source::File(repro)) {
+                Ok(bucket) => info!("Filed {seed} under {bucket}"),
+                Err(e) => error!("can't file {seed} under {}: {e}", buckets.display()),
+            }
+        }
+        Some(signature)
+    }
+
+    fn save(&self, name: &str, code: &str) -> Option<PathBuf> {
+        let path = self.repros.join(name);
+        match fs::write(&path, code) {
+            Ok(()) => Some(path),
+            Err(e) => {
+                error!("can't save {}: {e}", path.display());
+                None
+            }
+        }
+    }
+}
+
+/// The reduce subcommand
+fn reduce(matches: &ArgMatches) -> ExitCode {
+    let program = matches.get_one::<String>("program").unwrap();
+    let (name, repro, call_syntax) = if Path::new(program).is_file() {
+        let path = Path::new(program);
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        match load(path) {
+            Ok((repro, call_syntax)) => (name, repro, call_syntax),
+            Err(e) => {
+                error!("{e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else if let Ok(seed) = program.parse::<u64>() {
+        let options = generation(matches).options(matches.get_flag("debug"));
+        let (program, tcx) = generate::generate_program(seed, &options);
+        (
+            seed.to_string(),
+            Repro { program, tcx },
+            options.call_syntax,
+        )
+    } else {
+        error!("{program} is neither a seed nor a file");
+        return ExitCode::FAILURE;
+    };
+    let output = matches
+        .get_one::<PathBuf>("output")
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from(format!("{name}-reduced.rs")));
+
+    // Every candidate is run, so a reduction that makes a backend hang mustn't stall it
+    let settings = settings(&[("compile_timeout", 60), ("run_timeout", 60)]);
+    let split = repro.program.split.is_some();
+    let mut backends = match matrix::backends(&settings, split) {
+        Ok(backends) => backends,
+        Err(e) => {
+            error!("backend init failed\n{}", e.0);
+            return ExitCode::FAILURE;
+        }
+    };
+    // Without checking for UB, a reduction could fail the same way for another reason
+    if !backends.keys().any(|name| name.contains("miri")) {
+        let toolchain = settings
+            .get_string("miri_toolchain")
+            .unwrap_or_else(|_| "nightly".to_owned());
+        match Miri::from_rustup(&toolchain, true) {
+            Ok(miri) => {
+                let options = BackendOptions {
+                    timeouts: matrix::configured_timeouts(&settings),
+                    ..Default::default()
+                };
+                backends.insert("miri-checked", Box::new(miri.with_options(options)));
+            }
+            Err(e) => {
+                error!("miri init failed\n{}", e.0);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let code = repro.serialize(call_syntax);
+    let results = run_diff_test(&Source::Stdin(code), &backends);
+    let Some(signature) = Signature::of(&results) else {
+        error!("{name} is all the same, nothing to reduce");
+        return ExitCode::FAILURE;
+    };
+    if results.has_ub() {
+        error!("{name} has undefined behaviour, so reductions can't be told apart\n{results}");
+        return ExitCode::FAILURE;
+    }
+    info!("Reducing {name}: {signature}");
+
+    let repro = reduce::reduce(
+        repro,
+        call_syntax,
+        |code| {
+            let results = run_diff_test(&Source::Stdin(code.to_owned()), &backends);
+            !results.has_ub() && Signature::of(&results).as_ref() == Some(&signature)
+        },
+        |code| {
+            if let Err(e) = fs::write(&output, code) {
+                error!("can't save {}: {e}", output.display());
+            }
+        },
+    );
+    let code = repro.serialize(call_syntax);
+    if let Err(e) = fs::write(&output, &code) {
+        error!("can't save {}: {e}", output.display());
+        return ExitCode::FAILURE;
+    }
+    println!("{}: {} bytes", output.display(), code.len());
+    ExitCode::SUCCESS
+}
+
+/// Reads a repro back, along with the call syntax it is written in. Hand-edited repros
+/// that don't serialize back to the same text are written in the current syntax
+fn load(path: &Path) -> Result<(Repro, CallSynatx), String> {
+    let code =
+        fs::read_to_string(path).map_err(|e| format!("can't read {}: {e}", path.display()))?;
+    let (program, tcx) =
+        mir::parse::parse(&code).map_err(|e| format!("can't parse {}: {e}", path.display()))?;
+    let repro = Repro { program, tcx };
+    let call_syntax = [
+        CallSynatx::V4,
+        CallSynatx::V3,
+        CallSynatx::V2,
+        CallSynatx::V1,
+    ]
+    .into_iter()
+    .find(|call_syntax| repro.serialize(*call_syntax) == code)
+    .unwrap_or(CallSynatx::V4);
+    Ok((repro, call_syntax))
+}
+
+/// The program generated from the seed, None if the generator panicked
+fn generate(seed: u64, generation: Generation, debug_dump: bool) -> Option<String> {
+    let options = generation.options(debug_dump);
+    let code = panic::catch_unwind(AssertUnwindSafe(|| generate::generate(seed, &options)));
+    if code.is_err() {
+        error!("generating {seed} panicked");
+    }
+    code.ok()
+}
@@ -0,0 +1,1333 @@
+//! Reducing a failing program on its MIR instead of its text, so that every candidate
+//! is a well-formed program. Each pass proposes edits, which are tried in chunks, from
+//! all of them at once down to one at a time as in delta debugging. A candidate is
+//! kept if its source code is shorter and it is still interesting, i.e. fails the
+//! same way.
+
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    mem,
+    ops::Range,
+    slice,
+};
+
+use index_vec::IndexVec;
+
+use log::{debug, info};
+use mir::{
+    analysis,
+    serialize::CallSynatx,
+    syntax::{
+        AggregateKind, BasicBlock, Body, Callee, CompoundConst, ConstValue, FieldIdx, Function,
+        Literal, Local, Operand, Place, Program, ProjectionElem, Rvalue, Statement, Terminator,
+        TyId, TyKind, VariantIdx,
+    },
+    tyctxt::TyCtxt,
+    validate::Location,
+    visit::MutVisitor,
+};
+
+/// A program and its types
+#[derive(Clone)]
+pub struct Repro {
+    pub program: Program,
+    pub tcx: TyCtxt,
+}
+
+impl Repro {
+    pub fn serialize(&self, call_syntax: CallSynatx) -> String {
+        generate::serialize(&self.program, &self.tcx, call_syntax)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Pass {
+    /// Removes functions other than fn0, and calls to them
+    RemoveFunctions,
+    /// Replaces terminators with `Return`
+    ReturnEarly,
+    /// Replaces calls, drops and switches with a `Goto` to one of their targets
+    Goto,
+    /// Removes blocks that can't be reached from the first one
+    RemoveUnreachable,
+    DeleteStatements,
+    /// Inlines a function into all of its callers, and removes it
+    Inline,
+    /// Replaces literals with zero
+    ShrinkLiterals,
+    RemoveUnusedLocals,
+    RemoveUnusedConsts,
+    /// Removes fields of ADTs and elements of tuples that are never projected to
+    RemoveFields,
+    /// Removes variants of enums that are never built or projected to
+    RemoveVariants,
+    /// Shortens arrays to the elements that are accessed
+    ShrinkArrays,
+    /// Replaces structs that have a single field with the field's type
+    InlineStructs,
+    RemoveUnusedTypes,
+}
+
+impl Pass {
+    /// Roughly the largest reductions first
+    const ALL: [Pass; 14] = [
+        Pass::RemoveFunctions,
+        Pass::ReturnEarly,
+        Pass::Goto,
+        Pass::RemoveUnreachable,
+        Pass::DeleteStatements,
+        Pass::Inline,
+        Pass::ShrinkLiterals,
+        Pass::RemoveUnusedLocals,
+        Pass::RemoveUnusedConsts,
+        Pass::RemoveFields,
+        Pass::RemoveVariants,
+        Pass::ShrinkArrays,
+        Pass::InlineStructs,
+        Pass::RemoveUnusedTypes,
+    ];
+
+    /// How many edits the pass can make to the program
+    fn targets(self, repro: &Repro) -> usize {
+        let program = &repro.program;
+        match self {
+            Pass::RemoveFunctions => program.functions.len() - 1,
+            Pass::ReturnEarly => returns(program).len(),
+            Pass::Goto => gotos(program).len(),
+            Pass::DeleteStatements => statements(program).len(),
+            Pass::Inline => inlinable(program).len(),
+            Pass::ShrinkLiterals => {
+                let mut count = 0;
+                for_each_literal(&mut program.clone(), &mut |_| count += 1);
+                count
+            }
+            Pass::RemoveFields => unused_fields(repro).len(),
+            Pass::RemoveVariants => unused_variants(repro).len(),
+            Pass::ShrinkArrays => oversized_arrays(repro).len(),
+            Pass::InlineStructs => single_field_structs(&repro.tcx).len(),
+            // Everything that is unused goes at once
+            Pass::RemoveUnreachable
+            | Pass::RemoveUnusedLocals
+            | Pass::RemoveUnusedConsts
+            | Pass::RemoveUnusedTypes => 1,
+        }
+    }
+
+    /// The program with the edits in `targets` made, None if that doesn't change it
+    fn apply(self, repro: &Repro, targets: Range<usize>) -> Option<Repro> {
+        let mut repro = repro.clone();
+        let program = &mut repro.program;
+        let changed = match self {
+            Pass::RemoveFunctions => {
+                let removed = targets.map(|i| Function::new(i + 1)).collect();
+                remove_functions(program, &removed);
+                true
+            }
+            Pass::ReturnEarly => {
+                for (func, bb) in returns(program).drain(targets) {
+                    *program.functions[func].basic_blocks[bb].terminator_mut() = Terminator::Return;
+                }
+                true
+            }
+            Pass::Goto => {
+                let mut changed = HashSet::new();
+                for (func, bb, target) in gotos(program).drain(targets) {
+                    // Switches have one edit per target, the first one in the chunk wins
+                    if changed.insert((func, bb)) {
+                        *program.functions[func].basic_blocks[bb].terminator_mut() =
+                            Terminator::Goto { target };
+                    }
+                }
+                true
+            }
+            Pass::RemoveUnreachable => {
+                let mut changed = false;
+                for body in program.functions.iter_mut() {
+                    changed |= remove_unreachable(body);
+                }
+                changed
+            }
+            Pass::DeleteStatements => {
+                // From the last one, so that the indices of the others stay put
+                for (func, bb, i) in statements(program).drain(targets).rev() {
+                    program.functions[func].basic_blocks[bb]
+                        .statements_mut()
+                        .remove(i);
+                }
+                true
+            }
+            Pass::Inline => {
+                // From the last one, so that removing it doesn't renumber the others
+                for func in inlinable(program).drain(targets).rev() {
+                    inline(program, func);
+                }
+                true
+            }
+            Pass::ShrinkLiterals => {
+                let mut changed = false;
+                let mut i = 0;
+                for_each_literal(program, &mut |literal| {
+                    if targets.contains(&i) && !is_zero(literal) {
+                        *literal = zero(*literal);
+                        changed = true;
+                    }
+                    i += 1;
+                });
+                changed
+            }
+            Pass::RemoveUnusedLocals => {
+                let mut changed = false;
+                for (func, body) in program.functions.iter_mut_enumerated() {
+                    changed |= remove_unused_locals(func, body);
+                }
+                changed
+            }
+            Pass::RemoveUnusedConsts => remove_unused_consts(program),
+            Pass::RemoveFields => {
+                let removed = unused_fields(&repro).drain(targets).collect();
+                remove_fields(&mut repro, &removed);
+                true
+            }
+            Pass::RemoveVariants => {
+                // Enums keep two variants, as they would become structs with one
+                let mut removed = HashSet::new();
+                for (ty, variant) in unused_variants(&repro).drain(targets) {
+                    let TyKind::Adt(adt) = repro.tcx.kind(ty) else {
+                        unreachable!("only enums have variants to remove");
+                    };
+                    let remaining = adt.variants.len()
+                        - removed.iter().filter(|(other, _)| *other == ty).count();
+                    if remaining > 2 {
+                        removed.insert((ty, variant));
+                    }
+                }
+                remove_variants(&mut repro, &removed);
+                !removed.is_empty()
+            }
+            Pass::ShrinkArrays => {
+                let lens = oversized_arrays(&repro).drain(targets).collect();
+                shrink_arrays(&mut repro, &lens);
+                true
+            }
+            Pass::InlineStructs => {
+                let inlined = single_field_structs(&repro.tcx).drain(targets).collect();
+                inline_structs(&mut repro, &inlined);
+                true
+            }
+            Pass::RemoveUnusedTypes => remove_unused_types(&mut repro),
+        };
+        changed.then_some(repro)
+    }
+}
+
+/// Reduces the program for as long as `interesting` holds for the source code of the
+/// candidates, calling `reduced` with each smaller program it finds
+pub fn reduce(
+    mut repro: Repro,
+    call_syntax: CallSynatx,
+    mut interesting: impl FnMut(&str) -> bool,
+    mut reduced: impl FnMut(&str),
+) -> Repro {
+    let mut code = repro.serialize(call_syntax);
+    loop {
+        let size = code.len();
+        for pass in Pass::ALL {
+            let mut chunk = pass.targets(&repro);
+            while chunk > 0 {
+                let mut start = 0;
+                loop {
+                    let targets = pass.targets(&repro);
+                    if start >= targets {
+                        break;
+                    }
+                    let end = cmp::min(start + chunk, targets);
+                    let candidate = pass.apply(&repro, start..end).map(|candidate| {
+                        let code = candidate.serialize(call_syntax);
+                        (candidate, code)
+                    });
+                    match candidate {
+                        Some((candidate, candidate_code))
+                            if candidate_code.len() < code.len()
+                                && interesting(&candidate_code) =>
+                        {
+                            debug!("{pass:?} {start}..{end}: {} bytes", candidate_code.len());
+                            repro = candidate;
+                            code = candidate_code;
+                            reduced(&code);
+                            // Unless the edits were removed, and the next ones took their place
+                            if pass.targets(&repro) == targets {
+                                start = end;
+                            }
+                        }
+                        _ => start = end,
+                    }
+                }
+                chunk /= 2;
+            }
+            info!("{pass:?}: {} bytes", code.len());
+        }
+        if code.len() == size {
+            return repro;
+        }
+    }
+}
+
+/// Blocks that don't return yet
+fn returns(program: &Program) -> Vec<(Function, BasicBlock)> {
+    let mut blocks = vec![];
+    for (func, body) in program.functions.iter_enumerated() {
+        for (bb, data) in body.basic_blocks.iter_enumerated() {
+            if !matches!(data.terminator(), Terminator::Return) {
+                blocks.push((func, bb));
+            }
+        }
+    }
+    blocks
+}
+
+/// Blocks that could jump to a target instead
+fn gotos(program: &Program) -> Vec<(Function, BasicBlock, BasicBlock)> {
+    let mut gotos = vec![];
+    for (func, body) in program.functions.iter_enumerated() {
+        for (bb, data) in body.basic_blocks.iter_enumerated() {
+            if matches!(
+                data.terminator(),
+                Terminator::Call { .. } | Terminator::Drop { .. } | Terminator::SwitchInt { .. }
+            ) {
+                gotos.extend(
+                    data.terminator()
+                        .successors()
+                        .into_iter()
+                        .map(|target| (func, bb, target)),
+                );
+            }
+        }
+    }
+    gotos
+}
+
+fn statements(program: &Program) -> Vec<(Function, BasicBlock, usize)> {
+    let mut statements = vec![];
+    for (func, body) in program.functions.iter_enumerated() {
+        for (bb, data) in body.basic_blocks.iter_enumerated() {
+            statements.extend((0..data.statements().len()).map(|i| (func, bb, i)));
+        }
+    }
+    statements
+}
+
+/// Functions that are only called directly, and not by themselves
+fn inlinable(program: &Program) -> Vec<Function> {
+    let mut called = HashSet::new();
+    let mut excluded = HashSet::new();
+    for (caller, body) in program.functions.iter_enumerated() {
+        for data in &body.basic_blocks {
+            match data.terminator() {
+                Terminator::Call {
+                    callee: Callee::Generated(func),
+                    ..
+                } => {
+                    called.insert(*func);
+                    if *func == caller {
+                        excluded.insert(*func);
+                    }
+                }
+                Terminator::Call {
+                    callee: Callee::Closure(func, ..),
+                    ..
+                } => {
+                    excluded.insert(*func);
+                }
+                _ => {}
+            }
+        }
+    }
+    program
+        .functions
+        .indices()
+        .filter(|func| called.contains(func) && !excluded.contains(func))
+        .collect()
+}
+
+fn map_blocks(body: &mut Body, mut f: impl FnMut(BasicBlock) -> BasicBlock) {
+    for data in body.basic_blocks.iter_mut() {
+        match data.terminator_mut() {
+            Terminator::Goto { target }
+            | Terminator::Drop { target, .. }
+            | Terminator::Call { target, .. } => *target = f(*target),
+            Terminator::SwitchInt { targets, .. } => {
+                for (_, bb) in &mut targets.branches {
+                    *bb = f(*bb);
+                }
+                targets.otherwise = f(targets.otherwise);
+            }
+            Terminator::Return | Terminator::Unreachable | Terminator::Hole => {}
+        }
+    }
+}
+
+/// Calls the closure on every operand it visits
+struct Operands<F>(F);
+
+impl<F: FnMut(&mut Operand)> MutVisitor for Operands<F> {
+    fn visit_operand(&mut self, operand: &mut Operand, _: Location) {
+        (self.0)(operand);
+    }
+}
+
+/// Calls the closure on every place it visits, including those of operands
+struct Places<F>(F);
+
+impl<F: FnMut(&mut Place)> MutVisitor for Places<F> {
+    fn visit_place(&mut self, place: &mut Place, _: Location) {
+        (self.0)(place);
+    }
+}
+
+/// Replaces every local it visits with what the closure returns
+struct Locals<F>(F);
+
+impl<F: FnMut(Local) -> Local> MutVisitor for Locals<F> {
+    fn visit_local(&mut self, local: &mut Local, _: Location) {
+        *local = (self.0)(*local);
+    }
+}
+
+/// Calls the closure on every type it visits
+struct Tys<F>(F);
+
+impl<F: FnMut(&mut TyId)> MutVisitor for Tys<F> {
+    fn visit_ty(&mut self, ty: &mut TyId, _: Location) {
+        (self.0)(ty);
+    }
+}
+
+fn for_each_operand(program: &mut Program, f: &mut impl FnMut(&mut Operand)) {
+    Operands(f).visit_program(program);
+}
+
+fn for_each_place(func: Function, body: &mut Body, f: &mut impl FnMut(&mut Place)) {
+    Places(f).visit_body(func, body);
+}
+
+fn map_locals(func: Function, body: &mut Body, f: impl FnMut(Local) -> Local) {
+    Locals(f).visit_body(func, body);
+}
+
+/// Removes the functions, and turns calls to them into jumps to the return block
+fn remove_functions(program: &mut Program, removed: &HashSet<Function>) {
+    for body in program.functions.iter_mut() {
+        for data in body.basic_blocks.iter_mut() {
+            let target = match data.terminator() {
+                Terminator::Call {
+                    callee:
+                        Callee::Generated(func) | Callee::Closure(func, ..) | Callee::Worker(func),
+                    target,
+                    ..
+                } if removed.contains(func) => *target,
+                _ => continue,
+            };
+            *data.terminator_mut() = Terminator::Goto { target };
+        }
+    }
+
+    let mut renumbered = HashMap::new();
+    for (func, body) in mem::take(&mut program.functions).into_iter_enumerated() {
+        if !removed.contains(&func) {
+            renumbered.insert(func, program.functions.push(body));
+        }
+    }
+    for body in program.functions.iter_mut() {
+        for data in body.basic_blocks.iter_mut() {
+            if let Terminator::Call {
+                callee: Callee::Generated(func) | Callee::Closure(func, ..) | Callee::Worker(func),
+                ..
+            } = data.terminator_mut()
+            {
+                *func = renumbered[func];
+            }
+        }
+    }
+    // The lower crate starts at its first remaining function
+    program.split = program.split.and_then(|split| {
+        renumbered
+            .iter()
+            .filter(|(old, _)| **old >= split)
+            .map(|(_, new)| *new)
+            .min()
+    });
+}
+
+/// Inlines the function into all of its callers, and removes it
+fn inline(program: &mut Program, func: Function) {
+    let callee = program.functions[func].clone();
+    for body in program.functions.iter_mut() {
+        let calls: Vec<BasicBlock> = body
+            .basic_blocks
+            .iter_enumerated()
+            .filter(|(_, data)| {
+                matches!(
+                    data.terminator(),
+                    Terminator::Call { callee: Callee::Generated(called), .. } if *called == func
+                )
+            })
+            .map(|(bb, _)| bb)
+            .collect();
+        for bb in calls {
+            inline_call(body, bb, func, &callee);
+        }
+    }
+    remove_functions(program, &HashSet::from([func]));
+}
+
+/// Replaces the call that terminates `bb` with the callee's blocks and locals. The
+/// arguments are assigned to the callee's parameters, and its return blocks assign its
+/// return value to the destination and jump to the call's target.
+fn inline_call(caller: &mut Body, bb: BasicBlock, func: Function, callee: &Body) {
+    let Terminator::Call {
+        destination,
+        target,
+        args,
+        ..
+    } = caller.basic_blocks[bb].terminator().clone()
+    else {
+        panic!("not a call");
+    };
+    let locals = caller.local_decls.len();
+    let blocks = caller.basic_blocks.len();
+
+    let mut inlined = callee.clone();
+    map_locals(func, &mut inlined, |local| local + locals);
+    map_blocks(&mut inlined, |bb| bb + blocks);
+    for decl in inlined.local_decls {
+        caller.local_decls.push(decl);
+    }
+    for mut data in inlined.basic_blocks {
+        if matches!(data.terminator(), Terminator::Return) {
+            data.insert_statement(Statement::Assign(
+                destination.clone(),
+                Rvalue::Use(Operand::Move(Place::from_local(Local::new(locals)))),
+            ));
+            *data.terminator_mut() = Terminator::Goto { target };
+        }
+        caller.basic_blocks.push(data);
+    }
+
+    let call = &mut caller.basic_blocks[bb];
+    for (param, arg) in callee.args_iter().zip(args) {
+        call.insert_statement(Statement::Assign(
+            Place::from_local(param + locals),
+            Rvalue::Use(arg),
+        ));
+    }
+    *call.terminator_mut() = Terminator::Goto {
+        target: BasicBlock::new(blocks),
+    };
+}
+
+fn remove_unreachable(body: &mut Body) -> bool {
+    let reachable = analysis::reachable(body);
+    if reachable.iter().all(|reachable| *reachable) {
+        return false;
+    }
+
+    let mut renumbered = HashMap::new();
+    for (bb, data) in mem::take(&mut body.basic_blocks).into_iter_enumerated() {
+        if reachable[bb] {
+            renumbered.insert(bb, body.basic_blocks.push(data));
+        }
+    }
+    map_blocks(body, |bb| renumbered[&bb]);
+    true
+}
+
+fn remove_unused_locals(func: Function, body: &mut Body) -> bool {
+    // The return place and the parameters are part of the signature
+    let mut used: HashSet<Local> = (0..=body.args_iter().len()).map(Local::new).collect();
+    map_locals(func, body, |local| {
+        used.insert(local);
+        local
+    });
+    if used.len() == body.local_decls.len() {
+        return false;
+    }
+
+    let mut renumbered = HashMap::new();
+    for (local, decl) in mem::take(&mut body.local_decls).into_iter_enumerated() {
+        if used.contains(&local) {
+            renumbered.insert(local, body.local_decls.push(decl));
+        }
+    }
+    map_locals(func, body, |local| renumbered[&local]);
+    true
+}
+
+fn remove_unused_consts(program: &mut Program) -> bool {
+    let mut used = HashSet::new();
+    for_each_operand(program, &mut |operand| {
+        if let Operand::Compound(CompoundConst::Item(item, _)) = operand {
+            used.insert(*item);
+        }
+    });
+    if used.len() == program.consts.len() {
+        return false;
+    }
+
+    let mut renumbered = HashMap::new();
+    for (item, value) in mem::take(&mut program.consts).into_iter_enumerated() {
+        if used.contains(&item) {
+            renumbered.insert(item, program.consts.push(value));
+        }
+    }
+    for_each_operand(program, &mut |operand| {
+        if let Operand::Compound(CompoundConst::Item(item, _)) = operand {
+            *item = renumbered[item];
+        }
+    });
+    true
+}
+
+/// Calls `f` on every constant value in the program, including the fields of aggregates
+/// (before the aggregates themselves), always in the same order
+fn for_each_const(program: &mut Program, f: &mut impl FnMut(&mut ConstValue)) {
+    fn visit(value: &mut ConstValue, f: &mut impl FnMut(&mut ConstValue)) {
+        if let ConstValue::Aggregate(_, _, fields) = value {
+            for field in fields.iter_mut() {
+                visit(field, f);
+            }
+        }
+        f(value);
+    }
+
+    for value in program.consts.iter_mut() {
+        visit(value, f);
+    }
+    for_each_operand(program, &mut |operand| {
+        if let Operand::Compound(CompoundConst::Block(value)) = operand {
+            visit(value, f);
+        }
+    });
+
+    // Uses of const items carry a copy of the value
+    let consts = program.consts.clone();
+    for_each_operand(program, &mut |operand| {
+        if let Operand::Compound(CompoundConst::Item(item, value)) = operand {
+            *value = consts[*item].clone();
+        }
+    });
+}
+
+/// Calls `f` on every literal in the program, always in the same order
+fn for_each_literal(program: &mut Program, f: &mut impl FnMut(&mut Literal)) {
+    program.entry_args.iter_mut().for_each(&mut *f);
+    for_each_const(program, &mut |value| {
+        if let ConstValue::Scalar(literal) = value {
+            f(literal);
+        }
+    });
+    for_each_operand(program, &mut |operand| {
+        if let Operand::Constant(literal) = operand {
+            f(literal);
+        }
+    });
+}
+
+fn is_zero(literal: &Literal) -> bool {
+    match *literal {
+        Literal::Uint(i, _) => i == 0,
+        Literal::Int(i, _) => i == 0,
+        Literal::Bool(b) => !b,
+        Literal::Char(c) => c == '\0',
+        Literal::Float(f, _) => f == 0.0,
+    }
+}
+
+fn zero(literal: Literal) -> Literal {
+    match literal {
+        Literal::Uint(_, ty) => Literal::Uint(0, ty),
+        Literal::Int(_, ty) => Literal::Int(0, ty),
+        Literal::Bool(_) => Literal::Bool(false),
+        Literal::Char(_) => Literal::Char('\0'),
+        Literal::Float(_, ty) => Literal::Float(0.0, ty),
+    }
+}
+
+/// Calls `f` on every type the program refers to, but not the types those are made of
+fn for_each_ty(program: &mut Program, f: &mut impl FnMut(&mut TyId)) {
+    Tys(f).visit_program(program);
+}
+
+/// The types a type is made of
+fn components(kind: &mut TyKind) -> Vec<&mut TyId> {
+    match kind {
+        TyKind::RawPtr(ty, _) | TyKind::Ref(ty, _) | TyKind::Array(ty, _) => vec![ty],
+        TyKind::Tuple(tys) => tys.iter_mut().collect(),
+        TyKind::Adt(adt) => adt
+            .variants
+            .iter_mut()
+            .flat_map(|variant| variant.fields.iter_mut())
+            .collect(),
+        TyKind::Unit
+        | TyKind::Bool
+        | TyKind::Char
+        | TyKind::Int(_)
+        | TyKind::Uint(_)
+        | TyKind::Float(_) => vec![],
+    }
+}
+
+/// Calls `f` with the type of the base of every projection in the program, and keeps
+/// the projection it returns, if any. Types are those of `tcx`, before any edits.
+fn for_each_projection(
+    program: &mut Program,
+    tcx: &TyCtxt,
+    f: &mut impl FnMut(TyId, ProjectionElem) -> Option<ProjectionElem>,
+) {
+    for (func, body) in program.functions.iter_mut_enumerated() {
+        let local_decls = body.local_decls.clone();
+        for_each_place(func, body, &mut |place| {
+            let mut ty = local_decls[place.local()].ty;
+            let mut projection = vec![];
+            for proj in place.projection() {
+                projection.extend(f(ty, *proj));
+                ty = ty.projected_ty(tcx, slice::from_ref(proj));
+            }
+            *place = Place::from_projected(place.local(), &projection);
+        });
+    }
+}
+
+/// Calls `f` on every statement in the program, with the type of the place it assigns
+/// or sets the discriminant of. The places' projections must still match `tcx`.
+fn for_each_typed_statement(
+    program: &mut Program,
+    tcx: &TyCtxt,
+    f: &mut impl FnMut(TyId, &mut Statement),
+) {
+    for body in program.functions.iter_mut() {
+        let Body {
+            basic_blocks,
+            local_decls,
+            ..
+        } = body;
+        for data in basic_blocks.iter_mut() {
+            for statement in data.statements_mut() {
+                if let Statement::Assign(place, _) | Statement::SetDiscriminant(place, _) =
+                    statement
+                {
+                    f(place.ty(local_decls, tcx), statement);
+                }
+            }
+        }
+    }
+}
+
+/// What the program does with the parts of its types
+#[derive(Default)]
+struct Uses {
+    /// Fields that are projected to, with tuple elements as fields of variant 0
+    fields: HashSet<(TyId, VariantIdx, FieldIdx)>,
+    /// Variants that are built, set or projected to
+    variants: HashSet<(TyId, VariantIdx)>,
+    /// How many elements of an array are accessed, None if it is indexed at runtime
+    elements: HashMap<TyId, Option<usize>>,
+    /// Results of checked operations, which must stay `(T, bool)`
+    checked: HashSet<TyId>,
+    /// Types whose size must stay the same, as a transmute reads or writes them (or
+    /// something made of them)
+    sized: HashSet<TyId>,
+}
+
+impl Uses {
+    fn of(repro: &Repro) -> Self {
+        let mut uses = Uses::default();
+        let mut program = repro.program.clone();
+        let tcx = &repro.tcx;
+        for body in &program.functions {
+            for data in &body.basic_blocks {
+                if let Terminator::Call {
+                    callee: Callee::Intrinsic("transmute"),
+                    destination,
+                    args,
+                    ..
+                } = data.terminator()
+                {
+                    uses.sized.insert(destination.ty(&body.local_decls, tcx));
+                    uses.sized
+                        .extend(args.iter().map(|arg| arg.ty(&body.local_decls, tcx)));
+                }
+            }
+        }
+        let mut stack: Vec<TyId> = uses.sized.iter().copied().collect();
+        while let Some(ty) = stack.pop() {
+            for component in components(&mut tcx.kind(ty).clone()) {
+                if uses.sized.insert(*component) {
+                    stack.push(*component);
+                }
+            }
+        }
+
+        for_each_projection(&mut program, tcx, &mut |ty, proj| {
+            match proj {
+                ProjectionElem::Field(field) | ProjectionElem::TupleField(field) => {
+                    uses.fields.insert((ty, VariantIdx::new(0), field));
+                }
+                ProjectionElem::DowncastField(variant, field, _) => {
+                    uses.fields.insert((ty, variant, field));
+                    uses.variants.insert((ty, variant));
+                }
+                ProjectionElem::Index(_) => {
+                    uses.elements.insert(ty, None);
+                }
+                ProjectionElem::ConstantIndex { offset } => {
+                    let len = offset as usize + 1;
+                    let elements = uses.elements.entry(ty).or_insert(Some(len));
+                    *elements = elements.map(|elements| cmp::max(elements, len));
+                }
+                ProjectionElem::Deref => {}
+            }
+            Some(proj)
+        });
+        for_each_typed_statement(&mut program, tcx, &mut |ty, statement| match statement {
+            Statement::Assign(_, Rvalue::Aggregate(AggregateKind::Adt(ty, variant), _)) => {
+                uses.variants.insert((*ty, *variant));
+            }
+            Statement::Assign(_, Rvalue::CheckedBinaryOp(..)) => {
+                uses.checked.insert(ty);
+            }
+            Statement::SetDiscriminant(_, variant) => {
+                uses.variants
+                    .insert((ty, VariantIdx::new(*variant as usize)));
+            }
+            _ => {}
+        });
+        for_each_const(&mut program, &mut |value| {
+            if let ConstValue::Aggregate(ty, AggregateKind::Adt(_, variant), _) = value {
+                uses.variants.insert((*ty, *variant));
+            }
+        });
+        uses
+    }
+}
+
+fn unused_fields(repro: &Repro) -> Vec<(TyId, VariantIdx, FieldIdx)> {
+    let uses = Uses::of(repro);
+    let mut fields = vec![];
+    for (ty, kind) in repro.tcx.iter_enumerated() {
+        match kind {
+            _ if uses.sized.contains(&ty) => {}
+            TyKind::Adt(adt) => {
+                for (variant, def) in adt.variants.iter_enumerated() {
+                    fields.extend(def.fields.indices().map(|field| (ty, variant, field)));
+                }
+            }
+            TyKind::Tuple(elems) if !uses.checked.contains(&ty) => {
+                fields.extend((0..elems.len()).map(|i| (ty, VariantIdx::new(0), FieldIdx::new(i))));
+            }
+            _ => {}
+        }
+    }
+    fields.retain(|field| !uses.fields.contains(field));
+    fields
+}
+
+fn unused_variants(repro: &Repro) -> Vec<(TyId, VariantIdx)> {
+    let uses = Uses::of(repro);
+    let mut variants = vec![];
+    for (ty, kind) in repro.tcx.iter_enumerated() {
+        match kind {
+            TyKind::Adt(adt) if adt.variants.len() > 2 && !uses.sized.contains(&ty) => {
+                variants.extend(adt.variants.indices().map(|variant| (ty, variant)));
+            }
+            _ => {}
+        }
+    }
+    variants.retain(|variant| !uses.variants.contains(variant));
+    variants
+}
+
+/// Arrays that are longer than the elements accessed, with the length they need
+fn oversized_arrays(repro: &Repro) -> Vec<(TyId, usize)> {
+    let uses = Uses::of(repro);
+    repro
+        .tcx
+        .iter_enumerated()
+        .filter_map(|(ty, kind)| {
+            let TyKind::Array(_, len) = kind else {
+                return None;
+            };
+            if uses.sized.contains(&ty) {
+                return None;
+            }
+            let needed = match uses.elements.get(&ty) {
+                Some(None) => return None,
+                Some(Some(elements)) => *elements,
+                None => 1,
+            };
+            (needed < *len).then_some((ty, needed))
+        })
+        .collect()
+}
+
+fn single_field_structs(tcx: &TyCtxt) -> Vec<TyId> {
+    tcx.iter_enumerated()
+        .filter(|(_, kind)| {
+            matches!(kind, TyKind::Adt(adt)
+                if adt.variants.len() == 1 && adt.variants[VariantIdx::new(0)].fields.len() == 1)
+        })
+        .map(|(ty, _)| ty)
+        .collect()
+}
+
+/// The fields of the aggregate that `keep` holds for
+fn retain_fields<T>(fields: &mut IndexVec<FieldIdx, T>, mut keep: impl FnMut(FieldIdx) -> bool) {
+    *fields = mem::take(fields)
+        .into_iter_enumerated()
+        .filter(|(field, _)| keep(*field))
+        .map(|(_, value)| value)
+        .collect();
+}
+
+/// Removes the fields (and tuple elements, as fields of variant 0) from their types,
+/// from the aggregates that build them, and renumbers the others
+fn remove_fields(repro: &mut Repro, removed: &HashSet<(TyId, VariantIdx, FieldIdx)>) {
+    let Repro { program, tcx } = repro;
+    let renumber = |ty, variant, field: FieldIdx| {
+        let before = (0..field.index())
+            .filter(|i| removed.contains(&(ty, variant, FieldIdx::new(*i))))
+            .count();
+        FieldIdx::new(field.index() - before)
+    };
+    let first = VariantIdx::new(0);
+
+    for_each_typed_statement(program, tcx, &mut |ty, statement| {
+        if let Statement::Assign(_, Rvalue::Aggregate(kind, operands)) = statement {
+            let variant = match kind {
+                AggregateKind::Adt(_, variant) => *variant,
+                AggregateKind::Tuple => first,
+                AggregateKind::Array(_) => return,
+            };
+            retain_fields(operands, |field| !removed.contains(&(ty, variant, field)));
+        }
+    });
+    for_each_projection(program, tcx, &mut |ty, proj| {
+        Some(match proj {
+            ProjectionElem::Field(field) => ProjectionElem::Field(renumber(ty, first, field)),
+            ProjectionElem::TupleField(field) => {
+                ProjectionElem::TupleField(renumber(ty, first, field))
+            }
+            ProjectionElem::DowncastField(variant, field, field_ty) => {
+                ProjectionElem::DowncastField(variant, renumber(ty, variant, field), field_ty)
+            }
+            proj => proj,
+        })
+    });
+    for_each_const(program, &mut |value| {
+        if let ConstValue::Aggregate(ty, kind, fields) = value {
+            let variant = match kind {
+                AggregateKind::Adt(_, variant) => *variant,
+                AggregateKind::Tuple => first,
+                AggregateKind::Array(_) => return,
+            };
+            retain_fields(fields, |field| !removed.contains(&(*ty, variant, field)));
+        }
+    });
+
+    for ty in removed.iter().map(|(ty, ..)| *ty).collect::<HashSet<_>>() {
+        match tcx.kind_mut(ty) {
+            TyKind::Adt(adt) => {
+                for (variant, def) in adt.variants.iter_mut_enumerated() {
+                    retain_fields(&mut def.fields, |field| {
+                        !removed.contains(&(ty, variant, field))
+                    });
+                }
+            }
+            TyKind::Tuple(elems) => {
+                let mut i = 0;
+                elems.retain(|_| {
+                    i += 1;
+                    !removed.contains(&(ty, first, FieldIdx::new(i - 1)))
+                });
+            }
+            _ => unreachable!("only ADTs and tuples have fields"),
+        }
+    }
+}
+
+/// Removes the variants from their enums, and renumbers the others
+fn remove_variants(repro: &mut Repro, removed: &HashSet<(TyId, VariantIdx)>) {
+    let Repro { program, tcx } = repro;
+    let renumber = |ty, variant: VariantIdx| {
+        let before = (0..variant.index())
+            .filter(|i| removed.contains(&(ty, VariantIdx::new(*i))))
+            .count();
+        VariantIdx::new(variant.index() - before)
+    };
+
+    for_each_typed_statement(program, tcx, &mut |ty, statement| match statement {
+        Statement::Assign(_, Rvalue::Aggregate(AggregateKind::Adt(ty, variant), _)) => {
+            *variant = renumber(*ty, *variant);
+        }
+        Statement::SetDiscriminant(_, variant) => {
+            *variant = renumber(ty, VariantIdx::new(*variant as usize)).index() as u32;
+        }
+        _ => {}
+    });
+    for_each_projection(program, tcx, &mut |ty, proj| {
+        Some(match proj {
+            ProjectionElem::DowncastField(variant, field, field_ty) => {
+                ProjectionElem::DowncastField(renumber(ty, variant), field, field_ty)
+            }
+            proj => proj,
+        })
+    });
+    for_each_const(program, &mut |value| {
+        if let ConstValue::Aggregate(ty, AggregateKind::Adt(_, variant), _) = value {
+            *variant = renumber(*ty, *variant);
+        }
+    });
+
+    for ty in removed.iter().map(|(ty, _)| *ty).collect::<HashSet<_>>() {
+        let TyKind::Adt(adt) = tcx.kind_mut(ty) else {
+            unreachable!("only ADTs have variants");
+        };
+        adt.variants = mem::take(&mut adt.variants)
+            .into_iter_enumerated()
+            .filter(|(variant, _)| !removed.contains(&(ty, *variant)))
+            .map(|(_, def)| def)
+            .collect();
+    }
+}
+
+/// Shortens the arrays to the given lengths, and the aggregates that build them
+fn shrink_arrays(repro: &mut Repro, lens: &HashMap<TyId, usize>) {
+    let Repro { program, tcx } = repro;
+    for_each_typed_statement(program, tcx, &mut |ty, statement| {
+        if let (Some(len), Statement::Assign(_, Rvalue::Aggregate(_, operands))) =
+            (lens.get(&ty), statement)
+        {
+            operands.truncate(*len);
+        }
+    });
+    for_each_const(program, &mut |value| match value {
+        ConstValue::Aggregate(ty, _, fields) if lens.contains_key(ty) => {
+            fields.truncate(lens[ty]);
+        }
+        _ => {}
+    });
+    for (ty, new_len) in lens {
+        let TyKind::Array(_, len) = tcx.kind_mut(*ty) else {
+            unreachable!("not an array");
+        };
+        *len = *new_len;
+    }
+}
+
+/// Replaces the structs with the types of their only fields, which drops projections to
+/// that field and turns the aggregates that build them into uses of the field's value
+fn inline_structs(repro: &mut Repro, inlined: &HashSet<TyId>) {
+    let Repro { program, tcx } = repro;
+    // The field may be one of the other structs
+    let mut replacements = HashMap::new();
+    for &ty in inlined {
+        let mut replacement = ty;
+        while inlined.contains(&replacement) {
+            let TyKind::Adt(adt) = tcx.kind(replacement) else {
+                unreachable!("not a struct");
+            };
+            replacement = adt.variants[VariantIdx::new(0)].fields[FieldIdx::new(0)];
+        }
+        replacements.insert(ty, replacement);
+    }
+    let replace = |ty: &mut TyId| {
+        if let Some(replacement) = replacements.get(ty) {
+            *ty = *replacement;
+        }
+    };
+
+    for_each_typed_statement(program, tcx, &mut |_, statement| {
+        let Statement::Assign(_, rvalue) = statement else {
+            return;
+        };
+        match rvalue {
+            Rvalue::Aggregate(AggregateKind::Adt(ty, _), operands) if inlined.contains(ty) => {
+                let field = operands.pop().expect("has one field");
+                *rvalue = Rvalue::Use(field);
+            }
+            _ => {}
+        }
+    });
+    for_each_projection(program, tcx, &mut |ty, proj| {
+        (!inlined.contains(&ty)).then_some(proj)
+    });
+    for_each_const(program, &mut |value| match value {
+        ConstValue::Aggregate(ty, _, fields) if inlined.contains(ty) => {
+            let field = fields.pop().expect("has one field");
+            *value = field;
+        }
+        _ => {}
+    });
+
+    for_each_ty(program, &mut |ty| replace(ty));
+    for ty in tcx.indices().collect::<Vec<_>>() {
+        for component in components(tcx.kind_mut(ty)) {
+            replace(component);
+        }
+    }
+    remove_unused_types(repro);
+}
+
+/// Rebuilds the type context with only the types the program uses, which renumbers
+/// (and so renames) the ADTs
+fn remove_unused_types(repro: &mut Repro) -> bool {
+    let Repro { program, tcx } = repro;
+    let mut used = HashSet::new();
+    for_each_ty(program, &mut |ty| {
+        used.insert(*ty);
+    });
+    let mut stack: Vec<TyId> = used.iter().copied().collect();
+    while let Some(ty) = stack.pop() {
+        for component in components(&mut tcx.kind(ty).clone()) {
+            if used.insert(*component) {
+                stack.push(*component);
+            }
+        }
+    }
+
+    let mut compact = TyCtxt::from_primitives();
+    let primitives = compact.len();
+    let kept: Vec<TyId> = tcx
+        .indices()
+        .filter(|ty| ty.index() < primitives || used.contains(ty))
+        .collect();
+    if kept.len() == tcx.len() {
+        return false;
+    }
+
+    let renumbered: HashMap<TyId, TyId> = kept
+        .iter()
+        .enumerate()
+        .map(|(new, old)| (*old, TyId::new(new)))
+        .collect();
+    for &ty in &kept[primitives..] {
+        let mut kind = tcx.kind(ty).clone();
+        for component in components(&mut kind) {
+            *component = renumbered[component];
+        }
+        let new = match kind {
+            TyKind::Adt(adt) => compact.push_adt(adt, tcx.meta(ty)),
+            kind => compact.push(kind),
+        };
+        assert_eq!(new, renumbered[&ty]);
+    }
+    for_each_ty(program, &mut |ty| *ty = renumbered[ty]);
+    *tcx = compact;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use mir::{
+        serialize::CallSynatx,
+        syntax::{
+            Adt, AggregateKind, BasicBlock, BasicBlockData, Body, Callee, FieldIdx, Literal, Local,
+            Mutability, Operand, Place, Program, ProjectionElem, Rvalue, Statement, Terminator,
+            TyId, TyKind, VariantDef, VariantIdx,
+        },
+        tyctxt::{AdtMeta, TyCtxt},
+    };
+
+    use super::{
+        inline, inline_structs, remove_fields, remove_unreachable, remove_unused_locals,
+        remove_unused_types, unused_fields, Repro,
+    };
+
+    fn block(statements: Vec<Statement>, terminator: Terminator) -> BasicBlockData {
+        let mut data = BasicBlockData::new();
+        for statement in statements {
+            data.insert_statement(statement);
+        }
+        data.set_terminator(terminator);
+        data
+    }
+
+    #[test]
+    fn unreachable_blocks() {
+        let mut body = Body::new(&[], TyCtxt::UNIT, false);
+        let goto = |target| Terminator::Goto {
+            target: BasicBlock::new(target),
+        };
+        body.basic_blocks.push(block(vec![], goto(2)));
+        body.basic_blocks.push(block(vec![], goto(2)));
+        body.basic_blocks.push(block(vec![], Terminator::Return));
+        assert!(remove_unreachable(&mut body));
+        assert_eq!(body.basic_blocks.len(), 2);
+        assert!(matches!(
+            body.basic_blocks[BasicBlock::new(0)].terminator(),
+            Terminator::Goto { target } if target.index() == 1
+        ));
+        assert!(!remove_unreachable(&mut body));
+    }
+
+    #[test]
+    fn inline_call() {
+        let tcx = TyCtxt::from_primitives();
+        let mut program = Program::new(false);
+
+        let mut caller = Body::new(&[], TyCtxt::I32, false);
+        caller.basic_blocks.push(block(
+            vec![],
+            Terminator::Call {
+                callee: Callee::Generated(mir::syntax::Function::new(1)),
+                destination: Place::RETURN_SLOT,
+                target: BasicBlock::new(1),
+                args: vec![Operand::Constant(Literal::Int(1, mir::syntax::IntTy::I32))],
+            },
+        ));
+        caller.basic_blocks.push(block(vec![], Terminator::Return));
+        program.push_fn(caller);
+
+        let mut callee = Body::new(&[TyCtxt::I32], TyCtxt::I32, false);
+        callee.basic_blocks.push(block(
+            vec![Statement::Assign(
+                Place::RETURN_SLOT,
+                Rvalue::Use(Operand::Copy(Place::from_local(Local::new(1)))),
+            )],
+            Terminator::Return,
+        ));
+        program.push_fn(callee);
+        program.set_entry_args(&[]);
+
+        inline(&mut program, mir::syntax::Function::new(1));
+        assert_eq!(program.functions.len(), 1);
+        let code = generate::serialize(&program, &tcx, CallSynatx::V4);
+        assert!(code.contains("let _1: i32;\nlet _2: i32;\n"), "{code}");
+        assert!(code.contains("_2 = 1_i32;\nGoto(bb2)"), "{code}");
+        assert!(
+            code.contains("_1 = _2;\nRET = Move(_1);\nGoto(bb1)"),
+            "{code}"
+        );
+    }
+
+    #[test]
+    fn unused_locals_and_types() {
+        let mut tcx = TyCtxt::from_primitives();
+        let unused = tcx.push_adt(
+            mir::syntax::Adt {
+                variants: [mir::syntax::VariantDef {
+                    fields: [TyCtxt::I8].into_iter().collect(),
+                }]
+                .into_iter()
+                .collect(),
+            },
+            AdtMeta {
+                copy: true,
+                repr_c: false,
+            },
+        );
+        let used = tcx.push(TyKind::Tuple(vec![TyCtxt::I8, TyCtxt::BOOL]));
+
+        let mut body = Body::new(&[], TyCtxt::UNIT, false);
+        body.declare_new_var(mir::syntax::Mutability::Mut, unused);
+        let tuple = body.declare_new_var(mir::syntax::Mutability::Mut, used);
+        body.basic_blocks.push(block(
+            vec![Statement::StorageLive(tuple)],
+            Terminator::Return,
+        ));
+        let mut program = Program::new(false);
+        program.push_fn(body);
+
+        let func = mir::syntax::Function::new(0);
+        assert!(remove_unused_locals(func, &mut program.functions[func]));
+        let mut repro = Repro { program, tcx };
+        assert!(remove_unused_types(&mut repro));
+        assert_eq!(repro.tcx.len(), TyCtxt::from_primitives().len() + 1);
+        let body = &repro.program.functions[mir::syntax::Function::new(0)];
+        assert_eq!(body.local_decls.len(), 2);
+        assert_eq!(body.local_decls[Local::new(1)].ty, unused);
+        assert!(matches!(
+            body.basic_blocks[BasicBlock::new(0)].statements(),
+            [Statement::StorageLive(local)] if local.index() == 1
+        ));
+    }
+
+    /// A program that builds a struct with these fields, and then assigns to the last one
+    fn struct_repro(fields: &[Literal]) -> (Repro, TyId) {
+        let mut tcx = TyCtxt::from_primitives();
+        let adt = tcx.push_adt(
+            Adt {
+                variants: [VariantDef {
+                    fields: fields.iter().map(|field| field.ty()).collect(),
+                }]
+                .into_iter()
+                .collect(),
+            },
+            AdtMeta {
+                copy: true,
+                repr_c: false,
+            },
+        );
+
+        let mut body = Body::new(&[], TyCtxt::UNIT, false);
+        let local = body.declare_new_var(Mutability::Mut, adt);
+        let last = FieldIdx::new(fields.len() - 1);
+        body.basic_blocks.push(block(
+            vec![
+                Statement::Assign(
+                    Place::from_local(local),
+                    Rvalue::Aggregate(
+                        AggregateKind::Adt(adt, VariantIdx::new(0)),
+                        fields
+                            .iter()
+                            .map(|field| Operand::Constant(*field))
+                            .collect(),
+                    ),
+                ),
+                Statement::Assign(
+                    Place::from_projected(local, &[ProjectionElem::Field(last)]),
+                    Rvalue::Use(Operand::Constant(fields[last.index()])),
+                ),
+            ],
+            Terminator::Return,
+        ));
+        let mut program = Program::new(false);
+        program.push_fn(body);
+        program.set_entry_args(&[]);
+        (Repro { program, tcx }, adt)
+    }
+
+    #[test]
+    fn unused_struct_fields() {
+        let (mut repro, adt) = struct_repro(&[Literal::Bool(true), 1_u32.into(), 2_i8.into()]);
+        let unused = unused_fields(&repro);
+        assert_eq!(
+            unused,
+            [
+                (adt, VariantIdx::new(0), FieldIdx::new(0)),
+                (adt, VariantIdx::new(0), FieldIdx::new(1)),
+            ]
+        );
+        remove_fields(&mut repro, &unused.into_iter().collect());
+        let code = repro.serialize(CallSynatx::V4);
+        assert!(
+            code.contains("pub struct Adt17 {\npub fld0: i8,\n}"),
+            "{code}"
+        );
+        assert!(
+            code.contains("_1 = Adt17 { fld0: 2_i8 };\n_1.fld0 = 2_i8;"),
+            "{code}"
+        );
+        assert!(unused_fields(&repro).is_empty());
+    }
+
+    #[test]
+    fn single_field_struct() {
+        let (mut repro, adt) = struct_repro(&[1_u16.into()]);
+        inline_structs(&mut repro, &[adt].into_iter().collect());
+        assert_eq!(repro.tcx.len(), TyCtxt::from_primitives().len());
+        let code = repro.serialize(CallSynatx::V4);
+        assert!(code.contains("let _1: u16;\n"), "{code}");
+        assert!(code.contains("_1 = 1_u16;\n_1 = 1_u16;"), "{code}");
+    }
+}